@@ -1,5 +1,6 @@
 //! Configuration constants and types for Slack VFS.
 
+use crate::storage::HostPolicy;
 use serde::{Deserialize, Serialize};
 
 /// Default block size (4KB, common for most file systems).
@@ -21,7 +22,7 @@ pub const MAX_REDUNDANCY_RATIO: f32 = 2.0;
 pub const VFS_MAGIC: [u8; 4] = [0x53, 0x56, 0x46, 0x53];
 
 /// Current VFS version.
-pub const VFS_VERSION: u32 = 1;
+pub const VFS_VERSION: u32 = 6;
 
 /// Argon2id parameters for key derivation.
 pub mod argon2_params {
@@ -50,6 +51,154 @@ pub mod wipe_params {
     pub const ZERO_PASSES: u8 = 1;
 }
 
+/// How the symbols of one encoded object are spread over host files.
+///
+/// Whatever the policy, placement honours the VFS's [`FaultTolerance`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PlacementPolicy {
+    /// Visit hosts in scan order, one symbol each, carrying on from where
+    /// the previous object stopped.
+    RoundRobin,
+    /// Give each symbol to the host holding the fewest of the object's
+    /// symbols so far, preferring hosts with more free space.
+    #[default]
+    FewestPerHost,
+    /// Share symbols out in proportion to each host's free space.
+    WeightedByFree,
+}
+
+impl std::fmt::Display for PlacementPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PlacementPolicy::RoundRobin => "round-robin",
+            PlacementPolicy::FewestPerHost => "fewest per host",
+            PlacementPolicy::WeightedByFree => "weighted by free space",
+        })
+    }
+}
+
+/// Which free extent of a region a new symbol is carved from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FitStrategy {
+    /// The lowest-offset extent large enough.
+    #[default]
+    FirstFit,
+    /// The smallest extent large enough, leaving big holes whole.
+    BestFit,
+}
+
+impl std::fmt::Display for FitStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FitStrategy::FirstFit => "first fit",
+            FitStrategy::BestFit => "best fit",
+        })
+    }
+}
+
+/// How slack given up by deleted files and old superblock copies is
+/// overwritten before it is reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ShredPolicy {
+    /// Leave the old symbols in place.
+    None,
+    /// One pass of zeros.
+    Zero,
+    /// One pass of random data, which looks like the symbols it replaces.
+    #[default]
+    Random,
+    /// This many passes of random data, then [`wipe_params::ZERO_PASSES`]
+    /// passes of zeros.
+    Passes(u8),
+}
+
+impl ShredPolicy {
+    /// The overwrite `wipe` uses: [`wipe_params::RANDOM_PASSES`] random
+    /// passes, then zeros.
+    pub const SECURE: ShredPolicy = ShredPolicy::Passes(wipe_params::RANDOM_PASSES);
+}
+
+impl std::fmt::Display for ShredPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShredPolicy::None => f.write_str("none"),
+            ShredPolicy::Zero => f.write_str("zeros"),
+            ShredPolicy::Random => f.write_str("random data"),
+            ShredPolicy::Passes(passes) => write!(f, "{} random passes, then zeros", passes),
+        }
+    }
+}
+
+/// What a single failure takes out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum FailureDomain {
+    /// One host file.
+    #[default]
+    Host,
+    /// Every host file directly in one directory.
+    Directory,
+    /// Every host file on one device (file system).
+    Device,
+}
+
+impl std::fmt::Display for FailureDomain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FailureDomain::Host => "host",
+            FailureDomain::Directory => "directory",
+            FailureDomain::Device => "device",
+        })
+    }
+}
+
+/// Losses every file must survive: any `losses` failure domains at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FaultTolerance {
+    /// What counts as one loss.
+    pub domain: FailureDomain,
+    /// How many domains may be lost together.
+    pub losses: usize,
+}
+
+impl Default for FaultTolerance {
+    fn default() -> Self {
+        Self {
+            domain: FailureDomain::Host,
+            losses: 1,
+        }
+    }
+}
+
+impl FaultTolerance {
+    /// Most symbols of an object with `repair_symbols` repair symbols that
+    /// a single domain may hold. Losing the `losses` fullest domains then
+    /// costs no more than the repair symbols.
+    ///
+    /// Needs at least one repair symbol per loss; with fewer the cap is 0
+    /// and no domain may hold a symbol. The encoder never makes fewer, as
+    /// [`EncodingConfig::min_repair_symbols`] is set to `losses`.
+    pub fn per_domain_cap(&self, repair_symbols: usize) -> usize {
+        match self.losses {
+            0 => usize::MAX,
+            losses => repair_symbols / losses,
+        }
+    }
+}
+
+impl std::fmt::Display for FaultTolerance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let noun = match (self.domain, self.losses) {
+            (FailureDomain::Host, 1) => "host",
+            (FailureDomain::Host, _) => "hosts",
+            (FailureDomain::Directory, 1) => "directory",
+            (FailureDomain::Directory, _) => "directories",
+            (FailureDomain::Device, 1) => "device",
+            (FailureDomain::Device, _) => "devices",
+        };
+        write!(f, "any {} {}", self.losses, noun)
+    }
+}
+
 /// Configuration for VFS initialization.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VfsConfig {
@@ -62,6 +211,26 @@ pub struct VfsConfig {
     /// Redundancy ratio (0.0 to 2.0).
     /// 0.5 means 50% extra repair symbols.
     pub redundancy_ratio: f32,
+
+    /// How symbols are spread over host files.
+    #[serde(default)]
+    pub placement: PlacementPolicy,
+
+    /// Failure domains every file must be able to lose.
+    #[serde(default)]
+    pub fault_tolerance: FaultTolerance,
+
+    /// How free slack is picked for new symbols.
+    #[serde(default)]
+    pub fit: FitStrategy,
+
+    /// How freed slack is overwritten.
+    #[serde(default)]
+    pub shred: ShredPolicy,
+
+    /// Which files may become hosts.
+    #[serde(default)]
+    pub host_policy: HostPolicy,
 }
 
 impl Default for VfsConfig {
@@ -70,6 +239,11 @@ impl Default for VfsConfig {
             block_size: DEFAULT_BLOCK_SIZE,
             symbol_size: DEFAULT_SYMBOL_SIZE,
             redundancy_ratio: DEFAULT_REDUNDANCY_RATIO,
+            placement: PlacementPolicy::default(),
+            fault_tolerance: FaultTolerance::default(),
+            fit: FitStrategy::default(),
+            shred: ShredPolicy::default(),
+            host_policy: HostPolicy::default(),
         }
     }
 }
@@ -81,6 +255,11 @@ impl VfsConfig {
             block_size,
             symbol_size,
             redundancy_ratio: redundancy_ratio.clamp(MIN_REDUNDANCY_RATIO, MAX_REDUNDANCY_RATIO),
+            placement: PlacementPolicy::default(),
+            fault_tolerance: FaultTolerance::default(),
+            fit: FitStrategy::default(),
+            shred: ShredPolicy::default(),
+            host_policy: HostPolicy::default(),
         }
     }
 
@@ -112,6 +291,10 @@ pub struct EncodingConfig {
 
     /// Ratio of repair symbols to source symbols.
     pub redundancy_ratio: f32,
+
+    /// Fewest repair symbols to generate, however small the data.
+    #[serde(default)]
+    pub min_repair_symbols: usize,
 }
 
 impl From<&VfsConfig> for EncodingConfig {
@@ -119,6 +302,8 @@ impl From<&VfsConfig> for EncodingConfig {
         Self {
             symbol_size: config.symbol_size,
             redundancy_ratio: config.redundancy_ratio,
+            // One repair symbol per domain that may be lost
+            min_repair_symbols: config.fault_tolerance.losses,
         }
     }
 }
@@ -128,6 +313,7 @@ impl Default for EncodingConfig {
         Self {
             symbol_size: DEFAULT_SYMBOL_SIZE,
             redundancy_ratio: DEFAULT_REDUNDANCY_RATIO,
+            min_repair_symbols: 0,
         }
     }
 }
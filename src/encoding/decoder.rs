@@ -171,6 +171,7 @@ mod tests {
         let config = EncodingConfig {
             symbol_size: 512,
             redundancy_ratio: 0.5, // 50% extra symbols
+            ..EncodingConfig::default()
         };
 
         let encoded = encode(&data, &config).unwrap();
@@ -189,6 +190,7 @@ mod tests {
         let config = EncodingConfig {
             symbol_size: 512,
             redundancy_ratio: 0.5,
+            ..EncodingConfig::default()
         };
 
         let encoded = encode(&data, &config).unwrap();
@@ -90,8 +90,9 @@ pub fn encode(data: &[u8], config: &EncodingConfig) -> Result<EncodedData> {
     let _oti = encoder.get_config();
 
     // Calculate number of source and repair symbols
-    let source_symbols = (data.len() + symbol_size - 1) / symbol_size;
-    let repair_symbols = ((source_symbols as f32) * config.redundancy_ratio).ceil() as usize;
+    let source_symbols = data.len().div_ceil(symbol_size);
+    let repair_symbols = (((source_symbols as f32) * config.redundancy_ratio).ceil() as usize)
+        .max(config.min_repair_symbols);
     let total_symbols = source_symbols + repair_symbols;
 
     // Generate all symbols
@@ -110,8 +111,7 @@ pub fn encode(data: &[u8], config: &EncodingConfig) -> Result<EncodedData> {
         }
 
         // Get repair symbols
-        let repair_per_block = (repair_symbols + encoder.get_block_encoders().len() - 1)
-            / encoder.get_block_encoders().len();
+        let repair_per_block = repair_symbols.div_ceil(encoder.get_block_encoders().len());
         for packet in block.repair_packets(0, repair_per_block as u32) {
             symbols.push(EncodingSymbol {
                 id: symbol_id,
@@ -183,4 +183,18 @@ mod tests {
 
         assert_eq!(ids.len(), encoded.symbols.len());
     }
+
+    #[test]
+    fn test_min_repair_symbols() {
+        let config = EncodingConfig {
+            min_repair_symbols: 3,
+            ..EncodingConfig::default()
+        };
+
+        // One source symbol would otherwise get a single repair symbol
+        let encoded = encode(b"short", &config).unwrap();
+        assert_eq!(encoded.source_symbols, 1);
+        assert_eq!(encoded.repair_symbols, 3);
+        assert_eq!(encode(b"", &config).unwrap().symbols.len(), 0);
+    }
 }
@@ -6,5 +6,5 @@
 mod decoder;
 mod encoder;
 
-pub use decoder::{can_decode, decode, DecodingProgress};
+pub use decoder::{can_decode, decode, decode_partial, DecodingProgress};
 pub use encoder::{encode, EncodedData, EncodingSymbol};
@@ -37,6 +37,15 @@ pub enum Error {
     #[error("Not enough slack space: need {needed} bytes, have {available} bytes")]
     InsufficientSpace { needed: u64, available: u64 },
 
+    /// Too few failure domains to spread symbols without exceeding the
+    /// per-domain cap.
+    #[error("Cannot fit {symbols} symbols into {domains} domains at {per_domain} per domain")]
+    InsufficientHosts {
+        symbols: usize,
+        per_domain: usize,
+        domains: usize,
+    },
+
     /// Host file not found.
     #[error("Host file not found: {0}")]
     HostFileNotFound(PathBuf),
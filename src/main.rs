@@ -3,10 +3,13 @@
 //! Stores encrypted data in file system slack space with erasure coding
 //! for resilience against partial data loss.
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use slack_vfs::config::{FailureDomain, FaultTolerance, FitStrategy, PlacementPolicy, ShredPolicy};
+use slack_vfs::storage::{Carrier, FileSlackBackend, HostPolicy};
 use slack_vfs::{Result, SlackVfs, VfsConfig};
 use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(name = "slack-vfs")]
@@ -16,10 +19,314 @@ use std::path::PathBuf;
     long_about = "A VFS that stores encrypted data in file system slack space with RaptorQ erasure coding for resilience."
 )]
 struct Cli {
+    #[command(flatten)]
+    backend: BackendArgs,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+/// Where symbols are stored.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BackendKind {
+    /// Append after each host file's end of file
+    File,
+    /// Write slack through the raw block device (requires root)
+    Device,
+    /// Write slack inside an unmounted ext4 image (see --image)
+    #[cfg(target_os = "linux")]
+    Image,
+    /// Write into unused ext4 inode space inside an unmounted image (see
+    /// --image)
+    #[cfg(target_os = "linux")]
+    InodePadding,
+    /// Write into ext4 directory entry padding inside an unmounted image
+    /// (see --image)
+    #[cfg(target_os = "linux")]
+    DirentPadding,
+    /// Write block slack inside an unmounted XFS image (see --image)
+    #[cfg(target_os = "linux")]
+    Xfs,
+    /// Write cluster slack inside an unmounted FAT12/16/32 image (see --image)
+    Fat,
+    /// Write cluster slack inside an unmounted exFAT image (see --image)
+    Exfat,
+    /// Write cluster slack inside an unmounted NTFS image (see --image)
+    Ntfs,
+    /// Write into partition table gaps and file system tails of an
+    /// unmounted whole-disk image (see --image)
+    PartitionGaps,
+    /// Embed data where each host's format allows it: PNG chunks, JPEG
+    /// APP15 segments, PDF comments and ZIP archive comments (see
+    /// --trailer-capacity)
+    FormatTrailers,
+    /// Hide data in the low bits of PNG and BMP pixels, in an order derived
+    /// from a separate embedding key (see --lsb-bits)
+    LsbImages,
+    /// Hide data in the low bits of 16- and 24-bit PCM WAVE samples, in an
+    /// order derived from a separate embedding key (see --lsb-bits and
+    /// --skip-silence)
+    LsbAudio,
+    /// Store symbols in user.* extended attributes of the host files (see
+    /// --xattr-naming)
+    #[cfg(target_os = "linux")]
+    Xattr,
+}
+
+/// How symbols are spread over host files.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Placement {
+    /// One symbol per host in turn
+    RoundRobin,
+    /// Each symbol to the host holding fewest of the file's symbols
+    FewestPerHost,
+    /// Symbols shared out in proportion to free space
+    WeightedByFree,
+}
+
+impl From<Placement> for PlacementPolicy {
+    fn from(placement: Placement) -> Self {
+        match placement {
+            Placement::RoundRobin => PlacementPolicy::RoundRobin,
+            Placement::FewestPerHost => PlacementPolicy::FewestPerHost,
+            Placement::WeightedByFree => PlacementPolicy::WeightedByFree,
+        }
+    }
+}
+
+/// Which free hole of a host's slack new symbols go in.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Fit {
+    /// The first hole large enough
+    FirstFit,
+    /// The smallest hole large enough
+    BestFit,
+}
+
+impl From<Fit> for FitStrategy {
+    fn from(fit: Fit) -> Self {
+        match fit {
+            Fit::FirstFit => FitStrategy::FirstFit,
+            Fit::BestFit => FitStrategy::BestFit,
+        }
+    }
+}
+
+/// Parse a shred policy: `none`, `zero`, `random`, or a number of random
+/// passes to follow with zeros.
+fn parse_shred(value: &str) -> std::result::Result<ShredPolicy, String> {
+    match value {
+        "none" => Ok(ShredPolicy::None),
+        "zero" => Ok(ShredPolicy::Zero),
+        "random" => Ok(ShredPolicy::Random),
+        passes => passes.parse().map(ShredPolicy::Passes).map_err(|_| {
+            format!(
+                "expected none, zero, random or a number of passes, not '{}'",
+                value
+            )
+        }),
+    }
+}
+
+/// Parse an age: a number of seconds, optionally followed by `s`, `m`,
+/// `h` or `d`.
+fn parse_age(value: &str) -> std::result::Result<Duration, String> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(at) => value.split_at(at),
+        None => (value, "s"),
+    };
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown unit '{}', expected s, m, h or d", unit)),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("expected an age like 90, 30m or 7d, not '{}'", value))?;
+    number
+        .checked_mul(scale)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("age '{}' is too large", value))
+}
+
+/// What a single failure takes out.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Domain {
+    /// One host file
+    Host,
+    /// Every host file in one directory
+    Directory,
+    /// Every host file on one device
+    Device,
+}
+
+impl From<Domain> for FailureDomain {
+    fn from(domain: Domain) -> Self {
+        match domain {
+            Domain::Host => FailureDomain::Host,
+            Domain::Directory => FailureDomain::Directory,
+            Domain::Device => FailureDomain::Device,
+        }
+    }
+}
+
+/// Attribute names used by the xattr backend.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum XattrScheme {
+    /// freedesktop.org attributes (user.xdg.origin.url, user.xdg.comment, ...)
+    Xdg,
+    /// Dropbox client attributes (user.com.dropbox.attrs, ...)
+    Dropbox,
+    /// rsync --fake-super attributes (user.rsync.%stat, ...)
+    Rsync,
+    /// shatag checksum attributes (user.shatag.sha256, ...)
+    Shatag,
+}
+
+#[derive(Args)]
+struct BackendArgs {
+    /// Storage backend
+    #[arg(long, global = true, value_enum, default_value = "file")]
+    backend: BackendKind,
+
+    /// Image or device holding the host files (for the image, inode-padding,
+    /// dirent-padding, xfs, fat, exfat, ntfs and partition-gaps backends)
+    #[arg(
+        long,
+        global = true,
+        required_if_eq_any([
+            ("backend", "image"),
+            ("backend", "inode-padding"),
+            ("backend", "dirent-padding"),
+            ("backend", "xfs"),
+            ("backend", "fat"),
+            ("backend", "exfat"),
+            ("backend", "ntfs"),
+            ("backend", "partition-gaps")
+        ])
+    )]
+    image: Option<PathBuf>,
+
+    /// Payload bytes per host for the format-trailers backend, capped by
+    /// what each format allows
+    #[arg(long, global = true, default_value_t = slack_vfs::storage::format::DEFAULT_CAPACITY)]
+    trailer_capacity: u64,
+
+    /// Low bits used in each colour or audio sample by the lsb-images and
+    /// lsb-audio backends (1-4); later commands must use the same count
+    #[arg(long, global = true, default_value_t = slack_vfs::storage::lsb::DEFAULT_BITS_PER_CHANNEL)]
+    lsb_bits: u8,
+
+    /// Leave near-silent samples alone with the lsb-audio backend; later
+    /// commands must pass it too
+    #[arg(long, global = true)]
+    skip_silence: bool,
+
+    /// Attribute naming scheme for the xattr backend; later commands must
+    /// use the same one
+    #[cfg(target_os = "linux")]
+    #[arg(long, global = true, value_enum, default_value = "xdg")]
+    xattr_naming: XattrScheme,
+}
+
+impl BackendArgs {
+    /// Open the selected backend. `block_size` is only used by file-append
+    /// mode; the other backends read it from the file system.
+    fn open(&self, block_size: u64) -> Result<Box<dyn Carrier>> {
+        match self.backend {
+            BackendKind::File => Ok(Box::new(FileSlackBackend::new(block_size))),
+            BackendKind::Device => slack_vfs::storage::create_backend(),
+            #[cfg(target_os = "linux")]
+            BackendKind::Image => {
+                use slack_vfs::storage::linux::Ext4ImageBackend;
+                let image = self.image.as_deref().expect("--image is required");
+                Ok(Box::new(Ext4ImageBackend::open(image)?))
+            }
+            #[cfg(target_os = "linux")]
+            BackendKind::InodePadding => {
+                use slack_vfs::storage::linux::Ext4InodePaddingBackend;
+                let image = self.image.as_deref().expect("--image is required");
+                Ok(Box::new(Ext4InodePaddingBackend::open(image)?))
+            }
+            #[cfg(target_os = "linux")]
+            BackendKind::DirentPadding => {
+                use slack_vfs::storage::linux::Ext4DirentPaddingBackend;
+                let image = self.image.as_deref().expect("--image is required");
+                Ok(Box::new(Ext4DirentPaddingBackend::open(image)?))
+            }
+            #[cfg(target_os = "linux")]
+            BackendKind::Xfs => {
+                use slack_vfs::storage::linux::XfsImageBackend;
+                let image = self.image.as_deref().expect("--image is required");
+                Ok(Box::new(XfsImageBackend::open(image)?))
+            }
+            BackendKind::Fat => {
+                use slack_vfs::storage::fat::FatImageBackend;
+                let image = self.image.as_deref().expect("--image is required");
+                Ok(Box::new(FatImageBackend::open(image)?))
+            }
+            BackendKind::Exfat => {
+                use slack_vfs::storage::exfat::ExfatImageBackend;
+                let image = self.image.as_deref().expect("--image is required");
+                Ok(Box::new(ExfatImageBackend::open(image)?))
+            }
+            BackendKind::Ntfs => {
+                use slack_vfs::storage::ntfs::NtfsImageBackend;
+                let image = self.image.as_deref().expect("--image is required");
+                Ok(Box::new(NtfsImageBackend::open(image)?))
+            }
+            BackendKind::PartitionGaps => {
+                use slack_vfs::storage::partition::PartitionGapBackend;
+                let image = self.image.as_deref().expect("--image is required");
+                Ok(Box::new(PartitionGapBackend::open(image)?))
+            }
+            BackendKind::FormatTrailers => {
+                use slack_vfs::storage::format::FormatTrailerBackend;
+                Ok(Box::new(FormatTrailerBackend::new(self.trailer_capacity)))
+            }
+            BackendKind::LsbImages => {
+                use slack_vfs::storage::lsb::LsbImageBackend;
+                let key = prompt_password("Embedding key: ");
+                Ok(Box::new(LsbImageBackend::new(
+                    key.as_bytes(),
+                    self.lsb_bits,
+                )?))
+            }
+            BackendKind::LsbAudio => {
+                use slack_vfs::storage::lsb::LsbAudioBackend;
+                let key = prompt_password("Embedding key: ");
+                Ok(Box::new(LsbAudioBackend::new(
+                    key.as_bytes(),
+                    self.lsb_bits,
+                    self.skip_silence,
+                )?))
+            }
+            #[cfg(target_os = "linux")]
+            BackendKind::Xattr => {
+                use slack_vfs::storage::{XattrBackend, XattrNaming};
+                let naming = match self.xattr_naming {
+                    XattrScheme::Xdg => XattrNaming::Xdg,
+                    XattrScheme::Dropbox => XattrNaming::Dropbox,
+                    XattrScheme::Rsync => XattrNaming::Rsync,
+                    XattrScheme::Shatag => XattrNaming::Shatag,
+                };
+                Ok(Box::new(XattrBackend::new(naming)))
+            }
+        }
+    }
+
+    /// Mount the VFS in `host_dir` with the selected backend.
+    fn mount(&self, host_dir: &Path, password: &str) -> Result<SlackVfs> {
+        match self.backend {
+            BackendKind::File => SlackVfs::mount(host_dir, password),
+            _ => SlackVfs::mount_with_backend(host_dir, password, self.open(0)?),
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Initialize a new VFS in the given directory
@@ -38,6 +345,58 @@ enum Commands {
         /// Symbol size for encoding (default: 1024)
         #[arg(long, default_value = "1024")]
         symbol_size: u16,
+
+        /// How symbols are spread over host files; no host ever holds more
+        /// of a file's symbols than its repair symbols
+        #[arg(long, value_enum, default_value = "fewest-per-host")]
+        placement: Placement,
+
+        /// Number of failure domains every file must survive losing at once
+        #[arg(long, default_value = "1")]
+        tolerate: usize,
+
+        /// What counts as one failure for --tolerate
+        #[arg(long, value_enum, default_value = "host")]
+        failure_domain: Domain,
+
+        /// Which free hole of a host's slack new symbols go in
+        #[arg(long, value_enum, default_value = "first-fit")]
+        fit: Fit,
+
+        /// How slack freed by deletes and superblock rewrites is
+        /// overwritten: none, zero, random, or N for N random passes then
+        /// zeros
+        #[arg(long, value_parser = parse_shred, default_value = "random")]
+        shred: ShredPolicy,
+
+        /// Only take host files matching this glob (repeatable). Patterns
+        /// without '/' match the file name, others the path relative to
+        /// the host directory; '**' spans directories
+        #[arg(long, value_name = "GLOB")]
+        include: Vec<String>,
+
+        /// Never take host files matching this glob (repeatable), e.g.
+        /// '*.log' or '**/.git/**'
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
+
+        /// Only take host files with one of these extensions (repeatable or
+        /// comma-separated)
+        #[arg(long = "extension", value_name = "EXT", value_delimiter = ',')]
+        extensions: Vec<String>,
+
+        /// Skip host files smaller than this many bytes
+        #[arg(long, value_name = "BYTES")]
+        min_size: Option<u64>,
+
+        /// Skip host files larger than this many bytes
+        #[arg(long, value_name = "BYTES")]
+        max_size: Option<u64>,
+
+        /// Skip host files modified more recently than this: seconds, or a
+        /// number followed by s, m, h or d
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        min_age: Option<Duration>,
     },
 
     /// List VFS directory contents
@@ -110,6 +469,12 @@ enum Commands {
         host_dir: PathBuf,
     },
 
+    /// Move symbols to the start of each host's slack and wipe the rest
+    Compact {
+        /// Directory containing host files
+        host_dir: PathBuf,
+    },
+
     /// Securely wipe all VFS data
     Wipe {
         /// Directory containing host files
@@ -125,6 +490,13 @@ enum Commands {
         /// Directory containing host files
         host_dir: PathBuf,
     },
+
+    /// List candidate host files inside an unmounted ext4 image
+    #[cfg(target_os = "linux")]
+    ImageHosts {
+        /// Path to the ext4 image file
+        image: PathBuf,
+    },
 }
 
 fn main() {
@@ -137,40 +509,79 @@ fn main() {
 }
 
 fn run(cli: Cli) -> Result<()> {
+    let backend = &cli.backend;
+
     match cli.command {
         Commands::Init {
             host_dir,
             block_size,
             redundancy,
             symbol_size,
-        } => cmd_init(&host_dir, block_size, redundancy, symbol_size),
+            placement,
+            tolerate,
+            failure_domain,
+            fit,
+            shred,
+            include,
+            exclude,
+            extensions,
+            min_size,
+            max_size,
+            min_age,
+        } => {
+            let host_policy = HostPolicy {
+                include,
+                exclude,
+                min_size,
+                max_size,
+                min_age,
+                extensions,
+            };
+            let config = VfsConfig {
+                placement: placement.into(),
+                fault_tolerance: FaultTolerance {
+                    domain: failure_domain.into(),
+                    losses: tolerate,
+                },
+                fit: fit.into(),
+                shred,
+                host_policy,
+                ..VfsConfig::new(block_size, symbol_size, redundancy)
+            };
+            cmd_init(&host_dir, backend, config)
+        }
 
-        Commands::Ls { host_dir, vfs_path } => cmd_ls(&host_dir, &vfs_path),
+        Commands::Ls { host_dir, vfs_path } => cmd_ls(&host_dir, backend, &vfs_path),
 
         Commands::Write {
             host_dir,
             vfs_path,
             input,
             data,
-        } => cmd_write(&host_dir, &vfs_path, input, data),
+        } => cmd_write(&host_dir, backend, &vfs_path, input, data),
 
         Commands::Read {
             host_dir,
             vfs_path,
             output,
-        } => cmd_read(&host_dir, &vfs_path, output),
+        } => cmd_read(&host_dir, backend, &vfs_path, output),
+
+        Commands::Rm { host_dir, vfs_path } => cmd_rm(&host_dir, backend, &vfs_path),
+
+        Commands::Mkdir { host_dir, vfs_path } => cmd_mkdir(&host_dir, backend, &vfs_path),
 
-        Commands::Rm { host_dir, vfs_path } => cmd_rm(&host_dir, &vfs_path),
+        Commands::Info { host_dir } => cmd_info(&host_dir, backend),
 
-        Commands::Mkdir { host_dir, vfs_path } => cmd_mkdir(&host_dir, &vfs_path),
+        Commands::Health { host_dir } => cmd_health(&host_dir, backend),
 
-        Commands::Info { host_dir } => cmd_info(&host_dir),
+        Commands::Compact { host_dir } => cmd_compact(&host_dir, backend),
 
-        Commands::Health { host_dir } => cmd_health(&host_dir),
+        Commands::Wipe { host_dir, force } => cmd_wipe(&host_dir, backend, force),
 
-        Commands::Wipe { host_dir, force } => cmd_wipe(&host_dir, force),
+        Commands::Passwd { host_dir } => cmd_passwd(&host_dir, backend),
 
-        Commands::Passwd { host_dir } => cmd_passwd(&host_dir),
+        #[cfg(target_os = "linux")]
+        Commands::ImageHosts { image } => cmd_image_hosts(&image),
     }
 }
 
@@ -184,7 +595,7 @@ fn prompt_password(prompt: &str) -> String {
     })
 }
 
-fn cmd_init(host_dir: &PathBuf, block_size: u64, redundancy: f32, symbol_size: u16) -> Result<()> {
+fn cmd_init(host_dir: &Path, backend: &BackendArgs, config: VfsConfig) -> Result<()> {
     let password = prompt_password("Enter password: ");
     let confirm = prompt_password("Confirm password: ");
 
@@ -193,8 +604,8 @@ fn cmd_init(host_dir: &PathBuf, block_size: u64, redundancy: f32, symbol_size: u
         std::process::exit(1);
     }
 
-    let config = VfsConfig::new(block_size, symbol_size, redundancy);
-    let vfs = SlackVfs::create(host_dir, &password, config)?;
+    let carrier = backend.open(config.block_size)?;
+    let vfs = SlackVfs::create_with_backend(host_dir, &password, config, carrier)?;
     let info = vfs.info();
 
     println!("VFS initialized successfully!");
@@ -202,13 +613,18 @@ fn cmd_init(host_dir: &PathBuf, block_size: u64, redundancy: f32, symbol_size: u
     println!("  Total capacity: {} bytes", info.total_capacity);
     println!("  Block size: {} bytes", info.block_size);
     println!("  Redundancy: {:.0}%", info.redundancy_ratio * 100.0);
+    println!("  Placement: {}", info.placement);
+    println!("  Survives losing: {}", info.fault_tolerance);
+    println!("  Allocation: {}", info.fit);
+    println!("  Shred freed slack: {}", info.shred);
+    println!("  Host rules: {}", info.host_policy);
 
     Ok(())
 }
 
-fn cmd_ls(host_dir: &PathBuf, vfs_path: &str) -> Result<()> {
+fn cmd_ls(host_dir: &Path, backend: &BackendArgs, vfs_path: &str) -> Result<()> {
     let password = prompt_password("Password: ");
-    let vfs = SlackVfs::mount(host_dir, &password)?;
+    let vfs = backend.mount(host_dir, &password)?;
 
     let entries = vfs.list_dir(vfs_path)?;
 
@@ -230,13 +646,14 @@ fn cmd_ls(host_dir: &PathBuf, vfs_path: &str) -> Result<()> {
 }
 
 fn cmd_write(
-    host_dir: &PathBuf,
+    host_dir: &Path,
+    backend: &BackendArgs,
     vfs_path: &str,
     input: Option<PathBuf>,
     data: Option<String>,
 ) -> Result<()> {
     let password = prompt_password("Password: ");
-    let mut vfs = SlackVfs::mount(host_dir, &password)?;
+    let mut vfs = backend.mount(host_dir, &password)?;
 
     let content = match (input, data) {
         (Some(path), None) => std::fs::read(&path)?,
@@ -256,9 +673,14 @@ fn cmd_write(
     Ok(())
 }
 
-fn cmd_read(host_dir: &PathBuf, vfs_path: &str, output: Option<PathBuf>) -> Result<()> {
+fn cmd_read(
+    host_dir: &Path,
+    backend: &BackendArgs,
+    vfs_path: &str,
+    output: Option<PathBuf>,
+) -> Result<()> {
     let password = prompt_password("Password: ");
-    let vfs = SlackVfs::mount(host_dir, &password)?;
+    let vfs = backend.mount(host_dir, &password)?;
 
     let data = vfs.read_file(vfs_path)?;
 
@@ -275,9 +697,9 @@ fn cmd_read(host_dir: &PathBuf, vfs_path: &str, output: Option<PathBuf>) -> Resu
     Ok(())
 }
 
-fn cmd_rm(host_dir: &PathBuf, vfs_path: &str) -> Result<()> {
+fn cmd_rm(host_dir: &Path, backend: &BackendArgs, vfs_path: &str) -> Result<()> {
     let password = prompt_password("Password: ");
-    let mut vfs = SlackVfs::mount(host_dir, &password)?;
+    let mut vfs = backend.mount(host_dir, &password)?;
 
     vfs.delete_file(vfs_path)?;
     println!("Deleted {}", vfs_path);
@@ -285,9 +707,9 @@ fn cmd_rm(host_dir: &PathBuf, vfs_path: &str) -> Result<()> {
     Ok(())
 }
 
-fn cmd_mkdir(host_dir: &PathBuf, vfs_path: &str) -> Result<()> {
+fn cmd_mkdir(host_dir: &Path, backend: &BackendArgs, vfs_path: &str) -> Result<()> {
     let password = prompt_password("Password: ");
-    let mut vfs = SlackVfs::mount(host_dir, &password)?;
+    let mut vfs = backend.mount(host_dir, &password)?;
 
     vfs.create_dir(vfs_path)?;
     println!("Created directory {}", vfs_path);
@@ -295,17 +717,24 @@ fn cmd_mkdir(host_dir: &PathBuf, vfs_path: &str) -> Result<()> {
     Ok(())
 }
 
-fn cmd_info(host_dir: &PathBuf) -> Result<()> {
+fn cmd_info(host_dir: &Path, backend: &BackendArgs) -> Result<()> {
     let password = prompt_password("Password: ");
-    let vfs = SlackVfs::mount(host_dir, &password)?;
+    let vfs = backend.mount(host_dir, &password)?;
     let info = vfs.info();
 
     println!("Slack VFS Information");
     println!("=====================");
     println!("Host directory:   {}", info.host_dir.display());
+    println!("Backend:          {}", info.backend);
     println!("Host files:       {}", info.host_count);
+    println!("Regions:          {}", info.region_count);
     println!("Block size:       {} bytes", info.block_size);
     println!("Redundancy:       {:.0}%", info.redundancy_ratio * 100.0);
+    println!("Placement:        {}", info.placement);
+    println!("Survives losing:  {}", info.fault_tolerance);
+    println!("Allocation:       {}", info.fit);
+    println!("Shred:            {}", info.shred);
+    println!("Host rules:       {}", info.host_policy);
     println!();
     println!("Capacity:");
     println!("  Total:          {} bytes", info.total_capacity);
@@ -320,9 +749,9 @@ fn cmd_info(host_dir: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-fn cmd_health(host_dir: &PathBuf) -> Result<()> {
+fn cmd_health(host_dir: &Path, backend: &BackendArgs) -> Result<()> {
     let password = prompt_password("Password: ");
-    let vfs = SlackVfs::mount(host_dir, &password)?;
+    let vfs = backend.mount(host_dir, &password)?;
     let report = vfs.health_check()?;
 
     println!("VFS Health Report");
@@ -346,10 +775,41 @@ fn cmd_health(host_dir: &PathBuf) -> Result<()> {
         println!("✓ All files are intact and recoverable");
     }
 
+    if !report.fault_tolerance.is_empty() {
+        let target = report.target_tolerance;
+        println!();
+        println!("Fault Tolerance (target: survive losing {}):", target);
+        for (name, losses) in &report.fault_tolerance {
+            let mark = if *losses < target.losses { "!" } else { " " };
+            let left = FaultTolerance {
+                losses: *losses,
+                ..target
+            };
+            println!("{} {} (survives losing {})", mark, name, left);
+        }
+    }
+
     Ok(())
 }
 
-fn cmd_wipe(host_dir: &PathBuf, force: bool) -> Result<()> {
+fn cmd_compact(host_dir: &Path, backend: &BackendArgs) -> Result<()> {
+    let password = prompt_password("Password: ");
+    let mut vfs = backend.mount(host_dir, &password)?;
+    let report = vfs.compact()?;
+
+    println!("VFS compacted");
+    println!("  Symbols moved:      {}", report.symbols_moved);
+    println!("  Space recovered:    {} bytes", report.recovered);
+    println!("  Shredded:           {} bytes", report.wiped);
+    println!(
+        "  Largest free hole:  {} -> {} bytes",
+        report.largest_free_before, report.largest_free_after
+    );
+
+    Ok(())
+}
+
+fn cmd_wipe(host_dir: &Path, backend: &BackendArgs, force: bool) -> Result<()> {
     if !force {
         eprint!("This will permanently destroy all VFS data. Continue? [y/N] ");
         io::stderr().flush().unwrap();
@@ -362,7 +822,7 @@ fn cmd_wipe(host_dir: &PathBuf, force: bool) -> Result<()> {
     }
 
     let password = prompt_password("Password: ");
-    let mut vfs = SlackVfs::mount(host_dir, &password)?;
+    let mut vfs = backend.mount(host_dir, &password)?;
 
     vfs.wipe()?;
     println!("VFS data securely wiped");
@@ -370,7 +830,7 @@ fn cmd_wipe(host_dir: &PathBuf, force: bool) -> Result<()> {
     Ok(())
 }
 
-fn cmd_passwd(host_dir: &PathBuf) -> Result<()> {
+fn cmd_passwd(host_dir: &Path, backend: &BackendArgs) -> Result<()> {
     let old_password = prompt_password("Current password: ");
     let new_password = prompt_password("New password: ");
     let confirm = prompt_password("Confirm new password: ");
@@ -380,10 +840,33 @@ fn cmd_passwd(host_dir: &PathBuf) -> Result<()> {
         std::process::exit(1);
     }
 
-    let mut vfs = SlackVfs::mount(host_dir, &old_password)?;
+    let mut vfs = backend.mount(host_dir, &old_password)?;
     vfs.change_password(&old_password, &new_password)?;
 
     println!("Password changed successfully");
 
     Ok(())
 }
+
+#[cfg(target_os = "linux")]
+fn cmd_image_hosts(image: &Path) -> Result<()> {
+    use slack_vfs::storage::linux::Ext4ImageBackend;
+
+    let backend = Ext4ImageBackend::open(image)?;
+    let hosts = backend.candidate_hosts()?;
+
+    if hosts.is_empty() {
+        println!("(no candidate hosts)");
+        return Ok(());
+    }
+
+    let mut total = 0;
+    for (path, region) in &hosts {
+        println!("{:>10}  {}", region.available, path.display());
+        total += region.available;
+    }
+    println!();
+    println!("{} hosts, {} bytes of slack", hosts.len(), total);
+
+    Ok(())
+}
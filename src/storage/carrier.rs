@@ -0,0 +1,194 @@
+//! Carrier abstraction: hosts that hide data in any number of regions.
+//!
+//! `SlackVfs` and `HostManager` see storage only through `Carrier`. A
+//! carrier lists the regions each host offers, and every region reports
+//! what writing to it costs in terms of detectability and durability, so
+//! file slack, metadata padding, partition gaps and extended attributes can
+//! be allocated from side by side. `HostManager` fills each host's regions
+//! at the lowest persistence risk first.
+//!
+//! Carriers with exactly one region per host implement the simpler
+//! `SlackBackend` trait instead and get this one for free.
+
+use crate::config::ShredPolicy;
+use crate::error::Result;
+use crate::storage::slack::shred;
+use crate::storage::slack_backend::{walk_host_files, SlackBackend, SlackRegion};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// How likely ordinary use of the host is to overwrite hidden data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum PersistenceRisk {
+    /// Only partitioning or low-level tools touch the region.
+    Low,
+    /// Lost when the host itself is rewritten, grown or copied.
+    Moderate,
+    /// Lost through routine activity, such as adding entries to a
+    /// directory or saving the host file.
+    High,
+}
+
+/// What writing to a region implies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// How likely the data is to be overwritten by normal use of the host.
+    pub persistence_risk: PersistenceRisk,
+    /// Whether writes change metadata a user can see, such as the host's
+    /// size or its list of extended attributes.
+    pub changes_visible_metadata: bool,
+    /// Whether access needs root (raw block devices).
+    pub needs_root: bool,
+}
+
+/// Storage that can hide bytes in one or more regions per host.
+pub trait Carrier: Send + Sync {
+    /// List the regions a host offers.
+    ///
+    /// Regions are identified by their position in this list, so the order
+    /// must stay the same as long as the host is not modified by anything
+    /// but this carrier. Regions without space may be listed; nothing is
+    /// allocated from them.
+    fn regions(&self, host: &Path) -> Result<Vec<SlackRegion>>;
+
+    /// List the regions of a host whose original logical size is known.
+    ///
+    /// See `SlackBackend::get_slack_info_at`. The default ignores
+    /// `logical_size`.
+    fn regions_at(&self, host: &Path, logical_size: u64) -> Result<Vec<SlackRegion>> {
+        let _ = logical_size;
+        self.regions(host)
+    }
+
+    /// List candidate hosts under `root`.
+    ///
+    /// The default walks the directory tree and skips hidden files.
+    fn enumerate_hosts(&self, root: &Path) -> Result<Vec<PathBuf>> {
+        walk_host_files(root)
+    }
+
+    /// Read bytes at `offset` within a region.
+    fn read_region(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>>;
+
+    /// Write bytes at `offset` within a region.
+    fn write_region(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()>;
+
+    /// Wipe a whole region.
+    fn wipe_region(&self, region: &SlackRegion) -> Result<()>;
+
+    /// Overwrite `len` bytes of a region from `offset` as `policy` says.
+    ///
+    /// The default overwrites the range through `write_region`.
+    fn wipe_range(
+        &self,
+        region: &SlackRegion,
+        offset: u64,
+        len: u64,
+        policy: ShredPolicy,
+    ) -> Result<()> {
+        shred(len as usize, policy, |data| {
+            self.write_region(region, offset, data)
+        })
+    }
+
+    /// Make every write so far durable.
+    ///
+    /// See `SlackBackend::flush`. The default does nothing.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Report what writing to a region implies.
+    fn capabilities(&self, region: &SlackRegion) -> Capabilities;
+
+    /// Check if this carrier is available on the current system.
+    fn is_available(&self) -> bool;
+
+    /// Get the name of this carrier, as recorded in the bootstrap metadata.
+    fn name(&self) -> &'static str;
+}
+
+impl<T: SlackBackend + ?Sized> Carrier for T {
+    fn regions(&self, host: &Path) -> Result<Vec<SlackRegion>> {
+        Ok(vec![self.get_slack_info(host)?])
+    }
+
+    fn regions_at(&self, host: &Path, logical_size: u64) -> Result<Vec<SlackRegion>> {
+        Ok(vec![self.get_slack_info_at(host, logical_size)?])
+    }
+
+    fn enumerate_hosts(&self, root: &Path) -> Result<Vec<PathBuf>> {
+        SlackBackend::enumerate_hosts(self, root)
+    }
+
+    fn read_region(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        self.read_slack(region, offset, len)
+    }
+
+    fn write_region(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        self.write_slack(region, offset, data)
+    }
+
+    fn wipe_region(&self, region: &SlackRegion) -> Result<()> {
+        self.wipe_slack(region)
+    }
+
+    fn wipe_range(
+        &self,
+        region: &SlackRegion,
+        offset: u64,
+        len: u64,
+        policy: ShredPolicy,
+    ) -> Result<()> {
+        self.wipe_slack_range(region, offset, len, policy)
+    }
+
+    fn flush(&self) -> Result<()> {
+        SlackBackend::flush(self)
+    }
+
+    fn capabilities(&self, _region: &SlackRegion) -> Capabilities {
+        SlackBackend::capabilities(self)
+    }
+
+    fn is_available(&self) -> bool {
+        SlackBackend::is_available(self)
+    }
+
+    fn name(&self) -> &'static str {
+        SlackBackend::name(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FileSlackBackend;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_single_region_backend_is_a_carrier() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("host.txt");
+        std::fs::write(&path, vec![0u8; 1000]).unwrap();
+
+        let carrier: &dyn Carrier = &FileSlackBackend::new(4096);
+        let regions = carrier.regions(&path).unwrap();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].available, 3096);
+
+        carrier.write_region(&regions[0], 10, b"carried").unwrap();
+        assert_eq!(carrier.read_region(&regions[0], 10, 7).unwrap(), b"carried");
+
+        let caps = carrier.capabilities(&regions[0]);
+        assert!(caps.changes_visible_metadata);
+        assert!(!caps.needs_root);
+        assert_eq!(carrier.name(), FileSlackBackend::NAME);
+    }
+
+    #[test]
+    fn test_persistence_risk_order() {
+        assert!(PersistenceRisk::Low < PersistenceRisk::Moderate);
+        assert!(PersistenceRisk::Moderate < PersistenceRisk::High);
+    }
+}
@@ -0,0 +1,248 @@
+//! exFAT slack backend.
+//!
+//! Hides data in cluster slack like the FAT backend, for the file system
+//! SDXC cards and large removable drives ship with. Files are either
+//! FAT-chained or contiguous (`NoFatChain`, the FAT is not maintained for
+//! them); slack always starts at `DataLength`, never at `ValidDataLength`.
+
+#[cfg(test)]
+mod test_image;
+mod volume;
+
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, PersistenceRisk};
+use crate::storage::slack_backend::{SlackBackend, SlackRegion};
+use std::path::{Path, PathBuf};
+
+pub use volume::{ExfatBootSector, ExfatDirEntry, ExfatVolume};
+
+/// Slack backend over an exFAT volume.
+pub struct ExfatImageBackend {
+    volume: ExfatVolume,
+}
+
+impl ExfatImageBackend {
+    pub const NAME: &'static str = "exFAT image";
+
+    /// Open an exFAT image file or block device.
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            volume: ExfatVolume::open(path)?,
+        })
+    }
+
+    /// Get the parsed volume.
+    pub fn volume(&self) -> &ExfatVolume {
+        &self.volume
+    }
+
+    /// List files in the volume that have usable slack space.
+    pub fn candidate_hosts(&self) -> Result<Vec<(PathBuf, SlackRegion)>> {
+        let mut hosts = Vec::new();
+
+        for (path, entry) in self.volume.walk_files()? {
+            if let Ok(region) = self.region_for_entry(&entry) {
+                if region.available > 0 {
+                    hosts.push((path, region));
+                }
+            }
+        }
+
+        Ok(hosts)
+    }
+
+    /// Build the slack region for a file's entry set.
+    fn region_for_entry(&self, entry: &ExfatDirEntry) -> Result<SlackRegion> {
+        let (offset, available) = self.volume.file_slack(entry)?;
+
+        Ok(SlackRegion {
+            device_path: self.volume.image().path().to_path_buf(),
+            offset,
+            available,
+            logical_size: entry.data_length,
+            block_size: self.volume.cluster_size(),
+        })
+    }
+}
+
+impl SlackBackend for ExfatImageBackend {
+    fn get_slack_info(&self, path: &Path) -> Result<SlackRegion> {
+        let entry = self.volume.lookup(path)?;
+        self.region_for_entry(&entry).map_err(|e| match e {
+            Error::NotAFile(_) => Error::NotAFile(path.display().to_string()),
+            e => e,
+        })
+    }
+
+    /// Lists files inside the volume; `root` (the directory holding the VFS
+    /// metadata) is not part of it and is ignored.
+    fn enumerate_hosts(&self, _root: &Path) -> Result<Vec<PathBuf>> {
+        Ok(self
+            .volume
+            .walk_files()?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    fn read_slack(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        region.check_bounds(offset, len)?;
+        self.volume.image().read_at(region.offset + offset, len)
+    }
+
+    fn write_slack(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        region.check_bounds(offset, data.len())?;
+        self.volume.image().write_at(region.offset + offset, data)
+    }
+
+    fn wipe_slack(&self, region: &SlackRegion) -> Result<()> {
+        let zeros = vec![0u8; region.available as usize];
+        self.write_slack(region, 0, &zeros)
+    }
+
+    /// As with FAT, writes to the host reclaim the slack. Drivers that
+    /// grow `ValidDataLength` zero up to the new length, which never
+    /// reaches past `DataLength`.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::Moderate,
+            changes_visible_metadata: false,
+            needs_root: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.volume.image().path().exists()
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_image::ExfatTestImage;
+
+    #[test]
+    fn test_chained_and_contiguous_files() {
+        let mut image = ExfatTestImage::new();
+        let cluster = image.cluster_size() as usize;
+        let docs = image.mkdir(ExfatTestImage::ROOT, "Documents");
+        image.add_fragmented_file(docs, "chained.bin", &vec![b'c'; 2 * cluster + 100]);
+        image.add_contiguous_file(docs, "contiguous.bin", &vec![b'n'; 3 * cluster + 200]);
+        image.add_file(ExfatTestImage::ROOT, "Résumé with a long name.txt", b"cv");
+        let file = image.build();
+
+        let backend = ExfatImageBackend::open(file.path()).unwrap();
+        let raw = std::fs::read(file.path()).unwrap();
+
+        for (path, fill, used) in [
+            ("/documents/CHAINED.bin", b'c', 100),
+            ("/Documents/contiguous.bin", b'n', 200),
+        ] {
+            let region = backend.get_slack_info(Path::new(path)).unwrap();
+            assert_eq!(region.available, (cluster - used) as u64, "{}", path);
+            let end = region.offset as usize;
+            assert_eq!(&raw[end - used..end], &vec![fill; used][..], "{}", path);
+
+            backend.write_slack(&region, 0, b"exfat slack").unwrap();
+            assert_eq!(backend.read_slack(&region, 0, 11).unwrap(), b"exfat slack");
+        }
+
+        let hosts: Vec<_> = backend
+            .candidate_hosts()
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            hosts,
+            vec![
+                PathBuf::from("/Documents/chained.bin"),
+                PathBuf::from("/Documents/contiguous.bin"),
+                PathBuf::from("/Résumé with a long name.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_slack_starts_at_data_length() {
+        let mut image = ExfatTestImage::new();
+        let cluster = image.cluster_size();
+        // 100 valid bytes in a file preallocated to 1.5 clusters
+        image.add_preallocated_file(
+            ExfatTestImage::ROOT,
+            "prealloc.db",
+            &[b'v'; 100],
+            cluster * 3 / 2,
+        );
+        let file = image.build();
+
+        let backend = ExfatImageBackend::open(file.path()).unwrap();
+        let entry = backend.volume().lookup(Path::new("/prealloc.db")).unwrap();
+        assert_eq!(entry.valid_data_length, 100);
+        assert!(entry.no_fat_chain);
+
+        let region = backend.get_slack_info(Path::new("/prealloc.db")).unwrap();
+        assert_eq!(region.logical_size, cluster * 3 / 2);
+        assert_eq!(region.available, cluster / 2);
+        let clusters = backend.volume().entry_clusters(&entry).unwrap();
+        assert_eq!(
+            region.offset,
+            backend.volume().cluster_offset(clusters[1]) + cluster / 2
+        );
+    }
+
+    #[test]
+    fn test_unallocated_last_cluster_rejected() {
+        let mut image = ExfatTestImage::new();
+        let clusters = image.add_contiguous_file(ExfatTestImage::ROOT, "a.txt", b"abc");
+        image.mark_free(clusters[0]);
+        let file = image.build();
+
+        let backend = ExfatImageBackend::open(file.path()).unwrap();
+        assert!(matches!(
+            backend.get_slack_info(Path::new("/a.txt")),
+            Err(Error::DataCorruption(_))
+        ));
+        assert!(backend.candidate_hosts().unwrap().is_empty());
+        assert!(matches!(
+            backend.get_slack_info(Path::new("/missing.txt")),
+            Err(Error::FileNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_vfs_on_exfat_image() {
+        use crate::{SlackVfs, VfsConfig};
+
+        let mut image = ExfatTestImage::new();
+        for i in 0..8 {
+            image.add_contiguous_file(
+                ExfatTestImage::ROOT,
+                &format!("clip_{}.mp4", i),
+                &vec![b'v'; 500 + i],
+            );
+        }
+        let file = image.build();
+        let meta_dir = tempfile::TempDir::new().unwrap();
+
+        {
+            let backend = Box::new(ExfatImageBackend::open(file.path()).unwrap());
+            let mut vfs =
+                SlackVfs::create_with_backend(meta_dir.path(), "pw", VfsConfig::default(), backend)
+                    .unwrap();
+            vfs.create_file("/secret.txt", b"stored in exFAT slack")
+                .unwrap();
+        }
+
+        let backend = Box::new(ExfatImageBackend::open(file.path()).unwrap());
+        let vfs = SlackVfs::mount_with_backend(meta_dir.path(), "pw", backend).unwrap();
+        assert_eq!(
+            vfs.read_file("/secret.txt").unwrap(),
+            b"stored in exFAT slack"
+        );
+    }
+}
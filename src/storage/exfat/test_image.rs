@@ -0,0 +1,305 @@
+//! Minimal exFAT image builder for unit tests.
+//!
+//! Produces a 4 MiB cluster heap with 512-byte sectors and 4 KiB clusters:
+//! the allocation bitmap in cluster 2, an up-case table stub in cluster 3
+//! and the root directory in cluster 4. Images are written sparsely.
+
+use super::ExfatVolume;
+use std::collections::BTreeMap;
+use std::io::{Seek, SeekFrom, Write};
+use tempfile::NamedTempFile;
+
+const SECTOR: u64 = 512;
+const SECTORS_PER_CLUSTER_SHIFT: u8 = 3;
+const CLUSTER_COUNT: u32 = 1024;
+const FAT_OFFSET: u64 = 24;
+const CLUSTER_HEAP_OFFSET: u64 = 40;
+
+const BITMAP_CLUSTER: u32 = 2;
+const UPCASE_CLUSTER: u32 = 3;
+const ROOT_CLUSTER: u32 = 4;
+
+/// How a file's clusters are laid out.
+#[derive(Clone, Copy)]
+enum Layout {
+    /// Consecutive clusters linked in the FAT.
+    Chained,
+    /// Clusters linked in the FAT with a free cluster between each.
+    Fragmented,
+    /// Consecutive clusters flagged `NoFatChain`; the FAT is left empty.
+    Contiguous,
+}
+
+/// Builder for a small exFAT volume.
+pub(crate) struct ExfatTestImage {
+    /// Data written at absolute offsets.
+    chunks: BTreeMap<u64, Vec<u8>>,
+    /// FAT entries by cluster.
+    fat: Vec<u32>,
+    /// Allocation bitmap, one bit per cluster starting at cluster 2.
+    bitmap: Vec<u8>,
+    next_cluster: u32,
+    /// Raw 32-byte entries per directory, keyed by first cluster or `ROOT`.
+    dirs: BTreeMap<u32, Vec<[u8; 32]>>,
+}
+
+impl ExfatTestImage {
+    pub const ROOT: u32 = 0;
+
+    /// Create an empty volume.
+    pub fn new() -> Self {
+        let mut image = Self {
+            chunks: BTreeMap::new(),
+            fat: vec![0; CLUSTER_COUNT as usize + 2],
+            bitmap: vec![0; CLUSTER_COUNT as usize / 8],
+            next_cluster: 2,
+            dirs: BTreeMap::new(),
+        };
+        image.fat[0] = 0xFFFF_FFF8;
+        image.fat[1] = 0xFFFF_FFFF;
+
+        for expected in [BITMAP_CLUSTER, UPCASE_CLUSTER, ROOT_CLUSTER] {
+            assert_eq!(image.alloc(1, Layout::Chained), vec![expected]);
+        }
+
+        let mut bitmap_entry = [0u8; 32];
+        bitmap_entry[0] = 0x81;
+        bitmap_entry[20..24].copy_from_slice(&BITMAP_CLUSTER.to_le_bytes());
+        bitmap_entry[24..32].copy_from_slice(&(image.bitmap.len() as u64).to_le_bytes());
+
+        let mut upcase_entry = [0u8; 32];
+        upcase_entry[0] = 0x82;
+        upcase_entry[20..24].copy_from_slice(&UPCASE_CLUSTER.to_le_bytes());
+        upcase_entry[24..32].copy_from_slice(&128u64.to_le_bytes());
+
+        image
+            .dirs
+            .insert(Self::ROOT, vec![bitmap_entry, upcase_entry]);
+        image
+    }
+
+    /// Get the cluster size in bytes.
+    pub fn cluster_size(&self) -> u64 {
+        SECTOR << SECTORS_PER_CLUSTER_SHIFT
+    }
+
+    /// Create a subdirectory and return its id.
+    pub fn mkdir(&mut self, parent: u32, name: &str) -> u32 {
+        let cluster = self.alloc(1, Layout::Chained)[0];
+        let size = self.cluster_size();
+        self.link(parent, name, 0x10, cluster, size, size, false);
+        self.dirs.insert(cluster, Vec::new());
+        cluster
+    }
+
+    /// Add a file in consecutive FAT-chained clusters.
+    pub fn add_file(&mut self, parent: u32, name: &str, content: &[u8]) -> Vec<u32> {
+        self.add_with_layout(parent, name, content, content.len() as u64, Layout::Chained)
+    }
+
+    /// Add a FAT-chained file whose clusters are separated by free ones.
+    pub fn add_fragmented_file(&mut self, parent: u32, name: &str, content: &[u8]) -> Vec<u32> {
+        self.add_with_layout(
+            parent,
+            name,
+            content,
+            content.len() as u64,
+            Layout::Fragmented,
+        )
+    }
+
+    /// Add a contiguous `NoFatChain` file.
+    pub fn add_contiguous_file(&mut self, parent: u32, name: &str, content: &[u8]) -> Vec<u32> {
+        self.add_with_layout(
+            parent,
+            name,
+            content,
+            content.len() as u64,
+            Layout::Contiguous,
+        )
+    }
+
+    /// Add a contiguous file preallocated to `data_length` bytes, of which
+    /// only `content` is valid.
+    pub fn add_preallocated_file(
+        &mut self,
+        parent: u32,
+        name: &str,
+        content: &[u8],
+        data_length: u64,
+    ) -> Vec<u32> {
+        self.add_with_layout(parent, name, content, data_length, Layout::Contiguous)
+    }
+
+    /// Clear a cluster's bit in the allocation bitmap.
+    pub fn mark_free(&mut self, cluster: u32) {
+        let index = (cluster - 2) as usize;
+        self.bitmap[index / 8] &= !(1 << (index % 8));
+    }
+
+    /// Write the image to a temporary file.
+    pub fn build(mut self) -> NamedTempFile {
+        let cluster_size = self.cluster_size() as usize;
+
+        for (id, entries) in std::mem::take(&mut self.dirs) {
+            let bytes: Vec<u8> = entries.iter().flatten().copied().collect();
+            assert!(bytes.len() <= cluster_size, "test directory full");
+            let cluster = if id == Self::ROOT { ROOT_CLUSTER } else { id };
+            self.chunks.insert(self.cluster_offset(cluster), bytes);
+        }
+
+        let fat: Vec<u8> = self.fat.iter().flat_map(|e| e.to_le_bytes()).collect();
+        self.chunks.insert(FAT_OFFSET * SECTOR, fat);
+        self.chunks
+            .insert(self.cluster_offset(BITMAP_CLUSTER), self.bitmap.clone());
+        self.chunks.insert(0, Self::boot_sector());
+
+        let mut file = NamedTempFile::new().unwrap();
+        let total = CLUSTER_HEAP_OFFSET * SECTOR + CLUSTER_COUNT as u64 * self.cluster_size();
+        file.as_file().set_len(total).unwrap();
+        for (offset, data) in &self.chunks {
+            file.seek(SeekFrom::Start(*offset)).unwrap();
+            file.write_all(data).unwrap();
+        }
+        file.flush().unwrap();
+        file
+    }
+
+    fn add_with_layout(
+        &mut self,
+        parent: u32,
+        name: &str,
+        content: &[u8],
+        data_length: u64,
+        layout: Layout,
+    ) -> Vec<u32> {
+        let cluster_size = self.cluster_size();
+        let count = data_length.div_ceil(cluster_size) as u32;
+        let clusters = self.alloc(count, layout);
+
+        for (cluster, chunk) in clusters.iter().zip(content.chunks(cluster_size as usize)) {
+            self.chunks
+                .insert(self.cluster_offset(*cluster), chunk.to_vec());
+        }
+
+        let first = clusters.first().copied().unwrap_or(0);
+        let no_fat_chain = matches!(layout, Layout::Contiguous);
+        self.link(
+            parent,
+            name,
+            0x20,
+            first,
+            content.len() as u64,
+            data_length,
+            no_fat_chain,
+        );
+        clusters
+    }
+
+    /// Allocate clusters, mark them in the bitmap and link them in the FAT
+    /// unless the layout is contiguous.
+    fn alloc(&mut self, count: u32, layout: Layout) -> Vec<u32> {
+        let step = if matches!(layout, Layout::Fragmented) {
+            2
+        } else {
+            1
+        };
+        let clusters: Vec<u32> = (0..count)
+            .map(|_| {
+                let cluster = self.next_cluster;
+                self.next_cluster += step;
+                cluster
+            })
+            .collect();
+        assert!(self.next_cluster <= CLUSTER_COUNT + 2, "test image full");
+
+        for &cluster in &clusters {
+            let index = (cluster - 2) as usize;
+            self.bitmap[index / 8] |= 1 << (index % 8);
+        }
+        if !matches!(layout, Layout::Contiguous) {
+            for pair in clusters.windows(2) {
+                self.fat[pair[0] as usize] = pair[1];
+            }
+            if let Some(&last) = clusters.last() {
+                self.fat[last as usize] = 0xFFFF_FFFF;
+            }
+        }
+        clusters
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        CLUSTER_HEAP_OFFSET * SECTOR + (cluster as u64 - 2) * self.cluster_size()
+    }
+
+    /// Add a File / Stream Extension / File Name entry set.
+    #[allow(clippy::too_many_arguments)]
+    fn link(
+        &mut self,
+        parent: u32,
+        name: &str,
+        attributes: u16,
+        first_cluster: u32,
+        valid_data_length: u64,
+        data_length: u64,
+        no_fat_chain: bool,
+    ) {
+        let units: Vec<u16> = name.encode_utf16().collect();
+        let name_entries = units.len().div_ceil(15);
+
+        let mut file = [0u8; 32];
+        file[0] = 0x85;
+        file[1] = 1 + name_entries as u8;
+        file[4..6].copy_from_slice(&attributes.to_le_bytes());
+
+        let mut stream = [0u8; 32];
+        stream[0] = 0xC0;
+        stream[1] = 0x01 | if no_fat_chain { 0x02 } else { 0 };
+        stream[3] = units.len() as u8;
+        stream[8..16].copy_from_slice(&valid_data_length.to_le_bytes());
+        stream[20..24].copy_from_slice(&first_cluster.to_le_bytes());
+        stream[24..32].copy_from_slice(&data_length.to_le_bytes());
+
+        let mut set = vec![file, stream];
+        for piece in units.chunks(15) {
+            let mut entry = [0u8; 32];
+            entry[0] = 0xC1;
+            for (i, unit) in piece.iter().enumerate() {
+                entry[2 + i * 2..4 + i * 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            set.push(entry);
+        }
+
+        let refs: Vec<&[u8]> = set.iter().map(|e| &e[..]).collect();
+        let checksum = ExfatVolume::set_checksum(&refs);
+        set[0][2..4].copy_from_slice(&checksum.to_le_bytes());
+
+        self.dirs
+            .get_mut(&parent)
+            .expect("parent is not a directory")
+            .extend(set);
+    }
+
+    fn boot_sector() -> Vec<u8> {
+        let mut bs = vec![0u8; SECTOR as usize];
+        bs[0..3].copy_from_slice(&[0xEB, 0x76, 0x90]);
+        bs[3..11].copy_from_slice(b"EXFAT   ");
+        let volume_length =
+            CLUSTER_HEAP_OFFSET + ((CLUSTER_COUNT as u64) << SECTORS_PER_CLUSTER_SHIFT);
+        let fat_length = ((CLUSTER_COUNT as u64 + 2) * 4).div_ceil(SECTOR);
+        bs[0x48..0x50].copy_from_slice(&volume_length.to_le_bytes());
+        bs[0x50..0x54].copy_from_slice(&(FAT_OFFSET as u32).to_le_bytes());
+        bs[0x54..0x58].copy_from_slice(&(fat_length as u32).to_le_bytes());
+        bs[0x58..0x5C].copy_from_slice(&(CLUSTER_HEAP_OFFSET as u32).to_le_bytes());
+        bs[0x5C..0x60].copy_from_slice(&CLUSTER_COUNT.to_le_bytes());
+        bs[0x60..0x64].copy_from_slice(&ROOT_CLUSTER.to_le_bytes());
+        bs[0x68..0x6A].copy_from_slice(&0x0100u16.to_le_bytes());
+        bs[0x6C] = 9;
+        bs[0x6D] = SECTORS_PER_CLUSTER_SHIFT;
+        bs[0x6E] = 1;
+        bs[0x6F] = 0x80;
+        bs[510] = 0x55;
+        bs[511] = 0xAA;
+        bs
+    }
+}
@@ -0,0 +1,501 @@
+//! exFAT volume parsing.
+//!
+//! Parses the main boot sector, the FAT, the allocation bitmap and directory
+//! entry sets (File, Stream Extension and File Name entries) to map files to
+//! their clusters.
+
+use crate::error::{Error, Result};
+use crate::storage::image_file::ImageFile;
+use std::path::{Component, Path, PathBuf};
+
+/// Size of a directory entry.
+const DIR_ENTRY_SIZE: usize = 32;
+
+/// Directory entry types (with the InUse bit set).
+const ENTRY_END_OF_DIRECTORY: u8 = 0x00;
+const ENTRY_ALLOCATION_BITMAP: u8 = 0x81;
+const ENTRY_FILE: u8 = 0x85;
+const ENTRY_STREAM_EXTENSION: u8 = 0xC0;
+const ENTRY_FILE_NAME: u8 = 0xC1;
+
+/// File attribute: directory.
+const ATTR_DIRECTORY: u16 = 0x10;
+
+/// Stream extension flags.
+const FLAG_ALLOCATION_POSSIBLE: u8 = 0x01;
+const FLAG_NO_FAT_CHAIN: u8 = 0x02;
+
+/// FAT entry values.
+const FAT_BAD_CLUSTER: u32 = 0xFFFF_FFF7;
+const FAT_END_OF_CHAIN: u32 = 0xFFFF_FFFF;
+
+/// Parsed main boot sector.
+#[derive(Debug, Clone)]
+pub struct ExfatBootSector {
+    /// Volume size in sectors.
+    pub volume_length: u64,
+    /// First sector of the first FAT.
+    pub fat_offset: u32,
+    /// FAT size in sectors.
+    pub fat_length: u32,
+    /// First sector of the cluster heap (cluster 2).
+    pub cluster_heap_offset: u32,
+    pub cluster_count: u32,
+    pub root_cluster: u32,
+    pub bytes_per_sector_shift: u8,
+    pub sectors_per_cluster_shift: u8,
+    pub num_fats: u8,
+}
+
+impl ExfatBootSector {
+    /// Get the sector size in bytes.
+    pub fn bytes_per_sector(&self) -> u64 {
+        1 << self.bytes_per_sector_shift
+    }
+}
+
+/// A file or directory, resolved from its directory entry set.
+#[derive(Debug, Clone)]
+pub struct ExfatDirEntry {
+    pub name: String,
+    pub attributes: u16,
+    pub first_cluster: u32,
+    /// Bytes written by the file system; reads beyond return zeros.
+    pub valid_data_length: u64,
+    /// Allocated file length, which is also the size users see.
+    pub data_length: u64,
+    /// Clusters are contiguous and the FAT is not used.
+    pub no_fat_chain: bool,
+}
+
+impl ExfatDirEntry {
+    /// Check if this entry is a directory.
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    /// Check if a path component names this entry. exFAT names compare
+    /// through the volume's up-case table; simple case folding covers the
+    /// names we generate and almost all real ones.
+    fn matches(&self, name: &str) -> bool {
+        self.name.to_lowercase() == name.to_lowercase()
+    }
+}
+
+/// An exFAT volume read from an image file or block device.
+#[derive(Debug)]
+pub struct ExfatVolume {
+    image: ImageFile,
+    boot: ExfatBootSector,
+    /// First FAT, raw bytes.
+    fat: Vec<u8>,
+    /// Allocation bitmap, one bit per cluster starting at cluster 2.
+    bitmap: Vec<u8>,
+}
+
+impl ExfatVolume {
+    /// Open an exFAT volume.
+    pub fn open(path: &Path) -> Result<Self> {
+        let image = ImageFile::open(path)?;
+        let sector = image.read_at(0, 512)?;
+        let boot = Self::parse_boot_sector(&sector)?;
+
+        let fat = image.read_at(
+            boot.fat_offset as u64 * boot.bytes_per_sector(),
+            (boot.fat_length as u64 * boot.bytes_per_sector()) as usize,
+        )?;
+
+        let mut volume = Self {
+            image,
+            boot,
+            fat,
+            bitmap: Vec::new(),
+        };
+        volume.bitmap = volume.read_bitmap()?;
+        Ok(volume)
+    }
+
+    /// Parse the main boot sector.
+    fn parse_boot_sector(data: &[u8]) -> Result<ExfatBootSector> {
+        if &data[3..11] != b"EXFAT   " || data[510] != 0x55 || data[511] != 0xAA {
+            return Err(Error::InvalidMagic);
+        }
+
+        let u32_at = |off: usize| {
+            u32::from_le_bytes([data[off], data[off + 1], data[off + 2], data[off + 3]])
+        };
+
+        let boot = ExfatBootSector {
+            volume_length: u64::from_le_bytes(data[0x48..0x50].try_into().unwrap()),
+            fat_offset: u32_at(0x50),
+            fat_length: u32_at(0x54),
+            cluster_heap_offset: u32_at(0x58),
+            cluster_count: u32_at(0x5C),
+            root_cluster: u32_at(0x60),
+            bytes_per_sector_shift: data[0x6C],
+            sectors_per_cluster_shift: data[0x6D],
+            num_fats: data[0x6E],
+        };
+
+        // Sectors are 512 B to 4 KiB, clusters at most 32 MiB
+        if !(9..=12).contains(&boot.bytes_per_sector_shift)
+            || boot.bytes_per_sector_shift + boot.sectors_per_cluster_shift > 25
+            || !matches!(boot.num_fats, 1 | 2)
+            || boot.fat_length == 0
+            || boot.root_cluster < 2
+        {
+            return Err(Error::DataCorruption(
+                "Invalid exFAT boot sector".to_string(),
+            ));
+        }
+
+        Ok(boot)
+    }
+
+    /// Load the allocation bitmap named by the root directory.
+    fn read_bitmap(&self) -> Result<Vec<u8>> {
+        let root = self.read_chain(self.boot.root_cluster)?;
+
+        for raw in root.chunks_exact(DIR_ENTRY_SIZE) {
+            match raw[0] {
+                ENTRY_END_OF_DIRECTORY => break,
+                // With two FATs (TexFAT) bit 0 picks the bitmap; use the first
+                ENTRY_ALLOCATION_BITMAP if raw[1] & 0x01 == 0 => {
+                    let first_cluster = u32::from_le_bytes(raw[20..24].try_into().unwrap());
+                    let length = u64::from_le_bytes(raw[24..32].try_into().unwrap());
+                    if length < (self.boot.cluster_count as u64).div_ceil(8) {
+                        return Err(Error::DataCorruption(
+                            "Allocation bitmap is too small".to_string(),
+                        ));
+                    }
+                    return self.read_clusters(&self.cluster_chain(first_cluster)?, length);
+                }
+                _ => {}
+            }
+        }
+
+        Err(Error::DataCorruption(
+            "exFAT root directory has no allocation bitmap".to_string(),
+        ))
+    }
+
+    /// Get the parsed boot sector.
+    pub fn boot_sector(&self) -> &ExfatBootSector {
+        &self.boot
+    }
+
+    /// Get the cluster size in bytes.
+    pub fn cluster_size(&self) -> u64 {
+        1 << (self.boot.bytes_per_sector_shift + self.boot.sectors_per_cluster_shift)
+    }
+
+    /// Get the image the volume is read from.
+    pub fn image(&self) -> &ImageFile {
+        &self.image
+    }
+
+    /// Byte offset of a cluster in the cluster heap.
+    pub fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.boot.cluster_heap_offset as u64 * self.boot.bytes_per_sector()
+            + (cluster as u64 - 2) * self.cluster_size()
+    }
+
+    /// Check the allocation bitmap for a cluster.
+    pub fn is_allocated(&self, cluster: u32) -> bool {
+        let index = cluster.wrapping_sub(2) as usize;
+        self.bitmap
+            .get(index / 8)
+            .is_some_and(|byte| byte & (1 << (index % 8)) != 0)
+    }
+
+    fn check_cluster(&self, cluster: u32) -> Result<()> {
+        if cluster < 2 || cluster > self.boot.cluster_count + 1 {
+            return Err(Error::DataCorruption(format!(
+                "Cluster points outside the volume: {}",
+                cluster
+            )));
+        }
+        Ok(())
+    }
+
+    /// Read a FAT entry.
+    fn fat_entry(&self, cluster: u32) -> Result<u32> {
+        let off = cluster as usize * 4;
+        self.fat
+            .get(off..off + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+            .ok_or_else(|| Error::DataCorruption(format!("Cluster {} outside the FAT", cluster)))
+    }
+
+    /// Follow a FAT cluster chain from its first cluster.
+    pub fn cluster_chain(&self, first: u32) -> Result<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+
+        loop {
+            self.check_cluster(cluster)?;
+            // A chain can't be longer than the volume; guards against loops
+            if chain.len() as u32 >= self.boot.cluster_count {
+                return Err(Error::DataCorruption("Cluster chain loops".to_string()));
+            }
+            chain.push(cluster);
+
+            match self.fat_entry(cluster)? {
+                FAT_END_OF_CHAIN => return Ok(chain),
+                FAT_BAD_CLUSTER | 0 => {
+                    return Err(Error::DataCorruption(format!(
+                        "Cluster chain broken after cluster {}",
+                        cluster
+                    )))
+                }
+                next => cluster = next,
+            }
+        }
+    }
+
+    /// List the clusters holding `data_length` bytes of an entry, either
+    /// contiguous (`NoFatChain`) or from its FAT chain.
+    pub fn entry_clusters(&self, entry: &ExfatDirEntry) -> Result<Vec<u32>> {
+        if entry.first_cluster == 0 || entry.data_length == 0 {
+            return Ok(Vec::new());
+        }
+
+        let count = entry.data_length.div_ceil(self.cluster_size());
+        if entry.no_fat_chain {
+            let last = entry.first_cluster as u64 + count - 1;
+            self.check_cluster(entry.first_cluster)?;
+            if last > self.boot.cluster_count as u64 + 1 {
+                return Err(Error::DataCorruption(format!(
+                    "Contiguous file {} runs past the end of the volume",
+                    entry.name
+                )));
+            }
+            return Ok((entry.first_cluster..=last as u32).collect());
+        }
+
+        let mut chain = self.cluster_chain(entry.first_cluster)?;
+        if (chain.len() as u64) < count {
+            return Err(Error::DataCorruption(format!(
+                "Cluster chain of {} is shorter than its size",
+                entry.name
+            )));
+        }
+        chain.truncate(count as usize);
+        Ok(chain)
+    }
+
+    /// Read whole clusters, keeping the first `len` bytes.
+    fn read_clusters(&self, clusters: &[u32], len: u64) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        for &cluster in clusters {
+            data.extend(
+                self.image
+                    .read_at(self.cluster_offset(cluster), self.cluster_size() as usize)?,
+            );
+        }
+        data.truncate(len as usize);
+        Ok(data)
+    }
+
+    /// Read all clusters of a FAT chain.
+    fn read_chain(&self, first: u32) -> Result<Vec<u8>> {
+        let chain = self.cluster_chain(first)?;
+        let len = chain.len() as u64 * self.cluster_size();
+        self.read_clusters(&chain, len)
+    }
+
+    /// Parse directory entry sets. Sets with a bad checksum, missing
+    /// secondary entries or deleted primaries are skipped.
+    fn parse_dir_entries(data: &[u8]) -> Vec<ExfatDirEntry> {
+        let raw: Vec<&[u8]> = data.chunks_exact(DIR_ENTRY_SIZE).collect();
+        let mut entries = Vec::new();
+        let mut i = 0;
+
+        while i < raw.len() {
+            let entry_type = raw[i][0];
+            if entry_type == ENTRY_END_OF_DIRECTORY {
+                break;
+            }
+            if entry_type != ENTRY_FILE {
+                i += 1;
+                continue;
+            }
+
+            let secondary_count = raw[i][1] as usize;
+            let Some(set) = raw.get(i..i + 1 + secondary_count) else {
+                break;
+            };
+            i += 1 + secondary_count;
+
+            if let Some(entry) = Self::parse_entry_set(set) {
+                entries.push(entry);
+            }
+        }
+
+        entries
+    }
+
+    /// Parse one File entry set.
+    fn parse_entry_set(set: &[&[u8]]) -> Option<ExfatDirEntry> {
+        let file = set[0];
+        let stored_checksum = u16::from_le_bytes([file[2], file[3]]);
+        if Self::set_checksum(set) != stored_checksum {
+            return None;
+        }
+
+        let stream = set.get(1).filter(|e| e[0] == ENTRY_STREAM_EXTENSION)?;
+        let flags = stream[1];
+        let name_length = stream[3] as usize;
+        let valid_data_length = u64::from_le_bytes(stream[8..16].try_into().unwrap());
+        let first_cluster = u32::from_le_bytes(stream[20..24].try_into().unwrap());
+        let data_length = u64::from_le_bytes(stream[24..32].try_into().unwrap());
+
+        let name_units: Vec<u16> = set[2..]
+            .iter()
+            .take_while(|e| e[0] == ENTRY_FILE_NAME)
+            .flat_map(|e| e[2..32].chunks_exact(2))
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take(name_length)
+            .collect();
+        if name_units.len() != name_length {
+            return None;
+        }
+
+        let allocated = flags & FLAG_ALLOCATION_POSSIBLE != 0;
+        Some(ExfatDirEntry {
+            name: String::from_utf16(&name_units).ok()?,
+            attributes: u16::from_le_bytes([file[4], file[5]]),
+            first_cluster: if allocated { first_cluster } else { 0 },
+            valid_data_length,
+            data_length,
+            no_fat_chain: flags & FLAG_NO_FAT_CHAIN != 0,
+        })
+    }
+
+    /// Compute the checksum of an entry set, skipping the checksum field of
+    /// the File entry.
+    pub(crate) fn set_checksum(set: &[&[u8]]) -> u16 {
+        let mut checksum: u16 = 0;
+        for (n, entry) in set.iter().enumerate() {
+            for (i, &byte) in entry.iter().enumerate() {
+                if n == 0 && (i == 2 || i == 3) {
+                    continue;
+                }
+                checksum = checksum.rotate_right(1).wrapping_add(byte as u16);
+            }
+        }
+        checksum
+    }
+
+    /// List the root directory.
+    pub fn read_root(&self) -> Result<Vec<ExfatDirEntry>> {
+        Ok(Self::parse_dir_entries(
+            &self.read_chain(self.boot.root_cluster)?,
+        ))
+    }
+
+    /// List a subdirectory.
+    pub fn read_dir(&self, dir: &ExfatDirEntry) -> Result<Vec<ExfatDirEntry>> {
+        if !dir.is_directory() {
+            return Err(Error::NotADirectory(dir.name.clone()));
+        }
+        let clusters = self.entry_clusters(dir)?;
+        Ok(Self::parse_dir_entries(
+            &self.read_clusters(&clusters, dir.data_length)?,
+        ))
+    }
+
+    /// Resolve an absolute path inside the volume to its entry.
+    pub fn lookup(&self, path: &Path) -> Result<ExfatDirEntry> {
+        let mut entries = self.read_root()?;
+        let mut found: Option<ExfatDirEntry> = None;
+
+        for component in path.components() {
+            let name = match component {
+                Component::RootDir => continue,
+                Component::Normal(name) => name.to_string_lossy(),
+                _ => return Err(Error::InvalidPath(path.display().to_string())),
+            };
+
+            if let Some(dir) = &found {
+                entries = self.read_dir(dir)?;
+            }
+
+            found = Some(
+                entries
+                    .iter()
+                    .find(|e| e.matches(&name))
+                    .cloned()
+                    .ok_or_else(|| Error::FileNotFound(path.display().to_string()))?,
+            );
+        }
+
+        found.ok_or_else(|| Error::NotAFile(path.display().to_string()))
+    }
+
+    /// List every regular file in the volume with its absolute path, sorted
+    /// by path.
+    pub fn walk_files(&self) -> Result<Vec<(PathBuf, ExfatDirEntry)>> {
+        let mut files = Vec::new();
+        let mut pending = vec![(PathBuf::from("/"), self.read_root()?)];
+
+        while let Some((dir_path, entries)) = pending.pop() {
+            for entry in entries {
+                let path = dir_path.join(&entry.name);
+                if entry.is_directory() {
+                    if let Ok(children) = self.read_dir(&entry) {
+                        pending.push((path, children));
+                    }
+                } else {
+                    files.push((path, entry));
+                }
+            }
+        }
+
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(files)
+    }
+
+    /// Calculate the slack of a file: the bytes between the end of its
+    /// allocated length (`DataLength`) and the end of its last cluster.
+    ///
+    /// Bytes between `ValidDataLength` and `DataLength` belong to the file
+    /// (they read as zeros and are written when the file grows), so they
+    /// are never slack.
+    ///
+    /// Returns (absolute offset where slack starts, available bytes).
+    pub fn file_slack(&self, entry: &ExfatDirEntry) -> Result<(u64, u64)> {
+        if entry.is_directory() {
+            return Err(Error::NotAFile(entry.name.clone()));
+        }
+        if entry.valid_data_length > entry.data_length {
+            return Err(Error::DataCorruption(format!(
+                "Valid data length of {} exceeds its data length",
+                entry.name
+            )));
+        }
+
+        let clusters = self.entry_clusters(entry)?;
+        let Some(&last) = clusters.last() else {
+            return Ok((0, 0));
+        };
+        // Slack in a cluster the bitmap calls free would be overwritten
+        if !self.is_allocated(last) {
+            return Err(Error::DataCorruption(format!(
+                "Last cluster of {} is not marked allocated",
+                entry.name
+            )));
+        }
+
+        let cluster_size = self.cluster_size();
+        let used_in_last = entry.data_length % cluster_size;
+        if used_in_last == 0 {
+            return Ok((self.cluster_offset(last) + cluster_size, 0));
+        }
+
+        Ok((
+            self.cluster_offset(last) + used_in_last,
+            cluster_size - used_in_last,
+        ))
+    }
+}
@@ -0,0 +1,246 @@
+//! Free-extent tracking for slack regions.
+//!
+//! Each region keeps its free space as a sorted list of extents, so space
+//! given back by deleted files and old superblock copies can be handed out
+//! again wherever it lies, not just at the end of the region.
+
+use crate::config::FitStrategy;
+use serde::{Deserialize, Serialize};
+
+/// A run of bytes within a slack region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Extent {
+    /// Offset within the region.
+    pub offset: u64,
+    /// Length in bytes.
+    pub length: u64,
+}
+
+impl Extent {
+    /// Get the offset just past the extent.
+    pub fn end(&self) -> u64 {
+        self.offset + self.length
+    }
+}
+
+/// The free space of one slack region.
+///
+/// Extents are kept sorted by offset, and ones that touch are merged.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FreeExtents {
+    extents: Vec<Extent>,
+}
+
+impl FreeExtents {
+    /// Create free space covering a whole region of `capacity` bytes.
+    pub fn new(capacity: u64) -> Self {
+        let mut free = Self::default();
+        free.release(0, capacity);
+        free
+    }
+
+    /// Get the free extents, in offset order.
+    pub fn extents(&self) -> &[Extent] {
+        &self.extents
+    }
+
+    /// Get the total free space.
+    pub fn total(&self) -> u64 {
+        self.extents.iter().map(|e| e.length).sum()
+    }
+
+    /// Get the length of the largest free extent.
+    pub fn largest(&self) -> u64 {
+        self.extents.iter().map(|e| e.length).max().unwrap_or(0)
+    }
+
+    /// Get how many pieces of `size` bytes fit, each in one extent. Empty
+    /// pieces take no room, but none are counted.
+    pub fn slots(&self, size: u64) -> u64 {
+        if size == 0 {
+            return 0;
+        }
+        self.extents.iter().map(|e| e.length / size).sum()
+    }
+
+    /// Get the length of the extent best fit would take `size` bytes from:
+    /// the smallest one large enough.
+    pub fn best_fit(&self, size: u64) -> Option<u64> {
+        self.extents
+            .iter()
+            .map(|e| e.length)
+            .filter(|&length| length >= size)
+            .min()
+    }
+
+    /// Take `size` bytes from the extent `fit` picks, returning their
+    /// offset. Best fit breaks ties by lowest offset.
+    pub fn allocate(&mut self, size: u64, fit: FitStrategy) -> Option<u64> {
+        let fits = |e: &&Extent| e.length >= size;
+        let index = match fit {
+            FitStrategy::FirstFit => self.extents.iter().position(|e| fits(&e))?,
+            FitStrategy::BestFit => {
+                let (index, _) = self
+                    .extents
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, e)| fits(e))
+                    .min_by_key(|(_, e)| e.length)?;
+                index
+            }
+        };
+        Some(self.take(index, size))
+    }
+
+    /// Take `size` bytes from the lowest-offset extent large enough that
+    /// starts before `limit`, returning their offset.
+    pub fn allocate_before(&mut self, size: u64, limit: u64) -> Option<u64> {
+        let index = self
+            .extents
+            .iter()
+            .position(|e| e.length >= size && e.offset < limit)?;
+        Some(self.take(index, size))
+    }
+
+    /// Take `size` bytes from the start of an extent.
+    fn take(&mut self, index: usize, size: u64) -> u64 {
+        let extent = &mut self.extents[index];
+        let offset = extent.offset;
+        extent.offset += size;
+        extent.length -= size;
+        if extent.length == 0 {
+            self.extents.remove(index);
+        }
+        offset
+    }
+
+    /// Mark a range as used, whichever parts of it are free.
+    pub fn reserve(&mut self, offset: u64, length: u64) {
+        let end = offset + length;
+        let mut kept = Vec::with_capacity(self.extents.len() + 1);
+        for extent in self.extents.drain(..) {
+            if extent.end() <= offset || extent.offset >= end {
+                kept.push(extent);
+                continue;
+            }
+            if extent.offset < offset {
+                kept.push(Extent {
+                    offset: extent.offset,
+                    length: offset - extent.offset,
+                });
+            }
+            if extent.end() > end {
+                kept.push(Extent {
+                    offset: end,
+                    length: extent.end() - end,
+                });
+            }
+        }
+        self.extents = kept;
+    }
+
+    /// Give a range back, merging it with the free space around it.
+    pub fn release(&mut self, offset: u64, length: u64) {
+        if length == 0 {
+            return;
+        }
+        let mut merged = Extent { offset, length };
+
+        // Extents overlapping or touching the range
+        let first = self.extents.partition_point(|e| e.end() < offset);
+        let last = self.extents.partition_point(|e| e.offset <= merged.end());
+        if first < last {
+            let start = merged.offset.min(self.extents[first].offset);
+            let end = merged.end().max(self.extents[last - 1].end());
+            merged = Extent {
+                offset: start,
+                length: end - start,
+            };
+        }
+        self.extents.splice(first..last, [merged]);
+    }
+
+    /// Drop free space at or past `capacity`, for regions that shrank.
+    pub fn truncate(&mut self, capacity: u64) {
+        self.reserve(capacity, u64::MAX - capacity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(free: &FreeExtents) -> Vec<(u64, u64)> {
+        free.extents().iter().map(|e| (e.offset, e.end())).collect()
+    }
+
+    #[test]
+    fn test_release_merges_neighbours() {
+        let mut free = FreeExtents::new(1000);
+        free.reserve(0, 1000);
+        assert_eq!(free.total(), 0);
+
+        free.release(100, 100);
+        free.release(400, 100);
+        assert_eq!(ranges(&free), vec![(100, 200), (400, 500)]);
+
+        // Touching on both sides joins all three
+        free.release(200, 200);
+        assert_eq!(ranges(&free), vec![(100, 500)]);
+
+        // Overlapping releases are harmless
+        free.release(450, 100);
+        free.release(0, 150);
+        assert_eq!(ranges(&free), vec![(0, 550)]);
+    }
+
+    #[test]
+    fn test_reserve_splits() {
+        let mut free = FreeExtents::new(1000);
+        free.reserve(100, 50);
+        free.reserve(900, 500);
+        assert_eq!(ranges(&free), vec![(0, 100), (150, 900)]);
+        free.reserve(120, 10);
+        assert_eq!(ranges(&free), vec![(0, 100), (150, 900)]);
+        assert_eq!(free.total(), 850);
+        assert_eq!(free.slots(100), 1 + 7);
+        assert_eq!(free.slots(0), 0);
+    }
+
+    #[test]
+    fn test_first_and_best_fit() {
+        let mut free = FreeExtents::new(1000);
+        free.reserve(300, 100);
+        free.reserve(500, 400);
+        // Holes: 0..300, 400..500, 900..1000
+        let mut first = free.clone();
+        let mut best = free.clone();
+
+        assert_eq!(first.allocate(100, FitStrategy::FirstFit), Some(0));
+        assert_eq!(best.allocate(100, FitStrategy::BestFit), Some(400));
+        assert_eq!(best.allocate(100, FitStrategy::BestFit), Some(900));
+        assert_eq!(best.allocate(100, FitStrategy::BestFit), Some(0));
+        assert_eq!(best.best_fit(150), Some(200));
+        assert_eq!(best.allocate(250, FitStrategy::BestFit), None);
+    }
+
+    #[test]
+    fn test_allocate_before() {
+        let mut free = FreeExtents::new(1000);
+        free.reserve(0, 100);
+        free.reserve(150, 600);
+        // Holes: 100..150, 750..1000
+        assert_eq!(free.allocate_before(100, 750), None);
+        assert_eq!(free.allocate_before(50, 750), Some(100));
+        assert_eq!(free.allocate_before(50, 751), Some(750));
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut free = FreeExtents::new(1000);
+        free.reserve(200, 100);
+        free.truncate(250);
+        assert_eq!(ranges(&free), vec![(0, 200)]);
+        assert_eq!(free.largest(), 200);
+    }
+}
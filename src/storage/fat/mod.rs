@@ -0,0 +1,243 @@
+//! FAT12/16/32 slack backend.
+//!
+//! Hides data in cluster slack: the bytes between a file's end and the end
+//! of the last cluster of its chain. FAT clusters are commonly 16-32 KiB, so
+//! each host offers far more slack than an ext4 block. Works on image files
+//! and on block devices of unmounted volumes (USB sticks, SD cards); host
+//! paths are absolute paths inside the volume.
+
+#[cfg(test)]
+mod test_image;
+mod volume;
+
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, PersistenceRisk};
+use crate::storage::slack_backend::{SlackBackend, SlackRegion};
+use std::path::{Path, PathBuf};
+
+pub use volume::{FatBootSector, FatDirEntry, FatType, FatVolume};
+
+/// Slack backend over a FAT volume.
+pub struct FatImageBackend {
+    volume: FatVolume,
+}
+
+impl FatImageBackend {
+    pub const NAME: &'static str = "FAT image";
+
+    /// Open a FAT image file or block device.
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            volume: FatVolume::open(path)?,
+        })
+    }
+
+    /// Get the parsed volume.
+    pub fn volume(&self) -> &FatVolume {
+        &self.volume
+    }
+
+    /// List files in the volume that have usable slack space.
+    pub fn candidate_hosts(&self) -> Result<Vec<(PathBuf, SlackRegion)>> {
+        let mut hosts = Vec::new();
+
+        for (path, entry) in self.volume.walk_files()? {
+            if let Ok(region) = self.region_for_entry(&entry) {
+                if region.available > 0 {
+                    hosts.push((path, region));
+                }
+            }
+        }
+
+        Ok(hosts)
+    }
+
+    /// Build the slack region for a file's directory entry.
+    fn region_for_entry(&self, entry: &FatDirEntry) -> Result<SlackRegion> {
+        let (offset, available) = self.volume.file_slack(entry)?;
+
+        Ok(SlackRegion {
+            device_path: self.volume.image().path().to_path_buf(),
+            offset,
+            available,
+            logical_size: entry.size as u64,
+            block_size: self.volume.cluster_size(),
+        })
+    }
+}
+
+impl SlackBackend for FatImageBackend {
+    fn get_slack_info(&self, path: &Path) -> Result<SlackRegion> {
+        let entry = self.volume.lookup(path)?;
+        self.region_for_entry(&entry).map_err(|e| match e {
+            Error::NotAFile(_) => Error::NotAFile(path.display().to_string()),
+            e => e,
+        })
+    }
+
+    /// Lists files inside the volume; `root` (the directory holding the VFS
+    /// metadata) is not part of it and is ignored.
+    fn enumerate_hosts(&self, _root: &Path) -> Result<Vec<PathBuf>> {
+        Ok(self
+            .volume
+            .walk_files()?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    fn read_slack(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        region.check_bounds(offset, len)?;
+        self.volume.image().read_at(region.offset + offset, len)
+    }
+
+    fn write_slack(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        region.check_bounds(offset, data.len())?;
+        self.volume.image().write_at(region.offset + offset, data)
+    }
+
+    fn wipe_slack(&self, region: &SlackRegion) -> Result<()> {
+        let zeros = vec![0u8; region.available as usize];
+        self.write_slack(region, 0, &zeros)
+    }
+
+    /// Appending to the host fills the cluster tail, and saving it anew
+    /// may move it to other clusters; nothing else touches the slack.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::Moderate,
+            changes_visible_metadata: false,
+            needs_root: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.volume.image().path().exists()
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_image::FatTestImage;
+
+    #[test]
+    fn test_slack_per_fat_type() {
+        for fat_type in [FatType::Fat12, FatType::Fat16, FatType::Fat32] {
+            let mut image = FatTestImage::new(fat_type);
+            let cluster = image.cluster_size() as usize;
+            image.add_file(FatTestImage::ROOT, "README.TXT", &vec![b'r'; cluster + 100]);
+            let file = image.build();
+
+            let backend = FatImageBackend::open(file.path()).unwrap();
+            assert_eq!(backend.volume().fat_type(), fat_type);
+
+            let region = backend.get_slack_info(Path::new("/README.TXT")).unwrap();
+            assert_eq!(region.available, cluster as u64 - 100);
+            assert_eq!(region.block_size, cluster as u64);
+
+            backend.write_slack(&region, 0, b"hidden in FAT").unwrap();
+            assert_eq!(
+                backend.read_slack(&region, 0, 13).unwrap(),
+                b"hidden in FAT"
+            );
+
+            // File content right before the slack is untouched
+            let raw = std::fs::read(file.path()).unwrap();
+            let end = region.offset as usize;
+            assert_eq!(&raw[end - 100..end], &vec![b'r'; 100][..], "{:?}", fat_type);
+        }
+    }
+
+    #[test]
+    fn test_fragmented_chain_and_long_names() {
+        let mut image = FatTestImage::new(FatType::Fat16);
+        let cluster = image.cluster_size() as usize;
+        let docs = image.mkdir(FatTestImage::ROOT, "Documents");
+        let content: Vec<u8> = (0..3 * cluster + 10)
+            .map(|i| (i / cluster) as u8 + 1)
+            .collect();
+        image.add_fragmented_file(docs, "quarterly report.bin", &content);
+        image.add_file(docs, "empty.txt", b"");
+        image.add_file(docs, "exact.bin", &vec![1u8; cluster]);
+        let file = image.build();
+
+        let backend = FatImageBackend::open(file.path()).unwrap();
+        let region = backend
+            .get_slack_info(Path::new("/documents/Quarterly Report.bin"))
+            .unwrap();
+        assert_eq!(region.logical_size, content.len() as u64);
+        assert_eq!(region.available, cluster as u64 - 10);
+
+        // The last cluster holds the tail of the content
+        let raw = std::fs::read(file.path()).unwrap();
+        let end = region.offset as usize;
+        assert_eq!(&raw[end - 10..end], &[4u8; 10]);
+
+        let hosts: Vec<_> = backend
+            .candidate_hosts()
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            hosts,
+            vec![PathBuf::from("/Documents/quarterly report.bin")]
+        );
+    }
+
+    #[test]
+    fn test_vfs_on_fat_image() {
+        use crate::{SlackVfs, VfsConfig};
+
+        let mut image = FatTestImage::new(FatType::Fat12);
+        let dir = image.mkdir(FatTestImage::ROOT, "photos");
+        for i in 0..16 {
+            image.add_file(dir, &format!("img_{}.jpg", i), &vec![b'p'; 300 + i]);
+        }
+        let file = image.build();
+        let image_len = std::fs::metadata(file.path()).unwrap().len();
+        let meta_dir = tempfile::TempDir::new().unwrap();
+
+        {
+            let backend = Box::new(FatImageBackend::open(file.path()).unwrap());
+            let mut vfs =
+                SlackVfs::create_with_backend(meta_dir.path(), "pw", VfsConfig::default(), backend)
+                    .unwrap();
+            assert_eq!(vfs.info().host_count, 16);
+            vfs.create_file("/secret.txt", b"stored in cluster slack")
+                .unwrap();
+        }
+
+        let backend = Box::new(FatImageBackend::open(file.path()).unwrap());
+        let vfs = SlackVfs::mount_with_backend(meta_dir.path(), "pw", backend).unwrap();
+        assert_eq!(
+            vfs.read_file("/secret.txt").unwrap(),
+            b"stored in cluster slack"
+        );
+        assert_eq!(std::fs::metadata(file.path()).unwrap().len(), image_len);
+    }
+
+    #[test]
+    fn test_write_past_slack_rejected() {
+        let mut image = FatTestImage::new(FatType::Fat12);
+        image.add_file(FatTestImage::ROOT, "A.TXT", b"abc");
+        let file = image.build();
+
+        let backend = FatImageBackend::open(file.path()).unwrap();
+        let region = backend.get_slack_info(Path::new("/a.txt")).unwrap();
+        let too_long = vec![0u8; region.available as usize + 1];
+        assert!(matches!(
+            backend.write_slack(&region, 0, &too_long),
+            Err(Error::InsufficientSpace { .. })
+        ));
+        assert!(matches!(
+            backend.get_slack_info(Path::new("/missing.txt")),
+            Err(Error::FileNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,327 @@
+//! Minimal FAT image builder for unit tests.
+//!
+//! Produces FAT12, FAT16 or FAT32 volumes with 512-byte sectors, sized just
+//! large enough for the cluster count that selects each FAT type. Images
+//! are written sparsely.
+
+use super::FatType;
+use std::collections::BTreeMap;
+use std::io::{Seek, SeekFrom, Write};
+use tempfile::NamedTempFile;
+
+const SECTOR: u64 = 512;
+/// Clusters allocated to every subdirectory.
+const DIR_CLUSTERS: u32 = 2;
+
+/// Builder for a small FAT volume.
+pub(crate) struct FatTestImage {
+    fat_type: FatType,
+    sectors_per_cluster: u64,
+    reserved_sectors: u64,
+    root_entry_count: u64,
+    total_sectors: u64,
+    fat_size: u64,
+    /// Data written at absolute offsets.
+    chunks: BTreeMap<u64, Vec<u8>>,
+    /// FAT entries by cluster.
+    fat: Vec<u32>,
+    next_cluster: u32,
+    /// Raw 32-byte entries per directory; the key is the directory's first
+    /// cluster, or `ROOT`.
+    dirs: BTreeMap<u32, Vec<[u8; 32]>>,
+    short_name_counter: u32,
+}
+
+impl FatTestImage {
+    pub const ROOT: u32 = 0;
+
+    /// Create an empty volume of the given type.
+    pub fn new(fat_type: FatType) -> Self {
+        let (sectors_per_cluster, reserved_sectors, root_entry_count, total_sectors) =
+            match fat_type {
+                FatType::Fat12 => (4, 1, 512, 4096),
+                FatType::Fat16 => (2, 1, 512, 16384),
+                FatType::Fat32 => (1, 32, 0, 70000),
+            };
+        let entry_bits = match fat_type {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        };
+
+        let root_sectors = (root_entry_count * 32u64).div_ceil(SECTOR);
+        let max_clusters = (total_sectors - reserved_sectors - root_sectors) / sectors_per_cluster;
+        let fat_size = ((max_clusters + 2) * entry_bits / 8).div_ceil(SECTOR);
+
+        let mut image = Self {
+            fat_type,
+            sectors_per_cluster,
+            reserved_sectors,
+            root_entry_count,
+            total_sectors,
+            fat_size,
+            chunks: BTreeMap::new(),
+            fat: vec![0; max_clusters as usize + 2],
+            next_cluster: 2,
+            dirs: BTreeMap::new(),
+            short_name_counter: 0,
+        };
+        image.fat[0] = 0x0FFF_FFF8;
+        image.fat[1] = 0x0FFF_FFFF;
+
+        let root = if fat_type == FatType::Fat32 {
+            image.alloc_chain(DIR_CLUSTERS, false)[0]
+        } else {
+            Self::ROOT
+        };
+        image.dirs.insert(Self::ROOT, Vec::new());
+        assert!(fat_type != FatType::Fat32 || root == 2);
+        image
+    }
+
+    /// Get the cluster size in bytes.
+    pub fn cluster_size(&self) -> u64 {
+        self.sectors_per_cluster * SECTOR
+    }
+
+    /// Create a subdirectory and return its id.
+    pub fn mkdir(&mut self, parent: u32, name: &str) -> u32 {
+        let cluster = self.alloc_chain(DIR_CLUSTERS, false)[0];
+        self.link(parent, name, 0x10, cluster, 0);
+
+        // ".." of a root child points at cluster 0
+        let parent_cluster = if parent == Self::ROOT { 0 } else { parent };
+        self.dirs.insert(
+            cluster,
+            vec![
+                Self::short_entry(b".          ", 0x10, cluster, 0),
+                Self::short_entry(b"..         ", 0x10, parent_cluster, 0),
+            ],
+        );
+        cluster
+    }
+
+    /// Add a file stored in contiguous clusters.
+    pub fn add_file(&mut self, parent: u32, name: &str, content: &[u8]) {
+        self.add_file_with_layout(parent, name, content, false);
+    }
+
+    /// Add a file whose clusters are separated by free clusters.
+    pub fn add_fragmented_file(&mut self, parent: u32, name: &str, content: &[u8]) {
+        self.add_file_with_layout(parent, name, content, true);
+    }
+
+    fn add_file_with_layout(&mut self, parent: u32, name: &str, content: &[u8], gaps: bool) {
+        let cluster_size = self.cluster_size() as usize;
+        let count = content.len().div_ceil(cluster_size) as u32;
+        let chain = self.alloc_chain(count, gaps);
+
+        for (i, &cluster) in chain.iter().enumerate() {
+            let from = i * cluster_size;
+            let to = (from + cluster_size).min(content.len());
+            let offset = self.cluster_offset(cluster);
+            self.chunks.insert(offset, content[from..to].to_vec());
+        }
+
+        let first = chain.first().copied().unwrap_or(0);
+        self.link(parent, name, 0x20, first, content.len() as u32);
+    }
+
+    /// Write the image to a temporary file.
+    pub fn build(mut self) -> NamedTempFile {
+        let cluster_size = self.cluster_size() as usize;
+        let root_start = (self.reserved_sectors + 2 * self.fat_size) * SECTOR;
+
+        for (id, entries) in std::mem::take(&mut self.dirs) {
+            let bytes: Vec<u8> = entries.iter().flatten().copied().collect();
+            if id == Self::ROOT && self.fat_type != FatType::Fat32 {
+                assert!(entries.len() as u64 <= self.root_entry_count);
+                self.chunks.insert(root_start, bytes);
+                continue;
+            }
+
+            let first = if id == Self::ROOT { 2 } else { id };
+            assert!(bytes.len() <= DIR_CLUSTERS as usize * cluster_size);
+            for (i, chunk) in bytes.chunks(cluster_size).enumerate() {
+                let offset = self.cluster_offset(first + i as u32);
+                self.chunks.insert(offset, chunk.to_vec());
+            }
+        }
+
+        let fat = self.encode_fat();
+        for copy in 0..2 {
+            let offset = (self.reserved_sectors + copy * self.fat_size) * SECTOR;
+            self.chunks.insert(offset, fat.clone());
+        }
+        self.chunks.insert(0, self.boot_sector());
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.as_file().set_len(self.total_sectors * SECTOR).unwrap();
+        for (offset, data) in &self.chunks {
+            file.seek(SeekFrom::Start(*offset)).unwrap();
+            file.write_all(data).unwrap();
+        }
+        file.flush().unwrap();
+        file
+    }
+
+    /// Allocate a cluster chain, optionally leaving a free cluster after
+    /// each one so the chain is not contiguous.
+    fn alloc_chain(&mut self, count: u32, gaps: bool) -> Vec<u32> {
+        let chain: Vec<u32> = (0..count)
+            .map(|_| {
+                let cluster = self.next_cluster;
+                self.next_cluster += if gaps { 2 } else { 1 };
+                cluster
+            })
+            .collect();
+        assert!(
+            (self.next_cluster as usize) < self.fat.len(),
+            "test image full"
+        );
+
+        for pair in chain.windows(2) {
+            self.fat[pair[0] as usize] = pair[1];
+        }
+        if let Some(&last) = chain.last() {
+            self.fat[last as usize] = 0x0FFF_FFFF;
+        }
+        chain
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        let root_sectors = (self.root_entry_count * 32).div_ceil(SECTOR);
+        let first_data = self.reserved_sectors + 2 * self.fat_size + root_sectors;
+        (first_data + (cluster as u64 - 2) * self.sectors_per_cluster) * SECTOR
+    }
+
+    /// Add a directory entry, with long name entries unless the name is a
+    /// valid upper-case 8.3 name.
+    fn link(&mut self, parent: u32, name: &str, attributes: u8, cluster: u32, size: u32) {
+        let (stem, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+        let is_short = stem.len() <= 8
+            && ext.len() <= 3
+            && !stem.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '.' || c == '_');
+
+        let mut short = [b' '; 11];
+        let mut entries = Vec::new();
+        if is_short {
+            short[..stem.len()].copy_from_slice(stem.as_bytes());
+            short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+        } else {
+            self.short_name_counter += 1;
+            let clean = |s: &str, max: usize| -> Vec<u8> {
+                s.bytes()
+                    .filter(u8::is_ascii_alphanumeric)
+                    .map(|b| b.to_ascii_uppercase())
+                    .take(max)
+                    .collect()
+            };
+            let mut base = clean(stem, 6);
+            base.extend(format!("~{}", self.short_name_counter).bytes());
+            short[..base.len()].copy_from_slice(&base);
+            let ext = clean(ext, 3);
+            short[8..8 + ext.len()].copy_from_slice(&ext);
+            entries = Self::long_name_entries(name, &short);
+        }
+
+        entries.push(Self::short_entry(&short, attributes, cluster, size));
+        self.dirs
+            .get_mut(&parent)
+            .expect("parent is not a directory")
+            .extend(entries);
+    }
+
+    fn short_entry(name: &[u8; 11], attributes: u8, cluster: u32, size: u32) -> [u8; 32] {
+        let mut entry = [0u8; 32];
+        entry[..11].copy_from_slice(name);
+        entry[0x0B] = attributes;
+        entry[0x14..0x16].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        entry[0x1A..0x1C].copy_from_slice(&(cluster as u16).to_le_bytes());
+        entry[0x1C..0x20].copy_from_slice(&size.to_le_bytes());
+        entry
+    }
+
+    /// Long name entries in on-disk order (last piece first).
+    fn long_name_entries(name: &str, short: &[u8; 11]) -> Vec<[u8; 32]> {
+        let checksum = short
+            .iter()
+            .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b));
+
+        let mut units: Vec<u16> = name.encode_utf16().collect();
+        if !units.len().is_multiple_of(13) {
+            units.push(0);
+        }
+        while !units.len().is_multiple_of(13) {
+            units.push(0xFFFF);
+        }
+
+        let count = units.len() / 13;
+        let mut entries = Vec::new();
+        for (i, piece) in units.chunks(13).enumerate().rev() {
+            let mut entry = [0u8; 32];
+            entry[0] = (i + 1) as u8 | if i + 1 == count { 0x40 } else { 0 };
+            entry[0x0B] = 0x0F;
+            entry[0x0D] = checksum;
+            let slots = (1..11)
+                .step_by(2)
+                .chain((14..26).step_by(2))
+                .chain((28..32).step_by(2));
+            for (slot, unit) in slots.zip(piece) {
+                entry[slot..slot + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            entries.push(entry);
+        }
+        entries
+    }
+
+    fn encode_fat(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; (self.fat_size * SECTOR) as usize];
+        for (n, &value) in self.fat.iter().enumerate() {
+            match self.fat_type {
+                FatType::Fat12 => {
+                    let value = (value & 0xFFF) as u16;
+                    let off = n + n / 2;
+                    if n % 2 == 0 {
+                        bytes[off] = value as u8;
+                        bytes[off + 1] = (bytes[off + 1] & 0xF0) | (value >> 8) as u8;
+                    } else {
+                        bytes[off] = (bytes[off] & 0x0F) | ((value & 0x0F) << 4) as u8;
+                        bytes[off + 1] = (value >> 4) as u8;
+                    }
+                }
+                FatType::Fat16 => {
+                    bytes[n * 2..n * 2 + 2].copy_from_slice(&(value as u16).to_le_bytes())
+                }
+                FatType::Fat32 => bytes[n * 4..n * 4 + 4].copy_from_slice(&value.to_le_bytes()),
+            }
+        }
+        bytes
+    }
+
+    fn boot_sector(&self) -> Vec<u8> {
+        let mut bs = vec![0u8; SECTOR as usize];
+        bs[0..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+        bs[3..11].copy_from_slice(b"MSWIN4.1");
+        bs[0x0B..0x0D].copy_from_slice(&(SECTOR as u16).to_le_bytes());
+        bs[0x0D] = self.sectors_per_cluster as u8;
+        bs[0x0E..0x10].copy_from_slice(&(self.reserved_sectors as u16).to_le_bytes());
+        bs[0x10] = 2;
+        bs[0x11..0x13].copy_from_slice(&(self.root_entry_count as u16).to_le_bytes());
+        bs[0x15] = 0xF8;
+        if self.fat_type == FatType::Fat32 {
+            bs[0x20..0x24].copy_from_slice(&(self.total_sectors as u32).to_le_bytes());
+            bs[0x24..0x28].copy_from_slice(&(self.fat_size as u32).to_le_bytes());
+            bs[0x2C..0x30].copy_from_slice(&2u32.to_le_bytes());
+        } else {
+            bs[0x13..0x15].copy_from_slice(&(self.total_sectors as u16).to_le_bytes());
+            bs[0x16..0x18].copy_from_slice(&(self.fat_size as u16).to_le_bytes());
+        }
+        bs[510] = 0x55;
+        bs[511] = 0xAA;
+        bs
+    }
+}
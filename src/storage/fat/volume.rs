@@ -0,0 +1,549 @@
+//! FAT12/16/32 volume parsing.
+//!
+//! Parses the boot sector (BPB), the first FAT copy and directory entries
+//! (including long file names) to map files to their cluster chains.
+
+use crate::error::{Error, Result};
+use crate::storage::image_file::ImageFile;
+use std::path::{Component, Path, PathBuf};
+
+/// Size of a directory entry.
+const DIR_ENTRY_SIZE: usize = 32;
+
+/// Directory entry attribute: read-only, hidden, system and volume label
+/// together mark a long file name entry.
+const ATTR_LONG_NAME: u8 = 0x0F;
+/// Directory entry attribute: volume label.
+const ATTR_VOLUME_ID: u8 = 0x08;
+/// Directory entry attribute: subdirectory.
+const ATTR_DIRECTORY: u8 = 0x10;
+
+/// First name byte of a deleted entry.
+const ENTRY_DELETED: u8 = 0xE5;
+/// First name byte stored in place of a leading 0xE5 character.
+const ENTRY_KANJI_E5: u8 = 0x05;
+
+/// Long file name sequence flag for the last (first stored) entry.
+const LFN_LAST_ENTRY: u8 = 0x40;
+
+/// NT reserved byte flags: base name / extension are lower case.
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+
+/// Cluster count limits that decide the FAT type (from the FAT spec).
+const FAT12_MAX_CLUSTERS: u32 = 4085;
+const FAT16_MAX_CLUSTERS: u32 = 65525;
+
+/// FAT variant, determined by the number of data clusters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// Smallest FAT entry value marking the end of a cluster chain.
+    fn end_of_chain(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xFF8,
+            FatType::Fat16 => 0xFFF8,
+            FatType::Fat32 => 0x0FFF_FFF8,
+        }
+    }
+
+    /// FAT entry value marking a bad cluster.
+    fn bad_cluster(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xFF7,
+            FatType::Fat16 => 0xFFF7,
+            FatType::Fat32 => 0x0FFF_FFF7,
+        }
+    }
+}
+
+/// Parsed BIOS parameter block.
+#[derive(Debug, Clone)]
+pub struct FatBootSector {
+    pub bytes_per_sector: u32,
+    pub sectors_per_cluster: u32,
+    pub reserved_sectors: u32,
+    pub num_fats: u32,
+    pub root_entry_count: u32,
+    pub total_sectors: u32,
+    pub fat_size: u32,
+    /// First cluster of the root directory (FAT32 only).
+    pub root_cluster: u32,
+}
+
+impl FatBootSector {
+    /// Sectors occupied by the fixed FAT12/16 root directory.
+    fn root_dir_sectors(&self) -> u32 {
+        (self.root_entry_count * DIR_ENTRY_SIZE as u32).div_ceil(self.bytes_per_sector)
+    }
+
+    /// First sector of the data region (cluster 2).
+    fn first_data_sector(&self) -> u32 {
+        self.reserved_sectors + self.num_fats * self.fat_size + self.root_dir_sectors()
+    }
+
+    /// Number of data clusters.
+    fn cluster_count(&self) -> u32 {
+        self.total_sectors.saturating_sub(self.first_data_sector()) / self.sectors_per_cluster
+    }
+}
+
+/// A directory entry with its long name resolved.
+#[derive(Debug, Clone)]
+pub struct FatDirEntry {
+    /// Long file name if present, otherwise the 8.3 name.
+    pub name: String,
+    /// The 8.3 name, as `BASE.EXT`.
+    pub short_name: String,
+    pub attributes: u8,
+    pub first_cluster: u32,
+    pub size: u32,
+}
+
+impl FatDirEntry {
+    /// Check if this entry is a subdirectory.
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    /// Check if this is the "." or ".." entry.
+    pub fn is_dot(&self) -> bool {
+        self.name == "." || self.name == ".."
+    }
+
+    /// Check if a path component names this entry. FAT names are
+    /// case-insensitive.
+    fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || self.short_name.eq_ignore_ascii_case(name)
+    }
+}
+
+/// Where a directory's entries live.
+#[derive(Debug, Clone, Copy)]
+enum DirLocation {
+    /// The fixed root directory region of FAT12/16.
+    FixedRoot,
+    /// A cluster chain (subdirectories, FAT32 root).
+    Chain(u32),
+}
+
+/// A FAT volume read from an image file or block device.
+#[derive(Debug)]
+pub struct FatVolume {
+    image: ImageFile,
+    boot: FatBootSector,
+    fat_type: FatType,
+    /// First FAT copy, raw bytes.
+    fat: Vec<u8>,
+}
+
+impl FatVolume {
+    /// Open a FAT volume.
+    pub fn open(path: &Path) -> Result<Self> {
+        let image = ImageFile::open(path)?;
+        let sector = image.read_at(0, 512)?;
+        let boot = Self::parse_boot_sector(&sector)?;
+
+        let cluster_count = boot.cluster_count();
+        let fat_type = if cluster_count < FAT12_MAX_CLUSTERS {
+            FatType::Fat12
+        } else if cluster_count < FAT16_MAX_CLUSTERS {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+
+        let fat = image.read_at(
+            boot.reserved_sectors as u64 * boot.bytes_per_sector as u64,
+            (boot.fat_size * boot.bytes_per_sector) as usize,
+        )?;
+
+        Ok(Self {
+            image,
+            boot,
+            fat_type,
+            fat,
+        })
+    }
+
+    /// Parse the BIOS parameter block from the boot sector.
+    fn parse_boot_sector(data: &[u8]) -> Result<FatBootSector> {
+        if data[510] != 0x55 || data[511] != 0xAA {
+            return Err(Error::InvalidMagic);
+        }
+
+        let u16_at = |off: usize| u16::from_le_bytes([data[off], data[off + 1]]) as u32;
+        let u32_at = |off: usize| {
+            u32::from_le_bytes([data[off], data[off + 1], data[off + 2], data[off + 3]])
+        };
+
+        let bytes_per_sector = u16_at(0x0B);
+        let sectors_per_cluster = data[0x0D] as u32;
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+        {
+            return Err(Error::DataCorruption(format!(
+                "Invalid FAT geometry: {} bytes/sector, {} sectors/cluster",
+                bytes_per_sector, sectors_per_cluster
+            )));
+        }
+
+        let total_sectors = match u16_at(0x13) {
+            0 => u32_at(0x20),
+            n => n,
+        };
+        let (fat_size, root_cluster) = match u16_at(0x16) {
+            0 => (u32_at(0x24), u32_at(0x2C)),
+            n => (n, 0),
+        };
+
+        let boot = FatBootSector {
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors: u16_at(0x0E),
+            num_fats: data[0x10] as u32,
+            root_entry_count: u16_at(0x11),
+            total_sectors,
+            fat_size,
+            root_cluster,
+        };
+
+        if boot.reserved_sectors == 0 || boot.num_fats == 0 || boot.fat_size == 0 {
+            return Err(Error::DataCorruption("Invalid FAT boot sector".to_string()));
+        }
+
+        Ok(boot)
+    }
+
+    /// Get the FAT variant.
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    /// Get the parsed boot sector.
+    pub fn boot_sector(&self) -> &FatBootSector {
+        &self.boot
+    }
+
+    /// Get the cluster size in bytes.
+    pub fn cluster_size(&self) -> u64 {
+        self.boot.bytes_per_sector as u64 * self.boot.sectors_per_cluster as u64
+    }
+
+    /// Get the image the volume is read from.
+    pub fn image(&self) -> &ImageFile {
+        &self.image
+    }
+
+    /// Byte offset of a data cluster.
+    pub fn cluster_offset(&self, cluster: u32) -> u64 {
+        let sector = self.boot.first_data_sector() as u64
+            + (cluster as u64 - 2) * self.boot.sectors_per_cluster as u64;
+        sector * self.boot.bytes_per_sector as u64
+    }
+
+    /// Read a FAT entry.
+    fn fat_entry(&self, cluster: u32) -> Result<u32> {
+        let n = cluster as usize;
+        let byte = |i: usize| {
+            self.fat.get(i).copied().ok_or_else(|| {
+                Error::DataCorruption(format!("Cluster {} outside the FAT", cluster))
+            })
+        };
+
+        Ok(match self.fat_type {
+            FatType::Fat12 => {
+                let off = n + n / 2;
+                let value = u16::from_le_bytes([byte(off)?, byte(off + 1)?]) as u32;
+                if n % 2 == 1 {
+                    value >> 4
+                } else {
+                    value & 0xFFF
+                }
+            }
+            FatType::Fat16 => u16::from_le_bytes([byte(n * 2)?, byte(n * 2 + 1)?]) as u32,
+            FatType::Fat32 => {
+                let off = n * 4;
+                u32::from_le_bytes([byte(off)?, byte(off + 1)?, byte(off + 2)?, byte(off + 3)?])
+                    & 0x0FFF_FFFF
+            }
+        })
+    }
+
+    /// Follow a cluster chain from its first cluster.
+    pub fn cluster_chain(&self, first: u32) -> Result<Vec<u32>> {
+        let max_cluster = self.boot.cluster_count() + 1;
+        let mut chain = Vec::new();
+        let mut cluster = first;
+
+        loop {
+            if cluster < 2 || cluster > max_cluster {
+                return Err(Error::DataCorruption(format!(
+                    "Cluster chain points outside the volume: {}",
+                    cluster
+                )));
+            }
+            // A chain can't be longer than the volume; guards against loops
+            if chain.len() as u32 >= self.boot.cluster_count() {
+                return Err(Error::DataCorruption("Cluster chain loops".to_string()));
+            }
+            chain.push(cluster);
+
+            let next = self.fat_entry(cluster)?;
+            if next >= self.fat_type.end_of_chain() {
+                return Ok(chain);
+            }
+            if next == self.fat_type.bad_cluster() || next == 0 {
+                return Err(Error::DataCorruption(format!(
+                    "Cluster chain broken after cluster {}",
+                    cluster
+                )));
+            }
+            cluster = next;
+        }
+    }
+
+    /// List a directory's entries.
+    fn read_dir_at(&self, location: DirLocation) -> Result<Vec<FatDirEntry>> {
+        let data = match location {
+            DirLocation::FixedRoot => {
+                let start = self.boot.reserved_sectors + self.boot.num_fats * self.boot.fat_size;
+                self.image.read_at(
+                    start as u64 * self.boot.bytes_per_sector as u64,
+                    self.boot.root_entry_count as usize * DIR_ENTRY_SIZE,
+                )?
+            }
+            DirLocation::Chain(first) => {
+                let mut data = Vec::new();
+                for cluster in self.cluster_chain(first)? {
+                    data.extend(
+                        self.image
+                            .read_at(self.cluster_offset(cluster), self.cluster_size() as usize)?,
+                    );
+                }
+                data
+            }
+        };
+
+        Ok(Self::parse_dir_entries(&data))
+    }
+
+    /// Parse raw directory entries, joining long file name entries to the
+    /// short entry that follows them.
+    fn parse_dir_entries(data: &[u8]) -> Vec<FatDirEntry> {
+        let mut entries = Vec::new();
+        // Long name pieces collected so far: (sequence number, checksum, chars)
+        let mut long_name: Vec<(u8, u8, Vec<u16>)> = Vec::new();
+
+        for raw in data.chunks_exact(DIR_ENTRY_SIZE) {
+            match raw[0] {
+                0x00 => break,
+                ENTRY_DELETED => {
+                    long_name.clear();
+                    continue;
+                }
+                _ => {}
+            }
+
+            let attributes = raw[0x0B];
+            if attributes & ATTR_LONG_NAME == ATTR_LONG_NAME {
+                if raw[0] & LFN_LAST_ENTRY != 0 {
+                    long_name.clear();
+                }
+                let chars = [1..11, 14..26, 28..32]
+                    .into_iter()
+                    .flat_map(|range| raw[range].chunks_exact(2))
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect();
+                long_name.push((raw[0] & 0x1F, raw[0x0D], chars));
+                continue;
+            }
+
+            if attributes & ATTR_VOLUME_ID != 0 {
+                long_name.clear();
+                continue;
+            }
+
+            let short_name = Self::short_name(raw);
+            let name =
+                Self::join_long_name(&long_name, &raw[..11]).unwrap_or_else(|| short_name.clone());
+            long_name.clear();
+
+            let cluster_hi = u16::from_le_bytes([raw[0x14], raw[0x15]]) as u32;
+            let cluster_lo = u16::from_le_bytes([raw[0x1A], raw[0x1B]]) as u32;
+
+            entries.push(FatDirEntry {
+                name,
+                short_name,
+                attributes,
+                first_cluster: (cluster_hi << 16) | cluster_lo,
+                size: u32::from_le_bytes([raw[0x1C], raw[0x1D], raw[0x1E], raw[0x1F]]),
+            });
+        }
+
+        entries
+    }
+
+    /// Build the `BASE.EXT` display form of an 8.3 name.
+    fn short_name(raw: &[u8]) -> String {
+        let mut base = raw[..8].to_vec();
+        if base[0] == ENTRY_KANJI_E5 {
+            base[0] = ENTRY_DELETED;
+        }
+        let decode = |bytes: &[u8], lower: bool| {
+            let text: String = bytes.iter().map(|&b| b as char).collect();
+            let text = text.trim_end().to_string();
+            if lower {
+                text.to_ascii_lowercase()
+            } else {
+                text
+            }
+        };
+
+        let base = decode(&base, raw[0x0C] & NT_LOWER_BASE != 0);
+        let ext = decode(&raw[8..11], raw[0x0C] & NT_LOWER_EXT != 0);
+        if ext.is_empty() {
+            base
+        } else {
+            format!("{}.{}", base, ext)
+        }
+    }
+
+    /// Join collected long name entries if they form a complete name for
+    /// the given short name.
+    fn join_long_name(pieces: &[(u8, u8, Vec<u16>)], short: &[u8]) -> Option<String> {
+        if pieces.is_empty() {
+            return None;
+        }
+
+        // Pieces are stored last-first with sequence numbers counting down to 1
+        let checksum = short
+            .iter()
+            .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b));
+        let count = pieces.len();
+        for (i, (sequence, piece_checksum, _)) in pieces.iter().enumerate() {
+            if *sequence as usize != count - i || *piece_checksum != checksum {
+                return None;
+            }
+        }
+
+        let units: Vec<u16> = pieces
+            .iter()
+            .rev()
+            .flat_map(|(_, _, chars)| chars.iter().copied())
+            .take_while(|&c| c != 0x0000 && c != 0xFFFF)
+            .collect();
+        String::from_utf16(&units).ok()
+    }
+
+    /// List the root directory.
+    pub fn read_root(&self) -> Result<Vec<FatDirEntry>> {
+        match self.fat_type {
+            FatType::Fat32 => self.read_dir_at(DirLocation::Chain(self.boot.root_cluster)),
+            _ => self.read_dir_at(DirLocation::FixedRoot),
+        }
+    }
+
+    /// List a subdirectory.
+    pub fn read_dir(&self, dir: &FatDirEntry) -> Result<Vec<FatDirEntry>> {
+        if !dir.is_directory() {
+            return Err(Error::NotADirectory(dir.name.clone()));
+        }
+        // ".." pointing at the root stores cluster 0
+        if dir.first_cluster == 0 {
+            return self.read_root();
+        }
+        self.read_dir_at(DirLocation::Chain(dir.first_cluster))
+    }
+
+    /// Resolve an absolute path inside the volume to its directory entry.
+    pub fn lookup(&self, path: &Path) -> Result<FatDirEntry> {
+        let mut entries = self.read_root()?;
+        let mut found: Option<FatDirEntry> = None;
+
+        for component in path.components() {
+            let name = match component {
+                Component::RootDir => continue,
+                Component::Normal(name) => name.to_string_lossy(),
+                _ => return Err(Error::InvalidPath(path.display().to_string())),
+            };
+
+            if let Some(dir) = &found {
+                entries = self.read_dir(dir)?;
+            }
+
+            found = Some(
+                entries
+                    .iter()
+                    .find(|e| !e.is_dot() && e.matches(&name))
+                    .cloned()
+                    .ok_or_else(|| Error::FileNotFound(path.display().to_string()))?,
+            );
+        }
+
+        found.ok_or_else(|| Error::NotAFile(path.display().to_string()))
+    }
+
+    /// List every regular file in the volume with its absolute path, sorted
+    /// by path.
+    pub fn walk_files(&self) -> Result<Vec<(PathBuf, FatDirEntry)>> {
+        let mut files = Vec::new();
+        let mut pending = vec![(PathBuf::from("/"), self.read_root()?)];
+
+        while let Some((dir_path, entries)) = pending.pop() {
+            for entry in entries.into_iter().filter(|e| !e.is_dot()) {
+                let path = dir_path.join(&entry.name);
+                if entry.is_directory() {
+                    if let Ok(children) = self.read_dir(&entry) {
+                        pending.push((path, children));
+                    }
+                } else {
+                    files.push((path, entry));
+                }
+            }
+        }
+
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(files)
+    }
+
+    /// Calculate the slack of a file: the bytes between its end and the end
+    /// of the last cluster it uses.
+    ///
+    /// Returns (absolute offset where slack starts, available bytes).
+    pub fn file_slack(&self, entry: &FatDirEntry) -> Result<(u64, u64)> {
+        if entry.is_directory() {
+            return Err(Error::NotAFile(entry.name.clone()));
+        }
+        if entry.size == 0 || entry.first_cluster == 0 {
+            return Ok((0, 0));
+        }
+
+        let cluster_size = self.cluster_size();
+        let size = entry.size as u64;
+        let clusters_used = size.div_ceil(cluster_size) as usize;
+
+        let chain = self.cluster_chain(entry.first_cluster)?;
+        let last = *chain.get(clusters_used - 1).ok_or_else(|| {
+            Error::DataCorruption(format!(
+                "Cluster chain of {} is shorter than its size",
+                entry.name
+            ))
+        })?;
+
+        let used_in_last = size % cluster_size;
+        if used_in_last == 0 {
+            return Ok((self.cluster_offset(last) + cluster_size, 0));
+        }
+
+        Ok((
+            self.cluster_offset(last) + used_in_last,
+            cluster_size - used_in_last,
+        ))
+    }
+}
@@ -0,0 +1,163 @@
+//! File-append slack backend.
+//!
+//! Stores data after a host file's logical end of file, up to the end of its
+//! last block. This needs no privileges, but extends the host file: the
+//! hidden bytes show up in the file's size until they are wiped.
+
+use crate::config::ShredPolicy;
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, PersistenceRisk};
+use crate::storage::slack::{read_slack, wipe_slack, wipe_slack_range, write_slack};
+use crate::storage::slack_backend::{SlackBackend, SlackRegion};
+use std::path::Path;
+
+/// Slack backend that appends to host files through the regular file API.
+#[derive(Debug, Clone)]
+pub struct FileSlackBackend {
+    /// Block size used to compute slack capacity.
+    block_size: u64,
+}
+
+impl FileSlackBackend {
+    pub const NAME: &'static str = "file append";
+
+    /// Create a backend that assumes the given file system block size.
+    pub fn new(block_size: u64) -> Self {
+        Self { block_size }
+    }
+}
+
+impl SlackBackend for FileSlackBackend {
+    fn get_slack_info(&self, path: &Path) -> Result<SlackRegion> {
+        let logical_size = std::fs::metadata(path)?.len();
+        self.get_slack_info_at(path, logical_size)
+    }
+
+    fn get_slack_info_at(&self, path: &Path, logical_size: u64) -> Result<SlackRegion> {
+        if !std::fs::metadata(path)?.is_file() {
+            return Err(Error::NotAFile(path.display().to_string()));
+        }
+
+        let remainder = logical_size % self.block_size;
+        let available = if logical_size == 0 || remainder == 0 {
+            0
+        } else {
+            self.block_size - remainder
+        };
+
+        Ok(SlackRegion {
+            device_path: path.to_path_buf(),
+            offset: logical_size,
+            available,
+            logical_size,
+            block_size: self.block_size,
+        })
+    }
+
+    fn read_slack(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        region.check_bounds(offset, len)?;
+        read_slack(&region.device_path, region.offset + offset, len)
+    }
+
+    fn write_slack(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        region.check_bounds(offset, data.len())?;
+        write_slack(&region.device_path, data, region.offset + offset)
+    }
+
+    fn wipe_slack(&self, region: &SlackRegion) -> Result<()> {
+        wipe_slack(&region.device_path, region.logical_size, None)
+    }
+
+    /// Bytes never written are not there to wipe, and a wiped range at the
+    /// end of the host is cut off again.
+    fn wipe_slack_range(
+        &self,
+        region: &SlackRegion,
+        offset: u64,
+        len: u64,
+        policy: ShredPolicy,
+    ) -> Result<()> {
+        wipe_slack_range(&region.device_path, region.offset + offset, len, policy)
+    }
+
+    /// Data lives past the end of file: the host grows, and saving it again
+    /// truncates the data away.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::High,
+            changes_visible_metadata: true,
+            needs_root: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_region_after_eof() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("host.txt");
+        std::fs::write(&path, vec![b'x'; 1000]).unwrap();
+
+        let backend = FileSlackBackend::new(4096);
+        let region = backend.get_slack_info(&path).unwrap();
+        assert_eq!(region.offset, 1000);
+        assert_eq!(region.available, 3096);
+
+        backend.write_slack(&region, 10, b"hidden").unwrap();
+        assert_eq!(backend.read_slack(&region, 10, 6).unwrap(), b"hidden");
+
+        // Writing grew the file, but the pinned region stays put
+        let pinned = backend.get_slack_info_at(&path, 1000).unwrap();
+        assert_eq!(pinned.offset, region.offset);
+        assert_eq!(backend.read_slack(&pinned, 10, 6).unwrap(), b"hidden");
+
+        assert!(matches!(
+            backend.write_slack(&region, 3090, b"too long"),
+            Err(Error::InsufficientSpace { .. })
+        ));
+
+        backend.wipe_slack(&region).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 1000);
+    }
+
+    #[test]
+    fn test_wipe_range() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("host.txt");
+        std::fs::write(&path, vec![b'x'; 1000]).unwrap();
+
+        let backend = FileSlackBackend::new(4096);
+        let region = backend.get_slack_info(&path).unwrap();
+        backend.write_slack(&region, 0, &[7u8; 300]).unwrap();
+
+        // A hole is overwritten in place
+        backend
+            .wipe_slack_range(&region, 100, 50, ShredPolicy::SECURE)
+            .unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 1300);
+        assert_eq!(backend.read_slack(&region, 100, 50).unwrap(), vec![0u8; 50]);
+        assert_eq!(backend.read_slack(&region, 150, 1).unwrap(), vec![7]);
+
+        // The tail is cut off, however far the range reaches
+        backend
+            .wipe_slack_range(&region, 200, 2000, ShredPolicy::Zero)
+            .unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 1200);
+        backend
+            .wipe_slack_range(&region, 500, 100, ShredPolicy::Zero)
+            .unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 1200);
+    }
+}
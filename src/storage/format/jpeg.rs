@@ -0,0 +1,195 @@
+//! JPEG container: an `APP15` segment.
+//!
+//! The segment goes after the leading `APPn` segments, so JFIF or Exif
+//! headers stay first where readers expect them. Its payload starts with
+//! an identifier, as application segments do, so a foreign `APP15` is
+//! never taken for ours.
+
+use super::Container;
+use crate::error::{Error, Result};
+
+/// Start of image marker.
+pub(super) const SOI: &[u8] = &[0xFF, 0xD8, 0xFF];
+
+/// `APP15` marker.
+const APP15: u8 = 0xEF;
+/// Start of scan marker; entropy-coded data follows.
+const SOS: u8 = 0xDA;
+/// End of image marker.
+const EOI: u8 = 0xD9;
+
+/// Identifier at the start of the hidden segment's payload.
+const IDENTIFIER: &[u8] = b"SVFS\0";
+
+/// Segment length field limit, minus the field itself and the identifier.
+pub(super) const MAX_PAYLOAD: u64 = 0xFFFF - 2 - IDENTIFIER.len() as u64;
+
+/// A marker segment's position in the file.
+struct Segment {
+    /// Offset of the `0xFF` before the marker.
+    start: usize,
+    marker: u8,
+    /// Offset just past the segment's data.
+    end: usize,
+}
+
+impl Segment {
+    /// Payload of a segment with a length field.
+    fn data<'a>(&self, file: &'a [u8]) -> &'a [u8] {
+        &file[self.start + 4..self.end]
+    }
+
+    fn is_ours(&self, file: &[u8]) -> bool {
+        self.marker == APP15 && self.data(file).starts_with(IDENTIFIER)
+    }
+}
+
+/// List the marker segments from `SOI` up to and including `SOS`.
+fn segments(file: &[u8]) -> Result<Vec<Segment>> {
+    if !file.starts_with(&SOI[..2]) {
+        return Err(Error::DataCorruption("Missing JPEG SOI marker".to_string()));
+    }
+
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    loop {
+        if file.get(pos) != Some(&0xFF) {
+            return Err(Error::DataCorruption(format!(
+                "Expected a JPEG marker at byte {}",
+                pos
+            )));
+        }
+        // Markers may be preceded by fill bytes
+        let mut at = pos + 1;
+        while file.get(at) == Some(&0xFF) {
+            at += 1;
+        }
+        let marker = *file
+            .get(at)
+            .ok_or_else(|| Error::DataCorruption("JPEG ends before SOS".to_string()))?;
+
+        // Standalone markers carry no length
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            pos = at + 1;
+            continue;
+        }
+        if marker == EOI {
+            return Err(Error::DataCorruption("JPEG has no SOS".to_string()));
+        }
+
+        let len = file
+            .get(at + 1..at + 3)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+            .filter(|&len| len >= 2)
+            .ok_or_else(|| Error::DataCorruption(format!("Bad JPEG segment at byte {}", pos)))?;
+        let end = at + 1 + len;
+        if end > file.len() {
+            return Err(Error::DataCorruption(format!(
+                "JPEG segment at byte {} runs past the end of file",
+                pos
+            )));
+        }
+
+        // Fill bytes are dropped from the recorded start so `data` lines up
+        segments.push(Segment {
+            start: at - 1,
+            marker,
+            end,
+        });
+        if marker == SOS {
+            return Ok(segments);
+        }
+        pos = end;
+    }
+}
+
+/// Locate the hidden segment.
+pub(super) fn find(file: &[u8]) -> Result<Option<Container>> {
+    Ok(segments(file)?
+        .into_iter()
+        .find(|s| s.is_ours(file))
+        .map(|s| Container {
+            payload: s.data(file)[IDENTIFIER.len()..].to_vec(),
+            span: s.start..s.end,
+        }))
+}
+
+/// Replace the hidden segment, or insert it after the leading `APPn`
+/// segments.
+pub(super) fn embed(file: &mut Vec<u8>, payload: &[u8]) -> Result<usize> {
+    if payload.len() as u64 > MAX_PAYLOAD {
+        return Err(Error::InsufficientSpace {
+            needed: payload.len() as u64,
+            available: MAX_PAYLOAD,
+        });
+    }
+
+    let segments = segments(file)?;
+    let replace = match segments.iter().find(|s| s.is_ours(file)) {
+        Some(existing) => existing.start..existing.end,
+        None => {
+            let at = segments
+                .iter()
+                .find(|s| !(0xE0..=0xEF).contains(&s.marker))
+                .map(|s| s.start)
+                .expect("segments end with SOS");
+            at..at
+        }
+    };
+
+    let len = 2 + IDENTIFIER.len() + payload.len();
+    let mut segment = Vec::with_capacity(len + 2);
+    segment.extend_from_slice(&[0xFF, APP15]);
+    segment.extend_from_slice(&(len as u16).to_be_bytes());
+    segment.extend_from_slice(IDENTIFIER);
+    segment.extend_from_slice(payload);
+
+    let start = replace.start;
+    file.splice(replace, segment);
+    Ok(start)
+}
+
+/// Check the segments up to `SOS` and that the file ends with `EOI`.
+#[cfg(test)]
+pub(super) fn is_valid(file: &[u8]) -> bool {
+    segments(file).is_ok() && file.ends_with(&[0xFF, EOI])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::format::test_files;
+
+    #[test]
+    fn test_segment_after_app_headers() {
+        let mut file = test_files::jpeg();
+        assert!(is_valid(&file));
+        assert!(find(&file).unwrap().is_none());
+
+        // Right after SOI (2) and the JFIF APP0 segment (2 + 16)
+        let at = embed(&mut file, b"\xFF\xD9inside").unwrap();
+        assert_eq!(at, 20);
+        assert!(is_valid(&file));
+
+        let markers: Vec<_> = segments(&file).unwrap().iter().map(|s| s.marker).collect();
+        assert_eq!(markers, vec![0xE0, APP15, 0xDB, SOS]);
+        assert_eq!(find(&file).unwrap().unwrap().payload, b"\xFF\xD9inside");
+
+        embed(&mut file, b"\0\0\0\0\0\0\0\0").unwrap();
+        assert!(is_valid(&file));
+        assert_eq!(segments(&file).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_foreign_app15_left_alone() {
+        let mut file = test_files::jpeg();
+        // Someone else's APP15 after APP0
+        let foreign = [0xFF, APP15, 0x00, 0x07, b'o', b't', b'h', b'e', b'r'];
+        file.splice(20..20, foreign);
+        assert!(find(&file).unwrap().is_none());
+
+        embed(&mut file, b"ours").unwrap();
+        assert_eq!(&file[20..29], &foreign);
+        assert_eq!(find(&file).unwrap().unwrap().payload, b"ours");
+    }
+}
@@ -0,0 +1,433 @@
+//! File-format-aware trailer backend.
+//!
+//! Instead of appending raw bytes after the end of file, embeds data in a
+//! place the host's format allows, so the host stays a valid file that
+//! viewers and validators open normally:
+//!
+//! - PNG: a private ancillary chunk just before `IEND`
+//! - JPEG: an `APP15` segment after the leading `APPn` segments
+//! - PDF: a hex-encoded comment in an incremental update after `%%EOF`,
+//!   which repeats the previous `startxref`
+//! - ZIP: the archive comment
+//!
+//! Hosts are recognised by their magic bytes while scanning; other files
+//! are skipped. The container is created with room for a fixed number of
+//! bytes on the first write and located again on every access, so its
+//! region's `offset` is informational only. Each access reads the whole
+//! host, which suits images and documents but not very large archives.
+//!
+//! The host grows by the container's size. Re-saving it in an editor
+//! usually drops private chunks, segments and comments, and with them the
+//! data.
+
+mod jpeg;
+mod pdf;
+pub(crate) mod png;
+#[cfg(test)]
+pub(crate) mod test_files;
+mod zip;
+
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, PersistenceRisk};
+use crate::storage::slack_backend::{walk_host_files, SlackBackend, SlackRegion};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Default payload bytes per host.
+pub const DEFAULT_CAPACITY: u64 = 4096;
+
+/// Bytes read to recognise a host's format.
+const MAGIC_LEN: usize = 8;
+
+/// Host formats with a place for hidden data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Png,
+    Jpeg,
+    Pdf,
+    Zip,
+}
+
+/// A container found in a host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Container {
+    /// Decoded payload.
+    payload: Vec<u8>,
+    /// Bytes the container takes in the file.
+    span: Range<usize>,
+}
+
+impl FileFormat {
+    /// Recognise a format from the first bytes of a file.
+    pub fn detect(head: &[u8]) -> Option<Self> {
+        if head.starts_with(png::SIGNATURE) {
+            Some(Self::Png)
+        } else if head.starts_with(jpeg::SOI) {
+            Some(Self::Jpeg)
+        } else if head.starts_with(pdf::HEADER) {
+            Some(Self::Pdf)
+        } else if zip::SIGNATURES.iter().any(|sig| head.starts_with(sig)) {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+
+    /// Largest payload the format's container can hold.
+    pub fn max_capacity(self) -> u64 {
+        match self {
+            Self::Png => png::MAX_PAYLOAD,
+            Self::Jpeg => jpeg::MAX_PAYLOAD,
+            Self::Pdf => pdf::MAX_PAYLOAD,
+            Self::Zip => zip::MAX_PAYLOAD,
+        }
+    }
+
+    /// Locate the container in a file.
+    fn find(self, file: &[u8]) -> Result<Option<Container>> {
+        match self {
+            Self::Png => png::find(file),
+            Self::Jpeg => jpeg::find(file),
+            Self::Pdf => pdf::find(file),
+            Self::Zip => zip::find(file),
+        }
+    }
+
+    /// Store `payload` in the file, replacing the container or inserting
+    /// one. Returns the offset from which the file changed.
+    fn embed(self, file: &mut Vec<u8>, payload: &[u8]) -> Result<usize> {
+        match self {
+            Self::Png => png::embed(file, payload),
+            Self::Jpeg => jpeg::embed(file, payload),
+            Self::Pdf => pdf::embed(file, payload),
+            Self::Zip => zip::embed(file, payload),
+        }
+    }
+}
+
+/// Trailer backend embedding data in format-legal places of host files.
+#[derive(Debug, Clone)]
+pub struct FormatTrailerBackend {
+    /// Payload bytes given to each new container.
+    capacity: u64,
+}
+
+impl FormatTrailerBackend {
+    pub const NAME: &'static str = "format trailers";
+
+    /// Create a backend giving each host up to `capacity` bytes, capped by
+    /// what its format allows.
+    pub fn new(capacity: u64) -> Self {
+        Self { capacity }
+    }
+
+    /// Read a host and recognise its format.
+    fn load(path: &Path) -> Result<(FileFormat, Vec<u8>)> {
+        let file = std::fs::read(path)?;
+        let format = FileFormat::detect(&file).ok_or_else(|| {
+            Error::Unsupported(format!(
+                "{} is not a PNG, JPEG, PDF or ZIP file",
+                path.display()
+            ))
+        })?;
+        Ok((format, file))
+    }
+
+    /// Check whether a file starts with a recognised magic.
+    fn has_known_magic(path: &Path) -> bool {
+        let mut head = Vec::with_capacity(MAGIC_LEN);
+        File::open(path)
+            .and_then(|f| f.take(MAGIC_LEN as u64).read_to_end(&mut head))
+            .is_ok_and(|_| FileFormat::detect(&head).is_some())
+    }
+}
+
+impl SlackBackend for FormatTrailerBackend {
+    /// Resolves the existing container, or checks that one can be inserted.
+    fn get_slack_info(&self, path: &Path) -> Result<SlackRegion> {
+        let (format, mut file) = Self::load(path)?;
+
+        let (offset, available, logical_size) = match format.find(&file)? {
+            Some(found) => (
+                found.span.start as u64,
+                found.payload.len() as u64,
+                (file.len() - found.span.len()) as u64,
+            ),
+            None => {
+                let available = self.capacity.min(format.max_capacity());
+                let logical_size = file.len() as u64;
+                // Dry run on the copy we already hold
+                let at = format.embed(&mut file, &vec![0u8; available as usize])?;
+                (at as u64, available, logical_size)
+            }
+        };
+
+        Ok(SlackRegion {
+            device_path: path.to_path_buf(),
+            offset,
+            available,
+            logical_size,
+            block_size: 1,
+        })
+    }
+
+    /// Walks `root` like the default, keeping files with a known magic.
+    fn enumerate_hosts(&self, root: &Path) -> Result<Vec<PathBuf>> {
+        Ok(walk_host_files(root)?
+            .into_iter()
+            .filter(|path| Self::has_known_magic(path))
+            .collect())
+    }
+
+    fn read_slack(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        region.check_bounds(offset, len)?;
+
+        let (format, file) = Self::load(&region.device_path)?;
+        let found = format.find(&file)?.ok_or_else(|| {
+            Error::DataCorruption(format!(
+                "No hidden container in {}",
+                region.device_path.display()
+            ))
+        })?;
+
+        let start = offset as usize;
+        found
+            .payload
+            .get(start..start + len)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| {
+                Error::DataCorruption(format!(
+                    "Hidden container in {} is truncated",
+                    region.device_path.display()
+                ))
+            })
+    }
+
+    /// Rewrites the host from the first changed byte on; the rest of the
+    /// file is left in place.
+    fn write_slack(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        region.check_bounds(offset, data.len())?;
+
+        let (format, mut file) = Self::load(&region.device_path)?;
+        let mut payload = match format.find(&file)? {
+            Some(found) => found.payload,
+            None => vec![0u8; region.available as usize],
+        };
+        if payload.len() as u64 != region.available {
+            return Err(Error::DataCorruption(format!(
+                "Hidden container in {} has changed size",
+                region.device_path.display()
+            )));
+        }
+
+        let start = offset as usize;
+        payload[start..start + data.len()].copy_from_slice(data);
+        let from = format.embed(&mut file, &payload)?;
+
+        let mut out = OpenOptions::new().write(true).open(&region.device_path)?;
+        out.seek(SeekFrom::Start(from as u64))?;
+        out.write_all(&file[from..])?;
+        out.set_len(file.len() as u64)?;
+        out.sync_all()?;
+        Ok(())
+    }
+
+    /// Zeroes the payload; the container itself stays.
+    fn wipe_slack(&self, region: &SlackRegion) -> Result<()> {
+        let zeros = vec![0u8; region.available as usize];
+        self.write_slack(region, 0, &zeros)
+    }
+
+    /// The host grows by the container, and editors re-saving it tend to
+    /// drop unknown chunks, segments and comments.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::Moderate,
+            changes_visible_metadata: true,
+            needs_root: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::HostManager;
+    use tempfile::TempDir;
+
+    /// Write one host of each format plus a plain text file.
+    fn host_dir() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("image.png"), test_files::png()).unwrap();
+        std::fs::write(dir.path().join("photo.jpg"), test_files::jpeg()).unwrap();
+        std::fs::write(dir.path().join("paper.pdf"), test_files::pdf()).unwrap();
+        std::fs::write(dir.path().join("bundle.zip"), test_files::zip()).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"plain text").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            FileFormat::detect(&test_files::png()),
+            Some(FileFormat::Png)
+        );
+        assert_eq!(
+            FileFormat::detect(&test_files::jpeg()),
+            Some(FileFormat::Jpeg)
+        );
+        assert_eq!(
+            FileFormat::detect(&test_files::pdf()),
+            Some(FileFormat::Pdf)
+        );
+        assert_eq!(
+            FileFormat::detect(&test_files::zip()),
+            Some(FileFormat::Zip)
+        );
+        assert_eq!(FileFormat::detect(b"plain text"), None);
+        assert_eq!(FileFormat::detect(b""), None);
+    }
+
+    #[test]
+    fn test_scan_detects_by_magic() {
+        let dir = host_dir();
+        // A PNG by content, whatever its name says
+        std::fs::write(dir.path().join("renamed.dat"), test_files::png()).unwrap();
+
+        let backend = FormatTrailerBackend::new(1000);
+        let manager = HostManager::scan_with_backend(dir.path(), 4096, &backend).unwrap();
+        let mut names: Vec<_> = manager
+            .hosts()
+            .iter()
+            .map(|h| h.path.file_name().unwrap().to_str().unwrap().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "bundle.zip",
+                "image.png",
+                "paper.pdf",
+                "photo.jpg",
+                "renamed.dat"
+            ]
+        );
+        assert_eq!(manager.total_capacity(), 5 * 1000);
+    }
+
+    #[test]
+    fn test_write_read_every_format() {
+        let dir = host_dir();
+        let backend = FormatTrailerBackend::new(300);
+
+        for name in ["image.png", "photo.jpg", "paper.pdf", "bundle.zip"] {
+            let path = dir.path().join(name);
+            let before = std::fs::read(&path).unwrap();
+
+            let region = backend.get_slack_info(&path).unwrap();
+            assert_eq!(region.available, 300, "{}", name);
+            assert_eq!(region.logical_size, before.len() as u64);
+
+            // Binary data, including line breaks for the PDF comment
+            let data: Vec<u8> = (0..=255).collect();
+            backend.write_slack(&region, 20, &data).unwrap();
+            backend.write_slack(&region, 0, b"head").unwrap();
+            assert_eq!(backend.read_slack(&region, 20, 256).unwrap(), data);
+            assert_eq!(backend.read_slack(&region, 0, 4).unwrap(), b"head");
+
+            // The region resolves the same way once the container exists
+            let again = backend.get_slack_info(&path).unwrap();
+            assert_eq!(again.available, 300);
+            assert_eq!(again.logical_size, before.len() as u64);
+            assert!(std::fs::metadata(&path).unwrap().len() > before.len() as u64);
+
+            backend.wipe_slack(&again).unwrap();
+            assert_eq!(backend.read_slack(&again, 0, 300).unwrap(), vec![0u8; 300]);
+        }
+    }
+
+    #[test]
+    fn test_capacity_capped_by_format() {
+        let dir = host_dir();
+        let backend = FormatTrailerBackend::new(1 << 20);
+
+        let jpeg = backend
+            .get_slack_info(&dir.path().join("photo.jpg"))
+            .unwrap();
+        assert_eq!(jpeg.available, jpeg::MAX_PAYLOAD);
+        let zip = backend
+            .get_slack_info(&dir.path().join("bundle.zip"))
+            .unwrap();
+        assert_eq!(zip.available, zip::MAX_PAYLOAD);
+        let png = backend
+            .get_slack_info(&dir.path().join("image.png"))
+            .unwrap();
+        assert_eq!(png.available, 1 << 20);
+
+        let result = backend.read_slack(&png, 0, 1);
+        assert!(matches!(result, Err(Error::DataCorruption(_))));
+        let result = backend.write_slack(&png, png.available, b"x");
+        assert!(matches!(result, Err(Error::InsufficientSpace { .. })));
+    }
+
+    #[test]
+    fn test_unknown_format_rejected() {
+        let dir = host_dir();
+        let backend = FormatTrailerBackend::new(100);
+        let result = backend.get_slack_info(&dir.path().join("notes.txt"));
+        assert!(matches!(result, Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_vfs_on_format_trailers() {
+        use crate::{SlackVfs, VfsConfig};
+
+        let dir = host_dir();
+        let config = VfsConfig {
+            symbol_size: 256,
+            ..VfsConfig::default()
+        };
+
+        {
+            let backend = Box::new(FormatTrailerBackend::new(DEFAULT_CAPACITY));
+            let mut vfs = SlackVfs::create_with_backend(dir.path(), "pw", config, backend).unwrap();
+            vfs.create_file("/secret.txt", b"kept inside valid files")
+                .unwrap();
+        }
+
+        let backend = Box::new(FormatTrailerBackend::new(DEFAULT_CAPACITY));
+        let vfs = SlackVfs::mount_with_backend(dir.path(), "pw", backend).unwrap();
+        assert_eq!(
+            vfs.read_file("/secret.txt").unwrap(),
+            b"kept inside valid files"
+        );
+        assert_eq!(vfs.info().host_count, 4);
+
+        assert!(png::is_valid(
+            &std::fs::read(dir.path().join("image.png")).unwrap()
+        ));
+        assert!(jpeg::is_valid(
+            &std::fs::read(dir.path().join("photo.jpg")).unwrap()
+        ));
+        assert!(pdf::is_valid(
+            &std::fs::read(dir.path().join("paper.pdf")).unwrap()
+        ));
+        assert!(zip::is_valid(
+            &std::fs::read(dir.path().join("bundle.zip")).unwrap()
+        ));
+        assert_eq!(
+            std::fs::read(dir.path().join("notes.txt")).unwrap(),
+            b"plain text"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! PDF container: a comment in an incremental update.
+//!
+//! The update appended after `%%EOF` holds only a comment line and repeats
+//! the previous `startxref`, so readers still find the same cross-reference
+//! table. Comments end at a line break, so the payload is hex-encoded.
+
+use super::Container;
+use crate::error::{Error, Result};
+
+/// PDF header.
+pub(super) const HEADER: &[u8] = b"%PDF-";
+
+/// Start of the hidden comment line.
+const MARKER: &[u8] = b"%SVFS ";
+const STARTXREF: &[u8] = b"startxref";
+const EOF_MARKER: &[u8] = b"%%EOF";
+
+/// Comments have no length limit; this keeps the hex line to a size
+/// readers skip without trouble.
+pub(super) const MAX_PAYLOAD: u64 = 1 << 24;
+
+/// Find the last occurrence of `needle` in `haystack`.
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
+
+/// Find the hidden comment line: its start and the offset of its line feed.
+fn comment_line(file: &[u8]) -> Option<(usize, usize)> {
+    let mut end = file.len();
+    while let Some(start) = rfind(&file[..end], MARKER) {
+        // Only a marker at the start of a line counts
+        if start > 0 && matches!(file[start - 1], b'\n' | b'\r') {
+            let newline = file[start..].iter().position(|&b| b == b'\n')?;
+            return Some((start, start + newline));
+        }
+        end = start;
+    }
+    None
+}
+
+/// Locate the hidden comment and the update it belongs to.
+pub(super) fn find(file: &[u8]) -> Result<Option<Container>> {
+    let Some((start, newline)) = comment_line(file) else {
+        return Ok(None);
+    };
+
+    let payload = hex::decode(&file[start + MARKER.len()..newline])
+        .map_err(|e| Error::DataCorruption(format!("Bad hidden PDF comment: {}", e)))?;
+
+    // The update runs to its own %%EOF and line break
+    let rest = &file[newline..];
+    let eof = rest
+        .windows(EOF_MARKER.len())
+        .position(|w| w == EOF_MARKER)
+        .ok_or_else(|| Error::DataCorruption("Hidden PDF update has no %%EOF".to_string()))?;
+    let mut end = newline + eof + EOF_MARKER.len();
+    if file.get(end) == Some(&b'\n') {
+        end += 1;
+    }
+
+    Ok(Some(Container {
+        payload,
+        span: start..end,
+    }))
+}
+
+/// Read the offset the last `startxref` points to.
+fn last_startxref(file: &[u8]) -> Result<u64> {
+    let at = rfind(file, STARTXREF)
+        .ok_or_else(|| Error::Unsupported("PDF has no startxref".to_string()))?;
+    let rest = &file[at + STARTXREF.len()..];
+    let digits: Vec<u8> = rest
+        .iter()
+        .skip_while(|b| b.is_ascii_whitespace())
+        .take_while(|b| b.is_ascii_digit())
+        .copied()
+        .collect();
+    if rfind(rest, EOF_MARKER).is_none() {
+        return Err(Error::Unsupported(
+            "PDF does not end with %%EOF".to_string(),
+        ));
+    }
+    std::str::from_utf8(&digits)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| Error::Unsupported("PDF startxref has no offset".to_string()))
+}
+
+/// Rewrite the hidden comment, or append an update holding it.
+pub(super) fn embed(file: &mut Vec<u8>, payload: &[u8]) -> Result<usize> {
+    if payload.len() as u64 > MAX_PAYLOAD {
+        return Err(Error::InsufficientSpace {
+            needed: payload.len() as u64,
+            available: MAX_PAYLOAD,
+        });
+    }
+
+    let mut line = MARKER.to_vec();
+    line.extend_from_slice(hex::encode(payload).as_bytes());
+
+    if let Some((start, newline)) = comment_line(file) {
+        file.splice(start..newline, line);
+        return Ok(start);
+    }
+
+    let xref = last_startxref(file)?;
+    let start = file.len();
+    if !matches!(file.last(), Some(b'\n' | b'\r')) {
+        file.push(b'\n');
+    }
+    file.extend_from_slice(&line);
+    file.extend_from_slice(format!("\nstartxref\n{}\n%%EOF\n", xref).as_bytes());
+    Ok(start)
+}
+
+/// Check that the file ends with `%%EOF` and its last `startxref` points
+/// at a cross-reference table.
+#[cfg(test)]
+pub(super) fn is_valid(file: &[u8]) -> bool {
+    let trimmed = file.trim_ascii_end();
+    trimmed.ends_with(EOF_MARKER)
+        && last_startxref(file).is_ok_and(|xref| {
+            file.get(xref as usize..)
+                .is_some_and(|t| t.starts_with(b"xref"))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::format::test_files;
+
+    #[test]
+    fn test_update_appended() {
+        let mut file = test_files::pdf();
+        let original = file.clone();
+        assert!(is_valid(&file));
+        assert!(find(&file).unwrap().is_none());
+
+        let at = embed(&mut file, b"line\nbreak\r").unwrap();
+        assert_eq!(at, original.len());
+        assert!(file.starts_with(&original));
+        assert!(is_valid(&file));
+        assert_eq!(
+            last_startxref(&file).unwrap(),
+            last_startxref(&original).unwrap()
+        );
+
+        let found = find(&file).unwrap().unwrap();
+        assert_eq!(found.payload, b"line\nbreak\r");
+        assert_eq!(found.span, original.len()..file.len());
+
+        // Rewriting touches the comment line only
+        let len = file.len();
+        embed(&mut file, b"LINE\nBREAK\r").unwrap();
+        assert_eq!(file.len(), len);
+        assert_eq!(find(&file).unwrap().unwrap().payload, b"LINE\nBREAK\r");
+        assert!(is_valid(&file));
+    }
+
+    #[test]
+    fn test_pdf_without_startxref_rejected() {
+        let mut file = b"%PDF-1.4\n1 0 obj << >> endobj\n".to_vec();
+        assert!(matches!(embed(&mut file, b"x"), Err(Error::Unsupported(_))));
+    }
+}
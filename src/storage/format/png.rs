@@ -0,0 +1,163 @@
+//! PNG container: a private ancillary chunk before `IEND`.
+//!
+//! The chunk type's case bits mark it ancillary, private and safe to copy,
+//! so decoders skip it and editors that keep unknown chunks carry it along.
+
+use super::Container;
+use crate::error::{Error, Result};
+use crate::storage::partition::crc32;
+
+/// PNG file signature.
+pub(crate) const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Type of the hidden chunk: ancillary, private, reserved bit clear, safe
+/// to copy.
+const CHUNK_TYPE: &[u8; 4] = b"prVw";
+
+/// Chunk length field limit (2^31 - 1).
+pub(super) const MAX_PAYLOAD: u64 = 0x7FFF_FFFF;
+
+/// A chunk's position in the file.
+pub(crate) struct Chunk {
+    /// Offset of the length field.
+    pub start: usize,
+    pub kind: [u8; 4],
+    pub data_len: usize,
+}
+
+impl Chunk {
+    /// Offset of the chunk's data.
+    pub fn data_start(&self) -> usize {
+        self.start + 8
+    }
+
+    /// Offset just past the CRC.
+    pub fn end(&self) -> usize {
+        self.start + 12 + self.data_len
+    }
+}
+
+/// List the chunks up to and including `IEND`.
+pub(crate) fn chunks(file: &[u8]) -> Result<Vec<Chunk>> {
+    if !file.starts_with(SIGNATURE) {
+        return Err(Error::DataCorruption("Missing PNG signature".to_string()));
+    }
+
+    let mut chunks = Vec::new();
+    let mut pos = SIGNATURE.len();
+    loop {
+        let header = file
+            .get(pos..pos + 8)
+            .ok_or_else(|| Error::DataCorruption("PNG ends before IEND".to_string()))?;
+        let data_len = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+        let chunk = Chunk {
+            start: pos,
+            kind: header[4..8].try_into().unwrap(),
+            data_len,
+        };
+        if chunk.end() > file.len() {
+            return Err(Error::DataCorruption(format!(
+                "PNG chunk at byte {} runs past the end of file",
+                pos
+            )));
+        }
+
+        pos = chunk.end();
+        let last = &chunk.kind == b"IEND";
+        chunks.push(chunk);
+        if last {
+            return Ok(chunks);
+        }
+    }
+}
+
+/// Locate the hidden chunk.
+pub(super) fn find(file: &[u8]) -> Result<Option<Container>> {
+    Ok(chunks(file)?
+        .into_iter()
+        .find(|c| &c.kind == CHUNK_TYPE)
+        .map(|c| Container {
+            payload: file[c.data_start()..c.data_start() + c.data_len].to_vec(),
+            span: c.start..c.end(),
+        }))
+}
+
+/// Replace the hidden chunk, or insert it just before `IEND`.
+pub(super) fn embed(file: &mut Vec<u8>, payload: &[u8]) -> Result<usize> {
+    if payload.len() as u64 > MAX_PAYLOAD {
+        return Err(Error::InsufficientSpace {
+            needed: payload.len() as u64,
+            available: MAX_PAYLOAD,
+        });
+    }
+
+    let chunks = chunks(file)?;
+    let iend = chunks.last().expect("chunks end with IEND").start;
+    let replace = match chunks.iter().find(|c| &c.kind == CHUNK_TYPE) {
+        Some(existing) => existing.start..existing.end(),
+        None => iend..iend,
+    };
+
+    let mut chunk = Vec::with_capacity(payload.len() + 12);
+    chunk.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(CHUNK_TYPE);
+    chunk.extend_from_slice(payload);
+    let crc = crc32(&chunk[4..]);
+    chunk.extend_from_slice(&crc.to_be_bytes());
+
+    let start = replace.start;
+    file.splice(replace, chunk);
+    Ok(start)
+}
+
+/// Check the chunk structure and every CRC, with `IEND` last.
+#[cfg(test)]
+pub(crate) fn is_valid(file: &[u8]) -> bool {
+    let Ok(chunks) = chunks(file) else {
+        return false;
+    };
+    chunks.last().is_some_and(|c| c.end() == file.len())
+        && chunks.iter().all(|c| {
+            let body = &file[c.start + 4..c.start + 8 + c.data_len];
+            let stored = &file[c.start + 8 + c.data_len..c.end()];
+            crc32(body).to_be_bytes() == stored
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::format::test_files;
+
+    #[test]
+    fn test_chunk_inserted_before_iend() {
+        let mut file = test_files::png();
+        let original = file.clone();
+        assert!(is_valid(&file));
+        assert!(find(&file).unwrap().is_none());
+
+        let at = embed(&mut file, b"payload").unwrap();
+        assert_eq!(at, original.len() - 12);
+        assert!(is_valid(&file));
+
+        let kinds: Vec<_> = chunks(&file).unwrap().iter().map(|c| c.kind).collect();
+        assert_eq!(kinds, vec![*b"IHDR", *b"IDAT", *CHUNK_TYPE, *b"IEND"]);
+
+        let found = find(&file).unwrap().unwrap();
+        assert_eq!(found.payload, b"payload");
+        assert_eq!(found.span.len(), 12 + 7);
+
+        // Replacing keeps a single chunk
+        embed(&mut file, b"PAYLOAD").unwrap();
+        assert!(is_valid(&file));
+        assert_eq!(find(&file).unwrap().unwrap().payload, b"PAYLOAD");
+        assert_eq!(file.len(), original.len() + 19);
+    }
+
+    #[test]
+    fn test_truncated_png_rejected() {
+        let file = test_files::png();
+        assert!(find(&file[..file.len() - 12]).is_err());
+        assert!(find(&file[..20]).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+//! Minimal well-formed host files for unit tests.
+
+use crate::storage::partition::crc32;
+
+/// Build a PNG chunk.
+fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    let crc = crc32(&chunk[4..]);
+    chunk.extend_from_slice(&crc.to_be_bytes());
+    chunk
+}
+
+/// A 1x1 grayscale PNG.
+pub(crate) fn png() -> Vec<u8> {
+    let mut file = b"\x89PNG\r\n\x1a\n".to_vec();
+    // Width 1, height 1, 8-bit grayscale
+    file.extend(png_chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]));
+    // One scanline (filter byte, pixel) in a stored deflate block
+    file.extend(png_chunk(
+        b"IDAT",
+        &[
+            0x78, 0x01, 0x01, 0x02, 0x00, 0xFD, 0xFF, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01,
+        ],
+    ));
+    file.extend(png_chunk(b"IEND", &[]));
+    file
+}
+
+/// A JPEG with a JFIF header, one quantisation table and a scan.
+pub(crate) fn jpeg() -> Vec<u8> {
+    let mut file = vec![0xFF, 0xD8];
+    // APP0 JFIF, length 16
+    file.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x10]);
+    file.extend_from_slice(b"JFIF\0\x01\x01\x00\x00\x01\x00\x01\x00\x00");
+    // DQT
+    file.extend_from_slice(&[0xFF, 0xDB, 0x00, 0x43, 0x00]);
+    file.extend_from_slice(&[1u8; 64]);
+    // SOS for one component, then entropy-coded bytes
+    file.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3F, 0x00]);
+    file.extend_from_slice(&[0x12, 0x34, 0xFF, 0x00, 0x56]);
+    file.extend_from_slice(&[0xFF, 0xD9]);
+    file
+}
+
+/// A one-page PDF with a cross-reference table.
+pub(crate) fn pdf() -> Vec<u8> {
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>",
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 100 100] >>",
+    ];
+
+    let mut file = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(file.len());
+        file.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+    }
+
+    let xref = file.len();
+    file.extend_from_slice(
+        format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+    );
+    for offset in offsets {
+        file.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    file.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        )
+        .as_bytes(),
+    );
+    file
+}
+
+/// A ZIP archive holding one stored file.
+pub(crate) fn zip() -> Vec<u8> {
+    let name = b"hello.txt";
+    let data = b"hello, world\n";
+    let crc = crc32(data).to_le_bytes();
+    let size = (data.len() as u32).to_le_bytes();
+    let name_len = (name.len() as u16).to_le_bytes();
+
+    // Local file header
+    let mut file = b"PK\x03\x04".to_vec();
+    file.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+    file.extend_from_slice(&crc);
+    file.extend_from_slice(&size);
+    file.extend_from_slice(&size);
+    file.extend_from_slice(&name_len);
+    file.extend_from_slice(&[0, 0]);
+    file.extend_from_slice(name);
+    file.extend_from_slice(data);
+
+    // Central directory
+    let central = file.len();
+    file.extend_from_slice(b"PK\x01\x02");
+    file.extend_from_slice(&[20, 3, 20, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+    file.extend_from_slice(&crc);
+    file.extend_from_slice(&size);
+    file.extend_from_slice(&size);
+    file.extend_from_slice(&name_len);
+    // Extra, comment, disk, internal and external attributes
+    file.extend_from_slice(&[0; 12]);
+    // Local header offset
+    file.extend_from_slice(&[0; 4]);
+    file.extend_from_slice(name);
+    let central_len = (file.len() - central) as u32;
+
+    // End of central directory, no comment
+    file.extend_from_slice(b"PK\x05\x06");
+    file.extend_from_slice(&[0, 0, 0, 0, 1, 0, 1, 0]);
+    file.extend_from_slice(&central_len.to_le_bytes());
+    file.extend_from_slice(&(central as u32).to_le_bytes());
+    file.extend_from_slice(&[0, 0]);
+    file
+}
@@ -0,0 +1,128 @@
+//! ZIP container: the archive comment.
+//!
+//! The comment closes the end of central directory record, so setting it
+//! moves no entry and leaves every offset in the archive valid. Its text
+//! starts with an identifier so a comment someone else wrote is never
+//! overwritten.
+
+use super::Container;
+use crate::error::{Error, Result};
+
+/// Local file header and empty-archive signatures.
+pub(super) const SIGNATURES: [&[u8]; 2] = [b"PK\x03\x04", b"PK\x05\x06"];
+
+/// End of central directory signature.
+const EOCD_SIGNATURE: &[u8] = b"PK\x05\x06";
+/// End of central directory record size without the comment.
+const EOCD_LEN: usize = 22;
+
+/// Identifier at the start of the comment.
+const IDENTIFIER: &[u8] = b"SVFS";
+
+/// Comment length field limit, minus the identifier.
+pub(super) const MAX_PAYLOAD: u64 = 0xFFFF - IDENTIFIER.len() as u64;
+
+/// Find the end of central directory record whose comment runs exactly to
+/// the end of the file.
+fn eocd(file: &[u8]) -> Result<usize> {
+    let earliest = file.len().saturating_sub(EOCD_LEN + 0xFFFF);
+    (earliest..=file.len().saturating_sub(EOCD_LEN))
+        .rev()
+        .find(|&at| {
+            file[at..].starts_with(EOCD_SIGNATURE) && {
+                let comment_len = u16::from_le_bytes([file[at + 20], file[at + 21]]) as usize;
+                at + EOCD_LEN + comment_len == file.len()
+            }
+        })
+        .ok_or_else(|| Error::DataCorruption("ZIP has no end of central directory".to_string()))
+}
+
+/// Locate the hidden comment.
+pub(super) fn find(file: &[u8]) -> Result<Option<Container>> {
+    let at = eocd(file)?;
+    let comment = &file[at + EOCD_LEN..];
+    if !comment.starts_with(IDENTIFIER) {
+        return Ok(None);
+    }
+    Ok(Some(Container {
+        payload: comment[IDENTIFIER.len()..].to_vec(),
+        span: at + EOCD_LEN..file.len(),
+    }))
+}
+
+/// Set the comment, unless the archive already carries a foreign one.
+pub(super) fn embed(file: &mut Vec<u8>, payload: &[u8]) -> Result<usize> {
+    if payload.len() as u64 > MAX_PAYLOAD {
+        return Err(Error::InsufficientSpace {
+            needed: payload.len() as u64,
+            available: MAX_PAYLOAD,
+        });
+    }
+
+    let at = eocd(file)?;
+    let comment = &file[at + EOCD_LEN..];
+    if !comment.is_empty() && !comment.starts_with(IDENTIFIER) {
+        return Err(Error::Unsupported(
+            "ZIP archive already has a comment".to_string(),
+        ));
+    }
+
+    let len = (IDENTIFIER.len() + payload.len()) as u16;
+    file.truncate(at + 20);
+    file.extend_from_slice(&len.to_le_bytes());
+    file.extend_from_slice(IDENTIFIER);
+    file.extend_from_slice(payload);
+    Ok(at + 20)
+}
+
+/// Check the end of central directory record and that the central
+/// directory it points to starts with a file header.
+#[cfg(test)]
+pub(super) fn is_valid(file: &[u8]) -> bool {
+    let Ok(at) = eocd(file) else {
+        return false;
+    };
+    let entries = u16::from_le_bytes([file[at + 10], file[at + 11]]);
+    let size = u32::from_le_bytes(file[at + 12..at + 16].try_into().unwrap()) as usize;
+    let offset = u32::from_le_bytes(file[at + 16..at + 20].try_into().unwrap()) as usize;
+    offset + size == at && (entries == 0 || file[offset..].starts_with(b"PK\x01\x02"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::format::test_files;
+
+    #[test]
+    fn test_comment_set_in_place() {
+        let mut file = test_files::zip();
+        let original = file.clone();
+        assert!(is_valid(&file));
+        assert!(find(&file).unwrap().is_none());
+
+        // Bytes that look like another end of central directory record
+        let at = embed(&mut file, b"PK\x05\x06 inside").unwrap();
+        assert_eq!(at, original.len() - 2);
+        assert!(is_valid(&file));
+        assert_eq!(&file[..at], &original[..at]);
+        assert_eq!(find(&file).unwrap().unwrap().payload, b"PK\x05\x06 inside");
+
+        embed(&mut file, b"").unwrap();
+        assert!(is_valid(&file));
+        assert_eq!(find(&file).unwrap().unwrap().payload, b"");
+    }
+
+    #[test]
+    fn test_foreign_comment_kept() {
+        let mut file = test_files::zip();
+        let len = file.len();
+        file[len - 2..].copy_from_slice(&5u16.to_le_bytes());
+        file.extend_from_slice(b"hello");
+        assert!(is_valid(&file));
+        assert!(find(&file).unwrap().is_none());
+
+        let result = embed(&mut file, b"x");
+        assert!(matches!(result, Err(Error::Unsupported(_))));
+        assert!(file.ends_with(b"hello"));
+    }
+}
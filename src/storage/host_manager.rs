@@ -1,80 +1,150 @@
 //! Host file manager for tracking and allocating slack space.
+//!
+//! Every region a carrier offers is tracked on its own, so hosts with many
+//! regions and hosts with one are allocated from the same way.
 
+use crate::config::{FailureDomain, FitStrategy, PlacementPolicy};
 use crate::error::{Error, Result};
-use crate::storage::slack::get_slack_capacity;
+use crate::storage::carrier::{Capabilities, Carrier};
+use crate::storage::extents::FreeExtents;
+use crate::storage::file_backend::FileSlackBackend;
+use crate::storage::host_policy::HostPolicy;
+use crate::storage::slack_backend::{SlackBackend, SlackRegion};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
 
 /// Location of a stored symbol in slack space.
+///
+/// Addresses are backend-neutral: `offset` is relative to the start of one
+/// of the host's slack regions, whichever backend resolves that region.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SymbolLocation {
     /// Path to the host file.
     pub host_path: PathBuf,
-    /// Offset within the slack space (relative to logical_size).
+    /// Index of the region in the carrier's list for the host.
+    #[serde(default)]
+    pub region_index: u32,
+    /// Offset within the region.
     pub offset: u64,
     /// RaptorQ symbol ID.
     pub symbol_id: u32,
     /// Length of the symbol data.
     pub length: u32,
+    /// Logical size of the host when the symbol was written, used to find
+    /// the region again after file-append writes grew the host.
+    #[serde(default)]
+    pub logical_size: u64,
+}
+
+/// Identifies the failure domain a host file belongs to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DomainKey {
+    /// The host file itself, or the directory holding it.
+    Path(PathBuf),
+    /// The device the host file lives on.
+    Device(u64),
+}
+
+impl DomainKey {
+    /// Get the failure domain of kind `domain` holding `path`.
+    ///
+    /// Hosts whose device cannot be read, such as files inside an image
+    /// or hosts that are gone, all count as one device.
+    pub fn of(path: &Path, domain: FailureDomain) -> Self {
+        match domain {
+            FailureDomain::Host => DomainKey::Path(path.to_path_buf()),
+            FailureDomain::Directory => {
+                DomainKey::Path(path.parent().unwrap_or(path).to_path_buf())
+            }
+            FailureDomain::Device => DomainKey::Device(device_id(path).unwrap_or(0)),
+        }
+    }
+}
+
+/// Get the ID of the device holding a file.
+#[cfg(unix)]
+fn device_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).ok().map(|m| m.dev())
+}
+
+/// Get the ID of the device holding a file.
+#[cfg(not(unix))]
+fn device_id(_path: &Path) -> Option<u64> {
+    None
 }
 
-/// Information about a single host file.
+/// Information about a single slack region of a host file.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HostFile {
     /// Path to the file.
     pub path: PathBuf,
+    /// Index of the region in the carrier's list for the host.
+    pub region_index: u32,
     /// Original logical size of the file.
     pub logical_size: u64,
     /// Total slack capacity available.
     pub slack_capacity: u64,
-    /// Amount of slack space currently used.
-    pub used_slack: u64,
+    /// Parts of the slack not holding symbols.
+    pub free: FreeExtents,
+    /// Slack region as resolved by the storage backend.
+    pub region: SlackRegion,
+    /// What writing to the region implies.
+    pub capabilities: Capabilities,
 }
 
 impl HostFile {
-    /// Create a new HostFile from a path.
+    /// Create a new HostFile from a path, using file-append slack.
     pub fn new(path: PathBuf, block_size: u64) -> Result<Self> {
-        let metadata = std::fs::metadata(&path)?;
-        let logical_size = metadata.len();
-        let slack_capacity = if logical_size == 0 {
-            0
-        } else {
-            get_slack_capacity(&path, block_size)?
-        };
+        let backend = FileSlackBackend::new(block_size);
+        let region = backend.get_slack_info(&path)?;
+        let capabilities = SlackBackend::capabilities(&backend);
+        Ok(Self::from_region(path, 0, region, capabilities))
+    }
 
-        Ok(Self {
+    /// Create a HostFile from a slack region resolved by a carrier.
+    pub fn from_region(
+        path: PathBuf,
+        region_index: u32,
+        region: SlackRegion,
+        capabilities: Capabilities,
+    ) -> Self {
+        Self {
             path,
-            logical_size,
-            slack_capacity,
-            used_slack: 0,
-        })
+            region_index,
+            logical_size: region.logical_size,
+            slack_capacity: region.available,
+            free: FreeExtents::new(region.available),
+            region,
+            capabilities,
+        }
     }
 
     /// Get available slack space.
     pub fn available(&self) -> u64 {
-        self.slack_capacity.saturating_sub(self.used_slack)
+        self.free.total()
     }
 
-    /// Check if this host can accommodate data of given size.
+    /// Get used slack space.
+    pub fn used(&self) -> u64 {
+        self.slack_capacity - self.available()
+    }
+
+    /// Check if this host can accommodate data of given size in one piece.
     pub fn can_fit(&self, size: u64) -> bool {
-        self.available() >= size
+        self.free.largest() >= size
     }
 
-    /// Allocate space for a symbol.
-    pub fn allocate(&mut self, size: u64) -> Option<u64> {
-        if self.can_fit(size) {
-            let offset = self.used_slack;
-            self.used_slack += size;
-            Some(offset)
-        } else {
-            None
-        }
+    /// Allocate space for a symbol from the free extent `fit` picks.
+    pub fn allocate(&mut self, size: u64, fit: FitStrategy) -> Option<u64> {
+        self.free.allocate(size, fit)
     }
 
-    /// Get the write position for a given offset within slack space.
+    /// Get the absolute position in the region's device for a given offset
+    /// within slack space.
     pub fn get_write_position(&self, offset: u64) -> u64 {
-        self.logical_size + offset
+        self.region.offset + offset
     }
 }
 
@@ -83,17 +153,46 @@ impl HostFile {
 pub struct HostManager {
     /// Root directory containing host files.
     root_dir: PathBuf,
-    /// All tracked host files.
+    /// All tracked regions, grouped by host in scan order.
     hosts: Vec<HostFile>,
     /// Block size for slack calculation.
     block_size: u64,
+    /// Host, by position in scan order, where round-robin placement
+    /// carries on.
+    next_host: usize,
+    /// How free extents are picked within a host.
+    fit: FitStrategy,
 }
 
 impl HostManager {
-    /// Scan a directory for files that can be used as hosts.
+    /// Scan a directory for files that can be used as hosts, using
+    /// file-append slack.
     ///
     /// Skips hidden files, the metadata file, and empty files.
     pub fn scan(root: &Path, block_size: u64) -> Result<Self> {
+        Self::scan_with_backend(root, block_size, &FileSlackBackend::new(block_size))
+    }
+
+    /// Scan for host files using the given carrier to enumerate candidates
+    /// and resolve their slack regions.
+    ///
+    /// Files whose regions cannot be resolved are skipped, as are regions
+    /// without space.
+    pub fn scan_with_backend(root: &Path, block_size: u64, backend: &dyn Carrier) -> Result<Self> {
+        Self::scan_with_policy(root, block_size, backend, &HostPolicy::default())
+    }
+
+    /// Scan for host files, taking only those the policy allows.
+    ///
+    /// Sizes and modification times come from the file system when the
+    /// candidate is a real file under `root`; otherwise the size is the
+    /// logical size of its first region and the age rule is skipped.
+    pub fn scan_with_policy(
+        root: &Path,
+        block_size: u64,
+        backend: &dyn Carrier,
+        policy: &HostPolicy,
+    ) -> Result<Self> {
         if !root.exists() {
             return Err(Error::Io(std::io::Error::new(
                 std::io::ErrorKind::NotFound,
@@ -103,38 +202,34 @@ impl HostManager {
 
         let mut hosts = Vec::new();
 
-        for entry in WalkDir::new(root)
-            .min_depth(1)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            let path = entry.path();
-
-            // Skip directories
-            if path.is_dir() {
+        for path in backend.enumerate_hosts(root)? {
+            let Ok(regions) = backend.regions(&path) else {
                 continue;
-            }
-
-            // Skip hidden files and our metadata file
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name.starts_with('.') {
+            };
+            if !policy.is_unrestricted() {
+                let stat = path
+                    .strip_prefix(root)
+                    .ok()
+                    .and_then(|_| std::fs::metadata(&path).ok());
+                let size = match &stat {
+                    Some(stat) => stat.len(),
+                    None => regions.first().map_or(0, |r| r.logical_size),
+                };
+                let modified = stat.and_then(|stat| stat.modified().ok());
+                let relative = path.strip_prefix(root).unwrap_or(&path);
+                if !policy.allows(relative, size, modified) {
                     continue;
                 }
             }
-
-            // Try to create a host file
-            if let Ok(host) = HostFile::new(path.to_path_buf(), block_size) {
-                // Only include files with slack space
-                if host.slack_capacity > 0 {
-                    hosts.push(host);
-                }
-            }
+            Self::push_regions(&mut hosts, &path, regions, backend);
         }
 
         Ok(Self {
             root_dir: root.to_path_buf(),
             hosts,
             block_size,
+            next_host: 0,
+            fit: FitStrategy::default(),
         })
     }
 
@@ -148,7 +243,12 @@ impl HostManager {
         self.block_size
     }
 
-    /// Get all host files.
+    /// Set how free extents are picked within a host.
+    pub fn set_fit_strategy(&mut self, fit: FitStrategy) {
+        self.fit = fit;
+    }
+
+    /// Get all tracked regions of all host files.
     pub fn hosts(&self) -> &[HostFile] {
         &self.hosts
     }
@@ -170,34 +270,61 @@ impl HostManager {
 
     /// Get total used slack space.
     pub fn total_used(&self) -> u64 {
-        self.hosts.iter().map(|h| h.used_slack).sum()
+        self.hosts.iter().map(|h| h.used()).sum()
     }
 
     /// Get number of host files.
     pub fn host_count(&self) -> usize {
+        self.hosts
+            .iter()
+            .map(|h| &h.path)
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    /// Get number of regions across all host files.
+    pub fn region_count(&self) -> usize {
         self.hosts.len()
     }
 
-    /// Get a host file by path.
-    pub fn get_host(&self, path: &Path) -> Option<&HostFile> {
-        self.hosts.iter().find(|h| h.path == path)
+    /// Get the regions tracked for a host file.
+    pub fn host_regions<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = &'a HostFile> {
+        self.hosts.iter().filter(move |h| h.path == path)
+    }
+
+    /// Get one region of a host file.
+    pub fn get_region(&self, path: &Path, region_index: u32) -> Option<&HostFile> {
+        self.hosts
+            .iter()
+            .find(|h| h.path == path && h.region_index == region_index)
     }
 
-    /// Get a mutable host file by path.
-    pub fn get_host_mut(&mut self, path: &Path) -> Option<&mut HostFile> {
-        self.hosts.iter_mut().find(|h| h.path == path)
+    /// Get one region of a host file mutably.
+    pub fn get_region_mut(&mut self, path: &Path, region_index: u32) -> Option<&mut HostFile> {
+        self.hosts
+            .iter_mut()
+            .find(|h| h.path == path && h.region_index == region_index)
     }
 
-    /// Allocate space for symbols of given size.
+    /// Allocate space for the symbols of one encoded object.
     ///
-    /// Returns locations for each symbol, distributed across hosts.
+    /// `policy` decides which host each symbol goes to, and no failure
+    /// domain of kind `domain` receives more than `max_per_domain` of them,
+    /// so losing any one domain costs the object at most that many. Hosts
+    /// that would break the cap are passed over for others. Within a host,
+    /// regions at the lowest persistence risk fill first, then in order.
+    /// Nothing is allocated unless every symbol can be placed.
     pub fn allocate(
         &mut self,
         symbol_count: usize,
         symbol_size: usize,
         start_symbol_id: u32,
+        policy: PlacementPolicy,
+        domain: FailureDomain,
+        max_per_domain: usize,
     ) -> Result<Vec<SymbolLocation>> {
-        let total_needed = symbol_count as u64 * symbol_size as u64;
+        let size = symbol_size as u64;
+        let total_needed = symbol_count as u64 * size;
         let available = self.total_available();
 
         if total_needed > available {
@@ -207,49 +334,240 @@ impl HostManager {
             });
         }
 
-        let mut locations = Vec::with_capacity(symbol_count);
-        let mut symbol_id = start_symbol_id;
-        let mut remaining = symbol_count;
-
-        // Distribute symbols across hosts
-        for host in &mut self.hosts {
-            while remaining > 0 && host.can_fit(symbol_size as u64) {
-                if let Some(offset) = host.allocate(symbol_size as u64) {
-                    locations.push(SymbolLocation {
-                        host_path: host.path.clone(),
-                        offset,
-                        symbol_id,
-                        length: symbol_size as u32,
-                    });
-                    symbol_id += 1;
-                    remaining -= 1;
-                } else {
-                    break;
-                }
+        // Whole symbols each host has room for, hosts in scan order
+        let mut room: Vec<(PathBuf, usize)> = Vec::new();
+        for host in &self.hosts {
+            let fits = host.free.slots(size) as usize;
+            match room.last_mut() {
+                Some((path, slots)) if *path == host.path => *slots += fits,
+                _ => room.push((host.path.clone(), fits)),
             }
         }
 
-        if locations.len() < symbol_count {
+        // The domain of each host, as an index into `domains`
+        let mut domains: Vec<DomainKey> = Vec::new();
+        let mut domain_room: Vec<usize> = Vec::new();
+        let mut host_domain = Vec::with_capacity(room.len());
+        for (path, slots) in &room {
+            let key = DomainKey::of(path, domain);
+            let index = domains.iter().position(|k| *k == key).unwrap_or_else(|| {
+                domains.push(key);
+                domain_room.push(0);
+                domains.len() - 1
+            });
+            domain_room[index] += slots;
+            host_domain.push(index);
+        }
+
+        if domain_room.iter().sum::<usize>() < symbol_count {
             return Err(Error::InsufficientSpace {
                 needed: total_needed,
                 available,
             });
         }
+        // Each placement takes one from both the domain's room and its
+        // allowance, so this is also enough for every pick below to succeed
+        let reachable: usize = domain_room
+            .iter()
+            .map(|slots| (*slots).min(max_per_domain))
+            .sum();
+        if reachable < symbol_count {
+            return Err(Error::InsufficientHosts {
+                symbols: symbol_count,
+                per_domain: max_per_domain,
+                domains: domains.len(),
+            });
+        }
+
+        let weights: Vec<usize> = room.iter().map(|(_, slots)| *slots).collect();
+        let mut placed = vec![0usize; room.len()];
+        let mut in_domain = vec![0usize; domains.len()];
+        let mut locations = Vec::with_capacity(symbol_count);
+
+        for symbol_id in start_symbol_id..start_symbol_id + symbol_count as u32 {
+            let open: Vec<usize> = (0..room.len())
+                .filter(|&h| room[h].1 > 0 && in_domain[host_domain[h]] < max_per_domain)
+                .collect();
+            let host = match policy {
+                PlacementPolicy::RoundRobin => open
+                    .iter()
+                    .copied()
+                    .find(|&h| h >= self.next_host)
+                    .unwrap_or(open[0]),
+                // Ties go to the emptier domain, then to the host with more
+                // room, then to scan order
+                PlacementPolicy::FewestPerHost => open
+                    .iter()
+                    .copied()
+                    .min_by_key(|&h| {
+                        (
+                            placed[h],
+                            in_domain[host_domain[h]],
+                            std::cmp::Reverse(room[h].1),
+                        )
+                    })
+                    .unwrap(),
+                // Highest weight per symbol already placed, so each host ends
+                // up with a share proportional to its room
+                PlacementPolicy::WeightedByFree => open
+                    .iter()
+                    .copied()
+                    .max_by(|&a, &b| {
+                        (weights[a] * (placed[b] + 1))
+                            .cmp(&(weights[b] * (placed[a] + 1)))
+                            .then(b.cmp(&a))
+                    })
+                    .unwrap(),
+            };
+            self.next_host = (host + 1) % room.len();
+
+            // Best fit looks for the tightest hole across the host's safest
+            // regions
+            let path = &room[host].0;
+            let candidates = self
+                .hosts
+                .iter_mut()
+                .filter(|h| h.path == *path && h.can_fit(size));
+            let region = match self.fit {
+                FitStrategy::FirstFit => candidates.min_by_key(|h| h.capabilities.persistence_risk),
+                FitStrategy::BestFit => candidates
+                    .min_by_key(|h| (h.capabilities.persistence_risk, h.free.best_fit(size))),
+            }
+            .unwrap();
+            let offset = region.allocate(size, self.fit).unwrap();
+            locations.push(SymbolLocation {
+                host_path: region.path.clone(),
+                region_index: region.region_index,
+                offset,
+                symbol_id,
+                length: symbol_size as u32,
+                logical_size: region.logical_size,
+            });
+
+            room[host].1 -= 1;
+            placed[host] += 1;
+            in_domain[host_domain[host]] += 1;
+        }
 
         Ok(locations)
     }
 
-    /// Update host info after loading metadata.
-    pub fn apply_used_slack(&mut self, path: &Path, used: u64) {
-        if let Some(host) = self.hosts.iter_mut().find(|h| h.path == path) {
-            host.used_slack = used;
+    /// Re-resolve a host's regions from its original logical size.
+    ///
+    /// File-append writes grow the host, so a fresh scan sees the wrong
+    /// logical size (or no slack at all once the block is full). Hosts that
+    /// dropped out of the scan this way are added back. Usage recorded for
+    /// the host's regions is reset.
+    pub fn pin_logical_size(
+        &mut self,
+        path: &Path,
+        logical_size: u64,
+        backend: &dyn Carrier,
+    ) -> Result<()> {
+        let mut tracked = self.hosts.iter().filter(|h| h.path == path).peekable();
+        if tracked.peek().is_some() && tracked.all(|h| h.logical_size == logical_size) {
+            return Ok(());
+        }
+
+        let regions = backend.regions_at(path, logical_size)?;
+        self.hosts.retain(|h| h.path != path);
+        Self::push_regions(&mut self.hosts, path, regions, backend);
+
+        Ok(())
+    }
+
+    /// Track the regions of a host that have space.
+    fn push_regions(
+        hosts: &mut Vec<HostFile>,
+        path: &Path,
+        regions: Vec<SlackRegion>,
+        backend: &dyn Carrier,
+    ) {
+        for (index, region) in regions.into_iter().enumerate() {
+            if region.available > 0 {
+                let capabilities = backend.capabilities(&region);
+                hosts.push(HostFile::from_region(
+                    path.to_path_buf(),
+                    index as u32,
+                    region,
+                    capabilities,
+                ));
+            }
+        }
+    }
+
+    /// Get the free extents of every tracked region.
+    pub fn free_space(&self) -> HashMap<(PathBuf, u32), FreeExtents> {
+        self.hosts
+            .iter()
+            .map(|h| ((h.path.clone(), h.region_index), h.free.clone()))
+            .collect()
+    }
+
+    /// Restore free extents saved by `free_space`. Regions missing from
+    /// `free` are left wholly free; saved space beyond a region's current
+    /// capacity is dropped.
+    pub fn apply_free_space(&mut self, free: &HashMap<(PathBuf, u32), FreeExtents>) {
+        for host in &mut self.hosts {
+            if let Some(saved) = free.get(&(host.path.clone(), host.region_index)) {
+                host.free = saved.clone();
+                host.free.truncate(host.slack_capacity);
+            }
+        }
+    }
+
+    /// Mark a range of a region as holding a symbol.
+    pub fn reserve(&mut self, path: &Path, region_index: u32, offset: u64, length: u64) {
+        if let Some(host) = self.get_region_mut(path, region_index) {
+            host.free.reserve(offset, length);
+        }
+    }
+
+    /// Return a range of a region to free space.
+    pub fn release(&mut self, path: &Path, region_index: u32, offset: u64, length: u64) {
+        if let Some(host) = self.get_region_mut(path, region_index) {
+            let end = (offset + length).min(host.slack_capacity);
+            host.free.release(offset, end.saturating_sub(offset));
         }
     }
 
+    /// Take `size` bytes for a symbol now at `offset` in region
+    /// `region_index` of `path`, from the host's lowest free space before
+    /// it in a region no riskier than its own. Returns the new region index
+    /// and offset.
+    pub fn allocate_lower(
+        &mut self,
+        path: &Path,
+        region_index: u32,
+        offset: u64,
+        size: u64,
+    ) -> Option<(u32, u64)> {
+        let risk = self
+            .get_region(path, region_index)?
+            .capabilities
+            .persistence_risk;
+        self.hosts
+            .iter_mut()
+            .filter(|h| {
+                h.path == path
+                    && h.region_index <= region_index
+                    && h.capabilities.persistence_risk <= risk
+            })
+            .find_map(|h| {
+                let limit = if h.region_index < region_index {
+                    u64::MAX
+                } else {
+                    offset
+                };
+                let new_offset = h.free.allocate_before(size, limit)?;
+                Some((h.region_index, new_offset))
+            })
+    }
+
     /// Reset all allocations.
     pub fn reset_allocations(&mut self) {
         for host in &mut self.hosts {
-            host.used_slack = 0;
+            host.free = FreeExtents::new(host.slack_capacity);
         }
     }
 }
@@ -257,9 +575,59 @@ impl HostManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::carrier::PersistenceRisk;
     use std::io::Write;
     use tempfile::TempDir;
 
+    /// Carrier splitting file-append slack into two halves, the second one
+    /// reported as riskier, or the first one if the flag is set.
+    struct SplitCarrier(FileSlackBackend, bool);
+
+    impl Carrier for SplitCarrier {
+        fn regions(&self, host: &Path) -> Result<Vec<SlackRegion>> {
+            let whole = self.0.get_slack_info(host)?;
+            let half = whole.available / 2;
+            let mut second = whole.clone();
+            second.offset += half;
+            second.available -= half;
+            let mut first = whole;
+            first.available = half;
+            Ok(vec![first, second])
+        }
+
+        fn read_region(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+            self.0.read_slack(region, offset, len)
+        }
+
+        fn write_region(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+            self.0.write_slack(region, offset, data)
+        }
+
+        fn wipe_region(&self, region: &SlackRegion) -> Result<()> {
+            self.0.wipe_slack(region)
+        }
+
+        fn capabilities(&self, region: &SlackRegion) -> Capabilities {
+            Capabilities {
+                persistence_risk: if (region.offset > region.logical_size) != self.1 {
+                    PersistenceRisk::High
+                } else {
+                    PersistenceRisk::Moderate
+                },
+                changes_visible_metadata: true,
+                needs_root: false,
+            }
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        fn name(&self) -> &'static str {
+            "split"
+        }
+    }
+
     fn create_test_dir_with_files() -> TempDir {
         let dir = TempDir::new().unwrap();
 
@@ -284,6 +652,43 @@ mod tests {
         assert!(manager.total_capacity() > 0);
     }
 
+    #[test]
+    fn test_scan_with_policy() {
+        let dir = create_test_dir_with_files();
+        std::fs::write(dir.path().join("server.log"), vec![b'L'; 1000]).unwrap();
+        std::fs::create_dir_all(dir.path().join("repo/.git/objects")).unwrap();
+        std::fs::write(dir.path().join("repo/.git/objects/ab12"), vec![b'G'; 1000]).unwrap();
+        std::fs::write(dir.path().join("repo/notes.txt"), vec![b'N'; 1000]).unwrap();
+
+        let backend = FileSlackBackend::new(4096);
+        let all = HostManager::scan_with_backend(dir.path(), 4096, &backend).unwrap();
+        assert_eq!(all.host_count(), 8);
+
+        let policy = HostPolicy {
+            exclude: vec!["*.log".to_string(), "**/.git/**".to_string()],
+            max_size: Some(2500),
+            ..HostPolicy::default()
+        };
+        let manager = HostManager::scan_with_policy(dir.path(), 4096, &backend, &policy).unwrap();
+        let mut names: Vec<_> = manager
+            .hosts()
+            .iter()
+            .map(|h| h.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        // file4.txt is 3000 bytes
+        assert_eq!(
+            names,
+            [
+                "file0.txt",
+                "file1.txt",
+                "file2.txt",
+                "file3.txt",
+                "notes.txt"
+            ]
+        );
+    }
+
     #[test]
     fn test_host_file_available() {
         let dir = TempDir::new().unwrap();
@@ -305,7 +710,16 @@ mod tests {
         let initial_available = manager.total_available();
 
         // Allocate 10 symbols of 100 bytes each
-        let locations = manager.allocate(10, 100, 0).unwrap();
+        let locations = manager
+            .allocate(
+                10,
+                100,
+                0,
+                PlacementPolicy::FewestPerHost,
+                FailureDomain::Host,
+                10,
+            )
+            .unwrap();
 
         assert_eq!(locations.len(), 10);
         assert_eq!(manager.total_available(), initial_available - 1000);
@@ -320,8 +734,364 @@ mod tests {
         let mut manager = HostManager::scan(dir.path(), 4096).unwrap();
 
         // Try to allocate more than available
-        let result = manager.allocate(100, 1000, 0);
+        let result = manager.allocate(
+            100,
+            1000,
+            0,
+            PlacementPolicy::FewestPerHost,
+            FailureDomain::Host,
+            100,
+        );
 
         assert!(matches!(result, Err(Error::InsufficientSpace { .. })));
     }
+
+    /// Number of symbols placed on each host, in scan order.
+    fn per_host(manager: &HostManager, locations: &[SymbolLocation]) -> Vec<usize> {
+        let mut paths: Vec<&Path> = Vec::new();
+        for host in manager.hosts() {
+            if !paths.contains(&host.path.as_path()) {
+                paths.push(&host.path);
+            }
+        }
+        paths
+            .iter()
+            .map(|p| locations.iter().filter(|l| l.host_path == *p).count())
+            .collect()
+    }
+
+    #[test]
+    fn test_round_robin_carries_on() {
+        let dir = create_test_dir_with_files();
+        let mut manager = HostManager::scan(dir.path(), 4096).unwrap();
+
+        let first = manager
+            .allocate(
+                3,
+                100,
+                0,
+                PlacementPolicy::RoundRobin,
+                FailureDomain::Host,
+                1,
+            )
+            .unwrap();
+        assert_eq!(per_host(&manager, &first), vec![1, 1, 1, 0, 0]);
+        let second = manager
+            .allocate(
+                3,
+                100,
+                3,
+                PlacementPolicy::RoundRobin,
+                FailureDomain::Host,
+                1,
+            )
+            .unwrap();
+        assert_eq!(per_host(&manager, &second), vec![1, 0, 0, 1, 1]);
+        let ids: Vec<_> = second.iter().map(|l| l.symbol_id).collect();
+        assert_eq!(ids, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn test_fewest_per_host_evens_out() {
+        let dir = create_test_dir_with_files();
+        let mut manager = HostManager::scan(dir.path(), 4096).unwrap();
+
+        let locations = manager
+            .allocate(
+                12,
+                100,
+                0,
+                PlacementPolicy::FewestPerHost,
+                FailureDomain::Host,
+                12,
+            )
+            .unwrap();
+        let counts = per_host(&manager, &locations);
+        assert_eq!(counts.iter().sum::<usize>(), 12);
+        assert!(counts.iter().all(|&n| n == 2 || n == 3));
+    }
+
+    #[test]
+    fn test_weighted_by_free_space() {
+        let dir = TempDir::new().unwrap();
+        // 3000 and 1000 bytes of slack
+        std::fs::write(dir.path().join("a.txt"), vec![0u8; 1096]).unwrap();
+        std::fs::write(dir.path().join("b.txt"), vec![0u8; 3096]).unwrap();
+        let mut manager = HostManager::scan(dir.path(), 4096).unwrap();
+
+        let locations = manager
+            .allocate(
+                8,
+                100,
+                0,
+                PlacementPolicy::WeightedByFree,
+                FailureDomain::Host,
+                8,
+            )
+            .unwrap();
+        let on_a = locations
+            .iter()
+            .filter(|l| l.host_path.ends_with("a.txt"))
+            .count();
+        assert_eq!(on_a, 6);
+    }
+
+    #[test]
+    fn test_per_host_cap() {
+        let dir = TempDir::new().unwrap();
+        for i in 0..2 {
+            std::fs::write(dir.path().join(format!("host{}.txt", i)), vec![0u8; 96]).unwrap();
+        }
+        let mut manager = HostManager::scan(dir.path(), 4096).unwrap();
+        let available = manager.total_available();
+
+        for policy in [
+            PlacementPolicy::RoundRobin,
+            PlacementPolicy::FewestPerHost,
+            PlacementPolicy::WeightedByFree,
+        ] {
+            // Plenty of space, but two hosts only take four symbols
+            let result = manager.allocate(5, 100, 0, policy, FailureDomain::Host, 2);
+            assert!(matches!(result, Err(Error::InsufficientHosts { .. })));
+            assert_eq!(manager.total_available(), available);
+        }
+
+        let locations = manager
+            .allocate(
+                5,
+                100,
+                0,
+                PlacementPolicy::WeightedByFree,
+                FailureDomain::Host,
+                3,
+            )
+            .unwrap();
+        assert!(per_host(&manager, &locations).iter().all(|&n| n <= 3));
+    }
+
+    #[test]
+    fn test_directory_domains() {
+        let dir = TempDir::new().unwrap();
+        for (sub, files) in [("a", 3), ("b", 1), ("c", 1)] {
+            std::fs::create_dir(dir.path().join(sub)).unwrap();
+            for i in 0..files {
+                let path = dir.path().join(sub).join(format!("{}.txt", i));
+                std::fs::write(path, vec![0u8; 96]).unwrap();
+            }
+        }
+        let mut manager = HostManager::scan(dir.path(), 4096).unwrap();
+
+        // Three directories at one symbol each cannot take four
+        let result = manager.allocate(
+            4,
+            100,
+            0,
+            PlacementPolicy::FewestPerHost,
+            FailureDomain::Directory,
+            1,
+        );
+        assert!(matches!(
+            result,
+            Err(Error::InsufficientHosts { domains: 3, .. })
+        ));
+
+        // Fewest per host alone would give the three hosts in `a` two each
+        let locations = manager
+            .allocate(
+                6,
+                100,
+                0,
+                PlacementPolicy::FewestPerHost,
+                FailureDomain::Directory,
+                2,
+            )
+            .unwrap();
+        for sub in ["a", "b", "c"] {
+            let in_sub = locations
+                .iter()
+                .filter(|l| l.host_path.parent().unwrap().ends_with(sub))
+                .count();
+            assert_eq!(in_sub, 2);
+        }
+    }
+
+    #[test]
+    fn test_domain_keys() {
+        let dir = create_test_dir_with_files();
+        let a = dir.path().join("file0.txt");
+        let b = dir.path().join("file1.txt");
+
+        assert_ne!(
+            DomainKey::of(&a, FailureDomain::Host),
+            DomainKey::of(&b, FailureDomain::Host)
+        );
+        for domain in [FailureDomain::Directory, FailureDomain::Device] {
+            assert_eq!(DomainKey::of(&a, domain), DomainKey::of(&b, domain));
+        }
+    }
+
+    #[test]
+    fn test_allocate_across_regions() {
+        let dir = TempDir::new().unwrap();
+        for i in 0..2 {
+            std::fs::write(dir.path().join(format!("host{}.txt", i)), vec![0u8; 96]).unwrap();
+        }
+
+        let carrier = SplitCarrier(FileSlackBackend::new(4096), false);
+        let mut manager = HostManager::scan_with_backend(dir.path(), 4096, &carrier).unwrap();
+        assert_eq!(manager.host_count(), 2);
+        assert_eq!(manager.region_count(), 4);
+        assert_eq!(manager.total_capacity(), 2 * 4000);
+
+        let risks: Vec<_> = manager
+            .hosts()
+            .iter()
+            .map(|h| h.capabilities.persistence_risk)
+            .collect();
+        assert_eq!(
+            risks,
+            vec![
+                PersistenceRisk::Moderate,
+                PersistenceRisk::High,
+                PersistenceRisk::Moderate,
+                PersistenceRisk::High,
+            ]
+        );
+
+        // 2000-byte regions take four 500-byte symbols each; the fifth on
+        // a host spills into its next region
+        let locations = manager
+            .allocate(
+                10,
+                500,
+                0,
+                PlacementPolicy::RoundRobin,
+                FailureDomain::Host,
+                5,
+            )
+            .unwrap();
+        let path = &locations[0].host_path;
+        let placed: Vec<_> = locations
+            .iter()
+            .filter(|l| l.host_path == *path)
+            .map(|l| (l.region_index, l.offset))
+            .collect();
+        assert_eq!(placed, vec![(0, 0), (0, 500), (0, 1000), (0, 1500), (1, 0)]);
+
+        let region = &manager.get_region(path, 1).unwrap().region;
+        carrier.write_region(region, 0, b"second half").unwrap();
+        assert_eq!(carrier.read_region(region, 0, 11).unwrap(), b"second half");
+        assert_eq!(manager.host_regions(path).count(), 2);
+    }
+
+    #[test]
+    fn test_freed_holes_reused() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("host.txt"), vec![0u8; 96]).unwrap();
+        let mut manager = HostManager::scan(dir.path(), 4096).unwrap();
+        let path = manager.hosts()[0].path.clone();
+
+        // Holes at 0..300 and 1000..1100
+        manager.reserve(&path, 0, 0, 4000);
+        manager.release(&path, 0, 0, 300);
+        manager.release(&path, 0, 1000, 100);
+        // Only the part of a release within the region counts
+        manager.release(&path, 0, 3950, 100);
+        assert_eq!(manager.total_available(), 450);
+
+        // The map carries over to a freshly scanned manager
+        let free = manager.free_space();
+        let offsets = |fit| {
+            let mut manager = HostManager::scan(dir.path(), 4096).unwrap();
+            manager.apply_free_space(&free);
+            assert_eq!(manager.total_available(), 450);
+            manager.set_fit_strategy(fit);
+            manager
+                .allocate(
+                    3,
+                    100,
+                    0,
+                    PlacementPolicy::FewestPerHost,
+                    FailureDomain::Host,
+                    usize::MAX,
+                )
+                .unwrap()
+                .iter()
+                .map(|l| l.offset)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(offsets(FitStrategy::FirstFit), vec![0, 100, 200]);
+        assert_eq!(offsets(FitStrategy::BestFit), vec![1000, 0, 100]);
+    }
+
+    #[test]
+    fn test_allocate_lower() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("host.txt"), vec![0u8; 96]).unwrap();
+        let carrier = SplitCarrier(FileSlackBackend::new(4096), false);
+        let mut manager = HostManager::scan_with_backend(dir.path(), 4096, &carrier).unwrap();
+        let path = manager.hosts()[0].path.clone();
+
+        // Second region full but for a hole after the symbol at 1000
+        manager.reserve(&path, 1, 0, 2000);
+        manager.release(&path, 1, 1500, 100);
+        assert_eq!(manager.allocate_lower(&path, 1, 1000, 100), Some((0, 0)));
+        manager.reserve(&path, 0, 0, 2000);
+        assert_eq!(manager.allocate_lower(&path, 1, 1000, 100), None);
+        assert_eq!(manager.allocate_lower(&path, 1, 1600, 100), Some((1, 1500)));
+
+        // Symbols never move into a riskier region
+        let carrier = SplitCarrier(FileSlackBackend::new(4096), true);
+        let mut manager = HostManager::scan_with_backend(dir.path(), 4096, &carrier).unwrap();
+        manager.reserve(&path, 1, 0, 2000);
+        assert_eq!(manager.allocate_lower(&path, 1, 1000, 100), None);
+    }
+
+    #[test]
+    fn test_allocate_prefers_safer_regions() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("host.txt"), vec![0u8; 96]).unwrap();
+
+        // The first region is the riskier one, so it fills last
+        let carrier = SplitCarrier(FileSlackBackend::new(4096), true);
+        for fit in [FitStrategy::FirstFit, FitStrategy::BestFit] {
+            let mut manager = HostManager::scan_with_backend(dir.path(), 4096, &carrier).unwrap();
+            manager.set_fit_strategy(fit);
+            let placed: Vec<_> = manager
+                .allocate(
+                    5,
+                    500,
+                    0,
+                    PlacementPolicy::RoundRobin,
+                    FailureDomain::Host,
+                    5,
+                )
+                .unwrap()
+                .iter()
+                .map(|l| (l.region_index, l.offset))
+                .collect();
+            assert_eq!(placed, vec![(1, 0), (1, 500), (1, 1000), (1, 1500), (0, 0)]);
+        }
+    }
+
+    #[test]
+    fn test_pin_logical_size() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("full.txt");
+        std::fs::write(&path, vec![0u8; 100]).unwrap();
+
+        // Fill the slack so the file ends on a block boundary
+        let backend = FileSlackBackend::new(4096);
+        let region = backend.get_slack_info(&path).unwrap();
+        backend.write_slack(&region, 0, &vec![1u8; 3996]).unwrap();
+
+        let mut manager = HostManager::scan(dir.path(), 4096).unwrap();
+        assert_eq!(manager.host_count(), 0);
+
+        manager.pin_logical_size(&path, 100, &backend).unwrap();
+        let host = manager.get_region(&path, 0).unwrap();
+        assert_eq!(host.logical_size, 100);
+        assert_eq!(host.slack_capacity, 3996);
+        assert_eq!(host.get_write_position(0), 100);
+    }
 }
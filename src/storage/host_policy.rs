@@ -0,0 +1,331 @@
+//! Rules deciding which files a scan takes as hosts.
+//!
+//! Logs, databases, version-control objects and editor swap files are
+//! rewritten often, and every rewrite destroys the symbols in their slack.
+//! A [`HostPolicy`] keeps such files out. It is stored in the bootstrap
+//! metadata, since hosts are scanned before the superblock is decrypted.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// Which candidate files may become hosts.
+///
+/// Glob patterns support `*` and `?` (neither matches `/`), `**` (any
+/// number of directories) and character classes like `[a-z]` or `[!0-9]`.
+/// A pattern without `/` is matched against the file name; one with `/`
+/// against the path relative to the host directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostPolicy {
+    /// Patterns a host must match one of; empty takes every file.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Patterns that rule a host out, even if it is included.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Smallest host size in bytes.
+    #[serde(default)]
+    pub min_size: Option<u64>,
+    /// Largest host size in bytes.
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    /// Shortest time since the host was last modified.
+    #[serde(default)]
+    pub min_age: Option<Duration>,
+    /// File extensions a host must have one of, without the dot and
+    /// compared ignoring case; empty takes any.
+    #[serde(default)]
+    pub extensions: Vec<String>,
+}
+
+impl HostPolicy {
+    /// Check whether the policy takes every file.
+    pub fn is_unrestricted(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Check whether a candidate may become a host.
+    ///
+    /// `relative` is the candidate's path relative to the host directory.
+    /// The age rule is skipped when the modification time is unknown, as
+    /// it is for files inside file system images.
+    pub fn allows(&self, relative: &Path, size: u64, modified: Option<SystemTime>) -> bool {
+        let path = relative
+            .to_string_lossy()
+            .replace('\\', "/")
+            .trim_start_matches('/')
+            .to_string();
+        let name = path.rsplit('/').next().unwrap_or_default();
+        let matches = |pattern: &String| {
+            let pattern = pattern.trim_start_matches('/');
+            if pattern.contains('/') {
+                glob_match(pattern, &path)
+            } else {
+                glob_match(pattern, name)
+            }
+        };
+
+        if !self.include.is_empty() && !self.include.iter().any(matches) {
+            return false;
+        }
+        if self.exclude.iter().any(matches) {
+            return false;
+        }
+        if !self.extensions.is_empty() {
+            let extension = relative.extension().and_then(|e| e.to_str()).unwrap_or("");
+            let listed = self
+                .extensions
+                .iter()
+                .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(extension));
+            if !listed {
+                return false;
+            }
+        }
+        if self.min_size.is_some_and(|min| size < min)
+            || self.max_size.is_some_and(|max| size > max)
+        {
+            return false;
+        }
+        if let (Some(min_age), Some(modified)) = (self.min_age, modified) {
+            let age = SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default();
+            if age < min_age {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl std::fmt::Display for HostPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut rules = Vec::new();
+        if !self.include.is_empty() {
+            rules.push(format!("include {}", self.include.join(", ")));
+        }
+        if !self.exclude.is_empty() {
+            rules.push(format!("exclude {}", self.exclude.join(", ")));
+        }
+        if !self.extensions.is_empty() {
+            rules.push(format!("extensions {}", self.extensions.join(", ")));
+        }
+        if let Some(min) = self.min_size {
+            rules.push(format!("at least {} bytes", min));
+        }
+        if let Some(max) = self.max_size {
+            rules.push(format!("at most {} bytes", max));
+        }
+        if let Some(age) = self.min_age {
+            rules.push(format!("unmodified for {}s", age.as_secs()));
+        }
+        if rules.is_empty() {
+            f.write_str("any file")
+        } else {
+            f.write_str(&rules.join("; "))
+        }
+    }
+}
+
+/// Match a glob pattern against a `/`-separated path.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<Vec<char>> = pattern.split('/').map(|s| s.chars().collect()).collect();
+    // `None` stands for `**`, any number of whole directories
+    let mut segments: Vec<Option<Vec<Token>>> = pattern
+        .iter()
+        .map(|segment| match segment.as_slice() {
+            ['*', '*'] => None,
+            segment => Some(tokenize(segment)),
+        })
+        .collect();
+    // A trailing `**` takes what is inside a directory, not the directory
+    if segments.len() > 1 && segments.last().is_some_and(Option::is_none) {
+        segments.insert(segments.len() - 1, Some(vec![Token::Star]));
+    }
+
+    let text: Vec<Vec<char>> = text.split('/').map(|s| s.chars().collect()).collect();
+    wildcard_match(&segments, &text, Option::is_none, |segment, name| {
+        segment.as_ref().is_some_and(|tokens| {
+            wildcard_match(tokens, name, |t| matches!(t, Token::Star), Token::matches)
+        })
+    })
+}
+
+/// One piece of a path segment's pattern.
+enum Token<'a> {
+    /// `*`: any run of characters.
+    Star,
+    /// `?`: any one character.
+    Any,
+    /// `[...]`: one character of the class, or not of it when negated.
+    Class(&'a [char], bool),
+    /// A character standing for itself.
+    Char(char),
+}
+
+impl Token<'_> {
+    fn matches(&self, c: &char) -> bool {
+        match *self {
+            Token::Star | Token::Any => true,
+            Token::Class(class, negated) => class_contains(class, *c) != negated,
+            Token::Char(expected) => *c == expected,
+        }
+    }
+}
+
+fn tokenize(pattern: &[char]) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = pattern;
+    while let [c, after @ ..] = rest {
+        rest = after;
+        tokens.push(match c {
+            '*' => Token::Star,
+            '?' => Token::Any,
+            '[' => match parse_class(after) {
+                Some((class, negated, after)) => {
+                    rest = after;
+                    Token::Class(class, negated)
+                }
+                // An unclosed bracket is an ordinary character
+                None => Token::Char('['),
+            },
+            &c => Token::Char(c),
+        });
+    }
+    tokens
+}
+
+/// Match `text` against `pattern`, where a star takes any run of items and
+/// every other item one matching item. A mismatch only backtracks to the
+/// last star, so this takes time proportional to pattern times text.
+fn wildcard_match<P, T>(
+    pattern: &[P],
+    text: &[T],
+    is_star: impl Fn(&P) -> bool,
+    matches: impl Fn(&P, &T) -> bool,
+) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // The last star and where in the text it started
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && is_star(&pattern[p]) {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && matches(&pattern[p], &text[t]) {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Let the star take one more item
+            star = Some((star_p, star_t + 1));
+            p = star_p + 1;
+            t = star_t + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(is_star)
+}
+
+/// Split a character class body (after `[`) into its members, whether it
+/// is negated, and the rest of the pattern. A `]` first is a member.
+fn parse_class(pattern: &[char]) -> Option<(&[char], bool, &[char])> {
+    let negated = matches!(pattern.first(), Some('!' | '^'));
+    let body = if negated { &pattern[1..] } else { pattern };
+    let close = body.iter().skip(1).position(|&c| c == ']')? + 1;
+    Some((&body[..close], negated, &body[close + 1..]))
+}
+
+fn class_contains(class: &[char], c: char) -> bool {
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            if (class[i]..=class[i + 2]).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.log", "server.log"));
+        assert!(!glob_match("*.log", "logs/server.log"));
+        assert!(glob_match("photo?.jpg", "photo1.jpg"));
+        assert!(!glob_match("photo?.jpg", "photo10.jpg"));
+        assert!(glob_match("**/.git/**", ".git/objects/ab/cdef"));
+        assert!(glob_match("**/.git/**", "src/.git/HEAD"));
+        assert!(!glob_match("**/.git/**", "src/git/HEAD"));
+        assert!(glob_match("docs/**", "docs/a/b.pdf"));
+        assert!(glob_match("**/*.swp", "a.swp"));
+        assert!(glob_match("img[0-9].png", "img7.png"));
+        assert!(!glob_match("img[!0-9].png", "img7.png"));
+        assert!(glob_match("[]x]", "]"));
+        assert!(glob_match("a[b", "a[b"));
+        assert!(glob_match("a/**/b", "a/b"));
+        assert!(glob_match("a/**/b", "a/x/y/b"));
+        assert!(!glob_match("docs/**", "docs"));
+        assert!(glob_match("*a*b*c", "xaybzc"));
+        assert!(!glob_match("*a*b*c", "xaybzcd"));
+    }
+
+    #[test]
+    fn test_glob_match_many_stars() {
+        // Backtracking into every star would take far too long here
+        let name = "a".repeat(200);
+        assert!(!glob_match(&format!("{}b", "*a".repeat(30)), &name));
+        let path = format!("{}b", "a/".repeat(200));
+        assert!(!glob_match(&format!("{}x", "**/".repeat(30)), &path));
+    }
+
+    #[test]
+    fn test_allows_globs_and_extensions() {
+        let policy = HostPolicy {
+            include: vec!["photos/**".to_string(), "*.pdf".to_string()],
+            exclude: vec!["*~".to_string(), "**/.git/**".to_string()],
+            extensions: vec!["JPG".to_string(), ".pdf".to_string()],
+            ..HostPolicy::default()
+        };
+        let allows = |path: &str| policy.allows(Path::new(path), 100, None);
+
+        assert!(allows("photos/2020/beach.jpg"));
+        assert!(allows("notes/report.pdf"));
+        assert!(!allows("photos/beach.jpg~"));
+        assert!(!allows("photos/.git/objects/beach.jpg"));
+        assert!(!allows("photos/notes.txt"));
+        assert!(!allows("music/track.jpg"));
+        assert!(HostPolicy::default().allows(Path::new("/any/file"), 0, None));
+    }
+
+    #[test]
+    fn test_allows_size_and_age() {
+        let policy = HostPolicy {
+            min_size: Some(100),
+            max_size: Some(1000),
+            min_age: Some(Duration::from_secs(3600)),
+            ..HostPolicy::default()
+        };
+        let old = SystemTime::now() - Duration::from_secs(7200);
+        let path = Path::new("a.dat");
+
+        assert!(policy.allows(path, 100, Some(old)));
+        assert!(policy.allows(path, 1000, None));
+        assert!(!policy.allows(path, 99, Some(old)));
+        assert!(!policy.allows(path, 1001, Some(old)));
+        assert!(!policy.allows(path, 500, Some(SystemTime::now())));
+        assert_eq!(
+            policy.to_string(),
+            "at least 100 bytes; at most 1000 bytes; unmodified for 3600s"
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Positioned I/O on file system images and block devices.
+//!
+//! Used by every image backend (FAT, exFAT, NTFS, partition gaps and, on
+//! Linux, ext4 and XFS images), which parse on-disk structures themselves
+//! and need no platform-specific device access.
+
+use crate::error::{Error, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Positioned reads, as the file system parsers need them from either an
+/// image or a raw block device.
+pub trait ReadAt: Send + Sync {
+    /// Read exactly `len` bytes at `offset`.
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>>;
+}
+
+/// An image file or block device opened for positioned reads.
+#[derive(Debug)]
+pub struct ImageFile {
+    path: PathBuf,
+    file: Mutex<File>,
+    len: u64,
+}
+
+impl ImageFile {
+    /// Open an image for reading.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)?;
+        // Block devices report a zero metadata length; seek to find the end
+        let len = file.seek(SeekFrom::End(0))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            len,
+        })
+    }
+
+    /// Get the image path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the image size in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Check whether the image is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Read exactly `len` bytes at `offset`.
+    pub fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        if offset + len as u64 > self.len {
+            return Err(Error::DataCorruption(format!(
+                "Read past end of image: {} bytes at {}",
+                len, offset
+            )));
+        }
+
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buffer = vec![0u8; len];
+        file.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Write `data` at `offset` through a separate writable handle and
+    /// flush it to disk.
+    pub fn write_at(&self, offset: u64, data: &[u8]) -> Result<()> {
+        if offset + data.len() as u64 > self.len {
+            return Err(Error::DataCorruption(format!(
+                "Write past end of image: {} bytes at {}",
+                data.len(),
+                offset
+            )));
+        }
+
+        let mut file = OpenOptions::new().write(true).open(&self.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+impl ReadAt for ImageFile {
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        ImageFile::read_at(self, offset, len)
+    }
+}
@@ -1,9 +1,11 @@
 //! Raw block device access for Linux.
 //!
 //! Provides low-level read/write operations using O_DIRECT for
-//! bypassing the page cache.
+//! bypassing the page cache. File system image files are read through
+//! [`ImageFile`] instead.
 
 use crate::error::{Error, Result};
+use crate::storage::image_file::ReadAt;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::os::unix::fs::OpenOptionsExt;
@@ -75,18 +77,18 @@ impl BlockDevice {
         // Calculate aligned read bounds
         let align = DIRECT_IO_ALIGNMENT as u64;
         let aligned_start = (offset / align) * align;
-        let aligned_end = ((offset + len as u64 + align - 1) / align) * align;
+        let aligned_end = (offset + len as u64).div_ceil(align) * align;
         let aligned_len = (aligned_end - aligned_start) as usize;
 
         // Allocate aligned buffer
-        let mut aligned_buf = Self::alloc_aligned(aligned_len)?;
+        let mut raw = Self::alloc_aligned(aligned_len);
+        let pad = Self::aligned_offset(&raw);
+        let aligned_buf = &mut raw[pad..pad + aligned_len];
 
         // Seek and read
         let mut file = &self.file;
-        file.seek(SeekFrom::Start(aligned_start))
-            .map_err(|e| Error::Io(e))?;
-        file.read_exact(&mut aligned_buf)
-            .map_err(|e| Error::Io(e))?;
+        file.seek(SeekFrom::Start(aligned_start))?;
+        file.read_exact(aligned_buf)?;
 
         // Extract the requested portion
         let start_offset = (offset - aligned_start) as usize;
@@ -104,45 +106,46 @@ impl BlockDevice {
         // Calculate aligned bounds
         let align = DIRECT_IO_ALIGNMENT as u64;
         let aligned_start = (offset / align) * align;
-        let aligned_end = ((offset + data.len() as u64 + align - 1) / align) * align;
+        let aligned_end = (offset + data.len() as u64).div_ceil(align) * align;
         let aligned_len = (aligned_end - aligned_start) as usize;
 
         // Read existing data (read-modify-write)
-        let mut aligned_buf = self.read_at(aligned_start, aligned_len)?;
-        
-        // Make sure we have the right size
-        aligned_buf.resize(aligned_len, 0);
+        let existing = self.read_at(aligned_start, aligned_len)?;
+
+        // Copy existing and new data into an aligned buffer
+        let mut raw = Self::alloc_aligned(aligned_len);
+        let pad = Self::aligned_offset(&raw);
+        let aligned_buf = &mut raw[pad..pad + aligned_len];
+        aligned_buf.copy_from_slice(&existing);
 
-        // Copy new data into the aligned buffer
         let start_offset = (offset - aligned_start) as usize;
         aligned_buf[start_offset..start_offset + data.len()].copy_from_slice(data);
 
         // Write back
         let mut file = &self.file;
-        file.seek(SeekFrom::Start(aligned_start))
-            .map_err(|e| Error::Io(e))?;
-        file.write_all(&aligned_buf)
-            .map_err(|e| Error::Io(e))?;
+        file.seek(SeekFrom::Start(aligned_start))?;
+        file.write_all(aligned_buf)?;
+        file.sync_data()?;
 
         Ok(())
     }
 
-    /// Allocate a buffer with proper alignment for O_DIRECT.
-    fn alloc_aligned(size: usize) -> Result<Vec<u8>> {
-        // Use posix_memalign for proper alignment
-        // For simplicity, we'll use a Vec with extra capacity and manual alignment
-        // This is a simplified version - production code should use proper aligned allocation
-
-        // Round up size to alignment
-        let aligned_size = ((size + DIRECT_IO_ALIGNMENT - 1) / DIRECT_IO_ALIGNMENT) * DIRECT_IO_ALIGNMENT;
-        
-        // Allocate with extra space for alignment
-        let mut buf = vec![0u8; aligned_size];
-        
-        // Vec on modern allocators is usually already aligned to at least 16 bytes,
-        // which may not be enough for O_DIRECT. For simplicity, we assume the system
-        // handles this, but production code should use proper aligned allocation.
-        
-        Ok(buf)
+    /// Allocate a buffer large enough to hold `size` bytes at an
+    /// O_DIRECT-aligned address (see `aligned_offset`).
+    fn alloc_aligned(size: usize) -> Vec<u8> {
+        // Vec only guarantees byte alignment, so over-allocate by one
+        // alignment unit and slice from the first aligned address.
+        vec![0u8; size + DIRECT_IO_ALIGNMENT]
+    }
+
+    /// Offset of the first O_DIRECT-aligned byte within a buffer.
+    fn aligned_offset(buf: &[u8]) -> usize {
+        buf.as_ptr().align_offset(DIRECT_IO_ALIGNMENT)
+    }
+}
+
+impl ReadAt for BlockDevice {
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        BlockDevice::read_at(self, offset, len)
     }
 }
@@ -0,0 +1,373 @@
+//! Ext4 directory entry padding backend.
+//!
+//! Hides data in directory blocks: the last `ext4_dir_entry_2` of a block
+//! has a `rec_len` reaching to the end of the block (or to its checksum
+//! tail), so the bytes after its name are unused. Hosts are directories,
+//! and every block of a directory is a region of its own, so capacity grows
+//! with the number of directory blocks rather than with file sizes.
+//!
+//! Under `metadata_csum` the block's `dirent` tail checksum is recomputed
+//! on every write. Works on unmounted image files. The kernel reuses the
+//! padding when entries are added to the directory, overwriting the data.
+
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, Carrier, PersistenceRisk};
+use crate::storage::image_file::ImageFile;
+use crate::storage::linux::Ext4Parser;
+use crate::storage::slack_backend::SlackRegion;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Directory entry padding backend over an ext4 image file.
+pub struct Ext4DirentPaddingBackend {
+    /// Path to the image file.
+    image_path: PathBuf,
+    /// Parser over the image.
+    parser: Ext4Parser,
+    /// Directory inode owning each physical block handed out in a region,
+    /// needed to seed the block checksum.
+    owners: Mutex<HashMap<u64, u32>>,
+}
+
+impl Ext4DirentPaddingBackend {
+    pub const NAME: &'static str = "ext4 dirent padding";
+
+    /// Open an ext4 image file.
+    pub fn open(image_path: &Path) -> Result<Self> {
+        Ok(Self {
+            image_path: image_path.to_path_buf(),
+            parser: Ext4Parser::open_image(image_path)?,
+            owners: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Get the path to the image file.
+    pub fn image_path(&self) -> &Path {
+        &self.image_path
+    }
+
+    /// Get the parser over the image.
+    pub fn parser(&self) -> &Ext4Parser {
+        &self.parser
+    }
+
+    /// List directories inside the image that have entry padding, with
+    /// their padded blocks.
+    pub fn candidate_hosts(&self) -> Result<Vec<(PathBuf, Vec<SlackRegion>)>> {
+        let mut hosts = Vec::new();
+
+        for (path, inode_num) in self.parser.walk_dirs(Path::new("/"))? {
+            if let Ok(regions) = self.regions_for_dir(inode_num) {
+                let regions: Vec<_> = regions.into_iter().filter(|r| r.available > 0).collect();
+                if !regions.is_empty() {
+                    hosts.push((path, regions));
+                }
+            }
+        }
+
+        Ok(hosts)
+    }
+
+    /// Build one padding region per block of a directory, in logical block
+    /// order.
+    fn regions_for_dir(&self, inode_num: u32) -> Result<Vec<SlackRegion>> {
+        let dir = self.parser.read_inode(inode_num)?;
+        if !dir.is_directory() {
+            return Err(Error::NotADirectory(format!("inode {}", inode_num)));
+        }
+
+        let block_size = self.parser.block_size();
+        let blocks = self.parser.dir_entry_blocks(inode_num)?;
+
+        let mut owners = self
+            .owners
+            .lock()
+            .map_err(|_| Error::Unsupported("Directory owner cache poisoned".to_string()))?;
+
+        let mut regions = Vec::with_capacity(blocks.len());
+        for physical in blocks {
+            let block = self.parser.read_block(physical)?;
+            let (start, len) = Ext4Parser::dirent_padding(&block)?;
+            owners.insert(physical, inode_num);
+            regions.push(SlackRegion {
+                device_path: self.image_path.clone(),
+                offset: physical * block_size + start as u64,
+                available: len as u64,
+                logical_size: dir.size,
+                block_size,
+            });
+        }
+
+        Ok(regions)
+    }
+
+    /// Find the directory owning a block, walking the tree if the block was
+    /// not handed out by this instance.
+    fn owner_of(&self, physical: u64) -> Result<u32> {
+        let mut owners = self
+            .owners
+            .lock()
+            .map_err(|_| Error::Unsupported("Directory owner cache poisoned".to_string()))?;
+
+        if let Some(&inode_num) = owners.get(&physical) {
+            return Ok(inode_num);
+        }
+
+        for (_, inode_num) in self.parser.walk_dirs(Path::new("/"))? {
+            if let Ok(blocks) = self.parser.dir_entry_blocks(inode_num) {
+                for block in blocks {
+                    owners.insert(block, inode_num);
+                }
+            }
+        }
+
+        owners.get(&physical).copied().ok_or_else(|| {
+            Error::DataCorruption(format!("Block {} does not belong to a directory", physical))
+        })
+    }
+}
+
+impl Carrier for Ext4DirentPaddingBackend {
+    fn regions(&self, path: &Path) -> Result<Vec<SlackRegion>> {
+        let inode_num = self.parser.lookup(path)?;
+        self.regions_for_dir(inode_num).map_err(|e| match e {
+            Error::NotADirectory(_) => Error::NotADirectory(path.display().to_string()),
+            e => e,
+        })
+    }
+
+    /// Lists directories inside the image; `root` (the directory holding
+    /// the VFS metadata) is not part of the image and is ignored.
+    fn enumerate_hosts(&self, _root: &Path) -> Result<Vec<PathBuf>> {
+        Ok(self
+            .parser
+            .walk_dirs(Path::new("/"))?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    fn read_region(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        region.check_bounds(offset, len)?;
+        let image = ImageFile::open(&region.device_path)?;
+        image.read_at(region.offset + offset, len)
+    }
+
+    /// Splices the data into the directory block and writes it back with a
+    /// fresh checksum tail. Refuses to write if entries were added to the
+    /// block since the region was located.
+    fn write_region(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        region.check_bounds(offset, data.len())?;
+
+        let block_size = self.parser.block_size();
+        let physical = region.offset / block_size;
+        let mut block = self.parser.read_block(physical)?;
+
+        let (start, len) = Ext4Parser::dirent_padding(&block)?;
+        if physical * block_size + start as u64 != region.offset || (len as u64) < region.available
+        {
+            return Err(Error::DataCorruption(format!(
+                "Entry padding of directory block {} has changed",
+                physical
+            )));
+        }
+
+        let at = start + offset as usize;
+        block[at..at + data.len()].copy_from_slice(data);
+        self.parser
+            .update_dirent_checksum(self.owner_of(physical)?, &mut block)?;
+
+        let image = ImageFile::open(&region.device_path)?;
+        image.write_at(physical * block_size, &block)
+    }
+
+    fn wipe_region(&self, region: &SlackRegion) -> Result<()> {
+        let zeros = vec![0u8; region.available as usize];
+        self.write_region(region, 0, &zeros)
+    }
+
+    /// Adding entries to the directory reuses the padding.
+    fn capabilities(&self, _region: &SlackRegion) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::High,
+            changes_visible_metadata: false,
+            needs_root: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.image_path.is_file()
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::linux::test_image::TestImage;
+
+    #[test]
+    fn test_dirent_padding_layout() {
+        let mut block = vec![0u8; 64];
+        // "a" (rec_len 12), then "bcdef" covering the rest up to the tail
+        block[0] = 11;
+        block[4] = 12;
+        block[6] = 1;
+        block[8] = b'a';
+        block[12] = 12;
+        block[16] = 40;
+        block[18] = 5;
+        block[20..25].copy_from_slice(b"bcdef");
+        block[52..60].copy_from_slice(&[0, 0, 0, 0, 12, 0, 0, 0xDE]);
+
+        assert!(Ext4Parser::has_dirent_tail(&block));
+        assert_eq!(Ext4Parser::dirent_padding(&block).unwrap(), (28, 24));
+
+        // Without a tail the last entry reaches the end of the block
+        block[52..64].fill(0);
+        block[16] = 52;
+        assert_eq!(Ext4Parser::dirent_padding(&block).unwrap(), (28, 36));
+
+        block[16] = 60;
+        assert!(Ext4Parser::dirent_padding(&block).is_err());
+    }
+
+    #[test]
+    fn test_directories_are_hosts() {
+        let mut image = TestImage::new();
+        let docs = image.mkdir(TestImage::ROOT, "docs");
+        image.mkdir(docs, "nested");
+        image.add_file(docs, "report.txt", &[b'R'; 1000]);
+        let big = image.mkdir_indexed(TestImage::ROOT, "big", 4);
+        for i in 0..10 {
+            image.add_file(big, &format!("file{}.txt", i), b"x");
+        }
+        let file = image.build();
+
+        let backend = Ext4DirentPaddingBackend::open(file.path()).unwrap();
+        let hosts: Vec<_> = backend
+            .candidate_hosts()
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            hosts,
+            vec![
+                PathBuf::from("/"),
+                PathBuf::from("/big"),
+                PathBuf::from("/docs"),
+                PathBuf::from("/docs/nested"),
+            ]
+        );
+
+        // "." (12) + ".." (12) + "nested" (16) + "report.txt" (20)
+        let regions = backend.regions(Path::new("/docs")).unwrap();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].available, TestImage::BLOCK_SIZE - 60);
+
+        // Htree leaves only, one region each; the last leaf holds two entries
+        let available: Vec<_> = backend
+            .regions(Path::new("/big"))
+            .unwrap()
+            .iter()
+            .map(|r| r.available)
+            .collect();
+        assert_eq!(
+            available,
+            vec![
+                TestImage::BLOCK_SIZE - 4 * 20,
+                TestImage::BLOCK_SIZE - 4 * 20,
+                TestImage::BLOCK_SIZE - 2 * 20,
+            ]
+        );
+
+        assert!(matches!(
+            backend.regions(Path::new("/docs/report.txt")),
+            Err(Error::NotADirectory(_))
+        ));
+    }
+
+    #[test]
+    fn test_write_keeps_entries_and_checksum() {
+        let mut image = TestImage::new();
+        image.enable_metadata_csum();
+        let docs = image.mkdir(TestImage::ROOT, "docs");
+        image.add_file(docs, "report.txt", b"report");
+        let file = image.build();
+
+        let backend = Ext4DirentPaddingBackend::open(file.path()).unwrap();
+        let region = backend.regions(Path::new("/docs")).unwrap().remove(0);
+        assert_eq!(region.available, TestImage::BLOCK_SIZE - 12 - 44);
+
+        let physical = region.offset / TestImage::BLOCK_SIZE;
+        let before = backend.parser().read_block(physical).unwrap();
+        let mut expected = before.clone();
+        backend
+            .parser()
+            .update_dirent_checksum(docs, &mut expected)
+            .unwrap();
+        assert_eq!(expected, before, "builder and parser checksums agree");
+
+        // A fresh instance has to find the owning directory itself
+        let backend = Ext4DirentPaddingBackend::open(file.path()).unwrap();
+        backend.write_region(&region, 5, b"hidden").unwrap();
+        assert_eq!(backend.read_region(&region, 5, 6).unwrap(), b"hidden");
+
+        let after = backend.parser().read_block(physical).unwrap();
+        assert_ne!(after[4092..], before[4092..]);
+        let mut check = after.clone();
+        backend
+            .parser()
+            .update_dirent_checksum(docs, &mut check)
+            .unwrap();
+        assert_eq!(check, after);
+
+        let report = backend
+            .parser()
+            .lookup(Path::new("/docs/report.txt"))
+            .unwrap();
+        assert!(backend
+            .parser()
+            .read_inode(report)
+            .unwrap()
+            .is_regular_file());
+
+        let result = backend.write_region(&region, 0, &vec![1u8; region.available as usize + 1]);
+        assert!(matches!(result, Err(Error::InsufficientSpace { .. })));
+    }
+
+    #[test]
+    fn test_vfs_on_dirent_padding() {
+        use crate::{SlackVfs, VfsConfig};
+
+        let mut image = TestImage::new();
+        image.enable_metadata_csum();
+        for i in 0..8 {
+            let dir = image.mkdir(TestImage::ROOT, &format!("dir{}", i));
+            image.add_file(dir, "note.txt", b"n");
+        }
+        let file = image.build();
+        let meta_dir = tempfile::TempDir::new().unwrap();
+
+        {
+            let backend = Box::new(Ext4DirentPaddingBackend::open(file.path()).unwrap());
+            let mut vfs =
+                SlackVfs::create_with_backend(meta_dir.path(), "pw", VfsConfig::default(), backend)
+                    .unwrap();
+            vfs.create_file("/secret.txt", b"stored in directory blocks")
+                .unwrap();
+        }
+
+        let backend = Box::new(Ext4DirentPaddingBackend::open(file.path()).unwrap());
+        let vfs = SlackVfs::mount_with_backend(meta_dir.path(), "pw", backend).unwrap();
+        assert_eq!(
+            vfs.read_file("/secret.txt").unwrap(),
+            b"stored in directory blocks"
+        );
+    }
+}
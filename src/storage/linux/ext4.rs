@@ -1,10 +1,14 @@
 //! Ext4 file system parser for Linux.
 //!
 //! Parses ext4 superblock, block group descriptors, inodes, and extent trees
-//! to locate file blocks and calculate slack space offsets.
+//! to locate file blocks and calculate slack space offsets. Legacy ext2/ext3
+//! block maps and inline-data inodes are also understood.
 
+use super::dx_hash;
 use crate::error::{Error, Result};
+use crate::storage::image_file::{ImageFile, ReadAt};
 use crate::storage::linux::BlockDevice;
+use std::collections::HashSet;
 use std::path::Path;
 
 /// Ext4 magic number.
@@ -16,6 +20,90 @@ const SUPERBLOCK_SIZE: usize = 1024;
 /// Offset of the primary superblock (after boot sector).
 const SUPERBLOCK_OFFSET: u64 = 1024;
 
+/// Inode number of the root directory.
+pub const ROOT_INODE: u32 = 2;
+
+/// File type bits of `i_mode`.
+const S_IFMT: u16 = 0xF000;
+const S_IFREG: u16 = 0x8000;
+const S_IFDIR: u16 = 0x4000;
+
+/// Size of the fixed part of `ext4_dir_entry_2` (before the name).
+const DIR_ENTRY_HEADER_SIZE: usize = 8;
+
+/// Inode flag: directory is indexed with an htree.
+const EXT4_INDEX_FL: u32 = 0x1000;
+
+/// Inode flag: file data is mapped by an extent tree.
+const EXT4_EXTENTS_FL: u32 = 0x80000;
+
+/// Inode flag: file data is stored inside the inode.
+const EXT4_INLINE_DATA_FL: u32 = 0x1000_0000;
+
+/// Size of the `i_block` area in the inode.
+const I_BLOCK_SIZE: usize = 60;
+
+/// Number of direct block pointers in a block-mapped inode.
+const DIRECT_BLOCKS: usize = 12;
+
+/// Size of the original (revision 0) inode.
+const GOOD_OLD_INODE_SIZE: usize = 128;
+
+/// Magic number preceding in-inode extended attributes.
+const XATTR_IBODY_MAGIC: u32 = 0xEA02_0000;
+
+/// Extended attribute name index for the `system.` namespace.
+const XATTR_INDEX_SYSTEM: u8 = 7;
+
+/// Offset of `dx_root_info` in the first block of an indexed directory
+/// (after the fixed-size "." and ".." entries).
+const DX_ROOT_INFO_OFFSET: usize = 0x18;
+
+/// Offset of the `dx_countlimit` header in an htree interior node block
+/// (after the fake empty directory entry).
+const DX_NODE_ENTRIES_OFFSET: usize = 8;
+
+/// Extent tree node magic number.
+const EXTENT_MAGIC: u16 = 0xF30A;
+
+/// Size of an extent header, extent or index entry.
+const EXTENT_ENTRY_SIZE: usize = 12;
+
+/// Maximum extent tree depth allowed by the kernel.
+const EXTENT_MAX_DEPTH: u16 = 5;
+
+/// Extents longer than this are uninitialized (unwritten); the real
+/// length is `ee_len - EXT_INIT_MAX_LEN`.
+const EXT_INIT_MAX_LEN: u16 = 32768;
+
+/// Maximum htree depth (`indirect_levels` is at most 2, or 3 with largedir).
+const DX_MAX_LEVELS: u8 = 3;
+
+/// Read-only feature: metadata blocks and inodes carry crc32c checksums.
+const RO_COMPAT_METADATA_CSUM: u32 = 0x400;
+
+/// Incompatible feature: the checksum seed is stored in the superblock.
+const INCOMPAT_CSUM_SEED: u32 = 0x2000;
+
+/// Offsets of the low and high halves of the inode checksum.
+const I_CHECKSUM_LO: usize = 0x7C;
+const I_CHECKSUM_HI: usize = 0x82;
+
+/// Offset of `i_extra_isize`.
+const I_EXTRA_ISIZE: usize = 0x80;
+
+/// Size of the `ext4_dir_entry_tail` holding a directory block checksum.
+const DIRENT_TAIL_SIZE: usize = 12;
+
+/// File type marking a directory block checksum tail.
+const DIRENT_TAIL_FT: u8 = 0xDE;
+
+/// Inode flag: the directory's names are encrypted.
+const EXT4_ENCRYPT_FL: u32 = 0x800;
+
+/// Superblock flag: htree hashes were computed with unsigned chars.
+const FLAGS_UNSIGNED_HASH: u32 = 0x2;
+
 /// Ext4 superblock structure (partial - key fields only).
 #[derive(Debug, Clone)]
 pub struct Ext4Superblock {
@@ -28,6 +116,20 @@ pub struct Ext4Superblock {
     pub first_data_block: u32,
     pub desc_size: u16,
     pub feature_incompat: u32,
+    pub feature_ro_compat: u32,
+    /// Seed for metadata checksums, if `metadata_csum` is enabled.
+    pub csum_seed: Option<u32>,
+    /// Seed for htree name hashes (`s_hash_seed`).
+    pub hash_seed: [u32; 4],
+    /// Whether htree name hashes treat bytes as unsigned.
+    pub unsigned_hash: bool,
+}
+
+impl Ext4Superblock {
+    /// Check if inodes and metadata blocks carry crc32c checksums.
+    pub fn has_metadata_csum(&self) -> bool {
+        self.csum_seed.is_some()
+    }
 }
 
 /// Ext4 inode structure (partial - key fields only).
@@ -38,6 +140,59 @@ pub struct Ext4Inode {
     pub blocks: u32,
     pub flags: u32,
     pub extents: Vec<Ext4Extent>,
+    /// Inline file contents (`i_block` followed by the `system.data`
+    /// xattr value) for inodes with `EXT4_INLINE_DATA_FL`.
+    pub inline_data: Vec<u8>,
+}
+
+impl Ext4Inode {
+    /// Check if the file's data is stored inside the inode.
+    pub fn has_inline_data(&self) -> bool {
+        self.flags & EXT4_INLINE_DATA_FL != 0
+    }
+
+    /// Check if this inode is a regular file.
+    pub fn is_regular_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+
+    /// Check if this inode is a directory.
+    pub fn is_directory(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    /// Map a logical file block to its physical block number.
+    ///
+    /// Unwritten extents are included; their blocks read as zeros through
+    /// the file system but are still allocated on disk.
+    pub fn physical_block(&self, logical: u64) -> Option<u64> {
+        self.extents.iter().find_map(|e| {
+            let start = e.block as u64;
+            if logical >= start && logical < start + e.len as u64 {
+                Some(e.start + (logical - start))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// A directory entry (`ext4_dir_entry_2`).
+#[derive(Debug, Clone)]
+pub struct Ext4DirEntry {
+    /// Inode number the entry points to.
+    pub inode: u32,
+    /// Entry name.
+    pub name: String,
+    /// File type from the entry (1 = regular file, 2 = directory, ...).
+    pub file_type: u8,
+}
+
+impl Ext4DirEntry {
+    /// Check if this is the "." or ".." entry.
+    pub fn is_dot(&self) -> bool {
+        self.name == "." || self.name == ".."
+    }
 }
 
 /// Ext4 extent - represents a contiguous range of blocks.
@@ -49,24 +204,37 @@ pub struct Ext4Extent {
     pub len: u16,
     /// Physical block number where data starts.
     pub start: u64,
+    /// Whether the extent is preallocated but unwritten (reads as zeros).
+    pub unwritten: bool,
 }
 
 /// Parser for ext4 file systems.
 pub struct Ext4Parser {
-    device: BlockDevice,
+    device: Box<dyn ReadAt>,
     superblock: Ext4Superblock,
 }
 
 impl Ext4Parser {
     /// Create a new ext4 parser for the given block device.
     pub fn new(device_path: &Path) -> Result<Self> {
-        let device = BlockDevice::open(device_path)?;
-        
+        Self::from_device(BlockDevice::open(device_path)?)
+    }
+
+    /// Create a new ext4 parser for a file system image file.
+    pub fn open_image(image_path: &Path) -> Result<Self> {
+        Self::from_device(ImageFile::open(image_path)?)
+    }
+
+    /// Create a new ext4 parser over an already opened device.
+    pub fn from_device(device: impl ReadAt + 'static) -> Result<Self> {
         // Read superblock
         let sb_data = device.read_at(SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE)?;
         let superblock = Self::parse_superblock(&sb_data)?;
         
-        Ok(Self { device, superblock })
+        Ok(Self {
+            device: Box::new(device),
+            superblock,
+        })
     }
 
     /// Parse the ext4 superblock from raw bytes.
@@ -96,11 +264,40 @@ impl Ext4Parser {
         let blocks_per_group = u32::from_le_bytes([data[0x20], data[0x21], data[0x22], data[0x23]]);
         let inodes_per_group = u32::from_le_bytes([data[0x28], data[0x29], data[0x2A], data[0x2B]]);
 
-        let inode_size = u16::from_le_bytes([data[0x58], data[0x59]]);
+        // Revision 0 file systems have fixed 128-byte inodes
+        let rev_level = u32::from_le_bytes([data[0x4C], data[0x4D], data[0x4E], data[0x4F]]);
+        let inode_size = if rev_level == 0 {
+            GOOD_OLD_INODE_SIZE as u16
+        } else {
+            u16::from_le_bytes([data[0x58], data[0x59]])
+        };
         let first_data_block = u32::from_le_bytes([data[0x14], data[0x15], data[0x16], data[0x17]]);
 
         let desc_size = u16::from_le_bytes([data[0xFE], data[0xFF]]);
         let feature_incompat = u32::from_le_bytes([data[0x60], data[0x61], data[0x62], data[0x63]]);
+        let feature_ro_compat =
+            u32::from_le_bytes([data[0x64], data[0x65], data[0x66], data[0x67]]);
+
+        // The seed is either stored or derived from the file system UUID
+        let csum_seed = if feature_ro_compat & RO_COMPAT_METADATA_CSUM == 0 {
+            None
+        } else if feature_incompat & INCOMPAT_CSUM_SEED != 0 {
+            Some(u32::from_le_bytes([
+                data[0x270],
+                data[0x271],
+                data[0x272],
+                data[0x273],
+            ]))
+        } else {
+            Some(crc32c(!0, &data[0x68..0x78]))
+        };
+
+        let mut hash_seed = [0u32; 4];
+        for (i, word) in hash_seed.iter_mut().enumerate() {
+            let at = 0xEC + i * 4;
+            *word = u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        }
+        let flags = u32::from_le_bytes([data[0x160], data[0x161], data[0x162], data[0x163]]);
 
         Ok(Ext4Superblock {
             inodes_count,
@@ -112,6 +309,10 @@ impl Ext4Parser {
             first_data_block,
             desc_size: if desc_size == 0 { 32 } else { desc_size },
             feature_incompat,
+            feature_ro_compat,
+            csum_seed,
+            hash_seed,
+            unsigned_hash: flags & FLAGS_UNSIGNED_HASH != 0,
         })
     }
 
@@ -120,8 +321,25 @@ impl Ext4Parser {
         self.superblock.block_size
     }
 
+    /// Get the parsed superblock.
+    pub fn superblock(&self) -> &Ext4Superblock {
+        &self.superblock
+    }
+
     /// Read an inode by its number.
     pub fn read_inode(&self, inode_num: u32) -> Result<Ext4Inode> {
+        self.parse_inode(&self.read_raw_inode(inode_num)?)
+    }
+
+    /// Read the on-disk bytes of an inode (`inode_size` bytes).
+    pub fn read_raw_inode(&self, inode_num: u32) -> Result<Vec<u8>> {
+        let inode_offset = self.inode_offset(inode_num)?;
+        self.device
+            .read_at(inode_offset, self.superblock.inode_size as usize)
+    }
+
+    /// Get the absolute byte offset of an inode on the device.
+    pub fn inode_offset(&self, inode_num: u32) -> Result<u64> {
         if inode_num == 0 || inode_num > self.superblock.inodes_count {
             return Err(Error::DataCorruption(format!(
                 "Invalid inode number: {}",
@@ -133,7 +351,32 @@ impl Ext4Parser {
         let group = (inode_num - 1) / self.superblock.inodes_per_group;
         let index_in_group = (inode_num - 1) % self.superblock.inodes_per_group;
 
-        // Read block group descriptor to find inode table location
+        Ok((self.inode_table(group)? * self.superblock.block_size)
+            + (index_in_group as u64 * self.superblock.inode_size as u64))
+    }
+
+    /// Find the inode stored at an absolute byte offset on the device.
+    pub fn inode_at_offset(&self, offset: u64) -> Result<u32> {
+        let sb = &self.superblock;
+        let table_len = sb.inodes_per_group as u64 * sb.inode_size as u64;
+
+        for group in 0..sb.inodes_count.div_ceil(sb.inodes_per_group) {
+            let table_start = self.inode_table(group)? * sb.block_size;
+            if offset >= table_start && offset < table_start + table_len {
+                let index = ((offset - table_start) / sb.inode_size as u64) as u32;
+                return Ok(group * sb.inodes_per_group + index + 1);
+            }
+        }
+
+        Err(Error::DataCorruption(format!(
+            "Offset {} is not inside an inode table",
+            offset
+        )))
+    }
+
+    /// Get the first block of a group's inode table from its descriptor.
+    fn inode_table(&self, group: u32) -> Result<u64> {
+        // Block group descriptors follow the superblock's block
         let bgd_offset = if self.superblock.block_size == 1024 {
             2048  // Block 2 if block_size is 1024
         } else {
@@ -150,15 +393,63 @@ impl Ext4Parser {
         } else {
             0
         };
-        let inode_table = ((inode_table_hi as u64) << 32) | (inode_table_lo as u64);
+        Ok(((inode_table_hi as u64) << 32) | (inode_table_lo as u64))
+    }
+
+    /// Locate the unused space at the end of a raw inode.
+    ///
+    /// Returns `(start, len)` within the inode. The space past
+    /// `128 + i_extra_isize` is only unused when no in-inode extended
+    /// attributes live there. The first four bytes are left alone so the
+    /// area can never be mistaken for an xattr header.
+    pub fn unused_inode_space(raw: &[u8]) -> (usize, usize) {
+        if raw.len() <= GOOD_OLD_INODE_SIZE {
+            return (raw.len(), 0);
+        }
+
+        let extra_isize = u16::from_le_bytes([raw[I_EXTRA_ISIZE], raw[I_EXTRA_ISIZE + 1]]) as usize;
+        let xattr_start = GOOD_OLD_INODE_SIZE + extra_isize;
+        if !extra_isize.is_multiple_of(4) || xattr_start + 4 > raw.len() {
+            return (raw.len(), 0);
+        }
+
+        let header = &raw[xattr_start..xattr_start + 4];
+        if u32::from_le_bytes([header[0], header[1], header[2], header[3]]) == XATTR_IBODY_MAGIC {
+            return (raw.len(), 0);
+        }
+
+        let start = xattr_start + 4;
+        (start, raw.len() - start)
+    }
+
+    /// Recompute the checksum of a raw inode after modifying it.
+    ///
+    /// Does nothing unless the file system has `metadata_csum`.
+    pub fn update_inode_checksum(&self, inode_num: u32, raw: &mut [u8]) {
+        let Some(seed) = self.superblock.csum_seed else {
+            return;
+        };
 
-        // Calculate inode offset
-        let inode_offset = (inode_table * self.superblock.block_size)
-            + (index_in_group as u64 * self.superblock.inode_size as u64);
+        // The high half only exists if i_extra_isize covers it
+        let extra_isize = u16::from_le_bytes([raw[I_EXTRA_ISIZE], raw[I_EXTRA_ISIZE + 1]]) as usize;
+        let has_hi = raw.len() > GOOD_OLD_INODE_SIZE
+            && GOOD_OLD_INODE_SIZE + extra_isize >= I_CHECKSUM_HI + 2;
 
-        // Read inode
-        let inode_data = self.device.read_at(inode_offset, self.superblock.inode_size as usize)?;
-        self.parse_inode(&inode_data)
+        raw[I_CHECKSUM_LO..I_CHECKSUM_LO + 2].fill(0);
+        if has_hi {
+            raw[I_CHECKSUM_HI..I_CHECKSUM_HI + 2].fill(0);
+        }
+
+        let generation = &raw[0x64..0x68];
+        let mut crc = crc32c(seed, &inode_num.to_le_bytes());
+        crc = crc32c(crc, generation);
+        crc = crc32c(crc, raw);
+
+        raw[I_CHECKSUM_LO..I_CHECKSUM_LO + 2].copy_from_slice(&(crc as u16).to_le_bytes());
+        if has_hi {
+            raw[I_CHECKSUM_HI..I_CHECKSUM_HI + 2]
+                .copy_from_slice(&((crc >> 16) as u16).to_le_bytes());
+        }
     }
 
     /// Parse an inode from raw bytes.
@@ -171,9 +462,25 @@ impl Ext4Parser {
         let blocks = u32::from_le_bytes([data[0x1C], data[0x1D], data[0x1E], data[0x1F]]);
         let flags = u32::from_le_bytes([data[0x20], data[0x21], data[0x22], data[0x23]]);
 
-        // Parse extent tree from i_block (offset 0x28, 60 bytes)
-        let extent_data = &data[0x28..0x28 + 60];
-        let extents = self.parse_extent_tree(extent_data)?;
+        // Block mapping lives in i_block (offset 0x28, 60 bytes)
+        let i_block = &data[0x28..0x28 + I_BLOCK_SIZE];
+        let mut inline_data = Vec::new();
+
+        let extents = if flags & EXT4_INLINE_DATA_FL != 0 {
+            // No data blocks: contents live in i_block and system.data
+            inline_data.extend_from_slice(i_block);
+            if let Some(value) = Self::ibody_xattr(data, XATTR_INDEX_SYSTEM, b"data") {
+                inline_data.extend_from_slice(&value);
+            }
+            Vec::new()
+        } else if flags & EXT4_EXTENTS_FL != 0 {
+            self.parse_extent_tree(i_block)?
+        } else if blocks == 0 {
+            // Fast symlinks, device nodes and empty files own no blocks
+            Vec::new()
+        } else {
+            self.parse_block_map(i_block, size)?
+        };
 
         Ok(Ext4Inode {
             mode,
@@ -181,84 +488,1127 @@ impl Ext4Parser {
             blocks,
             flags,
             extents,
+            inline_data,
         })
     }
 
+    /// Find an extended attribute stored in the inode body (after
+    /// `i_extra_isize`), returning its value.
+    fn ibody_xattr(data: &[u8], name_index: u8, name: &[u8]) -> Option<Vec<u8>> {
+        if data.len() <= GOOD_OLD_INODE_SIZE + 2 {
+            return None;
+        }
+
+        let extra_isize = u16::from_le_bytes([data[0x80], data[0x81]]) as usize;
+        let start = GOOD_OLD_INODE_SIZE + extra_isize;
+        if start + 4 > data.len()
+            || u32::from_le_bytes([
+                data[start],
+                data[start + 1],
+                data[start + 2],
+                data[start + 3],
+            ]) != XATTR_IBODY_MAGIC
+        {
+            return None;
+        }
+
+        // Entries and value offsets are relative to the first entry
+        let base = start + 4;
+        let mut pos = base;
+        while pos + 16 <= data.len() {
+            let entry = &data[pos..];
+            if entry[..4] == [0, 0, 0, 0] {
+                break;
+            }
+
+            let e_name_len = entry[0] as usize;
+            let e_name_index = entry[1];
+            let value_offs = u16::from_le_bytes([entry[2], entry[3]]) as usize;
+            let value_size =
+                u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]) as usize;
+
+            if 16 + e_name_len > entry.len() {
+                break;
+            }
+            if e_name_index == name_index && &entry[16..16 + e_name_len] == name {
+                let value_start = base + value_offs;
+                return data
+                    .get(value_start..value_start + value_size)
+                    .map(|v| v.to_vec());
+            }
+
+            pos += (16 + e_name_len).div_ceil(4) * 4;
+        }
+
+        None
+    }
+
+    /// Map a legacy ext2/ext3 block map (12 direct pointers followed by
+    /// single, double and triple indirect blocks) into extents.
+    ///
+    /// Only blocks inside the file size are mapped; zero pointers are holes.
+    fn parse_block_map(&self, i_block: &[u8], size: u64) -> Result<Vec<Ext4Extent>> {
+        let block_count = size.div_ceil(self.superblock.block_size);
+        let ptrs_per_block = self.superblock.block_size / 4;
+        let pointer = |i: usize| {
+            u32::from_le_bytes([
+                i_block[i * 4],
+                i_block[i * 4 + 1],
+                i_block[i * 4 + 2],
+                i_block[i * 4 + 3],
+            ]) as u64
+        };
+
+        let mut extents = Vec::new();
+        let mut logical = 0u64;
+
+        for i in 0..DIRECT_BLOCKS {
+            if logical >= block_count {
+                return Ok(extents);
+            }
+            let physical = pointer(i);
+            if physical != 0 {
+                self.check_block(physical)?;
+                Self::push_mapped_block(&mut extents, logical, physical);
+            }
+            logical += 1;
+        }
+
+        for level in 1..=3u32 {
+            if logical >= block_count {
+                break;
+            }
+            let physical = pointer(DIRECT_BLOCKS + level as usize - 1);
+            if physical != 0 {
+                self.map_indirect(physical, level, logical, block_count, &mut extents)?;
+            }
+            logical += ptrs_per_block.pow(level);
+        }
+
+        Ok(extents)
+    }
+
+    /// Map the blocks reachable from an indirect block at the given level
+    /// (1 = single indirect), starting at `first_logical`.
+    fn map_indirect(
+        &self,
+        block: u64,
+        level: u32,
+        first_logical: u64,
+        block_count: u64,
+        extents: &mut Vec<Ext4Extent>,
+    ) -> Result<()> {
+        self.check_block(block)?;
+        let data = self.device.read_at(
+            block * self.superblock.block_size,
+            self.superblock.block_size as usize,
+        )?;
+        let ptrs_per_block = self.superblock.block_size / 4;
+        let child_span = ptrs_per_block.pow(level - 1);
+
+        for (i, chunk) in data.chunks_exact(4).enumerate() {
+            let logical = first_logical + i as u64 * child_span;
+            if logical >= block_count {
+                break;
+            }
+
+            let physical = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as u64;
+            if physical == 0 {
+                continue;
+            }
+
+            if level == 1 {
+                self.check_block(physical)?;
+                Self::push_mapped_block(extents, logical, physical);
+            } else {
+                self.map_indirect(physical, level - 1, logical, block_count, extents)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Append one mapped block, extending the previous extent when both
+    /// logical and physical numbers are contiguous.
+    fn push_mapped_block(extents: &mut Vec<Ext4Extent>, logical: u64, physical: u64) {
+        if let Some(last) = extents.last_mut() {
+            let len = last.len as u64;
+            if last.block as u64 + len == logical
+                && last.start + len == physical
+                && last.len < EXT_INIT_MAX_LEN
+            {
+                last.len += 1;
+                return;
+            }
+        }
+
+        extents.push(Ext4Extent {
+            block: logical as u32,
+            len: 1,
+            start: physical,
+            unwritten: false,
+        });
+    }
+
+    /// Ensure a block number lies inside the file system.
+    fn check_block(&self, block: u64) -> Result<()> {
+        if block >= self.superblock.blocks_count {
+            return Err(Error::DataCorruption(format!(
+                "Block pointer outside the file system: {}",
+                block
+            )));
+        }
+        Ok(())
+    }
+
     /// Parse extent tree from i_block area.
     fn parse_extent_tree(&self, data: &[u8]) -> Result<Vec<Ext4Extent>> {
-        // Extent header
         let magic = u16::from_le_bytes([data[0], data[1]]);
-        if magic != 0xF30A {
-            // Not using extents (old block map) - not supported
-            return Err(Error::Unsupported("Only extent-based files supported".to_string()));
+        if magic != EXTENT_MAGIC {
+            return Err(Error::DataCorruption(format!(
+                "Invalid extent header magic 0x{:04X}",
+                magic
+            )));
         }
 
-        let entries = u16::from_le_bytes([data[2], data[3]]);
         let depth = u16::from_le_bytes([data[6], data[7]]);
-
         let mut extents = Vec::new();
+        self.parse_extent_node(data, depth, &mut extents)?;
 
-        if depth == 0 {
-            // Leaf node - extents directly in this block
-            for i in 0..entries as usize {
-                let offset = 12 + i * 12; // Skip header (12 bytes), each extent is 12 bytes
-                if offset + 12 > data.len() {
-                    break;
-                }
+        // Index entries are sorted, but keep the result ordered regardless
+        extents.sort_by_key(|e| e.block);
+        Ok(extents)
+    }
+
+    /// Parse one extent tree node, following index entries into child
+    /// blocks until the leaves are reached.
+    ///
+    /// `expected_depth` is the depth the parent says this node has; it
+    /// guards against loops in corrupted trees.
+    fn parse_extent_node(
+        &self,
+        data: &[u8],
+        expected_depth: u16,
+        extents: &mut Vec<Ext4Extent>,
+    ) -> Result<()> {
+        let magic = u16::from_le_bytes([data[0], data[1]]);
+        let entries = u16::from_le_bytes([data[2], data[3]]) as usize;
+        let depth = u16::from_le_bytes([data[6], data[7]]);
+
+        if magic != EXTENT_MAGIC || depth != expected_depth || depth > EXTENT_MAX_DEPTH {
+            return Err(Error::DataCorruption(format!(
+                "Invalid extent node (magic 0x{:04X}, depth {})",
+                magic, depth
+            )));
+        }
+        if EXTENT_ENTRY_SIZE * (entries + 1) > data.len() {
+            return Err(Error::DataCorruption(format!(
+                "Extent node claims {} entries",
+                entries
+            )));
+        }
+
+        for i in 0..entries {
+            let offset = EXTENT_ENTRY_SIZE * (i + 1); // Skip header
+            let e = &data[offset..offset + EXTENT_ENTRY_SIZE];
+            let block = u32::from_le_bytes([e[0], e[1], e[2], e[3]]);
 
-                let block = u32::from_le_bytes([
-                    data[offset],
-                    data[offset + 1],
-                    data[offset + 2],
-                    data[offset + 3],
-                ]);
-                let len = u16::from_le_bytes([data[offset + 4], data[offset + 5]]);
-                let start_hi = u16::from_le_bytes([data[offset + 6], data[offset + 7]]);
-                let start_lo = u32::from_le_bytes([
-                    data[offset + 8],
-                    data[offset + 9],
-                    data[offset + 10],
-                    data[offset + 11],
-                ]);
+            if depth == 0 {
+                // Leaf node - ext4_extent
+                let raw_len = u16::from_le_bytes([e[4], e[5]]);
+                let start_hi = u16::from_le_bytes([e[6], e[7]]);
+                let start_lo = u32::from_le_bytes([e[8], e[9], e[10], e[11]]);
                 let start = ((start_hi as u64) << 32) | (start_lo as u64);
 
-                extents.push(Ext4Extent { block, len, start });
+                let (len, unwritten) = if raw_len > EXT_INIT_MAX_LEN {
+                    (raw_len - EXT_INIT_MAX_LEN, true)
+                } else {
+                    (raw_len, false)
+                };
+
+                extents.push(Ext4Extent {
+                    block,
+                    len,
+                    start,
+                    unwritten,
+                });
+            } else {
+                // Index node - ext4_extent_idx pointing to the next level
+                let leaf_lo = u32::from_le_bytes([e[4], e[5], e[6], e[7]]);
+                let leaf_hi = u16::from_le_bytes([e[8], e[9]]);
+                let leaf = ((leaf_hi as u64) << 32) | (leaf_lo as u64);
+
+                if leaf == 0 {
+                    return Err(Error::DataCorruption(
+                        "Extent index points at block 0".to_string(),
+                    ));
+                }
+                self.check_block(leaf)?;
+
+                let child = self.device.read_at(
+                    leaf * self.superblock.block_size,
+                    self.superblock.block_size as usize,
+                )?;
+                self.parse_extent_node(&child, depth - 1, extents)?;
             }
+        }
+
+        Ok(())
+    }
+
+    /// Read one logical block of a file or directory.
+    pub fn read_file_block(&self, inode: &Ext4Inode, logical: u64) -> Result<Vec<u8>> {
+        let physical = inode.physical_block(logical).ok_or_else(|| {
+            Error::DataCorruption(format!("Logical block {} is not mapped", logical))
+        })?;
+        self.read_block(physical)
+    }
+
+    /// Read one physical block.
+    pub fn read_block(&self, physical: u64) -> Result<Vec<u8>> {
+        self.check_block(physical)?;
+        self.device.read_at(
+            physical * self.superblock.block_size,
+            self.superblock.block_size as usize,
+        )
+    }
+
+    /// Resolve an absolute path inside the file system to an inode number.
+    pub fn lookup(&self, path: &Path) -> Result<u32> {
+        let mut inode_num = ROOT_INODE;
+
+        for component in path.components() {
+            let name = match component {
+                std::path::Component::RootDir | std::path::Component::CurDir => continue,
+                std::path::Component::Normal(name) => name.to_string_lossy(),
+                _ => {
+                    return Err(Error::InvalidPath(format!(
+                        "Unsupported path component in {}",
+                        path.display()
+                    )))
+                }
+            };
+
+            inode_num = self
+                .find_entry(inode_num, &name)
+                .map_err(|e| match e {
+                    Error::NotADirectory(_) => Error::NotADirectory(path.display().to_string()),
+                    e => e,
+                })?
+                .ok_or_else(|| Error::FileNotFound(path.display().to_string()))?;
+        }
+
+        Ok(inode_num)
+    }
+
+    /// Look a name up in one directory.
+    ///
+    /// Indexed directories are searched by descending the htree with the
+    /// name's hash. Other directories, and htrees whose hash cannot be
+    /// computed here, are scanned entry by entry.
+    fn find_entry(&self, inode_num: u32, name: &str) -> Result<Option<u32>> {
+        let dir = self.read_inode(inode_num)?;
+        if !dir.is_directory() {
+            return Err(Error::NotADirectory(format!("inode {}", inode_num)));
+        }
+
+        if dir.flags & EXT4_INDEX_FL != 0
+            && dir.flags & EXT4_ENCRYPT_FL == 0
+            && !dir.has_inline_data()
+        {
+            let root = self.read_file_block(&dir, 0)?;
+            let (hash_version, _, _) = Self::dx_root_info(&root)?;
+            let sb = &self.superblock;
+            let hash = dx_hash::name_hash(
+                name.as_bytes(),
+                hash_version,
+                &sb.hash_seed,
+                sb.unsigned_hash,
+            );
+            if let Some(hash) = hash {
+                return self.htree_find(&dir, &root, hash, name);
+            }
+        }
+
+        Ok(self
+            .read_dir(inode_num)?
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.inode))
+    }
+
+    /// List the entries of a directory, including "." and "..".
+    ///
+    /// Linear directories are read block by block. Indexed (htree)
+    /// directories are read by walking the dx tree down to its leaf blocks,
+    /// so interior index blocks are never misread as entries.
+    pub fn read_dir(&self, inode_num: u32) -> Result<Vec<Ext4DirEntry>> {
+        let dir = self.read_inode(inode_num)?;
+        if !dir.is_directory() {
+            return Err(Error::NotADirectory(format!("inode {}", inode_num)));
+        }
+
+        let mut entries = Vec::new();
+
+        if dir.has_inline_data() {
+            // Inline directories store the parent inode instead of "." and
+            // "..", then entries in the rest of i_block and in system.data.
+            let data = &dir.inline_data;
+            if data.len() < I_BLOCK_SIZE {
+                return Err(Error::DataCorruption(
+                    "Inline directory too small".to_string(),
+                ));
+            }
+            let parent = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            for (name, inode) in [(".", inode_num), ("..", parent)] {
+                entries.push(Ext4DirEntry {
+                    inode,
+                    name: name.to_string(),
+                    file_type: 2,
+                });
+            }
+            Self::parse_dir_block(&data[4..I_BLOCK_SIZE], &mut entries)?;
+            Self::parse_dir_block(&data[I_BLOCK_SIZE..], &mut entries)?;
         } else {
-            // Internal node - would need to follow index entries
-            // For now, return error - full implementation would recursively read index blocks
-            return Err(Error::Unsupported("Multi-level extent trees not yet supported".to_string()));
+            if dir.flags & EXT4_INDEX_FL != 0 {
+                // The root block holds only "." and ".." ahead of the index.
+                let root = self.read_file_block(&dir, 0)?;
+                Self::parse_dir_block(&root, &mut entries)?;
+            }
+            for logical in self.entry_blocks(&dir)? {
+                let block = self.read_file_block(&dir, logical)?;
+                Self::parse_dir_block(&block, &mut entries)?;
+            }
         }
 
-        Ok(extents)
+        Ok(entries)
+    }
+
+    /// Collect the logical numbers of a block-backed directory's blocks
+    /// that hold linear entries: every block of a linear directory, or the
+    /// leaves of an htree.
+    fn entry_blocks(&self, dir: &Ext4Inode) -> Result<Vec<u64>> {
+        if dir.flags & EXT4_INDEX_FL != 0 {
+            let root = self.read_file_block(dir, 0)?;
+            self.htree_leaves(dir, &root)
+        } else {
+            Ok((0..dir.size.div_ceil(self.superblock.block_size)).collect())
+        }
+    }
+
+    /// Get the physical blocks of a directory that hold linear entries.
+    ///
+    /// Inline directories have none. Encrypted directories are rejected,
+    /// since their entries may carry a hash after the name.
+    pub fn dir_entry_blocks(&self, inode_num: u32) -> Result<Vec<u64>> {
+        let dir = self.read_inode(inode_num)?;
+        if !dir.is_directory() {
+            return Err(Error::NotADirectory(format!("inode {}", inode_num)));
+        }
+        if dir.flags & EXT4_ENCRYPT_FL != 0 {
+            return Err(Error::Unsupported(format!(
+                "inode {} is an encrypted directory",
+                inode_num
+            )));
+        }
+        if dir.has_inline_data() {
+            return Ok(Vec::new());
+        }
+
+        self.entry_blocks(&dir)?
+            .into_iter()
+            .map(|logical| {
+                dir.physical_block(logical).ok_or_else(|| {
+                    Error::DataCorruption(format!("Directory block {} is not mapped", logical))
+                })
+            })
+            .collect()
+    }
+
+    /// Check if a directory block ends with a checksum tail.
+    pub fn has_dirent_tail(block: &[u8]) -> bool {
+        let Some(tail) = block
+            .len()
+            .checked_sub(DIRENT_TAIL_SIZE)
+            .map(|at| &block[at..])
+        else {
+            return false;
+        };
+        tail[..4] == [0, 0, 0, 0]
+            && u16::from_le_bytes([tail[4], tail[5]]) as usize == DIRENT_TAIL_SIZE
+            && tail[6] == 0
+            && tail[7] == DIRENT_TAIL_FT
+    }
+
+    /// Locate the unused bytes after the last entry of a directory block.
+    ///
+    /// Returns `(start, len)` within the block: from the end of the last
+    /// entry's name to the end of its `rec_len`, which stops short of the
+    /// checksum tail if there is one.
+    pub fn dirent_padding(block: &[u8]) -> Result<(usize, usize)> {
+        let end = if Self::has_dirent_tail(block) {
+            block.len() - DIRENT_TAIL_SIZE
+        } else {
+            block.len()
+        };
+
+        let mut pos = 0;
+        while pos + DIR_ENTRY_HEADER_SIZE <= end {
+            let inode =
+                u32::from_le_bytes([block[pos], block[pos + 1], block[pos + 2], block[pos + 3]]);
+            let rec_len = u16::from_le_bytes([block[pos + 4], block[pos + 5]]) as usize;
+            let name_len = block[pos + 6] as usize;
+
+            if rec_len < DIR_ENTRY_HEADER_SIZE || pos + rec_len > end {
+                return Err(Error::DataCorruption(format!(
+                    "Invalid directory entry length {} at offset {}",
+                    rec_len, pos
+                )));
+            }
+
+            if pos + rec_len == end {
+                // An unused entry (inode 0) keeps only its header
+                let used = if inode == 0 {
+                    DIR_ENTRY_HEADER_SIZE
+                } else {
+                    (DIR_ENTRY_HEADER_SIZE + name_len).next_multiple_of(4)
+                };
+                return Ok((pos + used.min(rec_len), rec_len - used.min(rec_len)));
+            }
+
+            pos += rec_len;
+        }
+
+        Err(Error::DataCorruption(
+            "Directory block has no final entry".to_string(),
+        ))
+    }
+
+    /// Recompute the checksum tail of a directory block after modifying it.
+    ///
+    /// Does nothing unless the file system has `metadata_csum` and the
+    /// block has a tail.
+    pub fn update_dirent_checksum(&self, dir_inode_num: u32, block: &mut [u8]) -> Result<()> {
+        let Some(seed) = self.superblock.csum_seed else {
+            return Ok(());
+        };
+        if !Self::has_dirent_tail(block) {
+            return Ok(());
+        }
+
+        let raw = self.read_raw_inode(dir_inode_num)?;
+        let tail = block.len() - DIRENT_TAIL_SIZE;
+        let mut crc = crc32c(seed, &dir_inode_num.to_le_bytes());
+        crc = crc32c(crc, &raw[0x64..0x68]);
+        crc = crc32c(crc, &block[..tail]);
+        block[tail + 8..tail + 12].copy_from_slice(&crc.to_le_bytes());
+        Ok(())
+    }
+
+    /// Read an htree root's `dx_root_info`: the hash version, the offset
+    /// of the root's index entries and the number of index levels below it.
+    fn dx_root_info(root: &[u8]) -> Result<(u8, usize, u8)> {
+        if root.len() < DX_ROOT_INFO_OFFSET + 8 {
+            return Err(Error::DataCorruption(
+                "Htree root block too small".to_string(),
+            ));
+        }
+
+        let info = &root[DX_ROOT_INFO_OFFSET..];
+        let levels = info[6];
+        if levels >= DX_MAX_LEVELS {
+            return Err(Error::Unsupported(format!(
+                "Htree depth {} not supported",
+                levels
+            )));
+        }
+
+        Ok((info[4], DX_ROOT_INFO_OFFSET + info[5] as usize, levels))
+    }
+
+    /// Collect the logical leaf block numbers of an htree directory.
+    fn htree_leaves(&self, dir: &Ext4Inode, root: &[u8]) -> Result<Vec<u64>> {
+        let (_, offset, levels) = Self::dx_root_info(root)?;
+
+        let mut nodes: Vec<u64> = Self::parse_dx_entries(root, offset)?
+            .into_iter()
+            .map(|(_, logical)| logical)
+            .collect();
+        for _ in 0..levels {
+            let mut next = Vec::new();
+            for logical in nodes {
+                let block = self.read_file_block(dir, logical)?;
+                let entries = Self::parse_dx_entries(&block, DX_NODE_ENTRIES_OFFSET)?;
+                next.extend(entries.into_iter().map(|(_, logical)| logical));
+            }
+            nodes = next;
+        }
+
+        Ok(nodes)
+    }
+
+    /// Find a name in an htree directory by its hash.
+    ///
+    /// Descends from the root to the leaf covering `hash` and scans it.
+    /// Names with equal hashes may spill into the following leaves, whose
+    /// index entries then carry the hash with its low bit set, so those
+    /// are scanned as well.
+    fn htree_find(
+        &self,
+        dir: &Ext4Inode,
+        root: &[u8],
+        hash: u32,
+        name: &str,
+    ) -> Result<Option<u32>> {
+        let (_, offset, levels) = Self::dx_root_info(root)?;
+        let depth = levels as usize + 1;
+
+        // Index nodes from the root down, with the entry taken in each
+        let mut path = Vec::with_capacity(depth);
+        let mut entries = Self::parse_dx_entries(root, offset)?;
+        loop {
+            let at = entries
+                .partition_point(|&(start, _)| start <= hash)
+                .saturating_sub(1);
+            let logical = entries[at].1;
+            path.push((entries, at));
+            if path.len() == depth {
+                break;
+            }
+            let node = self.read_file_block(dir, logical)?;
+            entries = Self::parse_dx_entries(&node, DX_NODE_ENTRIES_OFFSET)?;
+        }
+
+        loop {
+            let (entries, at) = &path[depth - 1];
+            let leaf = self.read_file_block(dir, entries[*at].1)?;
+            let mut found = Vec::new();
+            Self::parse_dir_block(&leaf, &mut found)?;
+            if let Some(entry) = found.into_iter().find(|entry| entry.name == name) {
+                return Ok(Some(entry.inode));
+            }
+
+            // Step to the next leaf, climbing as far as needed
+            while path
+                .last()
+                .is_some_and(|(entries, at)| at + 1 == entries.len())
+            {
+                path.pop();
+            }
+            let Some((entries, at)) = path.last_mut() else {
+                return Ok(None);
+            };
+            *at += 1;
+            if entries[*at].0 & !1 != hash {
+                return Ok(None);
+            }
+            let mut logical = entries[*at].1;
+            while path.len() < depth {
+                let node = self.read_file_block(dir, logical)?;
+                let entries = Self::parse_dx_entries(&node, DX_NODE_ENTRIES_OFFSET)?;
+                logical = entries[0].1;
+                path.push((entries, 0));
+            }
+        }
+    }
+
+    /// Parse the `dx_entry` array starting at `offset` and return its
+    /// `(hash, logical block)` pairs.
+    ///
+    /// The first entry is a `dx_countlimit` header whose second half holds
+    /// the block for hashes below the first explicit entry; it is returned
+    /// with hash 0.
+    fn parse_dx_entries(block: &[u8], offset: usize) -> Result<Vec<(u32, u64)>> {
+        if offset + 8 > block.len() {
+            return Err(Error::DataCorruption(
+                "Htree node header out of range".to_string(),
+            ));
+        }
+
+        let limit = u16::from_le_bytes([block[offset], block[offset + 1]]) as usize;
+        let count = u16::from_le_bytes([block[offset + 2], block[offset + 3]]) as usize;
+        if count == 0 || count > limit || offset + count * 8 > block.len() {
+            return Err(Error::DataCorruption(format!(
+                "Invalid htree node (count {}, limit {})",
+                count, limit
+            )));
+        }
+
+        let word = |pos: usize| {
+            u32::from_le_bytes([block[pos], block[pos + 1], block[pos + 2], block[pos + 3]])
+        };
+        Ok((0..count)
+            .map(|i| {
+                let pos = offset + i * 8;
+                let hash = if i == 0 { 0 } else { word(pos) };
+                (hash, word(pos + 4) as u64)
+            })
+            .collect())
+    }
+
+    /// Parse the linear directory entries in one block.
+    ///
+    /// Entries with inode 0 (deleted entries, checksum tails and the fake
+    /// entries covering htree index data) are skipped.
+    fn parse_dir_block(block: &[u8], entries: &mut Vec<Ext4DirEntry>) -> Result<()> {
+        let mut pos = 0;
+
+        while pos + DIR_ENTRY_HEADER_SIZE <= block.len() {
+            let inode =
+                u32::from_le_bytes([block[pos], block[pos + 1], block[pos + 2], block[pos + 3]]);
+            let rec_len = u16::from_le_bytes([block[pos + 4], block[pos + 5]]) as usize;
+            let name_len = block[pos + 6] as usize;
+            let file_type = block[pos + 7];
+
+            if rec_len < DIR_ENTRY_HEADER_SIZE || pos + rec_len > block.len() {
+                return Err(Error::DataCorruption(format!(
+                    "Invalid directory entry length {} at offset {}",
+                    rec_len, pos
+                )));
+            }
+
+            let name_start = pos + DIR_ENTRY_HEADER_SIZE;
+            if inode != 0 && name_start + name_len <= pos + rec_len {
+                entries.push(Ext4DirEntry {
+                    inode,
+                    name: String::from_utf8_lossy(&block[name_start..name_start + name_len])
+                        .into_owned(),
+                    file_type,
+                });
+            }
+
+            pos += rec_len;
+        }
+
+        Ok(())
+    }
+
+    /// Recursively list every regular file below a directory.
+    ///
+    /// Returns absolute paths inside the file system with their inode
+    /// numbers. Fails if any directory below `root` cannot be read.
+    pub fn walk_files(&self, root: &Path) -> Result<Vec<(std::path::PathBuf, u32)>> {
+        Ok(self.walk(root)?.0)
+    }
+
+    /// Recursively list a directory and every directory below it.
+    ///
+    /// Returns absolute paths inside the file system with their inode
+    /// numbers, starting with `root` itself.
+    pub fn walk_dirs(&self, root: &Path) -> Result<Vec<(std::path::PathBuf, u32)>> {
+        Ok(self.walk(root)?.1)
+    }
+
+    /// Walk the tree below `root`, returning its regular files and
+    /// directories, each sorted by path.
+    ///
+    /// A directory reached a second time (a corrupt image can link one
+    /// into its own subtree) is not descended into again.
+    #[allow(clippy::type_complexity)]
+    fn walk(
+        &self,
+        root: &Path,
+    ) -> Result<(
+        Vec<(std::path::PathBuf, u32)>,
+        Vec<(std::path::PathBuf, u32)>,
+    )> {
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        let mut visited = HashSet::new();
+        let mut pending = vec![(root.to_path_buf(), self.lookup(root)?)];
+
+        while let Some((dir_path, dir_inode)) = pending.pop() {
+            if !visited.insert(dir_inode) {
+                continue;
+            }
+            dirs.push((dir_path.clone(), dir_inode));
+
+            let entries = self.read_dir(dir_inode)?;
+            for entry in entries.into_iter().filter(|e| !e.is_dot()) {
+                let path = dir_path.join(&entry.name);
+                let file_type = match entry.file_type {
+                    // Without the filetype feature, fall back to the inode mode
+                    0 => {
+                        let inode = self.read_inode(entry.inode)?;
+                        if inode.is_regular_file() {
+                            1
+                        } else if inode.is_directory() {
+                            2
+                        } else {
+                            continue;
+                        }
+                    }
+                    t => t,
+                };
+                match file_type {
+                    1 => files.push((path, entry.inode)),
+                    2 => pending.push((path, entry.inode)),
+                    _ => {}
+                }
+            }
+        }
+
+        files.sort();
+        dirs.sort();
+        Ok((files, dirs))
     }
 
     /// Get the physical block offset and slack space for a file.
+    ///
+    /// Returns `(slack_start, slack_available)` where `slack_start` is the
+    /// absolute byte offset on the device of the first byte after EOF in the
+    /// file's last allocated block. Block-aligned files have no slack.
     pub fn get_file_slack(&self, inode: &Ext4Inode) -> Result<(u64, u64)> {
-        if inode.extents.is_empty() {
-            return Err(Error::DataCorruption("File has no extents".to_string()));
+        let block_size = self.superblock.block_size;
+
+        // Inline files own no data block, so there is nothing after EOF
+        if inode.size == 0 || inode.has_inline_data() {
+            return Ok((0, 0));
         }
 
-        // Find the last extent
-        let last_extent = inode.extents.last().unwrap();
-        
-        // Calculate the physical location of the last block
-        let blocks_used_in_extent = ((inode.size + self.superblock.block_size - 1)
-            / self.superblock.block_size) as u32
-            - last_extent.block;
-        
-        if blocks_used_in_extent == 0 || blocks_used_in_extent > last_extent.len as u32 {
-            return Err(Error::DataCorruption("Invalid extent coverage".to_string()));
+        // Find the extent covering the last logical block of the file. This is
+        // not necessarily the last extent: fallocate(KEEP_SIZE) can leave
+        // allocated blocks past EOF. A sparse file may end in a hole, which
+        // has no block to hide data in.
+        let last_logical = (inode.size - 1) / block_size;
+        let Some(extent) = inode.extents.iter().find(|e| {
+            let start = e.block as u64;
+            last_logical >= start && last_logical < start + e.len as u64
+        }) else {
+            return Ok((0, 0));
+        };
+
+        // An unwritten last block is zeroed by the kernel when the file is
+        // next written, so it has no usable slack.
+        if extent.unwritten {
+            return Ok((0, 0));
         }
 
-        let last_block_phys = last_extent.start + (blocks_used_in_extent as u64 - 1);
-        let last_block_offset = last_block_phys * self.superblock.block_size;
+        let last_block_phys = extent.start + (last_logical - extent.block as u64);
+        let last_block_offset = last_block_phys * block_size;
 
         // Slack starts at file_size mod block_size within the last block
-        let slack_offset_in_block = inode.size % self.superblock.block_size;
+        let slack_offset_in_block = inode.size % block_size;
+        if slack_offset_in_block == 0 {
+            return Ok((last_block_offset + block_size, 0));
+        }
+
         let slack_start = last_block_offset + slack_offset_in_block;
-        let slack_available = self.superblock.block_size - slack_offset_in_block;
+        let slack_available = block_size - slack_offset_in_block;
 
         Ok((slack_start, slack_available))
     }
 }
+
+/// Update a crc32c (Castagnoli) checksum, without the usual pre- and
+/// post-inversion, as ext4 uses it.
+pub(crate) fn crc32c(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0x82F6_3B78 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::linux::test_image::TestImage;
+
+    fn names(entries: &[Ext4DirEntry]) -> Vec<&str> {
+        let mut names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_read_dir_linear() {
+        let mut image = TestImage::new();
+        image.add_file(TestImage::ROOT, "a.txt", b"a");
+        image.mkdir(TestImage::ROOT, "docs");
+        let file = image.build();
+
+        let parser = Ext4Parser::open_image(file.path()).unwrap();
+        let entries = parser.read_dir(ROOT_INODE).unwrap();
+
+        assert_eq!(names(&entries), vec![".", "..", "a.txt", "docs"]);
+    }
+
+    #[test]
+    fn test_read_dir_htree() {
+        let mut image = TestImage::new();
+        let big = image.mkdir_indexed(TestImage::ROOT, "big", 4);
+        for i in 0..10 {
+            image.add_file(big, &format!("file{}.txt", i), b"x");
+        }
+        let file = image.build();
+
+        let parser = Ext4Parser::open_image(file.path()).unwrap();
+        let inode = parser.lookup(Path::new("/big")).unwrap();
+        assert_ne!(parser.read_inode(inode).unwrap().flags & EXT4_INDEX_FL, 0);
+
+        let entries = parser.read_dir(inode).unwrap();
+        assert_eq!(entries.iter().filter(|e| !e.is_dot()).count(), 10);
+
+        // Hashed lookups land in the right leaf for every name
+        for i in 0..10 {
+            let path = format!("/big/file{}.txt", i);
+            let found = parser.lookup(Path::new(&path)).unwrap();
+            assert!(parser.read_inode(found).unwrap().is_regular_file());
+        }
+        assert!(matches!(
+            parser.lookup(Path::new("/big/file10.txt")),
+            Err(Error::FileNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_lookup_errors() {
+        let mut image = TestImage::new();
+        image.add_file(TestImage::ROOT, "a.txt", b"a");
+        let file = image.build();
+
+        let parser = Ext4Parser::open_image(file.path()).unwrap();
+        assert_eq!(parser.lookup(Path::new("/")).unwrap(), ROOT_INODE);
+        assert!(matches!(
+            parser.lookup(Path::new("/missing")),
+            Err(Error::FileNotFound(_))
+        ));
+        assert!(matches!(
+            parser.lookup(Path::new("/a.txt/child")),
+            Err(Error::NotADirectory(_))
+        ));
+    }
+
+    #[test]
+    fn test_walk_files() {
+        let mut image = TestImage::new();
+        let docs = image.mkdir(TestImage::ROOT, "docs");
+        let nested = image.mkdir(docs, "nested");
+        image.add_file(TestImage::ROOT, "top.txt", b"t");
+        image.add_file(nested, "deep.txt", b"d");
+        let file = image.build();
+
+        let parser = Ext4Parser::open_image(file.path()).unwrap();
+        let files: Vec<_> = parser
+            .walk_files(Path::new("/"))
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+
+        assert_eq!(
+            files,
+            vec![
+                std::path::PathBuf::from("/docs/nested/deep.txt"),
+                std::path::PathBuf::from("/top.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walk_cycles_and_errors() {
+        let mut image = TestImage::new();
+        let docs = image.mkdir(TestImage::ROOT, "docs");
+        image.link(docs, "again", docs, 2);
+        image.add_file(docs, "a.txt", b"a");
+        let file = image.build();
+
+        // A directory linked into itself is listed once
+        let parser = Ext4Parser::open_image(file.path()).unwrap();
+        let dirs = parser.walk_dirs(Path::new("/")).unwrap();
+        assert_eq!(dirs.iter().filter(|(_, ino)| *ino == docs).count(), 1);
+        assert_eq!(parser.walk_files(Path::new("/")).unwrap().len(), 1);
+
+        // An entry typed as a directory whose inode is not one
+        let mut image = TestImage::new();
+        let a = image.add_file(TestImage::ROOT, "a.txt", b"a");
+        image.link(TestImage::ROOT, "bogus", a, 2);
+        let file = image.build();
+
+        let parser = Ext4Parser::open_image(file.path()).unwrap();
+        assert!(matches!(
+            parser.walk_files(Path::new("/")),
+            Err(Error::NotADirectory(_))
+        ));
+    }
+
+    #[test]
+    fn test_multi_level_extent_tree() {
+        let mut image = TestImage::new();
+        let block = TestImage::BLOCK_SIZE as usize;
+        let content: Vec<u8> = (0..6 * block + 100).map(|i| (i % 251) as u8).collect();
+        let layout = [(1, false); 7];
+        image.add_fragmented_file(TestImage::ROOT, "frag.bin", &content, &layout);
+        let file = image.build();
+
+        let parser = Ext4Parser::open_image(file.path()).unwrap();
+        let inode = parser
+            .read_inode(parser.lookup(Path::new("/frag.bin")).unwrap())
+            .unwrap();
+        assert_eq!(inode.extents.len(), 7);
+
+        // Every block reads back through the tree
+        for logical in 0..7 {
+            let data = parser.read_file_block(&inode, logical).unwrap();
+            let from = logical as usize * block;
+            let to = (from + block).min(content.len());
+            assert_eq!(&data[..to - from], &content[from..to]);
+        }
+
+        let (slack_start, available) = parser.get_file_slack(&inode).unwrap();
+        let last = inode.extents.last().unwrap();
+        assert_eq!(slack_start, last.start * TestImage::BLOCK_SIZE + 100);
+        assert_eq!(available, TestImage::BLOCK_SIZE - 100);
+    }
+
+    #[test]
+    fn test_unwritten_extents() {
+        let mut image = TestImage::new();
+        let block = TestImage::BLOCK_SIZE as usize;
+
+        // Preallocated past EOF: slack comes from the written extent
+        image.add_fragmented_file(
+            TestImage::ROOT,
+            "prealloc.bin",
+            &vec![1u8; block + 10],
+            &[(2, false), (3, true)],
+        );
+        // Last block itself unwritten: no usable slack
+        image.add_fragmented_file(
+            TestImage::ROOT,
+            "unwritten.bin",
+            &vec![1u8; block + 10],
+            &[(1, false), (1, true)],
+        );
+        let file = image.build();
+
+        let parser = Ext4Parser::open_image(file.path()).unwrap();
+
+        let inode = parser
+            .read_inode(parser.lookup(Path::new("/prealloc.bin")).unwrap())
+            .unwrap();
+        assert!(inode.extents[1].unwritten);
+        assert_eq!(inode.extents[1].len, 3);
+        let (slack_start, available) = parser.get_file_slack(&inode).unwrap();
+        assert_eq!(
+            slack_start,
+            (inode.extents[0].start + 1) * TestImage::BLOCK_SIZE + 10
+        );
+        assert_eq!(available, TestImage::BLOCK_SIZE - 10);
+
+        let inode = parser
+            .read_inode(parser.lookup(Path::new("/unwritten.bin")).unwrap())
+            .unwrap();
+        assert_eq!(parser.get_file_slack(&inode).unwrap().1, 0);
+    }
+
+    #[test]
+    fn test_block_map_indirect() {
+        let block = TestImage::BLOCK_SIZE as usize;
+        // 12 direct + 1024 single indirect + a few double indirect blocks
+        let len = (12 + 1024 + 3) * block + 100;
+        let content: Vec<u8> = (0..len).map(|i| (i / block) as u8).collect();
+
+        let mut image = TestImage::new();
+        image.add_blockmap_file(TestImage::ROOT, "big.bin", &content, &[]);
+        image.add_blockmap_file(TestImage::ROOT, "small.bin", &content[..3 * block + 7], &[]);
+        let file = image.build();
+
+        let parser = Ext4Parser::open_image(file.path()).unwrap();
+
+        let inode = parser
+            .read_inode(parser.lookup(Path::new("/big.bin")).unwrap())
+            .unwrap();
+        let mapped: u64 = inode.extents.iter().map(|e| e.len as u64).sum();
+        assert_eq!(mapped, 12 + 1024 + 4);
+        for logical in [0u64, 11, 12, 1035, 1036, 1039] {
+            let data = parser.read_file_block(&inode, logical).unwrap();
+            assert_eq!(
+                data[0],
+                content[logical as usize * block],
+                "block {}",
+                logical
+            );
+        }
+
+        let (slack_start, available) = parser.get_file_slack(&inode).unwrap();
+        assert_eq!(available, TestImage::BLOCK_SIZE - 100);
+        assert_eq!(
+            slack_start,
+            inode.physical_block(1039).unwrap() * TestImage::BLOCK_SIZE + 100
+        );
+
+        let inode = parser
+            .read_inode(parser.lookup(Path::new("/small.bin")).unwrap())
+            .unwrap();
+        assert_eq!(inode.extents.len(), 1);
+        assert_eq!(
+            parser.get_file_slack(&inode).unwrap().1,
+            TestImage::BLOCK_SIZE - 7
+        );
+    }
+
+    #[test]
+    fn test_block_map_holes() {
+        let block = TestImage::BLOCK_SIZE as usize;
+        let content = vec![5u8; 4 * block + 20];
+
+        let mut image = TestImage::new();
+        image.add_blockmap_file(TestImage::ROOT, "sparse.bin", &content, &[1, 2]);
+        image.add_blockmap_file(TestImage::ROOT, "tail_hole.bin", &content, &[4]);
+        let file = image.build();
+
+        let parser = Ext4Parser::open_image(file.path()).unwrap();
+
+        let inode = parser
+            .read_inode(parser.lookup(Path::new("/sparse.bin")).unwrap())
+            .unwrap();
+        assert_eq!(inode.extents.len(), 2);
+        assert!(inode.physical_block(1).is_none());
+        assert_eq!(
+            parser.get_file_slack(&inode).unwrap().1,
+            TestImage::BLOCK_SIZE - 20
+        );
+
+        // The last block is a hole, so there is no slack to use
+        let inode = parser
+            .read_inode(parser.lookup(Path::new("/tail_hole.bin")).unwrap())
+            .unwrap();
+        assert_eq!(parser.get_file_slack(&inode).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn test_inline_data() {
+        let long: Vec<u8> = (0..90u8).collect();
+
+        let mut image = TestImage::new();
+        image.add_inline_file(TestImage::ROOT, "tiny.txt", b"inline");
+        image.add_inline_file(TestImage::ROOT, "long.txt", &long);
+        let file = image.build();
+
+        let parser = Ext4Parser::open_image(file.path()).unwrap();
+
+        let inode = parser
+            .read_inode(parser.lookup(Path::new("/tiny.txt")).unwrap())
+            .unwrap();
+        assert!(inode.has_inline_data());
+        assert!(inode.extents.is_empty());
+        assert_eq!(&inode.inline_data[..6], b"inline");
+        assert_eq!(parser.get_file_slack(&inode).unwrap(), (0, 0));
+
+        let inode = parser
+            .read_inode(parser.lookup(Path::new("/long.txt")).unwrap())
+            .unwrap();
+        assert_eq!(&inode.inline_data[..90], &long[..]);
+        assert_eq!(parser.get_file_slack(&inode).unwrap(), (0, 0));
+
+        assert_eq!(
+            parser.walk_files(Path::new("/")).unwrap().len(),
+            2,
+            "inline files are still listed"
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Offline ext4 image backend.
+//!
+//! Reads and writes file slack inside an unmounted ext4 image file (forensic
+//! copies, loop images) instead of a live block device. Host paths passed to
+//! this backend are absolute paths inside the image's file system.
+
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, PersistenceRisk};
+use crate::storage::image_file::ImageFile;
+use crate::storage::linux::Ext4Parser;
+use crate::storage::slack_backend::{SlackBackend, SlackRegion};
+use std::path::{Path, PathBuf};
+
+/// Slack backend over an ext4 image file.
+pub struct Ext4ImageBackend {
+    /// Path to the image file.
+    image_path: PathBuf,
+    /// Parser over the image.
+    parser: Ext4Parser,
+}
+
+impl Ext4ImageBackend {
+    /// Open an ext4 image file.
+    pub fn open(image_path: &Path) -> Result<Self> {
+        Ok(Self {
+            image_path: image_path.to_path_buf(),
+            parser: Ext4Parser::open_image(image_path)?,
+        })
+    }
+
+    /// Get the path to the image file.
+    pub fn image_path(&self) -> &Path {
+        &self.image_path
+    }
+
+    /// Get the parser over the image.
+    pub fn parser(&self) -> &Ext4Parser {
+        &self.parser
+    }
+
+    /// List regular files inside the image that have usable slack space.
+    ///
+    /// Files whose slack cannot be determined are skipped.
+    pub fn candidate_hosts(&self) -> Result<Vec<(PathBuf, SlackRegion)>> {
+        let mut hosts = Vec::new();
+
+        for (path, inode_num) in self.parser.walk_files(Path::new("/"))? {
+            if let Ok(region) = self.region_for_inode(inode_num) {
+                if region.available > 0 {
+                    hosts.push((path, region));
+                }
+            }
+        }
+
+        Ok(hosts)
+    }
+
+    /// Build the slack region for a regular file's inode.
+    fn region_for_inode(&self, inode_num: u32) -> Result<SlackRegion> {
+        let inode = self.parser.read_inode(inode_num)?;
+
+        if !inode.is_regular_file() {
+            return Err(Error::NotAFile(format!("inode {}", inode_num)));
+        }
+
+        let (offset, available) = self.parser.get_file_slack(&inode)?;
+
+        Ok(SlackRegion {
+            device_path: self.image_path.clone(),
+            offset,
+            available,
+            logical_size: inode.size,
+            block_size: self.parser.block_size(),
+        })
+    }
+}
+
+impl SlackBackend for Ext4ImageBackend {
+    fn get_slack_info(&self, path: &Path) -> Result<SlackRegion> {
+        let inode_num = self.parser.lookup(path)?;
+        self.region_for_inode(inode_num).map_err(|e| match e {
+            Error::NotAFile(_) => Error::NotAFile(path.display().to_string()),
+            e => e,
+        })
+    }
+
+    /// Lists regular files inside the image; `root` (the directory holding
+    /// the VFS metadata) is not part of the image and is ignored.
+    fn enumerate_hosts(&self, _root: &Path) -> Result<Vec<PathBuf>> {
+        Ok(self
+            .parser
+            .walk_files(Path::new("/"))?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    fn read_slack(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        region.check_bounds(offset, len)?;
+        let image = ImageFile::open(&region.device_path)?;
+        image.read_at(region.offset + offset, len)
+    }
+
+    fn write_slack(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        region.check_bounds(offset, data.len())?;
+        let image = ImageFile::open(&region.device_path)?;
+        image.write_at(region.offset + offset, data)
+    }
+
+    fn wipe_slack(&self, region: &SlackRegion) -> Result<()> {
+        let zeros = vec![0u8; region.available as usize];
+        self.write_slack(region, 0, &zeros)
+    }
+
+    /// Nothing touches the slack while the image sits unmounted. Mounted
+    /// again, the host's block tail is reused as soon as the host grows.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::Moderate,
+            changes_visible_metadata: false,
+            needs_root: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.image_path.is_file()
+    }
+
+    fn name(&self) -> &'static str {
+        "ext4 image"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::linux::test_image::TestImage;
+
+    #[test]
+    fn test_slack_info_for_nested_file() {
+        let mut image = TestImage::new();
+        let docs = image.mkdir(TestImage::ROOT, "docs");
+        image.add_file(docs, "report.txt", &[b'R'; 1000]);
+        let file = image.build();
+
+        let backend = Ext4ImageBackend::open(file.path()).unwrap();
+        let region = backend
+            .get_slack_info(Path::new("/docs/report.txt"))
+            .unwrap();
+
+        assert_eq!(region.logical_size, 1000);
+        assert_eq!(region.available, TestImage::BLOCK_SIZE - 1000);
+        assert_eq!(region.offset % TestImage::BLOCK_SIZE, 1000);
+    }
+
+    #[test]
+    fn test_write_and_read_slack() {
+        let mut image = TestImage::new();
+        let content = vec![b'H'; 300];
+        image.add_file(TestImage::ROOT, "host.dat", &content);
+        let file = image.build();
+
+        let backend = Ext4ImageBackend::open(file.path()).unwrap();
+        let region = backend.get_slack_info(Path::new("/host.dat")).unwrap();
+
+        backend.write_slack(&region, 10, b"hidden").unwrap();
+        assert_eq!(backend.read_slack(&region, 10, 6).unwrap(), b"hidden");
+
+        // The host's visible content is untouched
+        let raw = std::fs::read(file.path()).unwrap();
+        let data_start = (region.offset - region.logical_size) as usize;
+        assert_eq!(&raw[data_start..data_start + content.len()], &content[..]);
+    }
+
+    #[test]
+    fn test_write_past_slack_rejected() {
+        let mut image = TestImage::new();
+        image.add_file(TestImage::ROOT, "host.dat", &[0u8; 4000]);
+        let file = image.build();
+
+        let backend = Ext4ImageBackend::open(file.path()).unwrap();
+        let region = backend.get_slack_info(Path::new("/host.dat")).unwrap();
+
+        let result = backend.write_slack(&region, 0, &[1u8; 200]);
+        assert!(matches!(result, Err(Error::InsufficientSpace { .. })));
+    }
+
+    #[test]
+    fn test_missing_and_directory_paths() {
+        let mut image = TestImage::new();
+        image.mkdir(TestImage::ROOT, "docs");
+        let file = image.build();
+
+        let backend = Ext4ImageBackend::open(file.path()).unwrap();
+        assert!(matches!(
+            backend.get_slack_info(Path::new("/nope.txt")),
+            Err(Error::FileNotFound(_))
+        ));
+        assert!(matches!(
+            backend.get_slack_info(Path::new("/docs")),
+            Err(Error::NotAFile(_))
+        ));
+    }
+}
@@ -0,0 +1,285 @@
+//! Ext4 inode padding backend.
+//!
+//! Hides data in the unused tail of ext4 inodes instead of file slack.
+//! Inodes are usually 256 bytes but only `128 + i_extra_isize` of them hold
+//! inode fields; without in-inode extended attributes the rest is unused.
+//! Capacity therefore depends on the number of files, not their sizes.
+//!
+//! Every write rewrites the whole inode and, under `metadata_csum`,
+//! recomputes its crc32c so `e2fsck` and the kernel accept it. Works on
+//! unmounted image files. The space is lost (and the data overwritten) if
+//! the kernel later stores extended attributes in the inode or grows
+//! `i_extra_isize`.
+
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, PersistenceRisk};
+use crate::storage::image_file::ImageFile;
+use crate::storage::linux::Ext4Parser;
+use crate::storage::slack_backend::{SlackBackend, SlackRegion};
+use std::path::{Path, PathBuf};
+
+/// Inode padding backend over an ext4 image file.
+pub struct Ext4InodePaddingBackend {
+    /// Path to the image file.
+    image_path: PathBuf,
+    /// Parser over the image.
+    parser: Ext4Parser,
+}
+
+impl Ext4InodePaddingBackend {
+    pub const NAME: &'static str = "ext4 inode padding";
+
+    /// Open an ext4 image file.
+    pub fn open(image_path: &Path) -> Result<Self> {
+        Ok(Self {
+            image_path: image_path.to_path_buf(),
+            parser: Ext4Parser::open_image(image_path)?,
+        })
+    }
+
+    /// Get the path to the image file.
+    pub fn image_path(&self) -> &Path {
+        &self.image_path
+    }
+
+    /// Get the parser over the image.
+    pub fn parser(&self) -> &Ext4Parser {
+        &self.parser
+    }
+
+    /// List regular files inside the image whose inodes have unused space.
+    pub fn candidate_hosts(&self) -> Result<Vec<(PathBuf, SlackRegion)>> {
+        let mut hosts = Vec::new();
+
+        for (path, inode_num) in self.parser.walk_files(Path::new("/"))? {
+            if let Ok(region) = self.region_for_inode(inode_num) {
+                if region.available > 0 {
+                    hosts.push((path, region));
+                }
+            }
+        }
+
+        Ok(hosts)
+    }
+
+    /// Build the padding region for a regular file's inode.
+    fn region_for_inode(&self, inode_num: u32) -> Result<SlackRegion> {
+        let raw = self.parser.read_raw_inode(inode_num)?;
+        let inode = self.parser.read_inode(inode_num)?;
+
+        if !inode.is_regular_file() {
+            return Err(Error::NotAFile(format!("inode {}", inode_num)));
+        }
+
+        let (start, len) = Ext4Parser::unused_inode_space(&raw);
+
+        Ok(SlackRegion {
+            device_path: self.image_path.clone(),
+            offset: self.parser.inode_offset(inode_num)? + start as u64,
+            available: len as u64,
+            logical_size: inode.size,
+            block_size: self.parser.block_size(),
+        })
+    }
+}
+
+impl SlackBackend for Ext4InodePaddingBackend {
+    fn get_slack_info(&self, path: &Path) -> Result<SlackRegion> {
+        let inode_num = self.parser.lookup(path)?;
+        self.region_for_inode(inode_num).map_err(|e| match e {
+            Error::NotAFile(_) => Error::NotAFile(path.display().to_string()),
+            e => e,
+        })
+    }
+
+    /// Lists regular files inside the image; `root` (the directory holding
+    /// the VFS metadata) is not part of the image and is ignored.
+    fn enumerate_hosts(&self, _root: &Path) -> Result<Vec<PathBuf>> {
+        Ok(self
+            .parser
+            .walk_files(Path::new("/"))?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    fn read_slack(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        region.check_bounds(offset, len)?;
+        let image = ImageFile::open(&region.device_path)?;
+        image.read_at(region.offset + offset, len)
+    }
+
+    /// Splices the data into the inode and writes it back with a fresh
+    /// checksum. Refuses to write if the inode has gained extended
+    /// attributes since the region was located.
+    fn write_slack(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        region.check_bounds(offset, data.len())?;
+
+        // Inode tables are block aligned, so the inode starts at the
+        // previous multiple of the inode size
+        let inode_size = self.parser.superblock().inode_size as u64;
+        let inode_start = region.offset - region.offset % inode_size;
+        let inode_num = self.parser.inode_at_offset(inode_start)?;
+
+        let mut raw = self.parser.read_raw_inode(inode_num)?;
+        let (start, len) = Ext4Parser::unused_inode_space(&raw);
+        if inode_start + start as u64 != region.offset || (len as u64) < region.available {
+            return Err(Error::DataCorruption(format!(
+                "Unused space of inode {} has changed",
+                inode_num
+            )));
+        }
+
+        let at = start + offset as usize;
+        raw[at..at + data.len()].copy_from_slice(data);
+        self.parser.update_inode_checksum(inode_num, &mut raw);
+
+        let image = ImageFile::open(&region.device_path)?;
+        image.write_at(inode_start, &raw)
+    }
+
+    fn wipe_slack(&self, region: &SlackRegion) -> Result<()> {
+        let zeros = vec![0u8; region.available as usize];
+        self.write_slack(region, 0, &zeros)
+    }
+
+    /// The kernel claims the padding for in-inode extended attributes or a
+    /// larger `i_extra_isize` once the image is mounted again.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::Moderate,
+            changes_visible_metadata: false,
+            needs_root: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.image_path.is_file()
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::linux::ext4::crc32c;
+    use crate::storage::linux::test_image::TestImage;
+
+    #[test]
+    fn test_padding_region() {
+        let mut image = TestImage::new();
+        let docs = image.mkdir(TestImage::ROOT, "docs");
+        let ino = image.add_file(docs, "report.txt", &[b'R'; 1000]);
+        image.add_file(TestImage::ROOT, "empty.txt", &[]);
+        image.add_inline_file(TestImage::ROOT, "inline.txt", &[b'i'; 100]);
+        let file = image.build();
+
+        let backend = Ext4InodePaddingBackend::open(file.path()).unwrap();
+        let region = backend
+            .get_slack_info(Path::new("/docs/report.txt"))
+            .unwrap();
+
+        // 256-byte inodes, i_extra_isize 32, 4 bytes kept clear
+        assert_eq!(region.available, 256 - 128 - 32 - 4);
+        assert_eq!(
+            region.offset,
+            backend.parser().inode_offset(ino).unwrap() + 164
+        );
+        assert_eq!(
+            backend.parser().inode_at_offset(region.offset).unwrap(),
+            ino
+        );
+
+        // File size does not matter, in-inode xattrs do
+        let hosts: Vec<_> = backend
+            .candidate_hosts()
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            hosts,
+            vec![
+                PathBuf::from("/docs/report.txt"),
+                PathBuf::from("/empty.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_preserves_inode_and_checksum() {
+        let mut image = TestImage::new();
+        image.enable_metadata_csum();
+        let ino = image.add_file(TestImage::ROOT, "host.dat", &[b'H'; 300]);
+        let file = image.build();
+
+        let backend = Ext4InodePaddingBackend::open(file.path()).unwrap();
+        assert!(backend.parser().superblock().has_metadata_csum());
+
+        let before = backend.parser().read_raw_inode(ino).unwrap();
+        let mut expected = before.clone();
+        backend.parser().update_inode_checksum(ino, &mut expected);
+        assert_eq!(expected, before, "builder and parser checksums agree");
+
+        let region = backend.get_slack_info(Path::new("/host.dat")).unwrap();
+        backend.write_slack(&region, 10, b"hidden").unwrap();
+        assert_eq!(backend.read_slack(&region, 10, 6).unwrap(), b"hidden");
+
+        // Only the padding and the checksum changed
+        let after = backend.parser().read_raw_inode(ino).unwrap();
+        for i in 0..164 {
+            if !(0x7C..0x7E).contains(&i) && !(0x82..0x84).contains(&i) {
+                assert_eq!(before[i], after[i], "byte {}", i);
+            }
+        }
+        assert_ne!(before[0x7C..0x7E], after[0x7C..0x7E]);
+
+        let mut check = after.clone();
+        backend.parser().update_inode_checksum(ino, &mut check);
+        assert_eq!(check, after);
+
+        let inode = backend.parser().read_inode(ino).unwrap();
+        assert_eq!(inode.size, 300);
+
+        let result = backend.write_slack(&region, 0, &[1u8; 93]);
+        assert!(matches!(result, Err(Error::InsufficientSpace { .. })));
+    }
+
+    #[test]
+    fn test_crc32c() {
+        assert_eq!(!crc32c(!0, b"123456789"), 0xE306_9283);
+    }
+
+    #[test]
+    fn test_vfs_on_inode_padding() {
+        use crate::{SlackVfs, VfsConfig};
+
+        let mut image = TestImage::new();
+        image.enable_metadata_csum();
+        for i in 0..50 {
+            image.add_file(TestImage::ROOT, &format!("f{}.txt", i), &[b'f'; 10]);
+        }
+        let file = image.build();
+        let meta_dir = tempfile::TempDir::new().unwrap();
+
+        // Each inode holds 92 bytes, so symbols must be smaller than that
+        let config = VfsConfig::new(4096, 64, 0.5);
+        {
+            let backend = Box::new(Ext4InodePaddingBackend::open(file.path()).unwrap());
+            let mut vfs =
+                SlackVfs::create_with_backend(meta_dir.path(), "pw", config, backend).unwrap();
+            vfs.create_file("/secret.txt", b"stored in inode padding")
+                .unwrap();
+        }
+
+        let backend = Box::new(Ext4InodePaddingBackend::open(file.path()).unwrap());
+        let vfs = SlackVfs::mount_with_backend(meta_dir.path(), "pw", backend).unwrap();
+        assert_eq!(
+            vfs.read_file("/secret.txt").unwrap(),
+            b"stored in inode padding"
+        );
+    }
+}
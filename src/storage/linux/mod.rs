@@ -1,58 +1,83 @@
-//! Linux-specific slack space implementation using ext4.
+//! Linux-specific slack space implementation using ext4 and XFS.
+//!
+//! Supports both live ext4 block devices (`LinuxSlackBackend`) and offline
+//! image files (`Ext4ImageBackend`, `XfsImageBackend`). Offline ext4 images
+//! can also carry data in unused inode space (`Ext4InodePaddingBackend`)
+//! and in directory entry padding (`Ext4DirentPaddingBackend`).
 
 mod block_device;
+mod dirent_padding;
+mod dx_hash;
 mod ext4;
+mod image;
+mod inode_padding;
+#[cfg(test)]
+mod test_image;
+mod xfs;
+mod xfs_image;
+#[cfg(test)]
+mod xfs_test_image;
 
 use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, PersistenceRisk};
 use crate::storage::slack_backend::{SlackBackend, SlackRegion};
-use std::path::Path;
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 pub use block_device::BlockDevice;
-pub use ext4::Ext4Parser;
+pub use dirent_padding::Ext4DirentPaddingBackend;
+pub use ext4::{Ext4DirEntry, Ext4Extent, Ext4Inode, Ext4Parser, Ext4Superblock};
+pub use image::Ext4ImageBackend;
+pub use inode_padding::Ext4InodePaddingBackend;
+pub use xfs::{XfsAgHeader, XfsDirEntry, XfsExtent, XfsInode, XfsParser, XfsSuperblock};
+pub use xfs_image::XfsImageBackend;
 
 /// Linux slack backend using raw block device access.
 pub struct LinuxSlackBackend {
-    /// Cached ext4 parser (per-device).
-    parsers: std::collections::HashMap<std::path::PathBuf, Ext4Parser>,
+    /// Cached ext4 parsers (per-device).
+    parsers: Mutex<HashMap<PathBuf, Arc<Ext4Parser>>>,
 }
 
 impl LinuxSlackBackend {
     pub fn new() -> Result<Self> {
         Ok(Self {
-            parsers: std::collections::HashMap::new(),
+            parsers: Mutex::new(HashMap::new()),
         })
     }
 
-    /// Get or create an ext4 parser for the device containing a file.
-    fn get_parser(&mut self, file_path: &Path) -> Result<&Ext4Parser> {
-        // Find the device for this file's mount point
-        let device_path = Self::find_device_for_path(file_path)?;
-        
-        if !self.parsers.contains_key(&device_path) {
-            let parser = Ext4Parser::new(&device_path)?;
-            self.parsers.insert(device_path.clone(), parser);
+    /// Get or create an ext4 parser for a block device.
+    fn get_parser(&self, device_path: &Path) -> Result<Arc<Ext4Parser>> {
+        let mut parsers = self
+            .parsers
+            .lock()
+            .map_err(|_| Error::Unsupported("Ext4 parser cache poisoned".to_string()))?;
+
+        if let Some(parser) = parsers.get(device_path) {
+            return Ok(Arc::clone(parser));
         }
-        
-        Ok(self.parsers.get(&device_path).unwrap())
+
+        let parser = Arc::new(Ext4Parser::new(device_path)?);
+        parsers.insert(device_path.to_path_buf(), Arc::clone(&parser));
+        Ok(parser)
     }
 
     /// Find the block device for a given file path by parsing /proc/mounts.
-    fn find_device_for_path(file_path: &Path) -> Result<std::path::PathBuf> {
+    fn find_device_for_path(file_path: &Path) -> Result<PathBuf> {
         use std::fs;
         use std::io::{BufRead, BufReader};
 
-        let file_path = file_path.canonicalize()
-            .map_err(|e| Error::Io(e))?;
+        let file_path = file_path.canonicalize()?;
 
-        let mounts = fs::File::open("/proc/mounts")
-            .map_err(|e| Error::Io(e))?;
+        let mounts = fs::File::open("/proc/mounts")?;
         let reader = BufReader::new(mounts);
 
-        let mut best_match: Option<(std::path::PathBuf, std::path::PathBuf)> = None;
+        let mut best_match: Option<(PathBuf, PathBuf)> = None;
         let mut best_len = 0;
 
         for line in reader.lines() {
-            let line = line.map_err(|e| Error::Io(e))?;
+            let line = line?;
             let parts: Vec<&str> = line.split_whitespace().collect();
             if parts.len() < 2 {
                 continue;
@@ -65,10 +90,7 @@ impl LinuxSlackBackend {
             if file_path.starts_with(mount_point) && mount_point.len() > best_len {
                 // Only consider block devices
                 if device.starts_with("/dev/") {
-                    best_match = Some((
-                        std::path::PathBuf::from(device),
-                        std::path::PathBuf::from(mount_point),
-                    ));
+                    best_match = Some((PathBuf::from(device), PathBuf::from(mount_point)));
                     best_len = mount_point.len();
                 }
             }
@@ -81,22 +103,53 @@ impl LinuxSlackBackend {
 }
 
 impl SlackBackend for LinuxSlackBackend {
-    fn get_slack_info(&self, _path: &Path) -> Result<SlackRegion> {
-        // TODO: Implement using ext4 parser
-        // 1. Find device and mount point
-        // 2. Parse inode for file
-        // 3. Get extent tree
-        // 4. Calculate slack offset
-        Err(Error::Unsupported("Linux slack backend not yet implemented".to_string()))
+    fn get_slack_info(&self, path: &Path) -> Result<SlackRegion> {
+        let metadata = std::fs::metadata(path)?;
+        if !metadata.is_file() {
+            return Err(Error::Unsupported(format!(
+                "Not a regular file: {}",
+                path.display()
+            )));
+        }
+
+        // Inode numbers on ext4 are 32-bit
+        let inode_num = u32::try_from(metadata.ino()).map_err(|_| {
+            Error::Unsupported(format!("Inode number out of range: {}", metadata.ino()))
+        })?;
+
+        let device_path = Self::find_device_for_path(path)?;
+        let parser = self.get_parser(&device_path)?;
+
+        let inode = parser.read_inode(inode_num)?;
+        let (offset, available) = parser.get_file_slack(&inode)?;
+
+        // Empty, inline-data, block-aligned and hole-ended files have no
+        // slack, and the offset reported for them is not theirs to write
+        if available == 0 {
+            return Err(Error::Unsupported(format!(
+                "No slack space after {}",
+                path.display()
+            )));
+        }
+
+        Ok(SlackRegion {
+            device_path,
+            offset,
+            available,
+            logical_size: inode.size,
+            block_size: parser.block_size(),
+        })
     }
 
     fn read_slack(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        region.check_bounds(offset, len)?;
         let device = BlockDevice::open(&region.device_path)?;
         let absolute_offset = region.offset + offset;
         device.read_at(absolute_offset, len)
     }
 
     fn write_slack(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        region.check_bounds(offset, data.len())?;
         let device = BlockDevice::open_write(&region.device_path)?;
         let absolute_offset = region.offset + offset;
         device.write_at(absolute_offset, data)
@@ -107,6 +160,17 @@ impl SlackBackend for LinuxSlackBackend {
         self.write_slack(region, 0, &zeros)
     }
 
+    /// The mounted file system reuses the block tail as soon as the host
+    /// grows, and `e4defrag` may move the file. Raw device access needs
+    /// root.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::Moderate,
+            changes_visible_metadata: false,
+            needs_root: true,
+        }
+    }
+
     fn is_available(&self) -> bool {
         // Check if we can access /proc/mounts and have necessary privileges
         std::path::Path::new("/proc/mounts").exists()
@@ -116,3 +180,38 @@ impl SlackBackend for LinuxSlackBackend {
         "Linux ext4"
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_access_stays_in_region() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let device_path = dir.path().join("device.img");
+        std::fs::write(&device_path, vec![0xAAu8; 8192]).unwrap();
+
+        let backend = LinuxSlackBackend::new().unwrap();
+        let region = SlackRegion {
+            device_path: device_path.clone(),
+            offset: 4096 + 100,
+            available: 3996,
+            logical_size: 100,
+            block_size: 4096,
+        };
+
+        // Past the end of the slack, nothing is read or written
+        assert!(matches!(
+            backend.write_slack(&region, 3990, &[0u8; 10]),
+            Err(Error::InsufficientSpace { .. })
+        ));
+        assert!(backend.read_slack(&region, 0, 3997).is_err());
+        let empty = SlackRegion {
+            offset: 0,
+            available: 0,
+            ..region
+        };
+        assert!(backend.write_slack(&empty, 0, &[0u8; 1]).is_err());
+        assert_eq!(std::fs::read(&device_path).unwrap(), vec![0xAAu8; 8192]);
+    }
+}
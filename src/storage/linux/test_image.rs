@@ -0,0 +1,504 @@
+//! Minimal ext4 image builder for unit tests.
+//!
+//! Produces a single-block-group file system with 4 KiB blocks and 256-byte
+//! inodes, containing only the structures `Ext4Parser` reads.
+
+use super::ext4::crc32c;
+use std::collections::BTreeMap;
+use std::io::Write;
+use tempfile::NamedTempFile;
+
+/// Inode table starts right after the superblock and group descriptor blocks.
+const INODE_TABLE_BLOCK: u64 = 2;
+const INODES_PER_GROUP: u32 = 64;
+const INODE_SIZE: usize = 256;
+const FIRST_DATA_BLOCK: u64 =
+    INODE_TABLE_BLOCK + (INODES_PER_GROUP as u64 * INODE_SIZE as u64) / TestImage::BLOCK_SIZE;
+
+/// File system UUID, which seeds `metadata_csum` checksums.
+const UUID: [u8; 16] = *b"slack-vfs-test-0";
+
+const EXTENTS_FL: u32 = 0x80000;
+const INDEX_FL: u32 = 0x1000;
+const INLINE_DATA_FL: u32 = 0x1000_0000;
+
+/// Builder for a small ext4 image.
+pub(crate) struct TestImage {
+    data: Vec<u8>,
+    next_inode: u32,
+    next_block: u64,
+    /// Directory entries per directory inode: (name, inode, file type).
+    dirs: BTreeMap<u32, Vec<(String, u32, u8)>>,
+    /// Htree-indexed directories and their entries per leaf block.
+    indexed: BTreeMap<u32, usize>,
+    /// Whether inodes carry `metadata_csum` checksums.
+    metadata_csum: bool,
+}
+
+impl TestImage {
+    pub const BLOCK_SIZE: u64 = 4096;
+    pub const ROOT: u32 = 2;
+
+    /// Create an image containing only the root directory.
+    pub fn new() -> Self {
+        let mut image = Self {
+            data: vec![0u8; (FIRST_DATA_BLOCK * Self::BLOCK_SIZE) as usize],
+            next_inode: 11,
+            next_block: FIRST_DATA_BLOCK,
+            dirs: BTreeMap::new(),
+            indexed: BTreeMap::new(),
+            metadata_csum: false,
+        };
+        image.dirs.insert(
+            Self::ROOT,
+            vec![
+                (".".to_string(), Self::ROOT, 2),
+                ("..".to_string(), Self::ROOT, 2),
+            ],
+        );
+        image
+    }
+
+    /// Create a subdirectory and return its inode number.
+    pub fn mkdir(&mut self, parent: u32, name: &str) -> u32 {
+        let ino = self.alloc_inode();
+        self.link(parent, name, ino, 2);
+        self.dirs.insert(
+            ino,
+            vec![(".".to_string(), ino, 2), ("..".to_string(), parent, 2)],
+        );
+        ino
+    }
+
+    /// Create an htree-indexed subdirectory whose entries are split into
+    /// leaf blocks of `per_leaf` entries.
+    pub fn mkdir_indexed(&mut self, parent: u32, name: &str, per_leaf: usize) -> u32 {
+        let ino = self.mkdir(parent, name);
+        self.indexed.insert(ino, per_leaf);
+        ino
+    }
+
+    /// Enable `metadata_csum`: inode checksums are computed at build time.
+    pub fn enable_metadata_csum(&mut self) {
+        self.metadata_csum = true;
+    }
+
+    /// Add a regular file stored in contiguous blocks and return its inode.
+    pub fn add_file(&mut self, parent: u32, name: &str, content: &[u8]) -> u32 {
+        let ino = self.alloc_inode();
+        self.link(parent, name, ino, 1);
+
+        let block_count = (content.len() as u64).div_ceil(Self::BLOCK_SIZE).max(1);
+        let start = self.alloc_blocks(block_count);
+        self.write_bytes(start * Self::BLOCK_SIZE, content);
+
+        let i_block = Self::extent_node(&[(0, block_count as u16, start, false)], 4);
+        self.write_inode(ino, 0x81A4, content.len() as u64, EXTENTS_FL, &i_block);
+        ino
+    }
+
+    /// Add a regular file split into one extent per `(blocks, unwritten)`
+    /// entry, with a free block between extents so they cannot merge.
+    ///
+    /// Files with more than four extents get a depth-1 tree with a single
+    /// leaf block.
+    pub fn add_fragmented_file(
+        &mut self,
+        parent: u32,
+        name: &str,
+        content: &[u8],
+        layout: &[(u16, bool)],
+    ) -> u32 {
+        let ino = self.alloc_inode();
+        self.link(parent, name, ino, 1);
+
+        let mut extents = Vec::new();
+        let mut logical = 0u32;
+        for &(len, unwritten) in layout {
+            let start = self.alloc_blocks(len as u64);
+            self.alloc_blocks(1); // gap
+
+            let from = (logical as u64 * Self::BLOCK_SIZE) as usize;
+            let to = (from + len as usize * Self::BLOCK_SIZE as usize).min(content.len());
+            if from < to {
+                self.write_bytes(start * Self::BLOCK_SIZE, &content[from..to]);
+            }
+
+            extents.push((logical, len, start, unwritten));
+            logical += len as u32;
+        }
+
+        let i_block = if extents.len() <= 4 {
+            Self::extent_node(&extents, 4)
+        } else {
+            let leaf_block = self.alloc_blocks(1);
+            let max = (Self::BLOCK_SIZE as usize - 12) / 12;
+            let leaf = Self::extent_node(&extents, max as u16);
+            self.write_bytes(leaf_block * Self::BLOCK_SIZE, &leaf);
+            Self::extent_index(leaf_block)
+        };
+        self.write_inode(ino, 0x81A4, content.len() as u64, EXTENTS_FL, &i_block);
+        ino
+    }
+
+    /// Add a regular file mapped by an ext2/ext3 block map. Logical blocks
+    /// listed in `holes` are left unmapped.
+    ///
+    /// Supports files up to the double-indirect range.
+    pub fn add_blockmap_file(
+        &mut self,
+        parent: u32,
+        name: &str,
+        content: &[u8],
+        holes: &[u64],
+    ) -> u32 {
+        let ino = self.alloc_inode();
+        self.link(parent, name, ino, 1);
+
+        let per_block = Self::BLOCK_SIZE / 4;
+        let block_count = (content.len() as u64).div_ceil(Self::BLOCK_SIZE);
+        let mut pointers = [0u32; 15];
+        let mut single = None;
+        let mut double = None;
+
+        for logical in 0..block_count {
+            if holes.contains(&logical) {
+                continue;
+            }
+            let physical = self.alloc_blocks(1);
+            let from = (logical * Self::BLOCK_SIZE) as usize;
+            let to = (from + Self::BLOCK_SIZE as usize).min(content.len());
+            self.write_bytes(physical * Self::BLOCK_SIZE, &content[from..to]);
+
+            if logical < 12 {
+                pointers[logical as usize] = physical as u32;
+            } else if logical < 12 + per_block {
+                let table = *single.get_or_insert_with(|| self.alloc_blocks(1));
+                pointers[12] = table as u32;
+                self.write_pointer(table, logical - 12, physical);
+            } else {
+                let index = logical - 12 - per_block;
+                let top = *double.get_or_insert_with(|| self.alloc_blocks(1));
+                pointers[13] = top as u32;
+                let slot = top * Self::BLOCK_SIZE + (index / per_block) * 4;
+                let mut table = u32::from_le_bytes(
+                    self.data[slot as usize..slot as usize + 4]
+                        .try_into()
+                        .unwrap(),
+                ) as u64;
+                if table == 0 {
+                    table = self.alloc_blocks(1);
+                    self.write_pointer(top, index / per_block, table);
+                }
+                self.write_pointer(table, index % per_block, physical);
+            }
+        }
+
+        let i_block: Vec<u8> = pointers.iter().flat_map(|p| p.to_le_bytes()).collect();
+        self.write_inode(ino, 0x81A4, content.len() as u64, 0, &i_block);
+        ino
+    }
+
+    /// Add a regular file stored inline: the first 60 bytes in `i_block`,
+    /// the rest in the in-inode `system.data` extended attribute.
+    pub fn add_inline_file(&mut self, parent: u32, name: &str, content: &[u8]) -> u32 {
+        let ino = self.alloc_inode();
+        self.link(parent, name, ino, 1);
+
+        let split = content.len().min(60);
+        self.write_inode(
+            ino,
+            0x81A4,
+            content.len() as u64,
+            INLINE_DATA_FL,
+            &content[..split],
+        );
+
+        // In-inode xattr area: magic, one entry, value at the end
+        let rest = &content[split..];
+        let area_start = 128 + 32;
+        let area_len = INODE_SIZE - area_start;
+        let mut area = vec![0u8; area_len];
+        Self::put_u32(&mut area, 0, 0xEA02_0000);
+        let value_offs = (area_len - 4 - rest.len().div_ceil(4) * 4) as u16;
+        area[4] = 4; // name_len
+        area[5] = 7; // system.
+        Self::put_u16(&mut area, 6, value_offs);
+        Self::put_u32(&mut area, 12, rest.len() as u32);
+        area[20..24].copy_from_slice(b"data");
+        let value_start = 4 + value_offs as usize;
+        area[value_start..value_start + rest.len()].copy_from_slice(rest);
+
+        let offset = Self::inode_offset(ino) + area_start as u64;
+        self.write_bytes(offset, &area);
+        ino
+    }
+
+    /// Write the image to a temporary file.
+    pub fn build(mut self) -> NamedTempFile {
+        let dirs = std::mem::take(&mut self.dirs);
+        for (ino, entries) in &dirs {
+            let tail = self.metadata_csum;
+            let mut blocks = match self.indexed.get(ino) {
+                Some(&per_leaf) => Self::htree_blocks(entries, per_leaf, tail),
+                None => vec![Self::dir_block(entries, tail)],
+            };
+            let count = blocks.len() as u64;
+            let start = self.alloc_blocks(count);
+            for (i, bytes) in blocks.iter_mut().enumerate() {
+                if tail {
+                    Self::write_dirent_checksum(*ino, bytes);
+                }
+                self.write_bytes((start + i as u64) * Self::BLOCK_SIZE, bytes);
+            }
+
+            let flags = if self.indexed.contains_key(ino) {
+                EXTENTS_FL | INDEX_FL
+            } else {
+                EXTENTS_FL
+            };
+            let size = count * Self::BLOCK_SIZE;
+            let i_block = Self::extent_node(&[(0, count as u16, start, false)], 4);
+            self.write_inode(*ino, 0x41ED, size, flags, &i_block);
+        }
+
+        self.write_superblock();
+        if self.metadata_csum {
+            for ino in std::iter::once(Self::ROOT).chain(11..self.next_inode) {
+                self.write_inode_checksum(ino);
+            }
+        }
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&self.data).unwrap();
+        file.flush().unwrap();
+        file
+    }
+
+    fn alloc_inode(&mut self) -> u32 {
+        let ino = self.next_inode;
+        assert!(ino <= INODES_PER_GROUP, "test image out of inodes");
+        self.next_inode += 1;
+        ino
+    }
+
+    fn alloc_blocks(&mut self, count: u64) -> u64 {
+        let start = self.next_block;
+        self.next_block += count;
+        self.data
+            .resize((self.next_block * Self::BLOCK_SIZE) as usize, 0);
+        start
+    }
+
+    /// Add a directory entry for an existing inode.
+    pub fn link(&mut self, parent: u32, name: &str, ino: u32, file_type: u8) {
+        self.dirs
+            .get_mut(&parent)
+            .expect("parent is not a directory")
+            .push((name.to_string(), ino, file_type));
+    }
+
+    fn write_bytes(&mut self, offset: u64, bytes: &[u8]) {
+        let offset = offset as usize;
+        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+        buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+        buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_pointer(&mut self, table: u64, index: u64, physical: u64) {
+        let offset = table * Self::BLOCK_SIZE + index * 4;
+        self.write_bytes(offset, &(physical as u32).to_le_bytes());
+    }
+
+    fn inode_offset(ino: u32) -> u64 {
+        INODE_TABLE_BLOCK * Self::BLOCK_SIZE + (ino as u64 - 1) * INODE_SIZE as u64
+    }
+
+    /// Write an inode with the given `i_block` contents.
+    fn write_inode(&mut self, ino: u32, mode: u16, size: u64, flags: u32, i_block: &[u8]) {
+        let mut inode = [0u8; INODE_SIZE];
+        Self::put_u16(&mut inode, 0x00, mode);
+        Self::put_u32(&mut inode, 0x04, size as u32);
+        Self::put_u16(&mut inode, 0x1A, 1);
+        Self::put_u32(&mut inode, 0x1C, size.div_ceil(512) as u32);
+        Self::put_u32(&mut inode, 0x20, flags);
+        inode[0x28..0x28 + i_block.len()].copy_from_slice(i_block);
+        Self::put_u32(&mut inode, 0x6C, (size >> 32) as u32);
+        Self::put_u16(&mut inode, 0x80, 32);
+
+        self.write_bytes(Self::inode_offset(ino), &inode);
+    }
+
+    /// Store the crc32c of an inode, seeded from the UUID, inode number and
+    /// generation, in `i_checksum_lo` and `i_checksum_hi`.
+    fn write_inode_checksum(&mut self, ino: u32) {
+        let offset = Self::inode_offset(ino) as usize;
+        let inode = &mut self.data[offset..offset + INODE_SIZE];
+        inode[0x7C..0x7E].fill(0);
+        inode[0x82..0x84].fill(0);
+
+        let seed = crc32c(!0, &UUID);
+        let mut crc = crc32c(seed, &ino.to_le_bytes());
+        crc = crc32c(crc, &inode[0x64..0x68]);
+        crc = crc32c(crc, inode);
+        inode[0x7C..0x7E].copy_from_slice(&(crc as u16).to_le_bytes());
+        inode[0x82..0x84].copy_from_slice(&((crc >> 16) as u16).to_le_bytes());
+    }
+
+    /// Store the crc32c of a directory block in its checksum tail, if it
+    /// has one. Directory inodes have generation 0.
+    fn write_dirent_checksum(ino: u32, block: &mut [u8]) {
+        let tail = block.len() - 12;
+        if block[tail + 7] != 0xDE {
+            return;
+        }
+        let seed = crc32c(!0, &UUID);
+        let mut crc = crc32c(seed, &ino.to_le_bytes());
+        crc = crc32c(crc, &[0; 4]);
+        crc = crc32c(crc, &block[..tail]);
+        block[tail + 8..].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Build an extent leaf node: header plus `(block, len, start, unwritten)`
+    /// entries.
+    fn extent_node(extents: &[(u32, u16, u64, bool)], max: u16) -> Vec<u8> {
+        let mut node = vec![0u8; 12 * (max as usize + 1)];
+        Self::put_u16(&mut node, 0, 0xF30A);
+        Self::put_u16(&mut node, 2, extents.len() as u16);
+        Self::put_u16(&mut node, 4, max);
+        for (i, &(block, len, start, unwritten)) in extents.iter().enumerate() {
+            let off = 12 + i * 12;
+            let raw_len = if unwritten { len + 32768 } else { len };
+            Self::put_u32(&mut node, off, block);
+            Self::put_u16(&mut node, off + 4, raw_len);
+            Self::put_u16(&mut node, off + 6, (start >> 32) as u16);
+            Self::put_u32(&mut node, off + 8, start as u32);
+        }
+        node
+    }
+
+    /// Build a depth-1 extent root pointing at a single leaf block.
+    fn extent_index(leaf_block: u64) -> Vec<u8> {
+        let mut node = vec![0u8; 60];
+        Self::put_u16(&mut node, 0, 0xF30A);
+        Self::put_u16(&mut node, 2, 1);
+        Self::put_u16(&mut node, 4, 4);
+        Self::put_u16(&mut node, 6, 1);
+        Self::put_u32(&mut node, 12, 0);
+        Self::put_u32(&mut node, 16, leaf_block as u32);
+        Self::put_u16(&mut node, 20, (leaf_block >> 32) as u16);
+        node
+    }
+
+    /// Lay out directory entries linearly in one block, optionally ending
+    /// with a checksum tail.
+    fn dir_block(entries: &[(String, u32, u8)], tail: bool) -> Vec<u8> {
+        let mut block = vec![0u8; TestImage::BLOCK_SIZE as usize];
+        let end = if tail { block.len() - 12 } else { block.len() };
+        let mut pos = 0;
+
+        if tail {
+            Self::put_u16(&mut block, end + 4, 12);
+            block[end + 7] = 0xDE;
+        }
+
+        for (i, (name, ino, file_type)) in entries.iter().enumerate() {
+            let rec_len = if i + 1 == entries.len() {
+                end - pos
+            } else {
+                (8 + name.len()).div_ceil(4) * 4
+            };
+            Self::put_u32(&mut block, pos, *ino);
+            Self::put_u16(&mut block, pos + 4, rec_len as u16);
+            block[pos + 6] = name.len() as u8;
+            block[pos + 7] = *file_type;
+            block[pos + 8..pos + 8 + name.len()].copy_from_slice(name.as_bytes());
+            pos += rec_len;
+        }
+
+        block
+    }
+
+    /// Lay out an htree directory: a dx_root block followed by leaf blocks.
+    ///
+    /// Entries are ordered by their half-MD4 hash under the default seed,
+    /// as the kernel would, and each leaf is indexed by its first hash.
+    fn htree_blocks(entries: &[(String, u32, u8)], per_leaf: usize, tail: bool) -> Vec<Vec<u8>> {
+        let (dots, children) = entries.split_at(2);
+        let hash =
+            |name: &str| super::dx_hash::name_hash(name.as_bytes(), 1, &[0; 4], false).unwrap();
+        let mut children = children.to_vec();
+        children.sort_by_key(|(name, _, _)| hash(name));
+        let chunks: Vec<_> = children.chunks(per_leaf).collect();
+        let leaves: Vec<Vec<u8>> = chunks
+            .iter()
+            .map(|chunk| Self::dir_block(chunk, tail))
+            .collect();
+
+        // "." and ".." with ".." covering the index data
+        let mut root = vec![0u8; TestImage::BLOCK_SIZE as usize];
+        Self::put_u32(&mut root, 0, dots[0].1);
+        Self::put_u16(&mut root, 4, 12);
+        root[6] = 1;
+        root[7] = 2;
+        root[8] = b'.';
+        Self::put_u32(&mut root, 12, dots[1].1);
+        Self::put_u16(&mut root, 16, TestImage::BLOCK_SIZE as u16 - 12);
+        root[18] = 2;
+        root[19] = 2;
+        root[20..22].copy_from_slice(b"..");
+
+        // dx_root_info: reserved, hash version, info length, levels, flags
+        root[0x1C] = 1;
+        root[0x1D] = 8;
+
+        // dx_countlimit followed by (hash, block) entries
+        let limit = (TestImage::BLOCK_SIZE as usize - 0x20) / 8;
+        Self::put_u16(&mut root, 0x20, limit as u16);
+        Self::put_u16(&mut root, 0x22, leaves.len() as u16);
+        for i in 0..leaves.len() {
+            let pos = 0x20 + i * 8;
+            if i > 0 {
+                // The low bit marks a leaf continuing the previous one's hash
+                let start = hash(&chunks[i][0].0);
+                let continued = hash(&chunks[i - 1].last().unwrap().0) == start;
+                Self::put_u32(&mut root, pos, start | continued as u32);
+            }
+            Self::put_u32(&mut root, pos + 4, i as u32 + 1);
+        }
+
+        let mut blocks = vec![root];
+        blocks.extend(leaves);
+        blocks
+    }
+
+    fn write_superblock(&mut self) {
+        let mut sb = [0u8; 1024];
+        Self::put_u32(&mut sb, 0x00, INODES_PER_GROUP);
+        Self::put_u32(&mut sb, 0x04, self.next_block as u32);
+        Self::put_u32(&mut sb, 0x14, 0);
+        Self::put_u32(&mut sb, 0x18, 2); // 1024 << 2 = 4096
+        Self::put_u32(&mut sb, 0x20, 32768);
+        Self::put_u32(&mut sb, 0x28, INODES_PER_GROUP);
+        Self::put_u16(&mut sb, 0x38, 0xEF53);
+        Self::put_u32(&mut sb, 0x4C, 1);
+        Self::put_u16(&mut sb, 0x58, INODE_SIZE as u16);
+        Self::put_u32(&mut sb, 0x60, 0x40); // INCOMPAT_EXTENTS
+        if self.metadata_csum {
+            Self::put_u32(&mut sb, 0x64, 0x400); // RO_COMPAT_METADATA_CSUM
+            sb[0x68..0x78].copy_from_slice(&UUID);
+        }
+        self.write_bytes(1024, &sb);
+
+        // Single group descriptor pointing at the inode table
+        let mut gd = [0u8; 32];
+        Self::put_u32(&mut gd, 8, INODE_TABLE_BLOCK as u32);
+        self.write_bytes(Self::BLOCK_SIZE, &gd);
+    }
+}
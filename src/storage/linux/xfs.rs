@@ -0,0 +1,956 @@
+//! XFS file system parser for Linux.
+//!
+//! Parses the XFS superblock, allocation group headers (AGF/AGI), inodes
+//! and their data forks (extent lists and B+trees) to locate file blocks
+//! and calculate slack space offsets. Shortform, block and multi-block
+//! directories are read for path lookup. Both v4 and v5 (CRC) file systems
+//! are understood; checksums are not verified.
+//!
+//! All on-disk XFS fields are big-endian.
+
+use crate::error::{Error, Result};
+use crate::storage::image_file::{ImageFile, ReadAt};
+use crate::storage::linux::BlockDevice;
+use std::path::{Component, Path, PathBuf};
+
+/// Superblock magic ("XFSB").
+const XFS_SB_MAGIC: u32 = 0x5846_5342;
+/// AG free space header magic ("XAGF").
+const XFS_AGF_MAGIC: u32 = 0x5841_4746;
+/// AG inode header magic ("XAGI").
+const XFS_AGI_MAGIC: u32 = 0x5841_4749;
+/// Inode magic ("IN").
+const XFS_DINODE_MAGIC: u16 = 0x494E;
+
+/// Bmap B+tree block magics, without and with CRCs.
+const XFS_BMAP_MAGIC: u32 = 0x424D_4150;
+const XFS_BMAP_CRC_MAGIC: u32 = 0x424D_4133;
+/// Long-format B+tree block header sizes.
+const BMAP_BLOCK_LEN: usize = 24;
+const BMAP_CRC_BLOCK_LEN: usize = 72;
+/// Deepest bmap B+tree the kernel builds.
+const BMAP_MAX_LEVELS: u16 = 9;
+
+/// Directory data block magics: single-block and multi-block, v4 and v5.
+const XFS_DIR2_BLOCK_MAGIC: u32 = 0x5844_3242;
+const XFS_DIR2_DATA_MAGIC: u32 = 0x5844_3244;
+const XFS_DIR3_BLOCK_MAGIC: u32 = 0x5844_4233;
+const XFS_DIR3_DATA_MAGIC: u32 = 0x5844_4433;
+/// Directory data block header sizes.
+const DIR2_DATA_HDR_LEN: usize = 16;
+const DIR3_DATA_HDR_LEN: usize = 64;
+/// Tag of an unused directory data entry.
+const DIR2_DATA_FREE_TAG: u16 = 0xFFFF;
+/// Byte offset of the leaf section of a directory; data blocks live below.
+const DIR2_LEAF_OFFSET: u64 = 1 << 35;
+
+/// Inode core sizes for v1/v2 and v3 inodes.
+const DINODE_CORE_LEN: usize = 100;
+const DINODE3_CORE_LEN: usize = 176;
+
+/// Data fork formats.
+const XFS_DINODE_FMT_LOCAL: u8 = 1;
+const XFS_DINODE_FMT_EXTENTS: u8 = 2;
+const XFS_DINODE_FMT_BTREE: u8 = 3;
+
+/// Inode flag: data lives on the realtime device.
+const XFS_DIFLAG_REALTIME: u16 = 0x0001;
+
+/// Inode flag: the data fork extent count is the 64-bit `di_big_nextents`.
+const XFS_DIFLAG2_NREXT64: u64 = 0x0010;
+
+/// Superblock feature bits for file types in directory entries.
+const XFS_SB_VERSION2_FTYPE: u32 = 0x0200;
+const XFS_SB_FEAT_INCOMPAT_FTYPE: u32 = 0x0001;
+
+/// File type bits of `di_mode`.
+const S_IFMT: u16 = 0xF000;
+const S_IFREG: u16 = 0x8000;
+const S_IFDIR: u16 = 0x4000;
+
+fn be16(data: &[u8], off: usize) -> u16 {
+    u16::from_be_bytes([data[off], data[off + 1]])
+}
+
+fn be32(data: &[u8], off: usize) -> u32 {
+    u32::from_be_bytes(data[off..off + 4].try_into().unwrap())
+}
+
+fn be64(data: &[u8], off: usize) -> u64 {
+    u64::from_be_bytes(data[off..off + 8].try_into().unwrap())
+}
+
+/// XFS superblock (partial - key fields only).
+#[derive(Debug, Clone)]
+pub struct XfsSuperblock {
+    pub block_size: u64,
+    pub data_blocks: u64,
+    pub root_ino: u64,
+    pub ag_blocks: u32,
+    pub ag_count: u32,
+    /// Version number; the low nibble is the format version (4 or 5).
+    pub version: u16,
+    pub sector_size: u16,
+    pub inode_size: u16,
+    pub block_log: u8,
+    pub inodes_per_block_log: u8,
+    pub ag_block_log: u8,
+    pub dir_block_log: u8,
+    pub features2: u32,
+    pub features_incompat: u32,
+}
+
+impl XfsSuperblock {
+    /// Check for a v5 (CRC-enabled) file system.
+    pub fn has_crc(&self) -> bool {
+        self.version & 0x000F == 5
+    }
+
+    /// Check if directory entries carry a file type byte.
+    pub fn has_ftype(&self) -> bool {
+        if self.has_crc() {
+            self.features_incompat & XFS_SB_FEAT_INCOMPAT_FTYPE != 0
+        } else {
+            self.features2 & XFS_SB_VERSION2_FTYPE != 0
+        }
+    }
+
+    /// Get the directory block size in bytes.
+    pub fn dir_block_size(&self) -> u64 {
+        self.block_size << self.dir_block_log
+    }
+}
+
+/// Allocation group headers (AGF and AGI).
+#[derive(Debug, Clone)]
+pub struct XfsAgHeader {
+    /// Blocks in this AG; the last AG may be shorter.
+    pub length: u32,
+    /// Allocated inodes in this AG.
+    pub inode_count: u32,
+}
+
+/// An extent of a data fork.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XfsExtent {
+    /// First file block this extent covers.
+    pub offset: u64,
+    /// First file system block (AG number and AG block packed together).
+    pub start: u64,
+    /// Number of blocks.
+    pub len: u64,
+    /// Whether the extent is preallocated but unwritten (reads as zeros).
+    pub unwritten: bool,
+}
+
+impl XfsExtent {
+    /// Decode a packed 128-bit extent record.
+    pub fn decode(record: &[u8]) -> Self {
+        let l0 = be64(record, 0);
+        let l1 = be64(record, 8);
+        Self {
+            unwritten: l0 >> 63 != 0,
+            offset: (l0 & ((1 << 63) - 1)) >> 9,
+            start: ((l0 & 0x1FF) << 43) | (l1 >> 21),
+            len: l1 & ((1 << 21) - 1),
+        }
+    }
+}
+
+/// XFS inode (partial - key fields only).
+#[derive(Debug, Clone)]
+pub struct XfsInode {
+    pub number: u64,
+    pub mode: u16,
+    pub format: u8,
+    pub size: u64,
+    pub flags: u16,
+    /// Data fork extents, for extent and B+tree formats.
+    pub extents: Vec<XfsExtent>,
+    /// Data fork bytes, for the local format.
+    pub local_data: Vec<u8>,
+}
+
+impl XfsInode {
+    /// Check if this inode is a regular file.
+    pub fn is_regular_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+
+    /// Check if this inode is a directory.
+    pub fn is_directory(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    /// Find the extent mapping a logical file block.
+    pub fn extent_for(&self, logical: u64) -> Option<&XfsExtent> {
+        self.extents
+            .iter()
+            .find(|e| logical >= e.offset && logical < e.offset + e.len)
+    }
+}
+
+/// A directory entry.
+#[derive(Debug, Clone)]
+pub struct XfsDirEntry {
+    pub inode: u64,
+    pub name: String,
+    /// File type from the entry (1 = regular file, 2 = directory), or 0
+    /// without the ftype feature.
+    pub file_type: u8,
+}
+
+impl XfsDirEntry {
+    /// Check if this is the "." or ".." entry.
+    pub fn is_dot(&self) -> bool {
+        self.name == "." || self.name == ".."
+    }
+}
+
+/// Parser for XFS file systems.
+pub struct XfsParser {
+    device: Box<dyn ReadAt>,
+    superblock: XfsSuperblock,
+    ags: Vec<XfsAgHeader>,
+}
+
+impl XfsParser {
+    /// Create a new XFS parser for the given block device.
+    pub fn new(device_path: &Path) -> Result<Self> {
+        Self::from_device(BlockDevice::open(device_path)?)
+    }
+
+    /// Create a new XFS parser for a file system image file.
+    pub fn open_image(image_path: &Path) -> Result<Self> {
+        Self::from_device(ImageFile::open(image_path)?)
+    }
+
+    /// Create a new XFS parser over an already opened device, reading the
+    /// superblock and every AG's headers.
+    pub fn from_device(device: impl ReadAt + 'static) -> Result<Self> {
+        let superblock = Self::parse_superblock(&device.read_at(0, 512)?)?;
+
+        let mut ags = Vec::with_capacity(superblock.ag_count as usize);
+        for agno in 0..superblock.ag_count {
+            let ag_start = agno as u64 * superblock.ag_blocks as u64 * superblock.block_size;
+            let sector = superblock.sector_size as u64;
+            let agf = device.read_at(ag_start + sector, 64)?;
+            let agi = device.read_at(ag_start + 2 * sector, 64)?;
+
+            if be32(&agf, 0) != XFS_AGF_MAGIC || be32(&agi, 0) != XFS_AGI_MAGIC {
+                return Err(Error::DataCorruption(format!(
+                    "Bad AG header magic in AG {}",
+                    agno
+                )));
+            }
+            if be32(&agf, 8) != agno || be32(&agi, 8) != agno {
+                return Err(Error::DataCorruption(format!(
+                    "AG {} headers carry the wrong sequence number",
+                    agno
+                )));
+            }
+            let length = be32(&agf, 12);
+            if length == 0 || length > superblock.ag_blocks {
+                return Err(Error::DataCorruption(format!(
+                    "AG {} has an invalid length {}",
+                    agno, length
+                )));
+            }
+
+            ags.push(XfsAgHeader {
+                length,
+                inode_count: be32(&agi, 16),
+            });
+        }
+
+        Ok(Self {
+            device: Box::new(device),
+            superblock,
+            ags,
+        })
+    }
+
+    /// Parse the XFS superblock from raw bytes.
+    fn parse_superblock(data: &[u8]) -> Result<XfsSuperblock> {
+        let magic = be32(data, 0);
+        if magic != XFS_SB_MAGIC {
+            return Err(Error::Unsupported(format!(
+                "Not an XFS filesystem (magic: 0x{:08X})",
+                magic
+            )));
+        }
+
+        let sb = XfsSuperblock {
+            block_size: be32(data, 4) as u64,
+            data_blocks: be64(data, 8),
+            root_ino: be64(data, 56),
+            ag_blocks: be32(data, 84),
+            ag_count: be32(data, 88),
+            version: be16(data, 100),
+            sector_size: be16(data, 102),
+            inode_size: be16(data, 104),
+            block_log: data[120],
+            inodes_per_block_log: data[123],
+            ag_block_log: data[124],
+            dir_block_log: data[192],
+            features2: be32(data, 200),
+            features_incompat: be32(data, 216),
+        };
+
+        let consistent = sb.block_size.is_power_of_two()
+            && sb.block_size == 1 << sb.block_log
+            && (512..=65536).contains(&sb.block_size)
+            && sb.sector_size.is_power_of_two()
+            && sb.inode_size.is_power_of_two()
+            && (sb.inode_size as u64) << sb.inodes_per_block_log == sb.block_size
+            && sb.ag_count > 0
+            && sb.ag_blocks > 0
+            && sb.ag_blocks as u64 <= 1 << sb.ag_block_log
+            && matches!(sb.version & 0x000F, 4 | 5);
+        if !consistent {
+            return Err(Error::DataCorruption(
+                "Inconsistent XFS superblock geometry".to_string(),
+            ));
+        }
+
+        Ok(sb)
+    }
+
+    /// Get the block size of this filesystem.
+    pub fn block_size(&self) -> u64 {
+        self.superblock.block_size
+    }
+
+    /// Get the parsed superblock.
+    pub fn superblock(&self) -> &XfsSuperblock {
+        &self.superblock
+    }
+
+    /// Get the parsed allocation group headers.
+    pub fn ag_headers(&self) -> &[XfsAgHeader] {
+        &self.ags
+    }
+
+    /// Convert an AG number and AG block to a device byte offset, checking
+    /// them against the AG headers.
+    fn ag_block_offset(&self, agno: u64, agbno: u64) -> Result<u64> {
+        let ag = self
+            .ags
+            .get(agno as usize)
+            .ok_or_else(|| Error::DataCorruption(format!("AG {} does not exist", agno)))?;
+        if agbno >= ag.length as u64 {
+            return Err(Error::DataCorruption(format!(
+                "Block {} is past the end of AG {}",
+                agbno, agno
+            )));
+        }
+        Ok((agno * self.superblock.ag_blocks as u64 + agbno) * self.superblock.block_size)
+    }
+
+    /// Convert a file system block number to a device byte offset.
+    pub fn fsblock_offset(&self, fsblock: u64) -> Result<u64> {
+        let log = self.superblock.ag_block_log;
+        self.ag_block_offset(fsblock >> log, fsblock & ((1 << log) - 1))
+    }
+
+    fn read_fsblock(&self, fsblock: u64) -> Result<Vec<u8>> {
+        self.device.read_at(
+            self.fsblock_offset(fsblock)?,
+            self.superblock.block_size as usize,
+        )
+    }
+
+    /// Read an inode by number.
+    pub fn read_inode(&self, ino: u64) -> Result<XfsInode> {
+        let sb = &self.superblock;
+        let agino_log = sb.ag_block_log + sb.inodes_per_block_log;
+        let agino = ino & ((1 << agino_log) - 1);
+        let agbno = agino >> sb.inodes_per_block_log;
+        let index = agino & ((1 << sb.inodes_per_block_log) - 1);
+
+        let offset = self.ag_block_offset(ino >> agino_log, agbno)? + index * sb.inode_size as u64;
+        let data = self.device.read_at(offset, sb.inode_size as usize)?;
+        self.parse_inode(ino, &data)
+    }
+
+    /// Parse an inode and its data fork.
+    fn parse_inode(&self, ino: u64, data: &[u8]) -> Result<XfsInode> {
+        if be16(data, 0) != XFS_DINODE_MAGIC {
+            return Err(Error::DataCorruption(format!("Bad magic in inode {}", ino)));
+        }
+
+        let version = data[4];
+        let core_len = if version >= 3 {
+            DINODE3_CORE_LEN
+        } else {
+            DINODE_CORE_LEN
+        };
+        let fork_len = match data[82] {
+            0 => data.len() - core_len,
+            forkoff => (forkoff as usize * 8).min(data.len() - core_len),
+        };
+        let fork = &data[core_len..core_len + fork_len];
+
+        let mut inode = XfsInode {
+            number: ino,
+            mode: be16(data, 2),
+            format: data[5],
+            size: be64(data, 56),
+            flags: be16(data, 90),
+            extents: Vec::new(),
+            local_data: Vec::new(),
+        };
+        let nextents = if version >= 3 && be64(data, 120) & XFS_DIFLAG2_NREXT64 != 0 {
+            be64(data, 24) as usize
+        } else {
+            be32(data, 76) as usize
+        };
+
+        match inode.format {
+            XFS_DINODE_FMT_LOCAL => {
+                let len = (inode.size as usize).min(fork.len());
+                inode.local_data = fork[..len].to_vec();
+            }
+            XFS_DINODE_FMT_EXTENTS => {
+                if nextents * 16 > fork.len() {
+                    return Err(Error::DataCorruption(format!(
+                        "Inode {} has more extents than fit its fork",
+                        ino
+                    )));
+                }
+                inode.extents = fork
+                    .chunks_exact(16)
+                    .take(nextents)
+                    .map(XfsExtent::decode)
+                    .collect();
+            }
+            XFS_DINODE_FMT_BTREE => {
+                inode.extents = self.read_bmap_root(ino, fork)?;
+            }
+            // Device files have no data blocks
+            _ => {}
+        }
+
+        inode.extents.sort_by_key(|e| e.offset);
+        Ok(inode)
+    }
+
+    /// Collect the extents of a B+tree data fork, starting from the root
+    /// stored in the inode (`xfs_bmdr_block`).
+    fn read_bmap_root(&self, ino: u64, fork: &[u8]) -> Result<Vec<XfsExtent>> {
+        let level = be16(fork, 0);
+        let numrecs = be16(fork, 2) as usize;
+        let maxrecs = (fork.len() - 4) / 16;
+        if level == 0 || level > BMAP_MAX_LEVELS || numrecs > maxrecs {
+            return Err(Error::DataCorruption(format!(
+                "Inode {} has an invalid bmap root",
+                ino
+            )));
+        }
+
+        let ptrs_at = 4 + maxrecs * 8;
+        let mut extents = Vec::new();
+        for i in 0..numrecs {
+            let ptr = be64(fork, ptrs_at + i * 8);
+            self.read_bmap_block(ptr, level - 1, &mut extents)?;
+        }
+        Ok(extents)
+    }
+
+    /// Collect the extents below a long-format bmap B+tree block.
+    fn read_bmap_block(&self, fsblock: u64, level: u16, out: &mut Vec<XfsExtent>) -> Result<()> {
+        let block = self.read_fsblock(fsblock)?;
+        let header_len = match be32(&block, 0) {
+            XFS_BMAP_MAGIC => BMAP_BLOCK_LEN,
+            XFS_BMAP_CRC_MAGIC => BMAP_CRC_BLOCK_LEN,
+            magic => {
+                return Err(Error::DataCorruption(format!(
+                    "Bad bmap block magic 0x{:08X} at block {}",
+                    magic, fsblock
+                )))
+            }
+        };
+        // Levels must count down by one, which also stops pointer loops
+        if be16(&block, 4) != level {
+            return Err(Error::DataCorruption(format!(
+                "Bmap block {} has level {}, expected {}",
+                fsblock,
+                be16(&block, 4),
+                level
+            )));
+        }
+
+        let numrecs = be16(&block, 6) as usize;
+        let maxrecs = (block.len() - header_len) / 16;
+        if numrecs > maxrecs {
+            return Err(Error::DataCorruption(format!(
+                "Bmap block {} has too many records",
+                fsblock
+            )));
+        }
+
+        if level == 0 {
+            out.extend(
+                block[header_len..]
+                    .chunks_exact(16)
+                    .take(numrecs)
+                    .map(XfsExtent::decode),
+            );
+            return Ok(());
+        }
+
+        let ptrs_at = header_len + maxrecs * 8;
+        for i in 0..numrecs {
+            self.read_bmap_block(be64(&block, ptrs_at + i * 8), level - 1, out)?;
+        }
+        Ok(())
+    }
+
+    /// Read one logical block of a file or directory.
+    pub fn read_file_block(&self, inode: &XfsInode, logical: u64) -> Result<Vec<u8>> {
+        let extent = inode.extent_for(logical).ok_or_else(|| {
+            Error::DataCorruption(format!("Logical block {} is not mapped", logical))
+        })?;
+        self.read_fsblock(extent.start + (logical - extent.offset))
+    }
+
+    /// Resolve an absolute path inside the file system to an inode number.
+    pub fn lookup(&self, path: &Path) -> Result<u64> {
+        let mut ino = self.superblock.root_ino;
+
+        for component in path.components() {
+            let name = match component {
+                Component::RootDir | Component::CurDir => continue,
+                Component::Normal(name) => name.to_string_lossy(),
+                _ => {
+                    return Err(Error::InvalidPath(format!(
+                        "Unsupported path component in {}",
+                        path.display()
+                    )))
+                }
+            };
+
+            ino = self
+                .read_dir(ino)
+                .map_err(|e| match e {
+                    Error::NotADirectory(_) => Error::NotADirectory(path.display().to_string()),
+                    e => e,
+                })?
+                .into_iter()
+                .find(|entry| entry.name == name)
+                .map(|entry| entry.inode)
+                .ok_or_else(|| Error::FileNotFound(path.display().to_string()))?;
+        }
+
+        Ok(ino)
+    }
+
+    /// List the entries of a directory, including "." and "..".
+    pub fn read_dir(&self, ino: u64) -> Result<Vec<XfsDirEntry>> {
+        let dir = self.read_inode(ino)?;
+        if !dir.is_directory() {
+            return Err(Error::NotADirectory(format!("inode {}", ino)));
+        }
+
+        if dir.format == XFS_DINODE_FMT_LOCAL {
+            return self.parse_shortform_dir(&dir);
+        }
+
+        // Data blocks sit below the leaf offset; leaf and free-index
+        // blocks above it hold no names
+        let blocks_per_dir_block = 1u64 << self.superblock.dir_block_log;
+        let leaf_block = DIR2_LEAF_OFFSET >> self.superblock.block_log;
+        let mut entries = Vec::new();
+
+        for extent in &dir.extents {
+            let end = (extent.offset + extent.len).min(leaf_block);
+            let mut logical = extent.offset.next_multiple_of(blocks_per_dir_block);
+            while logical < end {
+                let mut block = Vec::new();
+                for i in 0..blocks_per_dir_block {
+                    block.extend(self.read_file_block(&dir, logical + i)?);
+                }
+                self.parse_dir_data_block(&block, &mut entries)?;
+                logical += blocks_per_dir_block;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Parse a shortform directory stored in the inode.
+    fn parse_shortform_dir(&self, dir: &XfsInode) -> Result<Vec<XfsDirEntry>> {
+        let data = &dir.local_data;
+        let corrupt = || {
+            Error::DataCorruption(format!(
+                "Malformed shortform directory in inode {}",
+                dir.number
+            ))
+        };
+
+        let header = data.get(..2).ok_or_else(corrupt)?;
+        let count = header[0] as usize;
+        // Inode numbers are 8 bytes wide if any needs more than 32 bits
+        let ino_len = if header[1] > 0 { 8 } else { 4 };
+        let read_ino = |at: usize| -> Result<u64> {
+            let bytes = data.get(at..at + ino_len).ok_or_else(corrupt)?;
+            Ok(bytes.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+        };
+        let ftype_len = self.superblock.has_ftype() as usize;
+
+        let mut entries = vec![
+            XfsDirEntry {
+                inode: dir.number,
+                name: ".".to_string(),
+                file_type: 2,
+            },
+            XfsDirEntry {
+                inode: read_ino(2)?,
+                name: "..".to_string(),
+                file_type: 2,
+            },
+        ];
+
+        let mut pos = 2 + ino_len;
+        for _ in 0..count {
+            let name_len = *data.get(pos).ok_or_else(corrupt)? as usize;
+            // Skip the name length and the 2-byte offset tag
+            let name_at = pos + 3;
+            let name = data.get(name_at..name_at + name_len).ok_or_else(corrupt)?;
+            let file_type = if ftype_len == 1 {
+                *data.get(name_at + name_len).ok_or_else(corrupt)?
+            } else {
+                0
+            };
+            entries.push(XfsDirEntry {
+                inode: read_ino(name_at + name_len + ftype_len)?,
+                name: String::from_utf8_lossy(name).to_string(),
+                file_type,
+            });
+            pos = name_at + name_len + ftype_len + ino_len;
+        }
+
+        Ok(entries)
+    }
+
+    /// Parse the entries of one directory data block.
+    fn parse_dir_data_block(&self, block: &[u8], entries: &mut Vec<XfsDirEntry>) -> Result<()> {
+        let corrupt = || Error::DataCorruption("Malformed XFS directory block".to_string());
+
+        let (header_len, single_block) = match be32(block, 0) {
+            XFS_DIR2_BLOCK_MAGIC => (DIR2_DATA_HDR_LEN, true),
+            XFS_DIR3_BLOCK_MAGIC => (DIR3_DATA_HDR_LEN, true),
+            XFS_DIR2_DATA_MAGIC => (DIR2_DATA_HDR_LEN, false),
+            XFS_DIR3_DATA_MAGIC => (DIR3_DATA_HDR_LEN, false),
+            _ => return Err(corrupt()),
+        };
+
+        // A single-block directory ends with its leaf entries and a tail
+        // holding their count
+        let end = if single_block {
+            let leaf_count = be32(block, block.len() - 8) as usize;
+            block
+                .len()
+                .checked_sub(8 + leaf_count * 8)
+                .filter(|&end| end >= header_len)
+                .ok_or_else(corrupt)?
+        } else {
+            block.len()
+        };
+
+        let ftype_len = self.superblock.has_ftype() as usize;
+        let mut pos = header_len;
+        while pos < end {
+            let entry = block.get(pos..end).ok_or_else(corrupt)?;
+            if entry.len() < 8 {
+                return Err(corrupt());
+            }
+
+            if be16(entry, 0) == DIR2_DATA_FREE_TAG {
+                let length = be16(entry, 2) as usize;
+                if length == 0 || !length.is_multiple_of(8) {
+                    return Err(corrupt());
+                }
+                pos += length;
+                continue;
+            }
+
+            // inumber, namelen, name, [ftype], padding, 2-byte tag
+            let name_len = *entry.get(8).ok_or_else(corrupt)? as usize;
+            let name = entry.get(9..9 + name_len).ok_or_else(corrupt)?;
+            let file_type = if ftype_len == 1 {
+                *entry.get(9 + name_len).ok_or_else(corrupt)?
+            } else {
+                0
+            };
+            entries.push(XfsDirEntry {
+                inode: be64(entry, 0),
+                name: String::from_utf8_lossy(name).to_string(),
+                file_type,
+            });
+            pos += (9 + name_len + ftype_len + 2).next_multiple_of(8);
+        }
+
+        Ok(())
+    }
+
+    /// Recursively list every regular file below a directory.
+    ///
+    /// Returns absolute paths inside the file system with their inode
+    /// numbers. Entries that cannot be parsed are skipped.
+    pub fn walk_files(&self, root: &Path) -> Result<Vec<(PathBuf, u64)>> {
+        let mut files = Vec::new();
+        let mut pending = vec![(root.to_path_buf(), self.lookup(root)?)];
+
+        while let Some((dir_path, dir_ino)) = pending.pop() {
+            let entries = match self.read_dir(dir_ino) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            for entry in entries.into_iter().filter(|e| !e.is_dot()) {
+                let path = dir_path.join(&entry.name);
+                let file_type = match entry.file_type {
+                    // Without the ftype feature, fall back to the inode mode
+                    0 => match self.read_inode(entry.inode) {
+                        Ok(inode) if inode.is_regular_file() => 1,
+                        Ok(inode) if inode.is_directory() => 2,
+                        _ => continue,
+                    },
+                    t => t,
+                };
+                match file_type {
+                    1 => files.push((path, entry.inode)),
+                    2 => pending.push((path, entry.inode)),
+                    _ => {}
+                }
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+
+    /// Get the physical block offset and slack space for a file.
+    ///
+    /// Returns `(slack_start, slack_available)` where `slack_start` is the
+    /// absolute byte offset on the device of the first byte after EOF in the
+    /// file's last block. Block-aligned files, files ending in a hole or in
+    /// an unwritten extent have no slack.
+    pub fn get_file_slack(&self, inode: &XfsInode) -> Result<(u64, u64)> {
+        let block_size = self.superblock.block_size;
+
+        if inode.flags & XFS_DIFLAG_REALTIME != 0 {
+            return Err(Error::Unsupported(format!(
+                "inode {} stores its data on the realtime device",
+                inode.number
+            )));
+        }
+        if inode.size == 0 || inode.format == XFS_DINODE_FMT_LOCAL {
+            return Ok((0, 0));
+        }
+
+        // The last extent may lie past EOF (speculative preallocation), so
+        // look up the block holding the last byte
+        let last_logical = (inode.size - 1) / block_size;
+        let Some(extent) = inode.extent_for(last_logical) else {
+            return Ok((0, 0));
+        };
+        if extent.unwritten {
+            return Ok((0, 0));
+        }
+
+        let last_block_offset =
+            self.fsblock_offset(extent.start + (last_logical - extent.offset))?;
+
+        let slack_offset_in_block = inode.size % block_size;
+        if slack_offset_in_block == 0 {
+            return Ok((last_block_offset + block_size, 0));
+        }
+
+        Ok((
+            last_block_offset + slack_offset_in_block,
+            block_size - slack_offset_in_block,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::linux::xfs_test_image::XfsTestImage;
+
+    const BLOCK: u64 = XfsTestImage::BLOCK_SIZE;
+
+    #[test]
+    fn test_extent_record_decoding() {
+        // unwritten, startoff 5, startblock (1 << 43) | 7, count 3
+        let l0: u64 = (1 << 63) | (5 << 9) | 1;
+        let l1: u64 = (7 << 21) | 3;
+        let mut record = [0u8; 16];
+        record[..8].copy_from_slice(&l0.to_be_bytes());
+        record[8..].copy_from_slice(&l1.to_be_bytes());
+
+        assert_eq!(
+            XfsExtent::decode(&record),
+            XfsExtent {
+                offset: 5,
+                start: (1 << 43) | 7,
+                len: 3,
+                unwritten: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_superblock_and_ag_headers() {
+        let file = XfsTestImage::new().build();
+        let parser = XfsParser::open_image(file.path()).unwrap();
+
+        let sb = parser.superblock();
+        assert_eq!(sb.block_size, BLOCK);
+        assert_eq!(sb.root_ino, XfsTestImage::ROOT);
+        assert!(sb.has_crc());
+        assert!(sb.has_ftype());
+        assert_eq!(parser.ag_headers().len(), 2);
+
+        let file = XfsTestImage::new_v4().build();
+        let parser = XfsParser::open_image(file.path()).unwrap();
+        assert!(!parser.superblock().has_crc());
+        assert!(!parser.superblock().has_ftype());
+    }
+
+    #[test]
+    fn test_rejects_non_xfs() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, &[0u8; 8192]).unwrap();
+        assert!(matches!(
+            XfsParser::open_image(file.path()),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_shortform_and_block_directories() {
+        for mut image in [XfsTestImage::new(), XfsTestImage::new_v4()] {
+            let small = image.mkdir(XfsTestImage::ROOT, "small");
+            let big = image.mkdir(XfsTestImage::ROOT, "big");
+            image.add_file(small, "note.txt", b"n");
+            for i in 0..30 {
+                image.add_file(big, &format!("a rather long file name {:02}.txt", i), b"x");
+            }
+            let file = image.build();
+
+            let parser = XfsParser::open_image(file.path()).unwrap();
+            let small = parser.read_inode(small).unwrap();
+            assert_eq!(small.format, XFS_DINODE_FMT_LOCAL);
+            let big = parser.read_inode(big).unwrap();
+            assert_eq!(big.format, XFS_DINODE_FMT_EXTENTS);
+
+            let entries = parser.read_dir(big.number).unwrap();
+            assert_eq!(entries.iter().filter(|e| !e.is_dot()).count(), 30);
+            let dotdot = entries.iter().find(|e| e.name == "..").unwrap();
+            assert_eq!(dotdot.inode, XfsTestImage::ROOT);
+
+            let found = parser
+                .lookup(Path::new("/big/a rather long file name 17.txt"))
+                .unwrap();
+            assert!(parser.read_inode(found).unwrap().is_regular_file());
+            assert_eq!(
+                parser.walk_files(Path::new("/")).unwrap().len(),
+                31,
+                "v5: {}",
+                parser.superblock().has_crc()
+            );
+        }
+    }
+
+    #[test]
+    fn test_lookup_errors() {
+        let mut image = XfsTestImage::new();
+        image.add_file(XfsTestImage::ROOT, "a.txt", b"a");
+        let file = image.build();
+
+        let parser = XfsParser::open_image(file.path()).unwrap();
+        assert_eq!(parser.lookup(Path::new("/")).unwrap(), XfsTestImage::ROOT);
+        assert!(matches!(
+            parser.lookup(Path::new("/missing")),
+            Err(Error::FileNotFound(_))
+        ));
+        assert!(matches!(
+            parser.lookup(Path::new("/a.txt/child")),
+            Err(Error::NotADirectory(_))
+        ));
+    }
+
+    #[test]
+    fn test_slack_of_extent_list_file_in_second_ag() {
+        let mut image = XfsTestImage::new();
+        image.use_ag(1);
+        let content: Vec<u8> = (0..3 * BLOCK as usize + 10)
+            .map(|i| (i / BLOCK as usize) as u8 + 1)
+            .collect();
+        let ino = image.add_fragmented_file(XfsTestImage::ROOT, "frag.bin", &content);
+        let file = image.build();
+
+        let parser = XfsParser::open_image(file.path()).unwrap();
+        let inode = parser.read_inode(ino).unwrap();
+        assert_eq!(inode.extents.len(), 4);
+        assert!(inode.extents.iter().all(|e| e.start >> 10 == 1));
+
+        let (offset, available) = parser.get_file_slack(&inode).unwrap();
+        assert_eq!(available, BLOCK - 10);
+        assert!(offset > 1024 * BLOCK);
+
+        let raw = std::fs::read(file.path()).unwrap();
+        let end = offset as usize;
+        assert_eq!(&raw[end - 10..end], &[4u8; 10]);
+        assert_eq!(parser.read_file_block(&inode, 2).unwrap()[0], 3);
+    }
+
+    #[test]
+    fn test_slack_of_btree_file() {
+        for mut image in [XfsTestImage::new(), XfsTestImage::new_v4()] {
+            let content: Vec<u8> = (0..40 * BLOCK as usize + 123)
+                .map(|i| (i / BLOCK as usize) as u8)
+                .collect();
+            let ino = image.add_btree_file(XfsTestImage::ROOT, "tree.bin", &content);
+            let file = image.build();
+
+            let parser = XfsParser::open_image(file.path()).unwrap();
+            let inode = parser.read_inode(ino).unwrap();
+            assert_eq!(inode.format, XFS_DINODE_FMT_BTREE);
+            assert_eq!(inode.extents.len(), 41);
+
+            let (offset, available) = parser.get_file_slack(&inode).unwrap();
+            assert_eq!(available, BLOCK - 123);
+            let raw = std::fs::read(file.path()).unwrap();
+            let end = offset as usize;
+            assert_eq!(&raw[end - 123..end], &[40u8; 123][..]);
+        }
+    }
+
+    #[test]
+    fn test_slack_with_preallocation_and_unwritten_extents() {
+        let mut image = XfsTestImage::new();
+        let prealloc =
+            image.add_preallocated_file(XfsTestImage::ROOT, "grown.log", &[7u8; 5000], 16);
+        let unwritten = image.add_unwritten_file(XfsTestImage::ROOT, "fallocated.bin", 5000);
+        let aligned = image.add_file(XfsTestImage::ROOT, "aligned.bin", &[1u8; 8192]);
+        let file = image.build();
+
+        let parser = XfsParser::open_image(file.path()).unwrap();
+
+        // The slack follows EOF even though the extent runs on
+        let inode = parser.read_inode(prealloc).unwrap();
+        let (offset, available) = parser.get_file_slack(&inode).unwrap();
+        assert_eq!(available, 2 * BLOCK - 5000);
+        assert_eq!(offset % BLOCK, 5000 - BLOCK);
+
+        let inode = parser.read_inode(unwritten).unwrap();
+        assert_eq!(parser.get_file_slack(&inode).unwrap(), (0, 0));
+
+        let inode = parser.read_inode(aligned).unwrap();
+        assert_eq!(parser.get_file_slack(&inode).unwrap().1, 0);
+    }
+}
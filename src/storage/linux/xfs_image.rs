@@ -0,0 +1,225 @@
+//! Offline XFS image backend.
+//!
+//! Reads and writes file slack inside an unmounted XFS image file. The slack
+//! of a file is the tail of the block holding its last byte, located through
+//! the inode's extent list or bmap B+tree. Host paths passed to this backend
+//! are absolute paths inside the image's file system.
+
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, PersistenceRisk};
+use crate::storage::image_file::ImageFile;
+use crate::storage::linux::XfsParser;
+use crate::storage::slack_backend::{SlackBackend, SlackRegion};
+use std::path::{Path, PathBuf};
+
+/// Slack backend over an XFS image file.
+pub struct XfsImageBackend {
+    /// Path to the image file.
+    image_path: PathBuf,
+    /// Parser over the image.
+    parser: XfsParser,
+}
+
+impl XfsImageBackend {
+    pub const NAME: &'static str = "XFS image";
+
+    /// Open an XFS image file.
+    pub fn open(image_path: &Path) -> Result<Self> {
+        Ok(Self {
+            image_path: image_path.to_path_buf(),
+            parser: XfsParser::open_image(image_path)?,
+        })
+    }
+
+    /// Get the path to the image file.
+    pub fn image_path(&self) -> &Path {
+        &self.image_path
+    }
+
+    /// Get the parser over the image.
+    pub fn parser(&self) -> &XfsParser {
+        &self.parser
+    }
+
+    /// List regular files inside the image that have usable slack space.
+    ///
+    /// Files whose slack cannot be determined are skipped.
+    pub fn candidate_hosts(&self) -> Result<Vec<(PathBuf, SlackRegion)>> {
+        let mut hosts = Vec::new();
+
+        for (path, ino) in self.parser.walk_files(Path::new("/"))? {
+            if let Ok(region) = self.region_for_inode(ino) {
+                if region.available > 0 {
+                    hosts.push((path, region));
+                }
+            }
+        }
+
+        Ok(hosts)
+    }
+
+    /// Build the slack region for a regular file's inode.
+    fn region_for_inode(&self, ino: u64) -> Result<SlackRegion> {
+        let inode = self.parser.read_inode(ino)?;
+
+        if !inode.is_regular_file() {
+            return Err(Error::NotAFile(format!("inode {}", ino)));
+        }
+
+        let (offset, available) = self.parser.get_file_slack(&inode)?;
+
+        Ok(SlackRegion {
+            device_path: self.image_path.clone(),
+            offset,
+            available,
+            logical_size: inode.size,
+            block_size: self.parser.block_size(),
+        })
+    }
+}
+
+impl SlackBackend for XfsImageBackend {
+    fn get_slack_info(&self, path: &Path) -> Result<SlackRegion> {
+        let ino = self.parser.lookup(path)?;
+        self.region_for_inode(ino).map_err(|e| match e {
+            Error::NotAFile(_) => Error::NotAFile(path.display().to_string()),
+            e => e,
+        })
+    }
+
+    /// Lists regular files inside the image; `root` (the directory holding
+    /// the VFS metadata) is not part of the image and is ignored.
+    fn enumerate_hosts(&self, _root: &Path) -> Result<Vec<PathBuf>> {
+        Ok(self
+            .parser
+            .walk_files(Path::new("/"))?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    fn read_slack(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        region.check_bounds(offset, len)?;
+        let image = ImageFile::open(&region.device_path)?;
+        image.read_at(region.offset + offset, len)
+    }
+
+    fn write_slack(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        region.check_bounds(offset, data.len())?;
+        let image = ImageFile::open(&region.device_path)?;
+        image.write_at(region.offset + offset, data)
+    }
+
+    fn wipe_slack(&self, region: &SlackRegion) -> Result<()> {
+        let zeros = vec![0u8; region.available as usize];
+        self.write_slack(region, 0, &zeros)
+    }
+
+    /// Safe while the image stays unmounted; once mounted, growing the
+    /// host reuses the block tail and `xfs_fsr` may move the file.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::Moderate,
+            changes_visible_metadata: false,
+            needs_root: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.image_path.is_file()
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::linux::xfs_test_image::XfsTestImage;
+
+    #[test]
+    fn test_write_and_read_slack() {
+        let mut image = XfsTestImage::new();
+        let docs = image.mkdir(XfsTestImage::ROOT, "docs");
+        let content = vec![b'H'; 300];
+        image.add_file(docs, "host.dat", &content);
+        let file = image.build();
+
+        let backend = XfsImageBackend::open(file.path()).unwrap();
+        let region = backend.get_slack_info(Path::new("/docs/host.dat")).unwrap();
+        assert_eq!(region.available, XfsTestImage::BLOCK_SIZE - 300);
+
+        backend.write_slack(&region, 10, b"hidden").unwrap();
+        assert_eq!(backend.read_slack(&region, 10, 6).unwrap(), b"hidden");
+
+        // The host's visible content is untouched
+        let raw = std::fs::read(file.path()).unwrap();
+        let data_start = (region.offset - region.logical_size) as usize;
+        assert_eq!(&raw[data_start..data_start + content.len()], &content[..]);
+
+        let result = backend.write_slack(&region, 0, &vec![1u8; 4000]);
+        assert!(matches!(result, Err(Error::InsufficientSpace { .. })));
+    }
+
+    #[test]
+    fn test_candidate_hosts_skip_unusable_files() {
+        let mut image = XfsTestImage::new();
+        image.add_file(XfsTestImage::ROOT, "aligned.bin", &[1u8; 4096]);
+        image.add_unwritten_file(XfsTestImage::ROOT, "fallocated.bin", 1000);
+        image.add_realtime_file(XfsTestImage::ROOT, "realtime.bin", &[2u8; 100]);
+        image.add_file(XfsTestImage::ROOT, "empty.bin", &[]);
+        image.add_file(XfsTestImage::ROOT, "plain.bin", &[3u8; 100]);
+        image.mkdir(XfsTestImage::ROOT, "dir");
+        let file = image.build();
+
+        let backend = XfsImageBackend::open(file.path()).unwrap();
+        assert!(matches!(
+            backend.get_slack_info(Path::new("/realtime.bin")),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            backend.get_slack_info(Path::new("/dir")),
+            Err(Error::NotAFile(_))
+        ));
+
+        let hosts: Vec<_> = backend
+            .candidate_hosts()
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(hosts, vec![PathBuf::from("/plain.bin")]);
+        assert_eq!(backend.enumerate_hosts(Path::new("/")).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_vfs_on_xfs_image() {
+        use crate::{SlackVfs, VfsConfig};
+
+        let mut image = XfsTestImage::new();
+        let data = image.mkdir(XfsTestImage::ROOT, "data");
+        for i in 0..8 {
+            image.add_file(data, &format!("log_{}.txt", i), &vec![b'l'; 900 + i]);
+        }
+        let file = image.build();
+        let meta_dir = tempfile::TempDir::new().unwrap();
+
+        {
+            let backend = Box::new(XfsImageBackend::open(file.path()).unwrap());
+            let mut vfs =
+                SlackVfs::create_with_backend(meta_dir.path(), "pw", VfsConfig::default(), backend)
+                    .unwrap();
+            vfs.create_file("/secret.txt", b"stored in XFS slack")
+                .unwrap();
+        }
+
+        let backend = Box::new(XfsImageBackend::open(file.path()).unwrap());
+        let vfs = SlackVfs::mount_with_backend(meta_dir.path(), "pw", backend).unwrap();
+        assert_eq!(
+            vfs.read_file("/secret.txt").unwrap(),
+            b"stored in XFS slack"
+        );
+    }
+}
@@ -0,0 +1,465 @@
+//! Minimal XFS image builder for unit tests.
+//!
+//! Produces two 4 MiB allocation groups with 4 KiB blocks and 512-byte
+//! inodes. The only inode chunk sits in AG 0; file data can be placed in
+//! either AG. Directories are written in shortform while they fit the inode
+//! and as single-block directories otherwise. Images are written sparsely.
+
+use std::collections::BTreeMap;
+use std::io::{Seek, SeekFrom, Write};
+use tempfile::NamedTempFile;
+
+const SECTOR_SIZE: u64 = 512;
+const INODE_SIZE: usize = 512;
+const INOPB_LOG: u8 = 3;
+const AG_BLOCKS: u64 = 1024;
+const AG_BLOCK_LOG: u8 = 10;
+const AG_COUNT: u64 = 2;
+/// First block of the inode chunk in AG 0.
+const INODE_CHUNK_BLOCK: u64 = 8;
+const INODE_CHUNK_LEN: u64 = 64;
+
+const DIR_MODE: u16 = 0x41ED;
+const FILE_MODE: u16 = 0x81A4;
+const FMT_LOCAL: u8 = 1;
+const FMT_EXTENTS: u8 = 2;
+const FMT_BTREE: u8 = 3;
+
+/// Directory entries: (name, inode, file type).
+type Entries = Vec<(String, u64, u8)>;
+
+/// Builder for a small XFS file system.
+pub(crate) struct XfsTestImage {
+    /// v5 (CRC, ftype) instead of v4 (no ftype).
+    crc: bool,
+    /// Data written at absolute offsets.
+    chunks: BTreeMap<u64, Vec<u8>>,
+    next_ino: u64,
+    /// Next free block per AG.
+    next_block: [u64; AG_COUNT as usize],
+    /// AG new data is allocated from.
+    data_ag: usize,
+    /// Parent and entries per directory.
+    dirs: BTreeMap<u64, (u64, Entries)>,
+}
+
+impl XfsTestImage {
+    pub const BLOCK_SIZE: u64 = 4096;
+    pub const ROOT: u64 = INODE_CHUNK_BLOCK << INOPB_LOG;
+
+    /// Create a v5 file system containing only the root directory.
+    pub fn new() -> Self {
+        Self::with_crc(true)
+    }
+
+    /// Create a v4 file system without file types in directory entries.
+    pub fn new_v4() -> Self {
+        Self::with_crc(false)
+    }
+
+    fn with_crc(crc: bool) -> Self {
+        let mut dirs = BTreeMap::new();
+        dirs.insert(Self::ROOT, (Self::ROOT, Vec::new()));
+        Self {
+            crc,
+            chunks: BTreeMap::new(),
+            next_ino: Self::ROOT + 1,
+            next_block: [INODE_CHUNK_BLOCK + INODE_CHUNK_LEN / 8, 4],
+            data_ag: 0,
+            dirs,
+        }
+    }
+
+    /// Allocate the data of files added from now on in the given AG.
+    pub fn use_ag(&mut self, agno: usize) {
+        assert!((agno as u64) < AG_COUNT);
+        self.data_ag = agno;
+    }
+
+    /// Create a subdirectory and return its inode number.
+    pub fn mkdir(&mut self, parent: u64, name: &str) -> u64 {
+        let ino = self.alloc_inode();
+        self.link(parent, name, ino, 2);
+        self.dirs.insert(ino, (parent, Vec::new()));
+        ino
+    }
+
+    /// Add a file stored in one extent.
+    pub fn add_file(&mut self, parent: u64, name: &str, content: &[u8]) -> u64 {
+        self.add_preallocated_file(parent, name, content, 0)
+    }
+
+    /// Add a file whose single extent runs `extra_blocks` past EOF, as left
+    /// by speculative preallocation.
+    pub fn add_preallocated_file(
+        &mut self,
+        parent: u64,
+        name: &str,
+        content: &[u8],
+        extra_blocks: u64,
+    ) -> u64 {
+        let blocks = (content.len() as u64).div_ceil(Self::BLOCK_SIZE) + extra_blocks;
+        let start = self.alloc_blocks(blocks);
+        let blocks_written: Vec<u64> = (start..start + blocks).collect();
+        self.write_content(&blocks_written, content);
+        let extents = if blocks > 0 {
+            vec![(0, start, blocks, false)]
+        } else {
+            Vec::new()
+        };
+        self.add_extents_inode(parent, name, content.len() as u64, &extents, 0)
+    }
+
+    /// Add a file with one single-block extent per block and a free block
+    /// between each.
+    pub fn add_fragmented_file(&mut self, parent: u64, name: &str, content: &[u8]) -> u64 {
+        let extents = self.alloc_fragmented(content);
+        self.add_extents_inode(parent, name, content.len() as u64, &extents, 0)
+    }
+
+    /// Add a fragmented file whose extents live in a B+tree leaf block.
+    pub fn add_btree_file(&mut self, parent: u64, name: &str, content: &[u8]) -> u64 {
+        let extents = self.alloc_fragmented(content);
+        let leaf = self.alloc_blocks(1);
+
+        let header_len = if self.crc { 72 } else { 24 };
+        let mut block = vec![0u8; Self::BLOCK_SIZE as usize];
+        block[0..4].copy_from_slice(if self.crc { b"BMA3" } else { b"BMAP" });
+        block[6..8].copy_from_slice(&(extents.len() as u16).to_be_bytes());
+        block[8..16].copy_from_slice(&u64::MAX.to_be_bytes());
+        block[16..24].copy_from_slice(&u64::MAX.to_be_bytes());
+        for (i, extent) in extents.iter().enumerate() {
+            let at = header_len + i * 16;
+            block[at..at + 16].copy_from_slice(&Self::extent_record(*extent));
+        }
+        self.chunks.insert(Self::block_offset(leaf), block);
+
+        // Level 1 root in the inode: one key and one pointer to the leaf
+        let mut fork = vec![0u8; self.fork_len()];
+        let maxrecs = (fork.len() - 4) / 16;
+        fork[0..2].copy_from_slice(&1u16.to_be_bytes());
+        fork[2..4].copy_from_slice(&1u16.to_be_bytes());
+        let ptr_at = 4 + maxrecs * 8;
+        fork[ptr_at..ptr_at + 8].copy_from_slice(&leaf.to_be_bytes());
+
+        let ino = self.alloc_inode();
+        self.link(parent, name, ino, 1);
+        self.write_inode(
+            ino,
+            FILE_MODE,
+            FMT_BTREE,
+            content.len() as u64,
+            extents.len() as u32,
+            0,
+            &fork,
+        );
+        ino
+    }
+
+    /// Add a preallocated file of `size` bytes whose only extent is
+    /// unwritten.
+    pub fn add_unwritten_file(&mut self, parent: u64, name: &str, size: u64) -> u64 {
+        let blocks = size.div_ceil(Self::BLOCK_SIZE);
+        let start = self.alloc_blocks(blocks);
+        self.add_extents_inode(parent, name, size, &[(0, start, blocks, true)], 0)
+    }
+
+    /// Add a file flagged as living on the realtime device.
+    pub fn add_realtime_file(&mut self, parent: u64, name: &str, content: &[u8]) -> u64 {
+        let blocks = (content.len() as u64).div_ceil(Self::BLOCK_SIZE);
+        let start = self.alloc_blocks(blocks);
+        self.add_extents_inode(
+            parent,
+            name,
+            content.len() as u64,
+            &[(0, start, blocks, false)],
+            0x0001,
+        )
+    }
+
+    /// Write the image to a temporary file.
+    pub fn build(mut self) -> NamedTempFile {
+        for (ino, (parent, entries)) in std::mem::take(&mut self.dirs) {
+            match self.shortform_dir(parent, &entries) {
+                Some(fork) => {
+                    let size = fork.len() as u64;
+                    self.write_inode(ino, DIR_MODE, FMT_LOCAL, size, 0, 0, &fork)
+                }
+                None => {
+                    let block = self.block_dir(ino, parent, &entries);
+                    let start = self.alloc_blocks(1);
+                    self.chunks.insert(Self::block_offset(start), block);
+                    let fork = Self::extent_record((0, start, 1, false));
+                    self.write_inode(ino, DIR_MODE, FMT_EXTENTS, Self::BLOCK_SIZE, 1, 0, &fork)
+                }
+            }
+        }
+
+        for agno in 0..AG_COUNT {
+            let ag_start = agno * AG_BLOCKS * Self::BLOCK_SIZE;
+            self.chunks.insert(ag_start, self.superblock());
+            for (sector, magic) in [(1, b"XAGF"), (2, b"XAGI")] {
+                let mut header = vec![0u8; SECTOR_SIZE as usize];
+                header[0..4].copy_from_slice(magic);
+                header[4..8].copy_from_slice(&1u32.to_be_bytes());
+                header[8..12].copy_from_slice(&(agno as u32).to_be_bytes());
+                header[12..16].copy_from_slice(&(AG_BLOCKS as u32).to_be_bytes());
+                if magic == b"XAGI" && agno == 0 {
+                    header[16..20].copy_from_slice(&(INODE_CHUNK_LEN as u32).to_be_bytes());
+                }
+                self.chunks.insert(ag_start + sector * SECTOR_SIZE, header);
+            }
+        }
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.as_file()
+            .set_len(AG_COUNT * AG_BLOCKS * Self::BLOCK_SIZE)
+            .unwrap();
+        for (offset, data) in &self.chunks {
+            file.seek(SeekFrom::Start(*offset)).unwrap();
+            file.write_all(data).unwrap();
+        }
+        file.flush().unwrap();
+        file
+    }
+
+    fn alloc_inode(&mut self) -> u64 {
+        let ino = self.next_ino;
+        assert!(
+            ino < Self::ROOT + INODE_CHUNK_LEN,
+            "test image out of inodes"
+        );
+        self.next_ino += 1;
+        ino
+    }
+
+    /// Allocate consecutive blocks in the current data AG and return the
+    /// first as a file system block number.
+    fn alloc_blocks(&mut self, count: u64) -> u64 {
+        let next = &mut self.next_block[self.data_ag];
+        let agbno = *next;
+        *next += count;
+        assert!(*next <= AG_BLOCKS, "test image full");
+        ((self.data_ag as u64) << AG_BLOCK_LOG) | agbno
+    }
+
+    /// Allocate one block per content block with a free block between
+    /// each, and write the content.
+    fn alloc_fragmented(&mut self, content: &[u8]) -> Vec<(u64, u64, u64, bool)> {
+        let blocks = (content.len() as u64).div_ceil(Self::BLOCK_SIZE);
+        let starts: Vec<u64> = (0..blocks)
+            .map(|_| {
+                let start = self.alloc_blocks(1);
+                self.alloc_blocks(1);
+                start
+            })
+            .collect();
+        self.write_content(&starts, content);
+        starts
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| (i as u64, start, 1, false))
+            .collect()
+    }
+
+    /// Write content block by block into the given file system blocks.
+    fn write_content(&mut self, blocks: &[u64], content: &[u8]) {
+        for (fsblock, chunk) in blocks.iter().zip(content.chunks(Self::BLOCK_SIZE as usize)) {
+            self.chunks
+                .insert(Self::block_offset(*fsblock), chunk.to_vec());
+        }
+    }
+
+    fn add_extents_inode(
+        &mut self,
+        parent: u64,
+        name: &str,
+        size: u64,
+        extents: &[(u64, u64, u64, bool)],
+        flags: u16,
+    ) -> u64 {
+        let fork: Vec<u8> = extents
+            .iter()
+            .flat_map(|e| Self::extent_record(*e))
+            .collect();
+        assert!(
+            fork.len() <= self.fork_len(),
+            "too many extents for the inode"
+        );
+
+        let ino = self.alloc_inode();
+        self.link(parent, name, ino, 1);
+        self.write_inode(
+            ino,
+            FILE_MODE,
+            FMT_EXTENTS,
+            size,
+            extents.len() as u32,
+            flags,
+            &fork,
+        );
+        ino
+    }
+
+    fn link(&mut self, parent: u64, name: &str, ino: u64, file_type: u8) {
+        self.dirs
+            .get_mut(&parent)
+            .expect("parent is not a directory")
+            .1
+            .push((name.to_string(), ino, file_type));
+    }
+
+    fn block_offset(fsblock: u64) -> u64 {
+        let agno = fsblock >> AG_BLOCK_LOG;
+        let agbno = fsblock & ((1 << AG_BLOCK_LOG) - 1);
+        (agno * AG_BLOCKS + agbno) * Self::BLOCK_SIZE
+    }
+
+    fn core_len(&self) -> usize {
+        if self.crc {
+            176
+        } else {
+            100
+        }
+    }
+
+    fn fork_len(&self) -> usize {
+        INODE_SIZE - self.core_len()
+    }
+
+    /// Pack `(file offset, start block, length, unwritten)` into an extent
+    /// record.
+    fn extent_record((offset, start, len, unwritten): (u64, u64, u64, bool)) -> [u8; 16] {
+        let l0 = ((unwritten as u64) << 63) | (offset << 9) | (start >> 43);
+        let l1 = (start << 21) | len;
+        let mut record = [0u8; 16];
+        record[0..8].copy_from_slice(&l0.to_be_bytes());
+        record[8..16].copy_from_slice(&l1.to_be_bytes());
+        record
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn write_inode(
+        &mut self,
+        ino: u64,
+        mode: u16,
+        format: u8,
+        size: u64,
+        nextents: u32,
+        flags: u16,
+        fork: &[u8],
+    ) {
+        let mut inode = vec![0u8; INODE_SIZE];
+        inode[0..2].copy_from_slice(b"IN");
+        inode[2..4].copy_from_slice(&mode.to_be_bytes());
+        inode[4] = if self.crc { 3 } else { 2 };
+        inode[5] = format;
+        inode[16..20].copy_from_slice(&1u32.to_be_bytes());
+        inode[56..64].copy_from_slice(&size.to_be_bytes());
+        inode[76..80].copy_from_slice(&nextents.to_be_bytes());
+        inode[83] = FMT_EXTENTS;
+        inode[90..92].copy_from_slice(&flags.to_be_bytes());
+        if self.crc {
+            inode[152..160].copy_from_slice(&ino.to_be_bytes());
+        }
+        let core = self.core_len();
+        inode[core..core + fork.len()].copy_from_slice(fork);
+
+        let agbno = ino >> INOPB_LOG;
+        let index = ino & ((1 << INOPB_LOG) - 1);
+        let offset = agbno * Self::BLOCK_SIZE + index * INODE_SIZE as u64;
+        self.chunks.insert(offset, inode);
+    }
+
+    /// Encode a shortform directory, or `None` if it does not fit the fork.
+    fn shortform_dir(&self, parent: u64, entries: &[(String, u64, u8)]) -> Option<Vec<u8>> {
+        if entries.len() > u8::MAX as usize {
+            return None;
+        }
+
+        let mut fork = vec![entries.len() as u8, 0];
+        fork.extend((parent as u32).to_be_bytes());
+        for (i, (name, ino, file_type)) in entries.iter().enumerate() {
+            fork.push(name.len() as u8);
+            fork.extend((0x60 + i as u16 * 16).to_be_bytes());
+            fork.extend(name.as_bytes());
+            if self.crc {
+                fork.push(*file_type);
+            }
+            fork.extend((*ino as u32).to_be_bytes());
+        }
+
+        (fork.len() <= self.fork_len()).then_some(fork)
+    }
+
+    /// Encode a single-block directory: header, entries, one unused entry,
+    /// leaf entries and the tail.
+    fn block_dir(&self, ino: u64, parent: u64, entries: &[(String, u64, u8)]) -> Vec<u8> {
+        let mut block = vec![0u8; Self::BLOCK_SIZE as usize];
+        let header_len = if self.crc {
+            block[0..4].copy_from_slice(b"XDB3");
+            block[40..48].copy_from_slice(&ino.to_be_bytes());
+            64
+        } else {
+            block[0..4].copy_from_slice(b"XD2B");
+            16
+        };
+
+        let all: Vec<(&str, u64, u8)> = [(".", ino, 2), ("..", parent, 2)]
+            .into_iter()
+            .chain(entries.iter().map(|(n, i, t)| (n.as_str(), *i, *t)))
+            .collect();
+
+        let mut pos = header_len;
+        for (name, ino, file_type) in &all {
+            let ftype_len = self.crc as usize;
+            let len = (9 + name.len() + ftype_len + 2).next_multiple_of(8);
+            let entry = &mut block[pos..pos + len];
+            entry[0..8].copy_from_slice(&ino.to_be_bytes());
+            entry[8] = name.len() as u8;
+            entry[9..9 + name.len()].copy_from_slice(name.as_bytes());
+            if self.crc {
+                entry[9 + name.len()] = *file_type;
+            }
+            entry[len - 2..].copy_from_slice(&(pos as u16).to_be_bytes());
+            pos += len;
+        }
+
+        let leaf_start = block.len() - 8 - all.len() * 8;
+        assert!(pos <= leaf_start, "test directory full");
+        if pos < leaf_start {
+            let free = leaf_start - pos;
+            block[pos..pos + 2].copy_from_slice(&0xFFFFu16.to_be_bytes());
+            block[pos + 2..pos + 4].copy_from_slice(&(free as u16).to_be_bytes());
+            block[leaf_start - 2..leaf_start].copy_from_slice(&(pos as u16).to_be_bytes());
+        }
+
+        let tail = block.len() - 8;
+        block[tail..tail + 4].copy_from_slice(&(all.len() as u32).to_be_bytes());
+        block
+    }
+
+    fn superblock(&self) -> Vec<u8> {
+        let mut sb = vec![0u8; SECTOR_SIZE as usize];
+        sb[0..4].copy_from_slice(b"XFSB");
+        sb[4..8].copy_from_slice(&(Self::BLOCK_SIZE as u32).to_be_bytes());
+        sb[8..16].copy_from_slice(&(AG_COUNT * AG_BLOCKS).to_be_bytes());
+        sb[56..64].copy_from_slice(&Self::ROOT.to_be_bytes());
+        sb[84..88].copy_from_slice(&(AG_BLOCKS as u32).to_be_bytes());
+        sb[88..92].copy_from_slice(&(AG_COUNT as u32).to_be_bytes());
+        let version: u16 = if self.crc { 0xB4A5 } else { 0x3084 };
+        sb[100..102].copy_from_slice(&version.to_be_bytes());
+        sb[102..104].copy_from_slice(&(SECTOR_SIZE as u16).to_be_bytes());
+        sb[104..106].copy_from_slice(&(INODE_SIZE as u16).to_be_bytes());
+        sb[106..108].copy_from_slice(&(1u16 << INOPB_LOG).to_be_bytes());
+        sb[120] = 12;
+        sb[121] = 9;
+        sb[122] = 9;
+        sb[123] = INOPB_LOG;
+        sb[124] = AG_BLOCK_LOG;
+        if self.crc {
+            sb[216..220].copy_from_slice(&1u32.to_be_bytes());
+        }
+        sb
+    }
+}
@@ -0,0 +1,325 @@
+//! Least-significant-bit audio backend over PCM WAVE files.
+
+use super::wav::{self, Wave};
+use super::{random_bytes, Host, HostCache, Spread};
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, PersistenceRisk};
+use crate::storage::slack_backend::{walk_host_files, SlackBackend, SlackRegion};
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Default number of low bits used in each sample.
+pub const DEFAULT_BITS_PER_SAMPLE: u8 = 1;
+
+/// A WAVE file and its usable samples.
+struct Recording {
+    file: Vec<u8>,
+    samples: Vec<usize>,
+}
+
+impl Host for Recording {
+    fn samples(&self) -> (&[u8], &[usize]) {
+        (&self.file, &self.samples)
+    }
+
+    fn samples_mut(&mut self) -> (&mut [u8], &[usize]) {
+        (&mut self.file, &self.samples)
+    }
+
+    /// Samples are changed in the file itself.
+    fn encode(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.file)
+    }
+}
+
+/// Backend storing data in the low bits of 16- and 24-bit PCM samples.
+pub struct LsbAudioBackend {
+    spread: Spread,
+    skip_silence: bool,
+    recordings: HostCache<Recording>,
+}
+
+impl LsbAudioBackend {
+    /// Create a backend using the `bits_per_sample` low bits of each
+    /// sample, visited in an order derived from `key`. With
+    /// `skip_silence`, near-silent samples are left alone, where changed
+    /// low bits would be audible as hiss and easy to spot statistically.
+    pub fn new(key: &[u8], bits_per_sample: u8, skip_silence: bool) -> Result<Self> {
+        Ok(Self {
+            spread: Spread::new(key, bits_per_sample)?,
+            skip_silence,
+            recordings: HostCache::new(),
+        })
+    }
+
+    /// Get the number of low bits used in each sample.
+    pub fn bits_per_sample(&self) -> u8 {
+        self.spread.bits
+    }
+
+    /// Get the backend name recorded in the bootstrap metadata. The bit
+    /// count and silence skipping are part of it, since both decide where
+    /// every bit lies.
+    pub fn backend_name(bits_per_sample: u8, skip_silence: bool) -> &'static str {
+        match (bits_per_sample, skip_silence) {
+            (1, false) => "lsb audio (1 bit)",
+            (2, false) => "lsb audio (2 bits)",
+            (3, false) => "lsb audio (3 bits)",
+            (_, false) => "lsb audio (4 bits)",
+            (1, true) => "lsb audio (1 bit, skip silence)",
+            (2, true) => "lsb audio (2 bits, skip silence)",
+            (3, true) => "lsb audio (3 bits, skip silence)",
+            (_, true) => "lsb audio (4 bits, skip silence)",
+        }
+    }
+
+    /// Check whether a file starts with a RIFF/WAVE header.
+    fn has_known_magic(path: &Path) -> bool {
+        let mut head = Vec::new();
+        File::open(path)
+            .and_then(|f| f.take(12).read_to_end(&mut head))
+            .is_ok_and(|n| n == 12 && head.starts_with(wav::RIFF) && &head[8..] == wav::WAVE)
+    }
+
+    /// Read a host and list its usable samples.
+    fn load(&self, path: &Path) -> Result<Recording> {
+        let file = std::fs::read(path)?;
+        if !file.starts_with(wav::RIFF) {
+            return Err(Error::Unsupported(format!(
+                "{} is not a WAVE file",
+                path.display()
+            )));
+        }
+        let wave = Wave::parse(&file)?;
+        let samples = wave.samples(&file, self.spread.bits, self.skip_silence);
+        Ok(Recording { file, samples })
+    }
+}
+
+impl SlackBackend for LsbAudioBackend {
+    fn get_slack_info(&self, path: &Path) -> Result<SlackRegion> {
+        let (sample_count, len) = self.recordings.with(
+            &self.spread,
+            path,
+            |path| self.load(path),
+            |cached| (cached.slots.len(), cached.host.file.len()),
+        )?;
+
+        Ok(SlackRegion {
+            device_path: path.to_path_buf(),
+            offset: 0,
+            available: self.spread.capacity(sample_count),
+            logical_size: len as u64,
+            block_size: 1,
+        })
+    }
+
+    /// Walks `root` like the default, keeping WAVE files.
+    fn enumerate_hosts(&self, root: &Path) -> Result<Vec<PathBuf>> {
+        Ok(walk_host_files(root)?
+            .into_iter()
+            .filter(|path| Self::has_known_magic(path))
+            .collect())
+    }
+
+    fn read_slack(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        region.check_bounds(offset, len)?;
+
+        let load = |path: &Path| self.load(path);
+        self.recordings
+            .with_region(&self.spread, region, load, |cached| {
+                self.spread
+                    .read(&cached.host.file, &cached.slots, offset, len)
+            })
+    }
+
+    /// Changes only the `data` chunk's samples of the cached host, saved on
+    /// flush; the file keeps its size.
+    fn write_slack(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        region.check_bounds(offset, data.len())?;
+
+        let load = |path: &Path| self.load(path);
+        self.recordings
+            .with_region(&self.spread, region, load, |cached| {
+                self.spread
+                    .write(&mut cached.host.file, &cached.slots, offset, data);
+                cached.dirty = true;
+            })
+    }
+
+    /// Fills the region with random bits.
+    fn wipe_slack(&self, region: &SlackRegion) -> Result<()> {
+        self.write_slack(region, 0, &random_bytes(region.available))
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.recordings.flush()
+    }
+
+    /// Only sample values change; size, modification time and every other
+    /// chunk stay. Converting or editing the audio loses the data.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::Moderate,
+            changes_visible_metadata: false,
+            needs_root: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &'static str {
+        Self::backend_name(self.spread.bits, self.skip_silence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::HostManager;
+    use tempfile::TempDir;
+
+    /// A tone fading in from silence, `len` samples long.
+    fn fade_in(len: usize, peak: i32) -> Vec<i32> {
+        (0..len)
+            .map(|i| {
+                let level = peak as f64 * i as f64 / len as f64;
+                (level * (i as f64 * 0.3).sin()) as i32
+            })
+            .collect()
+    }
+
+    /// Write a stereo 16-bit file, a mono 24-bit file and a text file.
+    fn host_dir() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let stereo = wav::build(2, 16, &fade_in(4000, 30000));
+        std::fs::write(dir.path().join("stereo.wav"), stereo).unwrap();
+        let mono = wav::build(1, 24, &fade_in(3000, 8_000_000));
+        std::fs::write(dir.path().join("mono.wav"), mono).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"plain text").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_write_read_preserves_other_chunks() {
+        let dir = host_dir();
+        for skip_silence in [false, true] {
+            let backend = LsbAudioBackend::new(b"key", 2, skip_silence).unwrap();
+            for name in ["stereo.wav", "mono.wav"] {
+                let path = dir.path().join(name);
+                let before = std::fs::read(&path).unwrap();
+                let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+                let wave = Wave::parse(&before).unwrap();
+
+                let region = backend.get_slack_info(&path).unwrap();
+                let data: Vec<u8> = (0..region.available).map(|i| (i * 13) as u8).collect();
+                backend.write_slack(&region, 0, &data).unwrap();
+                backend.write_slack(&region, 5, b"mid").unwrap();
+                backend.flush().unwrap();
+                let read = backend.read_slack(&region, 0, data.len()).unwrap();
+                assert_eq!(&read[..5], &data[..5]);
+                assert_eq!(&read[5..8], b"mid");
+                assert_eq!(&read[8..], &data[8..]);
+
+                // Everything outside the samples is byte for byte the same
+                let after = std::fs::read(&path).unwrap();
+                assert_eq!(after.len(), before.len());
+                let metadata = std::fs::metadata(&path).unwrap();
+                assert_eq!(metadata.modified().unwrap(), modified);
+                assert_eq!(&after[..wave.data.start], &before[..wave.data.start]);
+                assert_eq!(&after[wave.data.end..], &before[wave.data.end..]);
+
+                // The region resolves the same after writing
+                let again = backend.get_slack_info(&path).unwrap();
+                assert_eq!(again.available, region.available);
+            }
+        }
+    }
+
+    #[test]
+    fn test_skip_silence_leaves_quiet_samples() {
+        let dir = host_dir();
+        let path = dir.path().join("stereo.wav");
+        let before = std::fs::read(&path).unwrap();
+
+        let all = LsbAudioBackend::new(b"key", 1, false).unwrap();
+        let loud = LsbAudioBackend::new(b"key", 1, true).unwrap();
+        let all_region = all.get_slack_info(&path).unwrap();
+        let loud_region = loud.get_slack_info(&path).unwrap();
+        assert_eq!(all_region.available, 4000 / 8);
+        assert!(loud_region.available < all_region.available);
+
+        loud.write_slack(&loud_region, 0, &vec![0x5A; loud_region.available as usize])
+            .unwrap();
+        loud.flush().unwrap();
+        let after = std::fs::read(&path).unwrap();
+        let wave = Wave::parse(&before).unwrap();
+        let loud_samples = wave.samples(&before, 1, true);
+        for at in wave.data.clone().step_by(2) {
+            if !loud_samples.contains(&at) {
+                assert_eq!(
+                    after[at..at + 2],
+                    before[at..at + 2],
+                    "quiet sample at {}",
+                    at
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_scan_keeps_pcm_wave_files() {
+        let dir = host_dir();
+        let mut float = wav::build(1, 16, &[0; 64]);
+        float[20] = 3;
+        std::fs::write(dir.path().join("float.wav"), float).unwrap();
+
+        let backend = LsbAudioBackend::new(b"key", 1, false).unwrap();
+        let manager = HostManager::scan_with_backend(dir.path(), 4096, &backend).unwrap();
+        let mut names: Vec<_> = manager
+            .hosts()
+            .iter()
+            .map(|h| h.path.file_name().unwrap().to_str().unwrap().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["mono.wav", "stereo.wav"]);
+        assert_eq!(manager.total_capacity(), (4000 + 3000) / 8);
+    }
+
+    #[test]
+    fn test_vfs_on_lsb_audio() {
+        use crate::{SlackVfs, VfsConfig};
+
+        let dir = TempDir::new().unwrap();
+        for i in 0..4 {
+            let file = wav::build(2, 16, &fade_in(40000 + i * 100, 20000));
+            std::fs::write(dir.path().join(format!("{}.wav", i)), file).unwrap();
+        }
+        let config = VfsConfig {
+            symbol_size: 256,
+            ..VfsConfig::default()
+        };
+
+        {
+            let backend = Box::new(LsbAudioBackend::new(b"key", 1, true).unwrap());
+            let mut vfs = SlackVfs::create_with_backend(dir.path(), "pw", config, backend).unwrap();
+            vfs.create_file("/secret.txt", b"hidden in the noise floor")
+                .unwrap();
+        }
+
+        let backend = Box::new(LsbAudioBackend::new(b"key", 1, true).unwrap());
+        let vfs = SlackVfs::mount_with_backend(dir.path(), "pw", backend).unwrap();
+        assert_eq!(
+            vfs.read_file("/secret.txt").unwrap(),
+            b"hidden in the noise floor"
+        );
+
+        // Without silence skipping the bits lie elsewhere
+        let backend = Box::new(LsbAudioBackend::new(b"key", 1, false).unwrap());
+        assert!(SlackVfs::mount_with_backend(dir.path(), "pw", backend).is_err());
+    }
+}
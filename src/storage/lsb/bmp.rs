@@ -0,0 +1,123 @@
+//! BMP pixel access.
+//!
+//! Uncompressed 24- and 32-bit bitmaps store samples as plain bytes, so
+//! they are changed in place and everything else in the file, headers,
+//! colour profile and row padding included, stays as it was.
+
+use crate::error::{Error, Result};
+
+/// BMP file signature.
+pub(super) const SIGNATURE: &[u8] = b"BM";
+
+/// File header size; the DIB header follows.
+const FILE_HEADER_LEN: usize = 14;
+/// `BITMAPINFOHEADER` size, the smallest header this reads.
+const INFO_HEADER_LEN: usize = 40;
+/// `BI_RGB`: no compression.
+const BI_RGB: u32 = 0;
+
+/// Offsets into the file of the blue, green and red sample of every pixel.
+/// The fourth byte of 32-bit pixels, alpha or unused, is left out.
+pub(super) fn samples(file: &[u8]) -> Result<Vec<usize>> {
+    if !file.starts_with(SIGNATURE) || file.len() < FILE_HEADER_LEN + INFO_HEADER_LEN {
+        return Err(Error::DataCorruption("Not a BMP file".to_string()));
+    }
+    let u16_at = |at: usize| u16::from_le_bytes([file[at], file[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes(file[at..at + 4].try_into().unwrap());
+
+    let data_offset = u32_at(10) as usize;
+    let header_len = u32_at(14) as usize;
+    if header_len < INFO_HEADER_LEN {
+        return Err(Error::Unsupported(
+            "OS/2 BMP headers are not supported".to_string(),
+        ));
+    }
+    let width = u32_at(18) as i32;
+    // Negative heights mark top-down bitmaps; row order does not matter here
+    let height = (u32_at(22) as i32).unsigned_abs() as usize;
+    let bits_per_pixel = u16_at(28);
+    let compression = u32_at(30);
+
+    if width <= 0 {
+        return Err(Error::DataCorruption(format!("Bad BMP width {}", width)));
+    }
+    if compression != BI_RGB || !matches!(bits_per_pixel, 24 | 32) {
+        return Err(Error::Unsupported(format!(
+            "Only uncompressed 24- and 32-bit BMPs are supported, not {} bits with compression {}",
+            bits_per_pixel, compression
+        )));
+    }
+
+    let width = width as usize;
+    let pixel_bytes = bits_per_pixel as usize / 8;
+    // Rows are padded to a multiple of four bytes
+    let stride = (width * pixel_bytes).div_ceil(4) * 4;
+    let end = data_offset + stride * height;
+    if data_offset < FILE_HEADER_LEN + header_len || end > file.len() {
+        return Err(Error::DataCorruption(
+            "BMP pixel data lies outside the file".to_string(),
+        ));
+    }
+
+    let mut samples = Vec::with_capacity(width * height * 3);
+    for row in 0..height {
+        let row_start = data_offset + row * stride;
+        for pixel in 0..width {
+            let at = row_start + pixel * pixel_bytes;
+            samples.extend([at, at + 1, at + 2]);
+        }
+    }
+    Ok(samples)
+}
+
+/// Build an uncompressed BMP, for tests.
+#[cfg(test)]
+pub(super) fn build(width: u32, height: i32, bits_per_pixel: u16, pixels: &[u8]) -> Vec<u8> {
+    let data_offset = (FILE_HEADER_LEN + INFO_HEADER_LEN) as u32;
+    let mut file = SIGNATURE.to_vec();
+    file.extend_from_slice(&(data_offset + pixels.len() as u32).to_le_bytes());
+    file.extend_from_slice(&[0; 4]);
+    file.extend_from_slice(&data_offset.to_le_bytes());
+    file.extend_from_slice(&(INFO_HEADER_LEN as u32).to_le_bytes());
+    file.extend_from_slice(&width.to_le_bytes());
+    file.extend_from_slice(&height.to_le_bytes());
+    file.extend_from_slice(&1u16.to_le_bytes());
+    file.extend_from_slice(&bits_per_pixel.to_le_bytes());
+    file.extend_from_slice(&BI_RGB.to_le_bytes());
+    file.extend_from_slice(&(pixels.len() as u32).to_le_bytes());
+    // Resolution (2835 px/m is 72 dpi), palette and important colours
+    file.extend_from_slice(&2835u32.to_le_bytes());
+    file.extend_from_slice(&2835u32.to_le_bytes());
+    file.extend_from_slice(&[0; 8]);
+    file.extend_from_slice(pixels);
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_row_padding_skipped() {
+        // 3 pixels of 3 bytes: 9 bytes of samples, 3 of padding per row
+        let file = build(3, 2, 24, &[0; 24]);
+        let samples = samples(&file).unwrap();
+        assert_eq!(samples.len(), 18);
+        assert_eq!(samples[..9], [54, 55, 56, 57, 58, 59, 60, 61, 62]);
+        assert_eq!(samples[9], 54 + 12);
+    }
+
+    #[test]
+    fn test_fourth_byte_skipped() {
+        let file = build(2, -1, 32, &[0; 8]);
+        assert_eq!(samples(&file).unwrap(), vec![54, 55, 56, 58, 59, 60]);
+    }
+
+    #[test]
+    fn test_paletted_rejected() {
+        let mut file = build(4, 1, 24, &[0; 12]);
+        file[28] = 8;
+        assert!(matches!(samples(&file), Err(Error::Unsupported(_))));
+        assert!(samples(&file[..30]).is_err());
+    }
+}
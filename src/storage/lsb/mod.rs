@@ -0,0 +1,774 @@
+//! Least-significant-bit backends for images and audio.
+//!
+//! Spread data over the low bits of media samples, for host sets without
+//! many large files. [`LsbImageBackend`] uses the colour samples of
+//! lossless images:
+//!
+//! - PNG: 8- and 16-bit grayscale, RGB, gray+alpha and RGBA, not interlaced.
+//!   The image data is inflated, changed and deflated again; other chunks
+//!   are kept byte for byte
+//! - BMP: uncompressed 24- and 32-bit bitmaps, where only samples change
+//!
+//! Alpha is never touched. [`LsbAudioBackend`] uses the samples of 16- and
+//! 24-bit PCM WAVE files, where only samples change, optionally leaving
+//! near-silent samples alone.
+//!
+//! Hosts are decoded once and kept while in use. Changes are saved when
+//! the backend is flushed, by writing a new file and renaming it over the
+//! host, keeping its permissions and modification time.
+//!
+//! A host's capacity is its usable sample count times the bits used per
+//! sample, divided by eight. Samples are visited in an order shuffled by a
+//! key and the host's file name, so consecutive bytes of a region do not
+//! sit in neighbouring samples and the changes are scattered over the whole
+//! host. The same key and settings are needed to find the data again.
+//!
+//! Lossless copies, re-saves and optimisers keep the data; converting,
+//! resizing, resampling or editing the host loses it.
+
+mod audio;
+mod bmp;
+mod png;
+mod wav;
+
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, PersistenceRisk};
+use crate::storage::slack_backend::{walk_host_files, SlackBackend, SlackRegion};
+pub use audio::{LsbAudioBackend, DEFAULT_BITS_PER_SAMPLE};
+use png::PngImage;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Default number of low bits used in each colour sample.
+pub const DEFAULT_BITS_PER_CHANNEL: u8 = 1;
+
+/// Most low bits used in each colour sample; beyond this, changes show.
+pub const MAX_BITS_PER_CHANNEL: u8 = 4;
+
+/// Domain separator for the sample order seed.
+const ORDER_DOMAIN: &[u8] = b"slack-vfs lsb order\0";
+
+/// A host decoded into its samples.
+trait Host: Sized {
+    /// Get the bytes holding the samples and the offset of each sample's
+    /// low byte.
+    fn samples(&self) -> (&[u8], &[usize]);
+
+    fn samples_mut(&mut self) -> (&mut [u8], &[usize]);
+
+    /// Encode the host back into a file.
+    fn encode(&self) -> Cow<'_, [u8]>;
+}
+
+/// An image's samples, ready to change.
+enum Cover {
+    Png { file: Vec<u8>, image: PngImage },
+    Bmp { file: Vec<u8>, samples: Vec<usize> },
+}
+
+impl Cover {
+    /// Read and decode a host.
+    fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::read(path)?;
+        if file.starts_with(png::SIGNATURE) {
+            let image = PngImage::decode(&file)?;
+            Ok(Self::Png { file, image })
+        } else if file.starts_with(bmp::SIGNATURE) {
+            let samples = bmp::samples(&file)?;
+            Ok(Self::Bmp { file, samples })
+        } else {
+            Err(Error::Unsupported(format!(
+                "{} is not a PNG or BMP file",
+                path.display()
+            )))
+        }
+    }
+}
+
+impl Host for Cover {
+    fn samples(&self) -> (&[u8], &[usize]) {
+        match self {
+            Self::Png { image, .. } => (&image.rows, &image.samples),
+            Self::Bmp { file, samples } => (file, samples),
+        }
+    }
+
+    fn samples_mut(&mut self) -> (&mut [u8], &[usize]) {
+        match self {
+            Self::Png { image, .. } => (&mut image.rows, &image.samples),
+            Self::Bmp { file, samples } => (file, samples),
+        }
+    }
+
+    /// Deflates a PNG's image data again.
+    fn encode(&self) -> Cow<'_, [u8]> {
+        match self {
+            Self::Png { file, image } => Cow::Owned(image.encode(file)),
+            Self::Bmp { file, .. } => Cow::Borrowed(file),
+        }
+    }
+}
+
+/// Replace a file's contents in one step.
+///
+/// The new contents go to a hidden file beside it, which is synced and
+/// renamed over it, so a crash or a full disk leaves either the old or the
+/// new file, never a mix. Permissions and modification time are kept.
+fn replace_file(path: &Path, bytes: &[u8]) -> Result<()> {
+    let original = std::fs::metadata(path)?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{:08x}.tmp", rand::random::<u32>()));
+    let temp = dir.join(name);
+
+    let written = (|| -> Result<()> {
+        let mut out = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+        out.write_all(bytes)?;
+        out.set_permissions(original.permissions())?;
+        out.set_modified(original.modified()?)?;
+        out.sync_all()?;
+        Ok(())
+    })();
+    if let Err(e) = written.and_then(|_| Ok(std::fs::rename(&temp, path)?)) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    // The rename is only durable once the directory is
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Most bytes of decoded hosts kept in a [`HostCache`].
+const CACHE_BUDGET: usize = 256 << 20;
+
+/// What a host file looked like when it was last read or saved.
+#[derive(PartialEq)]
+struct Stamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+/// A decoded host with its sample order.
+struct Cached<H> {
+    host: H,
+    /// Sample offsets in the order bits are stored in.
+    slots: Vec<usize>,
+    stamp: Stamp,
+    /// Changed since it was last saved.
+    dirty: bool,
+    /// When it was last used, for eviction.
+    used: u64,
+}
+
+impl<H: Host> Cached<H> {
+    /// Approximate memory held.
+    fn footprint(&self) -> usize {
+        let (bytes, samples) = self.host.samples();
+        bytes.len() + (samples.len() + self.slots.len()) * std::mem::size_of::<usize>()
+    }
+
+    fn save(&mut self, path: &Path) -> Result<()> {
+        replace_file(path, &self.host.encode())?;
+        self.stamp = Stamp::of(path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Decoded hosts kept between calls.
+///
+/// Writing many symbols to a host then decodes it once and encodes it once,
+/// on flush, rather than once per symbol. A cached host is decoded again
+/// if its file's size or modification time changes. The least recently
+/// used hosts are dropped past [`CACHE_BUDGET`], saved first if changed.
+struct HostCache<H> {
+    hosts: Mutex<HashMap<PathBuf, Cached<H>>>,
+}
+
+impl<H: Host> HostCache<H> {
+    fn new() -> Self {
+        Self {
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run `f` on a host and its sample order, decoding the host with
+    /// `load` unless it is cached and unchanged.
+    fn with<R>(
+        &self,
+        spread: &Spread,
+        path: &Path,
+        load: impl FnOnce(&Path) -> Result<H>,
+        f: impl FnOnce(&mut Cached<H>) -> R,
+    ) -> Result<R> {
+        let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        let tick = hosts.values().map(|c| c.used + 1).max().unwrap_or(0);
+
+        let stale = match hosts.get(path) {
+            Some(cached) => !cached.dirty && cached.stamp != Stamp::of(path)?,
+            None => true,
+        };
+        if stale {
+            let stamp = Stamp::of(path)?;
+            let host = load(path)?;
+            let slots = spread.slots(path, host.samples().1);
+            let cached = Cached {
+                host,
+                slots,
+                stamp,
+                dirty: false,
+                used: tick,
+            };
+            hosts.insert(path.to_path_buf(), cached);
+            Self::evict(&mut hosts, path)?;
+        }
+
+        let cached = hosts.get_mut(path).expect("host was just cached");
+        cached.used = tick;
+        Ok(f(cached))
+    }
+
+    /// Run `f` on a region's host like [`Self::with`], checking its sample
+    /// count still gives the region's capacity.
+    fn with_region<R>(
+        &self,
+        spread: &Spread,
+        region: &SlackRegion,
+        load: impl FnOnce(&Path) -> Result<H>,
+        f: impl FnOnce(&mut Cached<H>) -> R,
+    ) -> Result<R> {
+        self.with(spread, &region.device_path, load, |cached| {
+            if spread.capacity(cached.slots.len()) != region.available {
+                return Err(Error::DataCorruption(format!(
+                    "{} has changed since it was scanned",
+                    region.device_path.display()
+                )));
+            }
+            Ok(f(cached))
+        })?
+    }
+
+    /// Drop the least recently used hosts other than `keep` until the rest
+    /// fit the budget.
+    fn evict(hosts: &mut HashMap<PathBuf, Cached<H>>, keep: &Path) -> Result<()> {
+        let mut total: usize = hosts.values().map(Cached::footprint).sum();
+        while total > CACHE_BUDGET {
+            let Some(oldest) = hosts
+                .iter()
+                .filter(|(path, _)| path.as_path() != keep)
+                .min_by_key(|(_, cached)| cached.used)
+                .map(|(path, _)| path.clone())
+            else {
+                break;
+            };
+            let mut cached = hosts.remove(&oldest).expect("host is cached");
+            if cached.dirty {
+                if let Err(e) = cached.save(&oldest) {
+                    hosts.insert(oldest, cached);
+                    return Err(e);
+                }
+            }
+            total -= cached.footprint();
+        }
+        Ok(())
+    }
+
+    /// Save every changed host.
+    fn flush(&self) -> Result<()> {
+        let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        for (path, cached) in hosts.iter_mut() {
+            if cached.dirty {
+                cached.save(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Key-derived placement of a region's bits in a host's samples.
+///
+/// Region bit `n` is bit `n % bits` of the `n / bits`-th sample in an order
+/// shuffled by the key and the host's file name.
+struct Spread {
+    /// Hash of the key shuffling the sample order.
+    key: [u8; 32],
+    /// Low bits used in each sample.
+    bits: u8,
+}
+
+impl Spread {
+    fn new(key: &[u8], bits: u8) -> Result<Self> {
+        if !(1..=MAX_BITS_PER_CHANNEL).contains(&bits) {
+            return Err(Error::Unsupported(format!(
+                "Bits per sample must be between 1 and {}, not {}",
+                MAX_BITS_PER_CHANNEL, bits
+            )));
+        }
+        Ok(Self {
+            key: Sha256::digest(key).into(),
+            bits,
+        })
+    }
+
+    /// Get the capacity in bytes of a host with `sample_count` samples.
+    fn capacity(&self, sample_count: usize) -> u64 {
+        sample_count as u64 * self.bits as u64 / 8
+    }
+
+    /// Shuffle a host's sample offsets into the order bits are stored in.
+    ///
+    /// Fisher-Yates driven by SHA-256 in counter mode over the key and the
+    /// host's file name, so the order is the same on every platform and
+    /// release, and differs between hosts.
+    fn slots(&self, path: &Path, samples: &[usize]) -> Vec<usize> {
+        let mut seed = Sha256::new();
+        seed.update(ORDER_DOMAIN);
+        seed.update(self.key);
+        seed.update(path.file_name().unwrap_or_default().as_encoded_bytes());
+        let seed = seed.finalize();
+
+        let mut counter = 0u64;
+        let mut pool = Vec::new();
+        let mut next = || {
+            if pool.is_empty() {
+                let block = Sha256::new()
+                    .chain_update(seed)
+                    .chain_update(counter.to_le_bytes())
+                    .finalize();
+                counter += 1;
+                pool = block
+                    .chunks(8)
+                    .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+                    .collect();
+            }
+            pool.pop().unwrap()
+        };
+
+        let mut slots = samples.to_vec();
+        for i in (1..slots.len()).rev() {
+            // The modulo bias is below 2^-40 for any host that fits in memory
+            let j = (next() % (i as u64 + 1)) as usize;
+            slots.swap(i, j);
+        }
+        slots
+    }
+
+    /// Locate the `n`-th stored bit: the byte holding it and its bit plane.
+    fn locate(&self, slots: &[usize], n: u64) -> (usize, u8) {
+        let bits = self.bits as u64;
+        (slots[(n / bits) as usize], (n % bits) as u8)
+    }
+
+    /// Gather `len` region bytes from `offset` on.
+    fn read(&self, bytes: &[u8], slots: &[usize], offset: u64, len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        for n in offset * 8..(offset + len as u64) * 8 {
+            let (at, plane) = self.locate(slots, n);
+            let bit = (bytes[at] >> plane) & 1;
+            data[(n / 8 - offset) as usize] |= bit << (n % 8);
+        }
+        data
+    }
+
+    /// Scatter `data` into the region from `offset` on.
+    fn write(&self, bytes: &mut [u8], slots: &[usize], offset: u64, data: &[u8]) {
+        for n in offset * 8..(offset + data.len() as u64) * 8 {
+            let (at, plane) = self.locate(slots, n);
+            let bit = (data[(n / 8 - offset) as usize] >> (n % 8)) & 1;
+            bytes[at] = (bytes[at] & !(1 << plane)) | (bit << plane);
+        }
+    }
+}
+
+/// Random bytes to wipe a region with, since all-zero low bits stand out
+/// more than the noise they replace.
+fn random_bytes(len: u64) -> Vec<u8> {
+    let mut bytes = vec![0u8; len as usize];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Backend storing data in the low bits of image samples.
+pub struct LsbImageBackend {
+    spread: Spread,
+    covers: HostCache<Cover>,
+}
+
+impl LsbImageBackend {
+    /// Create a backend using the `bits_per_channel` low bits of each
+    /// colour sample, visited in an order derived from `key`.
+    pub fn new(key: &[u8], bits_per_channel: u8) -> Result<Self> {
+        Ok(Self {
+            spread: Spread::new(key, bits_per_channel)?,
+            covers: HostCache::new(),
+        })
+    }
+
+    /// Get the number of low bits used in each colour sample.
+    pub fn bits_per_channel(&self) -> u8 {
+        self.spread.bits
+    }
+
+    /// Get the backend name recorded in the bootstrap metadata. The bit
+    /// count is part of it, since it decides where every bit lies.
+    pub fn backend_name(bits_per_channel: u8) -> &'static str {
+        match bits_per_channel {
+            1 => "lsb images (1 bit)",
+            2 => "lsb images (2 bits)",
+            3 => "lsb images (3 bits)",
+            _ => "lsb images (4 bits)",
+        }
+    }
+
+    /// Check whether a file starts with a PNG or BMP signature.
+    fn has_known_magic(path: &Path) -> bool {
+        let mut head = Vec::new();
+        File::open(path)
+            .and_then(|f| f.take(8).read_to_end(&mut head))
+            .is_ok_and(|_| head.starts_with(png::SIGNATURE) || head.starts_with(bmp::SIGNATURE))
+    }
+}
+
+impl SlackBackend for LsbImageBackend {
+    fn get_slack_info(&self, path: &Path) -> Result<SlackRegion> {
+        let sample_count = self
+            .covers
+            .with(&self.spread, path, Cover::load, |cached| cached.slots.len())?;
+
+        Ok(SlackRegion {
+            device_path: path.to_path_buf(),
+            offset: 0,
+            available: self.spread.capacity(sample_count),
+            logical_size: std::fs::metadata(path)?.len(),
+            block_size: 1,
+        })
+    }
+
+    /// Walks `root` like the default, keeping PNG and BMP files.
+    fn enumerate_hosts(&self, root: &Path) -> Result<Vec<PathBuf>> {
+        Ok(walk_host_files(root)?
+            .into_iter()
+            .filter(|path| Self::has_known_magic(path))
+            .collect())
+    }
+
+    fn read_slack(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        region.check_bounds(offset, len)?;
+
+        self.covers
+            .with_region(&self.spread, region, Cover::load, |cached| {
+                let (bytes, _) = cached.host.samples();
+                self.spread.read(bytes, &cached.slots, offset, len)
+            })
+    }
+
+    /// Changes the cached host; it is re-encoded whole on flush, as PNG
+    /// image data is compressed as one stream.
+    fn write_slack(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        region.check_bounds(offset, data.len())?;
+
+        self.covers
+            .with_region(&self.spread, region, Cover::load, |cached| {
+                let (bytes, _) = cached.host.samples_mut();
+                self.spread.write(bytes, &cached.slots, offset, data);
+                cached.dirty = true;
+            })
+    }
+
+    /// Fills the region with random bits.
+    fn wipe_slack(&self, region: &SlackRegion) -> Result<()> {
+        self.write_slack(region, 0, &random_bytes(region.available))
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.covers.flush()
+    }
+
+    /// The host is rewritten on every flush, and a PNG's size changes with
+    /// its recompressed image data.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::Moderate,
+            changes_visible_metadata: true,
+            needs_root: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &'static str {
+        Self::backend_name(self.spread.bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::HostManager;
+    use tempfile::TempDir;
+
+    /// Pixel bytes that vary, like a photo's.
+    fn noise(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 131 % 253) as u8).collect()
+    }
+
+    /// Write an RGB PNG, an RGBA PNG with a text chunk, a 24-bit BMP and
+    /// a text file.
+    fn host_dir() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let rgb = png::build(16, 16, 8, 2, &noise(16 * 16 * 3), &[]);
+        std::fs::write(dir.path().join("rgb.png"), rgb).unwrap();
+        let rgba = png::build(
+            16,
+            8,
+            8,
+            6,
+            &noise(16 * 8 * 4),
+            &[(b"tEXt", b"Author\0someone")],
+        );
+        std::fs::write(dir.path().join("rgba.png"), rgba).unwrap();
+        // 10 pixels of 3 bytes plus 2 bytes of padding per row
+        std::fs::write(
+            dir.path().join("scan.bmp"),
+            bmp::build(10, 12, 24, &noise(32 * 12)),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"plain text").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_capacity_per_bits() {
+        let dir = host_dir();
+        let path = dir.path().join("rgb.png");
+        for bits in 1..=MAX_BITS_PER_CHANNEL {
+            let backend = LsbImageBackend::new(b"key", bits).unwrap();
+            let region = backend.get_slack_info(&path).unwrap();
+            assert_eq!(region.available, 16 * 16 * 3 * bits as u64 / 8);
+        }
+        assert!(LsbImageBackend::new(b"key", 0).is_err());
+        assert!(LsbImageBackend::new(b"key", MAX_BITS_PER_CHANNEL + 1).is_err());
+
+        // Alpha carries nothing
+        let backend = LsbImageBackend::new(b"key", 1).unwrap();
+        let rgba = backend
+            .get_slack_info(&dir.path().join("rgba.png"))
+            .unwrap();
+        assert_eq!(rgba.available, 16 * 8 * 3 / 8);
+        let bmp = backend
+            .get_slack_info(&dir.path().join("scan.bmp"))
+            .unwrap();
+        assert_eq!(bmp.available, 10 * 12 * 3 / 8);
+    }
+
+    #[test]
+    fn test_write_read_every_format() {
+        let dir = host_dir();
+        for bits in [1, 3] {
+            let backend = LsbImageBackend::new(b"key", bits).unwrap();
+            for name in ["rgb.png", "rgba.png", "scan.bmp"] {
+                let path = dir.path().join(name);
+                let region = backend.get_slack_info(&path).unwrap();
+                let data: Vec<u8> = (0..region.available - 7).map(|i| (i * 7) as u8).collect();
+
+                backend.write_slack(&region, 7, &data).unwrap();
+                backend.write_slack(&region, 0, b"header!").unwrap();
+                assert_eq!(backend.read_slack(&region, 7, data.len()).unwrap(), data);
+                assert_eq!(backend.read_slack(&region, 0, 7).unwrap(), b"header!");
+                assert_eq!(backend.read_slack(&region, 3, 2).unwrap(), b"de");
+
+                let result = backend.read_slack(&region, 1, region.available as usize);
+                assert!(matches!(result, Err(Error::InsufficientSpace { .. })));
+            }
+        }
+    }
+
+    #[test]
+    fn test_only_low_bits_change() {
+        let dir = host_dir();
+        let path = dir.path().join("rgba.png");
+        let before = PngImage::decode(&std::fs::read(&path).unwrap()).unwrap();
+
+        let backend = LsbImageBackend::new(b"key", 2).unwrap();
+        let region = backend.get_slack_info(&path).unwrap();
+        backend
+            .write_slack(&region, 0, &vec![0xA5; region.available as usize])
+            .unwrap();
+        backend.flush().unwrap();
+
+        let file = std::fs::read(&path).unwrap();
+        assert!(crate::storage::format::png::is_valid(&file));
+        let after = PngImage::decode(&file).unwrap();
+        let mut changed = 0;
+        for (i, (old, new)) in before.rows.iter().zip(&after.rows).enumerate() {
+            if old != new {
+                assert!(
+                    before.samples.contains(&i),
+                    "byte {} is not a colour sample",
+                    i
+                );
+                assert_eq!(old & !0b11, new & !0b11);
+                changed += 1;
+            }
+        }
+        assert!(changed > 0);
+
+        // The text chunk is still there, unchanged
+        let text = b"tEXtAuthor\0someone";
+        assert!(file.windows(text.len()).any(|w| w == text));
+    }
+
+    #[test]
+    fn test_order_depends_on_key() {
+        let dir = host_dir();
+        let path = dir.path().join("scan.bmp");
+        let original = std::fs::read(&path).unwrap();
+
+        let backend = LsbImageBackend::new(b"key", 1).unwrap();
+        let region = backend.get_slack_info(&path).unwrap();
+        backend.write_slack(&region, 0, b"secret").unwrap();
+        backend.flush().unwrap();
+
+        // Not laid out in sample order
+        let samples = bmp::samples(&original).unwrap();
+        let slots = backend.spread.slots(&path, &samples);
+        assert_ne!(slots[..48], samples[..48]);
+        let mut sorted = slots.clone();
+        sorted.sort();
+        assert_eq!(sorted, samples);
+
+        // Only the bytes of the chosen samples changed
+        let file = std::fs::read(&path).unwrap();
+        for (i, (old, new)) in original.iter().zip(&file).enumerate() {
+            if old != new {
+                assert!(slots[..48].contains(&i));
+            }
+        }
+
+        let other = LsbImageBackend::new(b"other key", 1).unwrap();
+        assert_ne!(other.read_slack(&region, 0, 6).unwrap(), b"secret");
+        assert_eq!(backend.read_slack(&region, 0, 6).unwrap(), b"secret");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_flush_replaces_host() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = host_dir();
+        let path = dir.path().join("rgb.png");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let original = std::fs::read(&path).unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        // Writes stay in the cached host until flushed
+        let backend = LsbImageBackend::new(b"key", 1).unwrap();
+        let region = backend.get_slack_info(&path).unwrap();
+        for i in 0..8u8 {
+            backend.write_slack(&region, i as u64 * 4, &[i; 4]).unwrap();
+        }
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+        assert_eq!(backend.read_slack(&region, 28, 4).unwrap(), [7; 4]);
+
+        backend.flush().unwrap();
+        assert_ne!(std::fs::read(&path).unwrap(), original);
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+        assert_eq!(metadata.modified().unwrap(), modified);
+        let names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names.len(), 4, "temporary file left: {:?}", names);
+
+        // A fresh backend finds the data in the file
+        let other = LsbImageBackend::new(b"key", 1).unwrap();
+        assert_eq!(other.read_slack(&region, 28, 4).unwrap(), [7; 4]);
+
+        // A host replaced by someone else is decoded again
+        std::fs::write(&path, &original).unwrap();
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(modified + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert_ne!(backend.read_slack(&region, 28, 4).unwrap(), [7; 4]);
+    }
+
+    #[test]
+    fn test_scan_skips_unsupported() {
+        let dir = host_dir();
+        let paletted = png::build(4, 4, 8, 3, &[0; 16], &[(b"PLTE", &[0; 3])]);
+        std::fs::write(dir.path().join("paletted.png"), paletted).unwrap();
+
+        let backend = LsbImageBackend::new(b"key", 1).unwrap();
+        let manager = HostManager::scan_with_backend(dir.path(), 4096, &backend).unwrap();
+        let mut names: Vec<_> = manager
+            .hosts()
+            .iter()
+            .map(|h| h.path.file_name().unwrap().to_str().unwrap().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["rgb.png", "rgba.png", "scan.bmp"]);
+    }
+
+    #[test]
+    fn test_vfs_on_lsb_images() {
+        use crate::{SlackVfs, VfsConfig};
+
+        let dir = TempDir::new().unwrap();
+        for i in 0..4u8 {
+            let pixels: Vec<u8> = noise(64 * 64 * 3).iter().map(|b| b ^ i).collect();
+            let file = png::build(64, 64, 8, 2, &pixels, &[]);
+            std::fs::write(dir.path().join(format!("{}.png", i)), file).unwrap();
+        }
+        let config = VfsConfig {
+            symbol_size: 256,
+            ..VfsConfig::default()
+        };
+
+        {
+            let backend = Box::new(LsbImageBackend::new(b"key", 2).unwrap());
+            let mut vfs = SlackVfs::create_with_backend(dir.path(), "pw", config, backend).unwrap();
+            vfs.create_file("/secret.txt", b"hidden in the pixels")
+                .unwrap();
+        }
+
+        let backend = Box::new(LsbImageBackend::new(b"key", 2).unwrap());
+        let vfs = SlackVfs::mount_with_backend(dir.path(), "pw", backend).unwrap();
+        assert_eq!(
+            vfs.read_file("/secret.txt").unwrap(),
+            b"hidden in the pixels"
+        );
+
+        // A different bit count is a different backend
+        let backend = Box::new(LsbImageBackend::new(b"key", 1).unwrap());
+        assert!(SlackVfs::mount_with_backend(dir.path(), "pw", backend).is_err());
+    }
+}
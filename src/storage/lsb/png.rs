@@ -0,0 +1,367 @@
+//! PNG pixel access: inflate and unfilter the image data, then filter and
+//! deflate it again.
+//!
+//! Every chunk other than `IDAT` is kept byte for byte, so text, colour
+//! profile, physical size and Exif metadata survive. Each scanline is
+//! re-filtered with the filter type it had, which keeps the filter choice
+//! an encoder made; only the compressed stream differs.
+
+use crate::error::{Error, Result};
+use crate::storage::format::png::chunks;
+use crate::storage::partition::crc32;
+use std::ops::Range;
+
+pub(super) use crate::storage::format::png::SIGNATURE;
+
+/// Compression level used when re-encoding, zlib's default.
+const COMPRESSION_LEVEL: u8 = 6;
+
+/// Size of each `IDAT` chunk written when the original's cannot be reused.
+const IDAT_CHUNK_LEN: usize = 8192;
+
+/// A decoded non-interlaced PNG with 8- or 16-bit samples.
+pub(super) struct PngImage {
+    /// Unfiltered scanlines, each led by its original filter type byte.
+    pub rows: Vec<u8>,
+    /// Offsets into `rows` of the low byte of every colour sample; alpha is
+    /// left out, as changing it shows on transparent pixels.
+    pub samples: Vec<usize>,
+    /// Bytes of the file covered by the `IDAT` chunks.
+    idat: Range<usize>,
+    /// Data length of the first `IDAT` chunk, reused when splitting.
+    idat_chunk_len: usize,
+    /// Bytes per complete pixel, the filter's left-neighbour distance.
+    pixel_bytes: usize,
+    /// Bytes per scanline, without the filter type byte.
+    stride: usize,
+}
+
+impl PngImage {
+    /// Decode the image data of a PNG file.
+    pub fn decode(file: &[u8]) -> Result<Self> {
+        let chunks = chunks(file)?;
+        let header = chunks
+            .first()
+            .filter(|c| &c.kind == b"IHDR" && c.data_len == 13)
+            .map(|c| &file[c.data_start()..c.data_start() + 13])
+            .ok_or_else(|| Error::DataCorruption("PNG does not start with IHDR".to_string()))?;
+
+        let width = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+        let height = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+        let (bit_depth, color_type, interlace) = (header[8], header[9], header[12]);
+
+        // (channels, whether the last one is alpha)
+        let (channels, alpha) = match color_type {
+            0 => (1, false),
+            2 => (3, false),
+            4 => (2, true),
+            6 => (4, true),
+            3 => {
+                return Err(Error::Unsupported(
+                    "Palette PNGs change colour, not shade, in their low bits".to_string(),
+                ))
+            }
+            _ => {
+                return Err(Error::DataCorruption(format!(
+                    "Bad PNG colour type {}",
+                    color_type
+                )))
+            }
+        };
+        if bit_depth != 8 && bit_depth != 16 {
+            return Err(Error::Unsupported(format!(
+                "PNG bit depth {} is not supported",
+                bit_depth
+            )));
+        }
+        if interlace != 0 {
+            return Err(Error::Unsupported(
+                "Interlaced PNGs are not supported".to_string(),
+            ));
+        }
+
+        // The IDAT chunks must be consecutive
+        let idats: Vec<_> = chunks.iter().filter(|c| &c.kind == b"IDAT").collect();
+        let (first, last) = match (idats.first(), idats.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(Error::DataCorruption("PNG has no IDAT".to_string())),
+        };
+        let idat = first.start..last.end();
+        if chunks
+            .iter()
+            .any(|c| idat.contains(&c.start) && &c.kind != b"IDAT")
+        {
+            return Err(Error::DataCorruption(
+                "PNG IDAT chunks are not consecutive".to_string(),
+            ));
+        }
+
+        let mut compressed = Vec::new();
+        for chunk in &idats {
+            compressed
+                .extend_from_slice(&file[chunk.data_start()..chunk.data_start() + chunk.data_len]);
+        }
+
+        let sample_bytes = bit_depth as usize / 8;
+        let pixel_bytes = channels * sample_bytes;
+        let stride = width * pixel_bytes;
+        let expected = height * (stride + 1);
+        let rows = miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(&compressed, expected)
+            .map_err(|e| {
+            Error::DataCorruption(format!("Bad PNG image data: {:?}", e.status))
+        })?;
+        if rows.len() != expected {
+            return Err(Error::DataCorruption(format!(
+                "PNG image data is {} bytes, expected {}",
+                rows.len(),
+                expected
+            )));
+        }
+
+        let colour_channels = if alpha { channels - 1 } else { channels };
+        let mut samples = Vec::with_capacity(height * width * colour_channels);
+        for row in 0..height {
+            let row_start = row * (stride + 1) + 1;
+            for pixel in 0..width {
+                for channel in 0..colour_channels {
+                    // Big-endian, so the low byte comes last
+                    samples.push(row_start + (pixel * channels + channel + 1) * sample_bytes - 1);
+                }
+            }
+        }
+
+        let mut image = Self {
+            rows,
+            samples,
+            idat,
+            idat_chunk_len: first.data_len,
+            pixel_bytes,
+            stride,
+        };
+        image.unfilter()?;
+        Ok(image)
+    }
+
+    /// Re-encode the image data into `file`, replacing its `IDAT` chunks.
+    pub fn encode(&self, file: &[u8]) -> Vec<u8> {
+        let compressed =
+            miniz_oxide::deflate::compress_to_vec_zlib(&self.filtered(), COMPRESSION_LEVEL);
+
+        let chunk_len = match self.idat_chunk_len {
+            0 => IDAT_CHUNK_LEN,
+            len => len,
+        };
+        let mut out = file[..self.idat.start].to_vec();
+        for part in compressed.chunks(chunk_len) {
+            let start = out.len();
+            out.extend_from_slice(&(part.len() as u32).to_be_bytes());
+            out.extend_from_slice(b"IDAT");
+            out.extend_from_slice(part);
+            let crc = crc32(&out[start + 4..]);
+            out.extend_from_slice(&crc.to_be_bytes());
+        }
+        out.extend_from_slice(&file[self.idat.end..]);
+        out
+    }
+
+    /// Undo each scanline's filter in place.
+    fn unfilter(&mut self) -> Result<()> {
+        let (stride, bpp) = (self.stride, self.pixel_bytes);
+        for start in (0..self.rows.len()).step_by(stride + 1) {
+            let filter = self.rows[start];
+            if filter > 4 {
+                return Err(Error::DataCorruption(format!(
+                    "Bad PNG filter type {}",
+                    filter
+                )));
+            }
+            for x in 0..stride {
+                let at = start + 1 + x;
+                let (a, b, c) = neighbours(&self.rows, at, x, stride, bpp);
+                self.rows[at] = self.rows[at].wrapping_add(predict(filter, a, b, c));
+            }
+        }
+        Ok(())
+    }
+
+    /// Filter every scanline with its original filter type.
+    fn filtered(&self) -> Vec<u8> {
+        let (stride, bpp) = (self.stride, self.pixel_bytes);
+        let mut out = self.rows.clone();
+        for start in (0..self.rows.len()).step_by(stride + 1) {
+            let filter = self.rows[start];
+            for x in 0..stride {
+                let at = start + 1 + x;
+                let (a, b, c) = neighbours(&self.rows, at, x, stride, bpp);
+                out[at] = self.rows[at].wrapping_sub(predict(filter, a, b, c));
+            }
+        }
+        out
+    }
+}
+
+/// Get the left, above and above-left bytes of the unfiltered byte at `at`,
+/// which is byte `x` of its scanline.
+fn neighbours(rows: &[u8], at: usize, x: usize, stride: usize, bpp: usize) -> (u8, u8, u8) {
+    let left = x >= bpp;
+    let up = at > stride + 1;
+    let a = if left { rows[at - bpp] } else { 0 };
+    let b = if up { rows[at - stride - 1] } else { 0 };
+    let c = if left && up {
+        rows[at - stride - 1 - bpp]
+    } else {
+        0
+    };
+    (a, b, c)
+}
+
+/// Predict a byte from its neighbours with a PNG filter type.
+fn predict(filter: u8, a: u8, b: u8, c: u8) -> u8 {
+    match filter {
+        1 => a,
+        2 => b,
+        3 => ((a as u16 + b as u16) / 2) as u8,
+        4 => {
+            let p = a as i16 + b as i16 - c as i16;
+            let (pa, pb, pc) = (
+                (p - a as i16).abs(),
+                (p - b as i16).abs(),
+                (p - c as i16).abs(),
+            );
+            if pa <= pb && pa <= pc {
+                a
+            } else if pb <= pc {
+                b
+            } else {
+                c
+            }
+        }
+        _ => 0,
+    }
+}
+
+/// Build a PNG from raw scanlines, for tests. Each row is filtered with
+/// type `row % 5` so every filter gets exercised.
+#[cfg(test)]
+pub(super) fn build(
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: u8,
+    pixels: &[u8],
+    extra_chunks: &[(&[u8; 4], &[u8])],
+) -> Vec<u8> {
+    let stride = pixels.len() / height as usize;
+    let mut rows = Vec::new();
+    for (y, row) in pixels.chunks(stride).enumerate() {
+        rows.push((y % 5) as u8);
+        rows.extend_from_slice(row);
+    }
+
+    let channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        _ => 4,
+    };
+    let image = PngImage {
+        rows,
+        samples: Vec::new(),
+        idat: 0..0,
+        idat_chunk_len: 0,
+        pixel_bytes: channels * bit_depth as usize / 8,
+        stride,
+    };
+
+    fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        let start = out.len();
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(kind);
+        out.extend_from_slice(data);
+        let crc = crc32(&out[start + 4..]);
+        out.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    let mut header = width.to_be_bytes().to_vec();
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+
+    let mut file = SIGNATURE.to_vec();
+    chunk(&mut file, b"IHDR", &header);
+    for (kind, data) in extra_chunks {
+        chunk(&mut file, kind, data);
+    }
+    let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&image.filtered(), 6);
+    chunk(&mut file, b"IDAT", &compressed);
+    chunk(&mut file, b"IEND", &[]);
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 7x6 RGBA gradient, odd-sized so no row is a multiple of anything.
+    fn rgba() -> (Vec<u8>, Vec<u8>) {
+        let pixels: Vec<u8> = (0..7 * 6 * 4).map(|i| (i * 37 % 251) as u8).collect();
+        let file = build(7, 6, 8, 6, &pixels, &[(b"tEXt", b"Comment\0hello")]);
+        (pixels, file)
+    }
+
+    #[test]
+    fn test_decode_round_trip() {
+        let (pixels, file) = rgba();
+        let image = PngImage::decode(&file).unwrap();
+
+        // Alpha is skipped: 3 colour samples per pixel
+        assert_eq!(image.samples.len(), 7 * 6 * 3);
+        let unfiltered: Vec<u8> = image
+            .rows
+            .chunks(7 * 4 + 1)
+            .flat_map(|row| row[1..].to_vec())
+            .collect();
+        assert_eq!(unfiltered, pixels);
+
+        // Nothing changed, so re-encoding gives the same pixels and chunks
+        let again = PngImage::decode(&image.encode(&file)).unwrap();
+        assert_eq!(again.rows, image.rows);
+    }
+
+    #[test]
+    fn test_metadata_chunks_kept() {
+        let (_, file) = rgba();
+        let mut image = PngImage::decode(&file).unwrap();
+        let at = image.samples[5];
+        image.rows[at] ^= 1;
+        let encoded = image.encode(&file);
+
+        let kinds =
+            |f: &[u8]| -> Vec<[u8; 4]> { chunks(f).unwrap().iter().map(|c| c.kind).collect() };
+        assert_eq!(kinds(&encoded), kinds(&file));
+        // IHDR and tEXt are untouched, byte for byte
+        let text_end = chunks(&file).unwrap()[1].end();
+        assert_eq!(&encoded[..text_end], &file[..text_end]);
+        assert!(crate::storage::format::png::is_valid(&encoded));
+
+        let decoded = PngImage::decode(&encoded).unwrap();
+        assert_eq!(decoded.rows, image.rows);
+    }
+
+    #[test]
+    fn test_sixteen_bit_low_bytes() {
+        let pixels: Vec<u8> = (0..4 * 2 * 2).map(|i| i as u8).collect();
+        let file = build(4, 2, 16, 0, &pixels, &[]);
+        let image = PngImage::decode(&file).unwrap();
+        // Second byte of each big-endian sample, after the filter byte
+        assert_eq!(image.samples, vec![2, 4, 6, 8, 11, 13, 15, 17]);
+    }
+
+    #[test]
+    fn test_palette_rejected() {
+        let file = build(2, 2, 8, 3, &[0; 4], &[(b"PLTE", &[0; 3])]);
+        assert!(matches!(
+            PngImage::decode(&file),
+            Err(Error::Unsupported(_))
+        ));
+    }
+}
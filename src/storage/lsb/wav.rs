@@ -0,0 +1,218 @@
+//! RIFF/WAVE PCM sample access.
+//!
+//! Only the `data` chunk's samples are changed, in place; every other chunk
+//! (`fmt `, `LIST`, `bext`, cue points, ...) and the RIFF header stay byte
+//! for byte as they were.
+
+use crate::error::{Error, Result};
+use std::ops::Range;
+
+/// RIFF file signature.
+pub(super) const RIFF: &[u8] = b"RIFF";
+/// RIFF form type of WAVE files, at offset 8.
+pub(super) const WAVE: &[u8] = b"WAVE";
+
+/// `WAVE_FORMAT_PCM`.
+const FORMAT_PCM: u16 = 1;
+/// `WAVE_FORMAT_EXTENSIBLE`; the sub-format GUID then names the encoding.
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Samples quieter than this fraction of full scale count as near-silent.
+/// 1/512 is about -54 dBFS.
+const SILENCE_SHIFT: u32 = 9;
+
+/// The PCM samples of a WAVE file.
+#[derive(Debug)]
+pub(super) struct Wave {
+    /// Bytes of the file holding the `data` chunk's samples.
+    pub data: Range<usize>,
+    /// Bytes per sample: 2 or 3.
+    pub sample_bytes: usize,
+}
+
+impl Wave {
+    /// Parse the chunks of a 16- or 24-bit PCM WAVE file.
+    pub fn parse(file: &[u8]) -> Result<Self> {
+        if file.len() < 12 || !file.starts_with(RIFF) || &file[8..12] != WAVE {
+            return Err(Error::DataCorruption("Not a RIFF/WAVE file".to_string()));
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut pos = 12;
+        while pos + 8 <= file.len() {
+            let id = &file[pos..pos + 4];
+            let len = u32::from_le_bytes(file[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            let body = pos + 8..pos + 8 + len;
+            if body.end > file.len() {
+                return Err(Error::DataCorruption(format!(
+                    "WAVE chunk at byte {} runs past the end of file",
+                    pos
+                )));
+            }
+            match id {
+                b"fmt " => format = Some(&file[body.clone()]),
+                b"data" => data = Some(body.clone()),
+                _ => {}
+            }
+            // Chunks are padded to an even length
+            pos = body.end + (len & 1);
+        }
+
+        let format =
+            format.ok_or_else(|| Error::DataCorruption("WAVE has no fmt chunk".to_string()))?;
+        let data =
+            data.ok_or_else(|| Error::DataCorruption("WAVE has no data chunk".to_string()))?;
+        if format.len() < 16 {
+            return Err(Error::DataCorruption(
+                "WAVE fmt chunk is too short".to_string(),
+            ));
+        }
+
+        let tag = u16::from_le_bytes([format[0], format[1]]);
+        let block_align = u16::from_le_bytes([format[12], format[13]]) as usize;
+        let bits_per_sample = u16::from_le_bytes([format[14], format[15]]);
+        // The sub-format GUID starts with the format tag it stands for
+        let pcm = tag == FORMAT_PCM
+            || (tag == FORMAT_EXTENSIBLE && format.len() >= 26 && format[24..26] == [1, 0]);
+        if !pcm || !matches!(bits_per_sample, 16 | 24) {
+            return Err(Error::Unsupported(format!(
+                "Only 16- and 24-bit PCM WAVE files are supported, not format {:#06x} with {} bits",
+                tag, bits_per_sample
+            )));
+        }
+
+        let sample_bytes = bits_per_sample as usize / 8;
+        if block_align == 0 || !block_align.is_multiple_of(sample_bytes) {
+            return Err(Error::DataCorruption(format!(
+                "Bad WAVE block alignment {}",
+                block_align
+            )));
+        }
+        // A trailing partial frame is left alone
+        let frames = data.len() / block_align;
+        Ok(Self {
+            data: data.start..data.start + frames * block_align,
+            sample_bytes,
+        })
+    }
+
+    /// Read a sample as a signed value.
+    fn sample(&self, file: &[u8], at: usize) -> i32 {
+        let mut bytes = [0u8; 4];
+        bytes[4 - self.sample_bytes..].copy_from_slice(&file[at..at + self.sample_bytes]);
+        // Little-endian in the file; shifting back down sign-extends
+        i32::from_le_bytes(bytes) >> (8 * (4 - self.sample_bytes))
+    }
+
+    /// Offsets of the low byte of every usable sample.
+    ///
+    /// With `skip_silence`, samples whose magnitude is below about -54 dBFS
+    /// are left out. The test ignores the `bits` low bits, which embedding
+    /// changes, so writing never moves a sample across the threshold.
+    pub fn samples(&self, file: &[u8], bits: u8, skip_silence: bool) -> Vec<usize> {
+        let threshold = 1i32 << (8 * self.sample_bytes as u32 - 1 - SILENCE_SHIFT);
+        let mask = !((1i32 << bits) - 1);
+        self.data
+            .clone()
+            .step_by(self.sample_bytes)
+            .filter(|&at| !skip_silence || (self.sample(file, at) & mask).abs() >= threshold)
+            .collect()
+    }
+}
+
+/// Build a PCM WAVE file, for tests, with a `LIST` chunk before and an odd
+/// length chunk after the samples.
+#[cfg(test)]
+pub(super) fn build(channels: u16, bits_per_sample: u16, samples: &[i32]) -> Vec<u8> {
+    let sample_bytes = bits_per_sample as usize / 8;
+    let mut pcm = Vec::new();
+    for sample in samples {
+        pcm.extend_from_slice(&sample.to_le_bytes()[..sample_bytes]);
+    }
+
+    let mut chunks = Vec::new();
+    let mut chunk = |id: &[u8], body: &[u8]| {
+        chunks.extend_from_slice(id);
+        chunks.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        chunks.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            chunks.push(0);
+        }
+    };
+
+    let block_align = channels * bits_per_sample / 8;
+    let mut format = FORMAT_PCM.to_le_bytes().to_vec();
+    format.extend_from_slice(&channels.to_le_bytes());
+    format.extend_from_slice(&44100u32.to_le_bytes());
+    format.extend_from_slice(&(44100 * block_align as u32).to_le_bytes());
+    format.extend_from_slice(&block_align.to_le_bytes());
+    format.extend_from_slice(&bits_per_sample.to_le_bytes());
+    chunk(b"fmt ", &format);
+    chunk(b"LIST", b"INFOINAM\x06\x00\x00\x00Track\x00");
+    chunk(b"data", &pcm);
+    chunk(b"note", b"odd");
+
+    let mut file = RIFF.to_vec();
+    file.extend_from_slice(&(4 + chunks.len() as u32).to_le_bytes());
+    file.extend_from_slice(WAVE);
+    file.extend_from_slice(&chunks);
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_finds_data() {
+        let file = build(2, 16, &[1, -1, 300, -300]);
+        let wave = Wave::parse(&file).unwrap();
+        assert_eq!(wave.sample_bytes, 2);
+        assert_eq!(wave.data.len(), 8);
+        assert_eq!(&file[wave.data.start - 8..wave.data.start - 4], b"data");
+        assert_eq!(wave.sample(&file, wave.data.start + 2), -1);
+        assert_eq!(wave.sample(&file, wave.data.start + 6), -300);
+    }
+
+    #[test]
+    fn test_twenty_four_bit_samples() {
+        let file = build(1, 24, &[-8_000_000, 5, 8_000_000]);
+        let wave = Wave::parse(&file).unwrap();
+        let start = wave.data.start;
+        assert_eq!(
+            wave.samples(&file, 1, false),
+            vec![start, start + 3, start + 6]
+        );
+        assert_eq!(wave.sample(&file, start), -8_000_000);
+        assert_eq!(wave.sample(&file, start + 6), 8_000_000);
+    }
+
+    #[test]
+    fn test_skip_silence() {
+        // The 16-bit threshold is 64. Clearing the low bits rounds down, so
+        // -63 counts as -64 and is kept while 63 counts as 60 and is not
+        let file = build(1, 16, &[0, 3, -63, -64, 63, 64, 65, 20000]);
+        let wave = Wave::parse(&file).unwrap();
+        let kept: Vec<_> = wave
+            .samples(&file, 2, true)
+            .iter()
+            .map(|&at| wave.sample(&file, at))
+            .collect();
+        assert_eq!(kept, vec![-63, -64, 64, 65, 20000]);
+        assert_eq!(wave.samples(&file, 2, false).len(), 8);
+    }
+
+    #[test]
+    fn test_unsupported_formats_rejected() {
+        let mut file = build(1, 16, &[0; 4]);
+        // 8-bit samples
+        file[34] = 8;
+        assert!(matches!(Wave::parse(&file), Err(Error::Unsupported(_))));
+        // IEEE float
+        file[34] = 16;
+        file[20] = 3;
+        assert!(matches!(Wave::parse(&file), Err(Error::Unsupported(_))));
+        assert!(Wave::parse(b"RIFF\0\0\0\0AVI LIST").is_err());
+    }
+}
@@ -4,6 +4,7 @@ mod apfs;
 mod raw_disk;
 
 use crate::error::Result;
+use crate::storage::carrier::{Capabilities, PersistenceRisk};
 use crate::storage::slack_backend::{SlackBackend, SlackRegion};
 use std::path::Path;
 
@@ -47,6 +48,16 @@ impl SlackBackend for MacSlackBackend {
         self.write_slack(region, 0, &zeros)
     }
 
+    /// APFS writes changed blocks copy-on-write, so any write to the host
+    /// leaves the slack behind in a block that is no longer the file's.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::Moderate,
+            changes_visible_metadata: false,
+            needs_root: true,
+        }
+    }
+
     fn is_available(&self) -> bool {
         // Check if we can use fcntl and access raw disk
         true
@@ -0,0 +1,303 @@
+//! In-memory slack backend.
+//!
+//! Models a set of virtual host files entirely in RAM, for deterministic
+//! tests and simulations of code built on `SlackVfs`. Hooks mutate hosts the
+//! way users and file systems do (corrupt, truncate, append, delete) so
+//! resilience can be tested without touching real files.
+
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, PersistenceRisk};
+use crate::storage::slack_backend::{SlackBackend, SlackRegion};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A virtual host file: its content plus the rest of its last block.
+#[derive(Debug, Clone)]
+struct MemoryHost {
+    /// Logical size of the file.
+    logical_size: u64,
+    /// Block size of the simulated file system.
+    block_size: u64,
+    /// All allocated bytes: file content followed by slack, padded to a
+    /// whole number of blocks.
+    data: Vec<u8>,
+}
+
+impl MemoryHost {
+    fn new(logical_size: u64, block_size: u64) -> Self {
+        let mut host = Self {
+            logical_size,
+            block_size,
+            data: (0..logical_size).map(|i| (i % 251) as u8).collect(),
+        };
+        host.pad_to_block();
+        host
+    }
+
+    /// Zero-fill from the end of file to the end of its last block, like a
+    /// file system does when it writes a partial block.
+    fn pad_to_block(&mut self) {
+        let allocated = self.logical_size.div_ceil(self.block_size) * self.block_size;
+        self.data.truncate(self.logical_size as usize);
+        self.data.resize(allocated as usize, 0);
+    }
+}
+
+/// Slack backend over virtual host files held in memory.
+///
+/// Clones share the same hosts, so a test can keep a handle after passing a
+/// boxed clone to `SlackVfs` and mutate hosts behind its back.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySlackBackend {
+    hosts: Arc<Mutex<BTreeMap<PathBuf, MemoryHost>>>,
+}
+
+impl MemorySlackBackend {
+    pub const NAME: &'static str = "memory";
+
+    /// Create a backend with no hosts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a virtual host file with the given logical size and block size.
+    ///
+    /// Content bytes follow a fixed pattern; slack starts zeroed.
+    pub fn add_host(&self, path: impl Into<PathBuf>, logical_size: u64, block_size: u64) {
+        self.lock()
+            .insert(path.into(), MemoryHost::new(logical_size, block_size));
+    }
+
+    /// Paths of all virtual host files.
+    pub fn host_paths(&self) -> Vec<PathBuf> {
+        self.lock().keys().cloned().collect()
+    }
+
+    /// Current logical size of a host.
+    pub fn logical_size(&self, path: &Path) -> Option<u64> {
+        self.lock().get(path).map(|h| h.logical_size)
+    }
+
+    /// Current slack bytes of a host (after its end of file).
+    pub fn slack_bytes(&self, path: &Path) -> Option<Vec<u8>> {
+        self.lock()
+            .get(path)
+            .map(|h| h.data[h.logical_size as usize..].to_vec())
+    }
+
+    /// Overwrite slack bytes of a host, starting `offset` bytes after its
+    /// end of file. Bytes past the end of the last block are dropped.
+    pub fn corrupt(&self, path: &Path, offset: u64, bytes: &[u8]) -> Result<()> {
+        self.with_host(path, |host| {
+            let start = (host.logical_size + offset) as usize;
+            for (i, byte) in bytes.iter().enumerate() {
+                match host.data.get_mut(start + i) {
+                    Some(slot) => *slot = *byte,
+                    None => break,
+                }
+            }
+        })
+    }
+
+    /// Truncate (or extend with zeros) a host to `new_size`. The slack of
+    /// the new last block is zeroed.
+    pub fn truncate(&self, path: &Path, new_size: u64) -> Result<()> {
+        self.with_host(path, |host| {
+            host.data
+                .resize(new_size.max(host.logical_size) as usize, 0);
+            host.logical_size = new_size;
+            host.pad_to_block();
+        })
+    }
+
+    /// Append content to a host. The appended bytes overwrite the start of
+    /// its slack, and the rest of the new last block is zeroed.
+    pub fn append(&self, path: &Path, content: &[u8]) -> Result<()> {
+        self.with_host(path, |host| {
+            host.data.truncate(host.logical_size as usize);
+            host.data.extend_from_slice(content);
+            host.logical_size += content.len() as u64;
+            host.pad_to_block();
+        })
+    }
+
+    /// Delete a host.
+    pub fn delete(&self, path: &Path) -> Result<()> {
+        self.lock()
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| Error::HostFileNotFound(path.to_path_buf()))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<PathBuf, MemoryHost>> {
+        self.hosts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn with_host<T>(&self, path: &Path, f: impl FnOnce(&mut MemoryHost) -> T) -> Result<T> {
+        let mut hosts = self.lock();
+        let host = hosts
+            .get_mut(path)
+            .ok_or_else(|| Error::HostFileNotFound(path.to_path_buf()))?;
+        Ok(f(host))
+    }
+}
+
+impl SlackBackend for MemorySlackBackend {
+    fn get_slack_info(&self, path: &Path) -> Result<SlackRegion> {
+        let logical_size = self
+            .logical_size(path)
+            .ok_or_else(|| Error::HostFileNotFound(path.to_path_buf()))?;
+        self.get_slack_info_at(path, logical_size)
+    }
+
+    fn get_slack_info_at(&self, path: &Path, logical_size: u64) -> Result<SlackRegion> {
+        self.with_host(path, |host| {
+            let remainder = logical_size % host.block_size;
+            let available = if logical_size == 0 || remainder == 0 {
+                0
+            } else {
+                host.block_size - remainder
+            };
+
+            SlackRegion {
+                device_path: path.to_path_buf(),
+                offset: logical_size,
+                available,
+                logical_size,
+                block_size: host.block_size,
+            }
+        })
+    }
+
+    /// Lists every virtual host; they do not live under `root`.
+    fn enumerate_hosts(&self, _root: &Path) -> Result<Vec<PathBuf>> {
+        Ok(self.host_paths())
+    }
+
+    fn read_slack(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        region.check_bounds(offset, len)?;
+        self.with_host(&region.device_path, |host| {
+            // A host that shrank returns fewer bytes, one that grew returns
+            // its new content, as a real file would
+            let start = ((region.offset + offset) as usize).min(host.data.len());
+            let end = (start + len).min(host.data.len());
+            host.data[start..end].to_vec()
+        })
+    }
+
+    fn write_slack(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        region.check_bounds(offset, data.len())?;
+        self.with_host(&region.device_path, |host| {
+            let start = region.offset + offset;
+            let end = start + data.len() as u64;
+            if start < host.logical_size || end > host.data.len() as u64 {
+                return Err(Error::DataCorruption(format!(
+                    "Slack region of {} no longer matches the host",
+                    region.device_path.display()
+                )));
+            }
+            host.data[start as usize..end as usize].copy_from_slice(data);
+            Ok(())
+        })?
+    }
+
+    fn wipe_slack(&self, region: &SlackRegion) -> Result<()> {
+        self.with_host(&region.device_path, |host| {
+            let start = (host.logical_size as usize).max(region.offset as usize);
+            for byte in host.data.iter_mut().skip(start) {
+                *byte = 0;
+            }
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::Low,
+            changes_visible_metadata: false,
+            needs_root: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_read_slack() {
+        let backend = MemorySlackBackend::new();
+        backend.add_host("a.txt", 1000, 4096);
+        backend.add_host("aligned.txt", 4096, 4096);
+
+        let region = backend.get_slack_info(Path::new("a.txt")).unwrap();
+        assert_eq!(region.available, 3096);
+        assert_eq!(
+            backend
+                .get_slack_info(Path::new("aligned.txt"))
+                .unwrap()
+                .available,
+            0
+        );
+
+        backend.write_slack(&region, 5, b"hidden").unwrap();
+        assert_eq!(backend.read_slack(&region, 5, 6).unwrap(), b"hidden");
+        assert_eq!(
+            &backend.slack_bytes(Path::new("a.txt")).unwrap()[5..11],
+            b"hidden"
+        );
+
+        assert!(matches!(
+            backend.write_slack(&region, 3095, b"xx"),
+            Err(Error::InsufficientSpace { .. })
+        ));
+
+        backend.wipe_slack(&region).unwrap();
+        assert_eq!(backend.read_slack(&region, 5, 6).unwrap(), vec![0u8; 6]);
+    }
+
+    #[test]
+    fn test_hooks() {
+        let backend = MemorySlackBackend::new();
+        backend.add_host("a.txt", 100, 4096);
+        let path = Path::new("a.txt");
+        let region = backend.get_slack_info(path).unwrap();
+        backend.write_slack(&region, 0, &[7u8; 64]).unwrap();
+
+        backend.corrupt(path, 8, &[0xFF; 4]).unwrap();
+        let data = backend.read_slack(&region, 0, 16).unwrap();
+        assert_eq!(&data[8..12], &[0xFF; 4]);
+        assert_eq!(data[12], 7);
+
+        // Appending overwrites the start of the slack and zeroes the rest
+        backend.append(path, &[1u8; 10]).unwrap();
+        assert_eq!(backend.logical_size(path), Some(110));
+        assert_eq!(backend.read_slack(&region, 0, 12).unwrap()[..10], [1u8; 10]);
+        assert_eq!(backend.read_slack(&region, 10, 4).unwrap(), vec![0u8; 4]);
+        assert!(backend.write_slack(&region, 0, b"stale").is_err());
+
+        // The old region is gone after truncation
+        backend.truncate(path, 50).unwrap();
+        assert_eq!(backend.slack_bytes(path).unwrap().len(), 4046);
+        assert!(backend
+            .read_slack(&region, 0, 16)
+            .unwrap()
+            .iter()
+            .all(|&b| b == 0));
+
+        backend.delete(path).unwrap();
+        assert!(matches!(
+            backend.read_slack(&region, 0, 1),
+            Err(Error::HostFileNotFound(_))
+        ));
+        assert!(backend.host_paths().is_empty());
+    }
+}
@@ -3,6 +3,8 @@
 //! This file contains only the essential data needed before decryption:
 //! - Salt for key derivation
 //! - Block size for slack calculation
+//! - Storage backend the VFS was created with
+//! - Host policy (which files may be hosts, needed to scan for them)
 //! - Superblock location (to bootstrap decryption)
 //!
 //! All other sensitive data (file mappings, symbol locations) is stored in the
@@ -13,12 +15,17 @@ use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 use crate::vfs::types::EncodingInfo;
 use crate::storage::host_manager::SymbolLocation;
+use crate::storage::host_policy::HostPolicy;
 
 /// Metadata file name (hidden file).
 pub const METADATA_FILENAME: &str = ".slack_meta.json";
 
 /// Current metadata version.
-pub const METADATA_VERSION: u32 = 3;
+pub const METADATA_VERSION: u32 = 10;
+
+/// First version whose superblock locations are relative to a slack region.
+/// Older versions record absolute offsets into the host file.
+pub const REGION_OFFSETS_VERSION: u32 = 4;
 
 /// Minimal bootstrap metadata - only contains data needed before decryption.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +37,13 @@ pub struct SlackMetadata {
     pub block_size: u64,
     /// Salt for key derivation (required for decryption).
     pub salt: Option<[u8; 32]>,
+    /// Name of the storage backend holding the symbols (`None` for
+    /// file-append storage created before backends were recorded).
+    #[serde(default)]
+    pub backend: Option<String>,
+    /// Which files the host scan takes (`None` for every file).
+    #[serde(default)]
+    pub host_policy: Option<HostPolicy>,
     
     /// Encoding parameters for the superblock (RaptorQ).
     pub superblock_encoding: Option<EncodingInfo>,
@@ -49,6 +63,8 @@ impl Default for SlackMetadata {
             version: METADATA_VERSION,
             block_size: 4096,
             salt: None,
+            backend: None,
+            host_policy: None,
             superblock_encoding: None,
             superblock_symbols: Vec::new(),
         }
@@ -62,6 +78,8 @@ impl SlackMetadata {
             version: METADATA_VERSION,
             block_size,
             salt: None,
+            backend: None,
+            host_policy: None,
             superblock_encoding: None,
             superblock_symbols: Vec::new(),
         }
@@ -108,6 +126,7 @@ impl SlackMetadata {
     /// Clear the metadata (reset to empty state).
     pub fn clear(&mut self) {
         self.salt = None;
+        self.host_policy = None;
         self.superblock_encoding = None;
         self.superblock_symbols.clear();
     }
@@ -140,4 +159,18 @@ mod tests {
         // Should be very small - just version, block_size, and salt
         assert!(json.len() < 200, "JSON should be minimal, got: {}", json);
     }
+
+    #[test]
+    fn test_host_policy_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let mut meta = SlackMetadata::new(4096);
+        meta.host_policy = Some(HostPolicy {
+            exclude: vec!["*.log".to_string()],
+            ..HostPolicy::default()
+        });
+        meta.save(dir.path()).unwrap();
+
+        let loaded = SlackMetadata::load(dir.path()).unwrap();
+        assert_eq!(loaded.host_policy, meta.host_policy);
+    }
 }
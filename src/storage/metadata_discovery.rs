@@ -95,7 +95,7 @@ impl MetadataDiscovery {
     }
 
     /// Try to read metadata from a specific file's slack space.
-    fn try_read_metadata(path: &Path, block_size: u64) -> Result<Option<SlackMetadata>> {
+    fn try_read_metadata(path: &Path, _block_size: u64) -> Result<Option<SlackMetadata>> {
         // Get file size
         let metadata = std::fs::metadata(path)?;
         let file_size = metadata.len();
@@ -272,6 +272,8 @@ mod tests {
             version: 3,
             block_size: 4096,
             salt: Some([1u8; 32]),
+            backend: None,
+            host_policy: None,
             superblock_encoding: Some(EncodingInfo {
                 original_length: 500,
                 source_symbols: 1,
@@ -280,9 +282,11 @@ mod tests {
             }),
             superblock_symbols: vec![SymbolLocation {
                 host_path: PathBuf::from("test.dat"),
+                region_index: 0,
                 offset: 4096,
                 length: 1024,
                 symbol_id: 0,
+                logical_size: 100,
             }],
         };
 
@@ -307,7 +311,7 @@ mod tests {
         // Create a file with no metadata
         let file_path = temp_dir.path().join("normal.dat");
         let mut file = File::create(&file_path).unwrap();
-        file.write_all(&vec![0u8; 100]).unwrap();
+        file.write_all(&[0u8; 100]).unwrap();
         drop(file);
 
         // Discovery should return None
@@ -1,8 +1,12 @@
 //! Storage layer for slack space operations.
 //!
 //! This module handles:
-//! - Reading/writing data to slack space
-//! - Managing host files
+//! - Reading/writing data to slack space through a `Carrier` (file slack,
+//!   file system images, partition gaps, format-legal spots in PNG, JPEG,
+//!   PDF and ZIP files, the low bits of PNG and BMP pixels or, on Linux,
+//!   user extended attributes), each host offering one or more regions
+//! - Managing host files and the free space of their regions, and the
+//!   rules deciding which files become hosts
 //! - Persisting minimal bootstrap metadata
 //!
 //! ## Block Device Slack Access
@@ -11,11 +15,25 @@
 //! access to file slack space (the unused bytes within allocated blocks).
 //! This requires elevated privileges (sudo) and is platform-specific.
 
+pub mod carrier;
+pub mod exfat;
+mod extents;
+pub mod fat;
+mod file_backend;
+pub mod format;
 mod host_manager;
+mod host_policy;
+mod image_file;
+pub mod lsb;
+mod memory_backend;
 pub(crate) mod metadata;
 pub(crate) mod metadata_discovery;
+pub mod ntfs;
+pub mod partition;
 mod slack;
 pub mod slack_backend;
+#[cfg(target_os = "linux")]
+mod xattr_backend;
 
 // Platform-specific implementations
 #[cfg(target_os = "linux")]
@@ -24,9 +42,17 @@ pub mod linux;
 #[cfg(target_os = "macos")]
 pub mod macos;
 
-pub use host_manager::{HostFile, HostManager, SymbolLocation};
+pub use carrier::{Capabilities, Carrier, PersistenceRisk};
+pub use extents::{Extent, FreeExtents};
+pub use file_backend::FileSlackBackend;
+pub use host_manager::{DomainKey, HostFile, HostManager, SymbolLocation};
+pub use host_policy::{glob_match, HostPolicy};
+pub use image_file::{ImageFile, ReadAt};
+pub use memory_backend::MemorySlackBackend;
 pub use metadata::SlackMetadata;
 pub use metadata_discovery::MetadataDiscovery;
 pub use slack::{get_slack_capacity, read_slack, wipe_slack, write_slack};
 pub use slack_backend::{create_backend, SlackBackend, SlackRegion};
+#[cfg(target_os = "linux")]
+pub use xattr_backend::{XattrBackend, XattrNaming};
 
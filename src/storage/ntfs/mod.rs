@@ -0,0 +1,300 @@
+//! NTFS slack backend.
+//!
+//! Hides data in the slack of non-resident files: the bytes between the end
+//! of a file and the end of the cluster holding its last byte. Resident
+//! files (content inside the MFT record) have no slack; compressed, sparse
+//! and encrypted files are skipped. Works on raw images of unmounted
+//! volumes; host paths are absolute paths inside the volume.
+
+#[cfg(test)]
+mod test_image;
+mod volume;
+
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, PersistenceRisk};
+use crate::storage::slack_backend::{SlackBackend, SlackRegion};
+use std::path::{Path, PathBuf};
+
+pub use volume::{DataAttribute, DataRun, NtfsBootSector, NtfsRecord, NtfsVolume, ROOT_RECORD};
+
+/// Slack backend over an NTFS volume.
+pub struct NtfsImageBackend {
+    volume: NtfsVolume,
+}
+
+impl NtfsImageBackend {
+    pub const NAME: &'static str = "NTFS image";
+
+    /// Open an NTFS image file or block device.
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            volume: NtfsVolume::open(path)?,
+        })
+    }
+
+    /// Get the parsed volume.
+    pub fn volume(&self) -> &NtfsVolume {
+        &self.volume
+    }
+
+    /// List files in the volume that have usable slack space.
+    pub fn candidate_hosts(&self) -> Result<Vec<(PathBuf, SlackRegion)>> {
+        let mut hosts = Vec::new();
+
+        for (path, record) in self.volume.walk_files()? {
+            if let Ok(region) = self.region_for_record(&record) {
+                if region.available > 0 {
+                    hosts.push((path, region));
+                }
+            }
+        }
+
+        Ok(hosts)
+    }
+
+    /// Build the slack region for a file record.
+    fn region_for_record(&self, record: &NtfsRecord) -> Result<SlackRegion> {
+        let (offset, available) = self.volume.file_slack(record)?;
+
+        Ok(SlackRegion {
+            device_path: self.volume.image().path().to_path_buf(),
+            offset,
+            available,
+            logical_size: record.data.as_ref().map_or(0, |d| d.data_size),
+            block_size: self.volume.cluster_size(),
+        })
+    }
+}
+
+impl SlackBackend for NtfsImageBackend {
+    fn get_slack_info(&self, path: &Path) -> Result<SlackRegion> {
+        let record = self.volume.lookup(path)?;
+        self.region_for_record(&record).map_err(|e| match e {
+            Error::NotAFile(_) => Error::NotAFile(path.display().to_string()),
+            e => e,
+        })
+    }
+
+    /// Lists files inside the volume; `root` (the directory holding the VFS
+    /// metadata) is not part of it and is ignored.
+    fn enumerate_hosts(&self, _root: &Path) -> Result<Vec<PathBuf>> {
+        Ok(self
+            .volume
+            .walk_files()?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    fn read_slack(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        region.check_bounds(offset, len)?;
+        self.volume.image().read_at(region.offset + offset, len)
+    }
+
+    fn write_slack(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        region.check_bounds(offset, data.len())?;
+        self.volume.image().write_at(region.offset + offset, data)
+    }
+
+    fn wipe_slack(&self, region: &SlackRegion) -> Result<()> {
+        let zeros = vec![0u8; region.available as usize];
+        self.write_slack(region, 0, &zeros)
+    }
+
+    /// Writing to the host reclaims the slack, and so does anything that
+    /// moves its clusters: defragmenting, or compressing the file.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::Moderate,
+            changes_visible_metadata: false,
+            needs_root: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.volume.image().path().exists()
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_image::NtfsTestImage;
+
+    #[test]
+    fn test_decode_runs() {
+        // 0x18 clusters at 0x5634, 0x10 clusters sparse, 2 clusters 0x34 back
+        let runs =
+            NtfsVolume::decode_runs(&[0x21, 0x18, 0x34, 0x56, 0x01, 0x10, 0x11, 0x02, 0xCC, 0x00])
+                .unwrap();
+        assert_eq!(
+            runs,
+            vec![
+                DataRun {
+                    lcn: Some(0x5634),
+                    length: 0x18
+                },
+                DataRun {
+                    lcn: None,
+                    length: 0x10
+                },
+                DataRun {
+                    lcn: Some(0x5600),
+                    length: 2
+                },
+            ]
+        );
+
+        let encoded = NtfsTestImage::encode_runs(&[(Some(300), 5), (Some(10), 1), (None, 7)]);
+        assert_eq!(
+            NtfsVolume::decode_runs(&encoded).unwrap(),
+            vec![
+                DataRun {
+                    lcn: Some(300),
+                    length: 5
+                },
+                DataRun {
+                    lcn: Some(10),
+                    length: 1
+                },
+                DataRun {
+                    lcn: None,
+                    length: 7
+                },
+            ]
+        );
+
+        // Missing terminator
+        assert!(NtfsVolume::decode_runs(&[0x11, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_slack_of_non_resident_files() {
+        let mut image = NtfsTestImage::new();
+        let cluster = image.cluster_size() as usize;
+        let docs = image.mkdir(NtfsTestImage::ROOT, "Documents");
+        image.add_file(docs, "REPORT.TXT", &vec![b'r'; 2 * cluster + 100]);
+        let content: Vec<u8> = (0..3 * cluster + 10)
+            .map(|i| (i / cluster) as u8 + 1)
+            .collect();
+        image.add_fragmented_file(docs, "scattered file.bin", &content);
+        image.add_resident_file(docs, "tiny.txt", b"resident");
+        let file = image.build();
+
+        let backend = NtfsImageBackend::open(file.path()).unwrap();
+        let raw = std::fs::read(file.path()).unwrap();
+
+        let region = backend
+            .get_slack_info(Path::new("/documents/report.txt"))
+            .unwrap();
+        assert_eq!(region.available, cluster as u64 - 100);
+        assert_eq!(region.block_size, cluster as u64);
+        let end = region.offset as usize;
+        assert_eq!(&raw[end - 100..end], &vec![b'r'; 100][..]);
+
+        // Negative run deltas: the last cluster is the lowest one
+        let region = backend
+            .get_slack_info(Path::new("/Documents/Scattered File.bin"))
+            .unwrap();
+        assert_eq!(region.logical_size, content.len() as u64);
+        assert_eq!(region.available, cluster as u64 - 10);
+        let end = region.offset as usize;
+        assert_eq!(&raw[end - 10..end], &[4u8; 10]);
+
+        backend.write_slack(&region, 0, b"hidden in NTFS").unwrap();
+        assert_eq!(
+            backend.read_slack(&region, 0, 14).unwrap(),
+            b"hidden in NTFS"
+        );
+
+        let tiny = backend
+            .get_slack_info(Path::new("/Documents/tiny.txt"))
+            .unwrap();
+        assert_eq!(tiny.available, 0);
+
+        // The Win32 name wins over the DOS alias
+        let hosts: Vec<_> = backend
+            .candidate_hosts()
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            hosts,
+            vec![
+                PathBuf::from("/Documents/REPORT.TXT"),
+                PathBuf::from("/Documents/scattered file.bin"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skips_unsupported_files() {
+        let mut image = NtfsTestImage::new();
+        let data = vec![b'x'; 5000];
+        image.add_file_with_flags(NtfsTestImage::ROOT, "packed.bin", &data, 0x0001);
+        image.add_file_with_flags(NtfsTestImage::ROOT, "secret.bin", &data, 0x4000);
+        image.add_file_with_flags(NtfsTestImage::ROOT, "holes.bin", &data, 0x8000);
+        let torn = image.add_file(NtfsTestImage::ROOT, "torn.bin", &data);
+        image.tear(torn);
+        image.add_file(NtfsTestImage::ROOT, "plain.bin", &data);
+        let file = image.build();
+
+        let backend = NtfsImageBackend::open(file.path()).unwrap();
+        for name in ["/packed.bin", "/secret.bin", "/holes.bin"] {
+            assert!(
+                matches!(
+                    backend.get_slack_info(Path::new(name)),
+                    Err(Error::Unsupported(_))
+                ),
+                "{}",
+                name
+            );
+        }
+        // A torn record is never indexed
+        assert!(matches!(
+            backend.get_slack_info(Path::new("/torn.bin")),
+            Err(Error::FileNotFound(_))
+        ));
+
+        let hosts = backend.candidate_hosts().unwrap();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].0, PathBuf::from("/plain.bin"));
+    }
+
+    #[test]
+    fn test_vfs_on_ntfs_image() {
+        use crate::{SlackVfs, VfsConfig};
+
+        let mut image = NtfsTestImage::new();
+        for i in 0..8 {
+            image.add_file(
+                NtfsTestImage::ROOT,
+                &format!("doc_{}.docx", i),
+                &vec![b'd'; 700 + i],
+            );
+        }
+        let file = image.build();
+        let meta_dir = tempfile::TempDir::new().unwrap();
+
+        {
+            let backend = Box::new(NtfsImageBackend::open(file.path()).unwrap());
+            let mut vfs =
+                SlackVfs::create_with_backend(meta_dir.path(), "pw", VfsConfig::default(), backend)
+                    .unwrap();
+            vfs.create_file("/secret.txt", b"stored in NTFS slack")
+                .unwrap();
+        }
+
+        let backend = Box::new(NtfsImageBackend::open(file.path()).unwrap());
+        let vfs = SlackVfs::mount_with_backend(meta_dir.path(), "pw", backend).unwrap();
+        assert_eq!(
+            vfs.read_file("/secret.txt").unwrap(),
+            b"stored in NTFS slack"
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! Minimal NTFS image builder for unit tests.
+//!
+//! Produces an 8 MiB volume with 512-byte sectors, 4 KiB clusters and
+//! 1 KiB file records. `$MFT` holds 64 records in two runs so record reads
+//! have to follow its data runs. Only the structures the parser needs are
+//! written: no `$Bitmap`, `$LogFile` or directory indexes.
+
+use super::ROOT_RECORD;
+use std::collections::BTreeMap;
+use std::io::{Seek, SeekFrom, Write};
+use tempfile::NamedTempFile;
+
+const SECTOR: u64 = 512;
+const SECTORS_PER_CLUSTER: u64 = 8;
+const CLUSTER: u64 = SECTOR * SECTORS_PER_CLUSTER;
+const RECORD_SIZE: usize = 1024;
+const TOTAL_CLUSTERS: u64 = 2048;
+
+/// `$MFT` runs: (first cluster, clusters); each run holds 32 records.
+const MFT_RUNS: [(u64, u64); 2] = [(4, 8), (40, 8)];
+const RECORDS_PER_RUN: u64 = 32;
+
+/// Update sequence number stamped into every record.
+const USN: u16 = 0x0001;
+
+/// Builder for a small NTFS volume.
+pub(crate) struct NtfsTestImage {
+    /// File content written at absolute offsets.
+    chunks: BTreeMap<u64, Vec<u8>>,
+    /// Finished records (fixups applied) by number.
+    records: BTreeMap<u64, Vec<u8>>,
+    next_record: u64,
+    next_cluster: u64,
+}
+
+impl NtfsTestImage {
+    pub const ROOT: u64 = ROOT_RECORD;
+
+    /// Create a volume holding only `$MFT` and the root directory.
+    pub fn new() -> Self {
+        let mut image = Self {
+            chunks: BTreeMap::new(),
+            records: BTreeMap::new(),
+            next_record: 24,
+            next_cluster: 64,
+        };
+
+        let runs: Vec<_> = MFT_RUNS
+            .iter()
+            .map(|&(lcn, len)| (Some(lcn), len))
+            .collect();
+        let mft_size = RECORDS_PER_RUN * MFT_RUNS.len() as u64 * RECORD_SIZE as u64;
+        let data = Self::non_resident_data(0, mft_size, &runs);
+        image.write_record(0, 0x01, vec![data]);
+
+        let root_name = Self::file_name(ROOT_RECORD, ".", 3);
+        image.write_record(ROOT_RECORD, 0x03, vec![root_name]);
+        image
+    }
+
+    /// Get the cluster size in bytes.
+    pub fn cluster_size(&self) -> u64 {
+        CLUSTER
+    }
+
+    /// Create a subdirectory and return its record number.
+    pub fn mkdir(&mut self, parent: u64, name: &str) -> u64 {
+        let number = self.alloc_record();
+        let mut attrs = Self::names(parent, name);
+        attrs.insert(0, Self::standard_information());
+        self.write_record(number, 0x03, attrs);
+        number
+    }
+
+    /// Add a non-resident file in contiguous clusters.
+    pub fn add_file(&mut self, parent: u64, name: &str, content: &[u8]) -> u64 {
+        self.add_file_with_flags(parent, name, content, 0)
+    }
+
+    /// Add a non-resident file whose `$DATA` attribute carries the given
+    /// flags (compressed, encrypted, sparse).
+    pub fn add_file_with_flags(
+        &mut self,
+        parent: u64,
+        name: &str,
+        content: &[u8],
+        flags: u16,
+    ) -> u64 {
+        let count = content.len().div_ceil(CLUSTER as usize) as u64;
+        let first = self.next_cluster;
+        self.next_cluster += count;
+        self.write_content(&[first], content, count);
+        self.add_non_resident(
+            parent,
+            name,
+            content.len() as u64,
+            flags,
+            &[(Some(first), count)],
+        )
+    }
+
+    /// Add a file with one cluster per run, placed at descending cluster
+    /// numbers so the run deltas are negative.
+    pub fn add_fragmented_file(&mut self, parent: u64, name: &str, content: &[u8]) -> u64 {
+        let count = content.len().div_ceil(CLUSTER as usize) as u64;
+        let clusters: Vec<u64> = (0..count)
+            .map(|i| self.next_cluster + 2 * (count - 1 - i))
+            .collect();
+        self.next_cluster += 2 * count;
+        self.write_content(&clusters, content, 1);
+
+        let runs: Vec<_> = clusters.iter().map(|&lcn| (Some(lcn), 1)).collect();
+        self.add_non_resident(parent, name, content.len() as u64, 0, &runs)
+    }
+
+    /// Add a file whose content lives inside its file record.
+    pub fn add_resident_file(&mut self, parent: u64, name: &str, content: &[u8]) -> u64 {
+        let number = self.alloc_record();
+        let mut attrs = vec![Self::standard_information()];
+        attrs.extend(Self::names(parent, name));
+        attrs.push(Self::resident(0x80, content));
+        self.write_record(number, 0x01, attrs);
+        number
+    }
+
+    /// Break the update sequence of a record, as a torn write would.
+    pub fn tear(&mut self, number: u64) {
+        let record = self.records.get_mut(&number).expect("no such record");
+        record[RECORD_SIZE - 1] ^= 0xFF;
+    }
+
+    /// Write the image to a temporary file.
+    pub fn build(mut self) -> NamedTempFile {
+        for (number, record) in std::mem::take(&mut self.records) {
+            let (lcn, _) = MFT_RUNS[(number / RECORDS_PER_RUN) as usize];
+            let offset = lcn * CLUSTER + (number % RECORDS_PER_RUN) * RECORD_SIZE as u64;
+            self.chunks.insert(offset, record);
+        }
+        self.chunks.insert(0, Self::boot_sector());
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.as_file().set_len(TOTAL_CLUSTERS * CLUSTER).unwrap();
+        for (offset, data) in &self.chunks {
+            file.seek(SeekFrom::Start(*offset)).unwrap();
+            file.write_all(data).unwrap();
+        }
+        file.flush().unwrap();
+        file
+    }
+
+    fn alloc_record(&mut self) -> u64 {
+        let number = self.next_record;
+        self.next_record += 1;
+        assert!(
+            number < RECORDS_PER_RUN * MFT_RUNS.len() as u64,
+            "test MFT full"
+        );
+        number
+    }
+
+    /// Write content across runs of `per_run` clusters each.
+    fn write_content(&mut self, run_starts: &[u64], content: &[u8], per_run: u64) {
+        assert!(self.next_cluster <= TOTAL_CLUSTERS, "test image full");
+        for (lcn, chunk) in run_starts
+            .iter()
+            .zip(content.chunks((per_run * CLUSTER) as usize))
+        {
+            self.chunks.insert(lcn * CLUSTER, chunk.to_vec());
+        }
+    }
+
+    fn add_non_resident(
+        &mut self,
+        parent: u64,
+        name: &str,
+        size: u64,
+        flags: u16,
+        runs: &[(Option<u64>, u64)],
+    ) -> u64 {
+        let number = self.alloc_record();
+        let mut attrs = vec![Self::standard_information()];
+        attrs.extend(Self::names(parent, name));
+        attrs.push(Self::non_resident_data(flags, size, runs));
+        self.write_record(number, 0x01, attrs);
+        number
+    }
+
+    /// `$FILE_NAME` attributes for a name: a single Win32 & DOS name when it
+    /// fits 8.3, otherwise a DOS alias followed by the Win32 name.
+    fn names(parent: u64, name: &str) -> Vec<Vec<u8>> {
+        let (stem, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+        let is_short = stem.len() <= 8
+            && ext.len() <= 3
+            && name
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '.');
+        if is_short {
+            return vec![Self::file_name(parent, name, 3)];
+        }
+
+        let clean = |s: &str, max: usize| -> String {
+            s.chars()
+                .filter(char::is_ascii_alphanumeric)
+                .map(|c| c.to_ascii_uppercase())
+                .take(max)
+                .collect()
+        };
+        let alias = format!("{}~1.{}", clean(stem, 6), clean(ext, 3));
+        vec![
+            Self::file_name(parent, &alias, 2),
+            Self::file_name(parent, name, 1),
+        ]
+    }
+
+    fn file_name(parent: u64, name: &str, namespace: u8) -> Vec<u8> {
+        let units: Vec<u16> = name.encode_utf16().collect();
+        let mut value = vec![0u8; 0x42];
+        value[0..8].copy_from_slice(&(parent | 1 << 48).to_le_bytes());
+        value[0x40] = units.len() as u8;
+        value[0x41] = namespace;
+        value.extend(units.iter().flat_map(|u| u.to_le_bytes()));
+        Self::resident(0x30, &value)
+    }
+
+    fn standard_information() -> Vec<u8> {
+        Self::resident(0x10, &[0u8; 0x48])
+    }
+
+    fn resident(attr_type: u32, value: &[u8]) -> Vec<u8> {
+        let mut attr = vec![0u8; 0x18];
+        attr[0..4].copy_from_slice(&attr_type.to_le_bytes());
+        attr[0x0A..0x0C].copy_from_slice(&0x18u16.to_le_bytes());
+        attr[0x10..0x14].copy_from_slice(&(value.len() as u32).to_le_bytes());
+        attr[0x14..0x16].copy_from_slice(&0x18u16.to_le_bytes());
+        attr.extend_from_slice(value);
+        Self::finish_attribute(attr)
+    }
+
+    fn non_resident_data(flags: u16, size: u64, runs: &[(Option<u64>, u64)]) -> Vec<u8> {
+        let clusters: u64 = runs.iter().map(|(_, len)| len).sum();
+        let mut attr = vec![0u8; 0x40];
+        attr[0..4].copy_from_slice(&0x80u32.to_le_bytes());
+        attr[8] = 1;
+        attr[0x0A..0x0C].copy_from_slice(&0x40u16.to_le_bytes());
+        attr[0x0C..0x0E].copy_from_slice(&flags.to_le_bytes());
+        attr[0x18..0x20].copy_from_slice(&clusters.saturating_sub(1).to_le_bytes());
+        attr[0x20..0x22].copy_from_slice(&0x40u16.to_le_bytes());
+        attr[0x28..0x30].copy_from_slice(&(clusters * CLUSTER).to_le_bytes());
+        attr[0x30..0x38].copy_from_slice(&size.to_le_bytes());
+        attr[0x38..0x40].copy_from_slice(&size.to_le_bytes());
+        attr.extend(Self::encode_runs(runs));
+        Self::finish_attribute(attr)
+    }
+
+    /// Encode a mapping pairs array with minimal field widths.
+    pub fn encode_runs(runs: &[(Option<u64>, u64)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut previous: i64 = 0;
+
+        for &(lcn, length) in runs {
+            let length_bytes = Self::trimmed(length as i64, false);
+            let delta_bytes = match lcn {
+                Some(lcn) => {
+                    let delta = lcn as i64 - previous;
+                    previous = lcn as i64;
+                    Self::trimmed(delta, true)
+                }
+                None => Vec::new(),
+            };
+            out.push(((delta_bytes.len() as u8) << 4) | length_bytes.len() as u8);
+            out.extend(length_bytes);
+            out.extend(delta_bytes);
+        }
+        out.push(0);
+        out
+    }
+
+    /// Shortest little-endian form of a value, keeping the sign bit when
+    /// `signed`.
+    fn trimmed(value: i64, signed: bool) -> Vec<u8> {
+        let bytes = value.to_le_bytes();
+        let mut len = 8;
+        while len > 1 {
+            let top = bytes[len - 1];
+            let next_sign = bytes[len - 2] & 0x80 != 0;
+            let redundant = match (signed, value < 0) {
+                (true, true) => top == 0xFF && next_sign,
+                (true, false) => top == 0 && !next_sign,
+                (false, _) => top == 0,
+            };
+            if !redundant {
+                break;
+            }
+            len -= 1;
+        }
+        bytes[..len].to_vec()
+    }
+
+    /// Set the attribute length, padded to 8 bytes.
+    fn finish_attribute(mut attr: Vec<u8>) -> Vec<u8> {
+        attr.resize(attr.len().div_ceil(8) * 8, 0);
+        let len = attr.len() as u32;
+        attr[4..8].copy_from_slice(&len.to_le_bytes());
+        attr
+    }
+
+    /// Assemble a file record and apply its update sequence.
+    fn write_record(&mut self, number: u64, flags: u16, attrs: Vec<Vec<u8>>) {
+        let mut record = vec![0u8; RECORD_SIZE];
+        record[0..4].copy_from_slice(b"FILE");
+        record[4..6].copy_from_slice(&0x30u16.to_le_bytes());
+        record[6..8].copy_from_slice(&3u16.to_le_bytes());
+        record[0x10..0x12].copy_from_slice(&1u16.to_le_bytes());
+        record[0x12..0x14].copy_from_slice(&1u16.to_le_bytes());
+        record[0x14..0x16].copy_from_slice(&0x38u16.to_le_bytes());
+        record[0x16..0x18].copy_from_slice(&flags.to_le_bytes());
+        record[0x1C..0x20].copy_from_slice(&(RECORD_SIZE as u32).to_le_bytes());
+        record[0x2C..0x30].copy_from_slice(&(number as u32).to_le_bytes());
+
+        let mut offset = 0x38;
+        for attr in attrs {
+            record[offset..offset + attr.len()].copy_from_slice(&attr);
+            offset += attr.len();
+        }
+        record[offset..offset + 4].copy_from_slice(&0xFFFF_FFFFu32.to_le_bytes());
+        record[0x18..0x1C].copy_from_slice(&(offset as u32 + 8).to_le_bytes());
+
+        record[0x30..0x32].copy_from_slice(&USN.to_le_bytes());
+        for i in 0..RECORD_SIZE / 512 {
+            let end = (i + 1) * 512;
+            let saved = 0x32 + i * 2;
+            record.copy_within(end - 2..end, saved);
+            record[end - 2..end].copy_from_slice(&USN.to_le_bytes());
+        }
+
+        self.records.insert(number, record);
+    }
+
+    fn boot_sector() -> Vec<u8> {
+        let mut bs = vec![0u8; SECTOR as usize];
+        bs[0..3].copy_from_slice(&[0xEB, 0x52, 0x90]);
+        bs[3..11].copy_from_slice(b"NTFS    ");
+        bs[0x0B..0x0D].copy_from_slice(&(SECTOR as u16).to_le_bytes());
+        bs[0x0D] = SECTORS_PER_CLUSTER as u8;
+        bs[0x15] = 0xF8;
+        bs[0x28..0x30].copy_from_slice(&(TOTAL_CLUSTERS * SECTORS_PER_CLUSTER).to_le_bytes());
+        bs[0x30..0x38].copy_from_slice(&MFT_RUNS[0].0.to_le_bytes());
+        bs[0x38..0x40].copy_from_slice(&2u64.to_le_bytes());
+        // 2^10 = 1024-byte records
+        bs[0x40] = (-10i8) as u8;
+        bs[0x44] = 1;
+        bs[510] = 0x55;
+        bs[511] = 0xAA;
+        bs
+    }
+}
@@ -0,0 +1,588 @@
+//! NTFS volume parsing.
+//!
+//! Parses the boot sector, reads `$MFT` file records (applying their fixup
+//! arrays) through the `$MFT`'s own data runs, and decodes `$DATA` runs of
+//! regular files. The directory tree is rebuilt from the `$FILE_NAME`
+//! attributes of every record rather than from directory indexes.
+
+use crate::error::{Error, Result};
+use crate::storage::image_file::ImageFile;
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+/// MFT record number of the root directory.
+pub const ROOT_RECORD: u64 = 5;
+/// Records below this one are metadata files ($MFT, $Bitmap, ...).
+const FIRST_USER_RECORD: u64 = 24;
+
+/// Record header flags.
+const RECORD_IN_USE: u16 = 0x0001;
+const RECORD_IS_DIRECTORY: u16 = 0x0002;
+
+/// Attribute types.
+const ATTR_ATTRIBUTE_LIST: u32 = 0x20;
+const ATTR_FILE_NAME: u32 = 0x30;
+const ATTR_DATA: u32 = 0x80;
+const ATTR_END: u32 = 0xFFFF_FFFF;
+
+/// Attribute header flags.
+const ATTR_FLAG_COMPRESSED: u16 = 0x0001;
+const ATTR_FLAG_ENCRYPTED: u16 = 0x4000;
+const ATTR_FLAG_SPARSE: u16 = 0x8000;
+
+/// `$FILE_NAME` namespace holding only the 8.3 alias.
+const NAMESPACE_DOS: u8 = 2;
+
+/// Fixups protect every 512-byte stride, whatever the sector size.
+const FIXUP_STRIDE: usize = 512;
+
+/// Low 48 bits of a file reference hold the record number.
+const FILE_REFERENCE_MASK: u64 = 0x0000_FFFF_FFFF_FFFF;
+
+/// Parsed NTFS boot sector.
+#[derive(Debug, Clone)]
+pub struct NtfsBootSector {
+    pub bytes_per_sector: u64,
+    pub sectors_per_cluster: u64,
+    pub total_sectors: u64,
+    /// First cluster of `$MFT`.
+    pub mft_cluster: u64,
+    /// Size of a file record in bytes.
+    pub mft_record_size: u64,
+}
+
+impl NtfsBootSector {
+    /// Get the cluster size in bytes.
+    pub fn cluster_size(&self) -> u64 {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+}
+
+/// One run of a non-resident attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataRun {
+    /// First cluster, or `None` for a sparse run.
+    pub lcn: Option<u64>,
+    /// Length in clusters.
+    pub length: u64,
+}
+
+/// The unnamed `$DATA` attribute of a file.
+#[derive(Debug, Clone)]
+pub struct DataAttribute {
+    pub non_resident: bool,
+    /// Attribute header flags (compressed, encrypted, sparse).
+    pub flags: u16,
+    /// File size in bytes.
+    pub data_size: u64,
+    /// First VCN this attribute covers (0 unless split across records).
+    pub starting_vcn: u64,
+    pub runs: Vec<DataRun>,
+}
+
+/// A parsed file record.
+#[derive(Debug, Clone)]
+pub struct NtfsRecord {
+    pub number: u64,
+    pub flags: u16,
+    /// Base record reference; non-zero for extension records.
+    pub base_record: u64,
+    /// Preferred name and parent record number.
+    pub name: Option<(String, u64)>,
+    pub data: Option<DataAttribute>,
+    pub has_attribute_list: bool,
+}
+
+impl NtfsRecord {
+    /// Check if the record is in use.
+    pub fn is_in_use(&self) -> bool {
+        self.flags & RECORD_IN_USE != 0
+    }
+
+    /// Check if the record is a directory.
+    pub fn is_directory(&self) -> bool {
+        self.flags & RECORD_IS_DIRECTORY != 0
+    }
+}
+
+/// An NTFS volume read from an image file or block device.
+#[derive(Debug)]
+pub struct NtfsVolume {
+    image: ImageFile,
+    boot: NtfsBootSector,
+    /// Where `$MFT` lives.
+    mft_runs: Vec<DataRun>,
+    record_count: u64,
+    /// Children of each directory: (name, record number), sorted by name.
+    children: BTreeMap<u64, Vec<(String, u64)>>,
+}
+
+impl NtfsVolume {
+    /// Open an NTFS volume and index its directory tree.
+    pub fn open(path: &Path) -> Result<Self> {
+        let image = ImageFile::open(path)?;
+        let boot = Self::parse_boot_sector(&image.read_at(0, 512)?)?;
+
+        // Record 0 describes $MFT itself
+        let mut raw = image.read_at(
+            boot.mft_cluster * boot.cluster_size(),
+            boot.mft_record_size as usize,
+        )?;
+        Self::apply_fixups(&mut raw)?;
+        let mft = Self::parse_record(0, &raw)?;
+        let data = mft
+            .data
+            .filter(|d| d.non_resident && d.starting_vcn == 0)
+            .ok_or_else(|| Error::DataCorruption("$MFT has no data runs".to_string()))?;
+
+        let mut volume = Self {
+            image,
+            record_count: data.data_size / boot.mft_record_size,
+            boot,
+            mft_runs: data.runs,
+            children: BTreeMap::new(),
+        };
+        volume.index_names()?;
+        Ok(volume)
+    }
+
+    /// Parse the boot sector.
+    fn parse_boot_sector(data: &[u8]) -> Result<NtfsBootSector> {
+        if &data[3..11] != b"NTFS    " || data[510] != 0x55 || data[511] != 0xAA {
+            return Err(Error::InvalidMagic);
+        }
+
+        let bytes_per_sector = u16::from_le_bytes([data[0x0B], data[0x0C]]) as u64;
+        // Values above 0x80 encode 2^(256 - n) sectors
+        let sectors_per_cluster = match data[0x0D] {
+            n if n > 0x80 => 1u64.checked_shl(256 - n as u32).unwrap_or(0),
+            n => n as u64,
+        };
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+        {
+            return Err(Error::DataCorruption(format!(
+                "Invalid NTFS geometry: {} bytes/sector, {} sectors/cluster",
+                bytes_per_sector, sectors_per_cluster
+            )));
+        }
+        let cluster_size = bytes_per_sector * sectors_per_cluster;
+
+        // Positive: clusters per record; negative: 2^-n bytes
+        let mft_record_size = match data[0x40] as i8 {
+            n if n > 0 => n as u64 * cluster_size,
+            n => 1u64.checked_shl(n.unsigned_abs() as u32).unwrap_or(0),
+        };
+        if !(512..=65536).contains(&mft_record_size) || !mft_record_size.is_power_of_two() {
+            return Err(Error::DataCorruption(format!(
+                "Invalid MFT record size: {}",
+                mft_record_size
+            )));
+        }
+
+        Ok(NtfsBootSector {
+            bytes_per_sector,
+            sectors_per_cluster,
+            total_sectors: u64::from_le_bytes(data[0x28..0x30].try_into().unwrap()),
+            mft_cluster: u64::from_le_bytes(data[0x30..0x38].try_into().unwrap()),
+            mft_record_size,
+        })
+    }
+
+    /// Verify and undo the update sequence of a multi-sector record: the
+    /// last two bytes of every 512-byte stride hold the update sequence
+    /// number, and the real bytes are kept in the array after it.
+    pub(crate) fn apply_fixups(record: &mut [u8]) -> Result<()> {
+        let usa_offset = u16::from_le_bytes([record[4], record[5]]) as usize;
+        let usa_count = u16::from_le_bytes([record[6], record[7]]) as usize;
+        let strides = record.len() / FIXUP_STRIDE;
+        if usa_count != strides + 1 || usa_offset + usa_count * 2 > record.len() {
+            return Err(Error::DataCorruption(
+                "Invalid update sequence array".to_string(),
+            ));
+        }
+
+        let usn = [record[usa_offset], record[usa_offset + 1]];
+        for i in 0..strides {
+            let end = (i + 1) * FIXUP_STRIDE;
+            if record[end - 2..end] != usn {
+                return Err(Error::DataCorruption(
+                    "Torn MFT record: update sequence mismatch".to_string(),
+                ));
+            }
+            let saved = usa_offset + 2 + i * 2;
+            record.copy_within(saved..saved + 2, end - 2);
+        }
+        Ok(())
+    }
+
+    /// Parse a file record whose fixups have been applied.
+    fn parse_record(number: u64, raw: &[u8]) -> Result<NtfsRecord> {
+        if &raw[0..4] != b"FILE" {
+            return Err(Error::DataCorruption(format!(
+                "MFT record {} has no FILE signature",
+                number
+            )));
+        }
+
+        let mut record = NtfsRecord {
+            number,
+            flags: u16::from_le_bytes([raw[0x16], raw[0x17]]),
+            base_record: u64::from_le_bytes(raw[0x20..0x28].try_into().unwrap())
+                & FILE_REFERENCE_MASK,
+            name: None,
+            data: None,
+            has_attribute_list: false,
+        };
+        // Preferred names beat DOS aliases
+        let mut name_is_dos = false;
+
+        let corrupt =
+            || Error::DataCorruption(format!("MFT record {} has a malformed attribute", number));
+        let mut offset = u16::from_le_bytes([raw[0x14], raw[0x15]]) as usize;
+
+        loop {
+            let header = raw.get(offset..offset + 16).ok_or_else(corrupt)?;
+            let attr_type = u32::from_le_bytes(header[0..4].try_into().unwrap());
+            if attr_type == ATTR_END {
+                break;
+            }
+            let length = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+            let attr = raw.get(offset..offset + length).ok_or_else(corrupt)?;
+            if length < 16 {
+                return Err(corrupt());
+            }
+            offset += length;
+
+            let non_resident = attr[8] != 0;
+            let named = attr[9] != 0;
+            let flags = u16::from_le_bytes([attr[0x0C], attr[0x0D]]);
+
+            match attr_type {
+                ATTR_ATTRIBUTE_LIST => record.has_attribute_list = true,
+                ATTR_FILE_NAME if !non_resident => {
+                    let value = Self::resident_value(attr).ok_or_else(corrupt)?;
+                    let (name, parent, namespace) =
+                        Self::parse_file_name(value).ok_or_else(corrupt)?;
+                    if record.name.is_none() || (name_is_dos && namespace != NAMESPACE_DOS) {
+                        record.name = Some((name, parent));
+                        name_is_dos = namespace == NAMESPACE_DOS;
+                    }
+                }
+                // Named $DATA attributes are alternate data streams
+                ATTR_DATA if !named => {
+                    record.data = Some(if non_resident {
+                        let field = |off: usize| {
+                            attr.get(off..off + 8)
+                                .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+                                .ok_or_else(corrupt)
+                        };
+                        let runs_offset = u16::from_le_bytes([
+                            *attr.get(0x20).ok_or_else(corrupt)?,
+                            *attr.get(0x21).ok_or_else(corrupt)?,
+                        ]) as usize;
+                        DataAttribute {
+                            non_resident,
+                            flags,
+                            data_size: field(0x30)?,
+                            starting_vcn: field(0x10)?,
+                            runs: Self::decode_runs(attr.get(runs_offset..).ok_or_else(corrupt)?)?,
+                        }
+                    } else {
+                        DataAttribute {
+                            non_resident,
+                            flags,
+                            data_size: Self::resident_value(attr).ok_or_else(corrupt)?.len() as u64,
+                            starting_vcn: 0,
+                            runs: Vec::new(),
+                        }
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(record)
+    }
+
+    /// Get the value of a resident attribute.
+    fn resident_value(attr: &[u8]) -> Option<&[u8]> {
+        let length = u32::from_le_bytes(attr.get(0x10..0x14)?.try_into().ok()?) as usize;
+        let offset = u16::from_le_bytes(attr.get(0x14..0x16)?.try_into().ok()?) as usize;
+        attr.get(offset..offset + length)
+    }
+
+    /// Parse a `$FILE_NAME` value into (name, parent record, namespace).
+    fn parse_file_name(value: &[u8]) -> Option<(String, u64, u8)> {
+        let parent = u64::from_le_bytes(value.get(0..8)?.try_into().ok()?) & FILE_REFERENCE_MASK;
+        let length = *value.get(0x40)? as usize;
+        let namespace = *value.get(0x41)?;
+        let units: Vec<u16> = value
+            .get(0x42..0x42 + length * 2)?
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        Some((String::from_utf16(&units).ok()?, parent, namespace))
+    }
+
+    /// Decode a mapping pairs array. Each run starts with a header byte
+    /// whose low nibble sizes the length field and high nibble the signed
+    /// LCN delta; a zero-sized delta marks a sparse run.
+    pub fn decode_runs(data: &[u8]) -> Result<Vec<DataRun>> {
+        let corrupt = || Error::DataCorruption("Malformed data runs".to_string());
+        let mut runs = Vec::new();
+        let mut lcn: i64 = 0;
+        let mut pos = 0;
+
+        loop {
+            let header = *data.get(pos).ok_or_else(corrupt)?;
+            if header == 0 {
+                return Ok(runs);
+            }
+            let length_size = (header & 0x0F) as usize;
+            let offset_size = (header >> 4) as usize;
+            if length_size == 0 || length_size > 8 || offset_size > 8 {
+                return Err(corrupt());
+            }
+            pos += 1;
+
+            let field = data.get(pos..pos + length_size).ok_or_else(corrupt)?;
+            let length = field
+                .iter()
+                .rev()
+                .fold(0u64, |acc, &b| (acc << 8) | b as u64);
+            pos += length_size;
+
+            if offset_size == 0 {
+                runs.push(DataRun { lcn: None, length });
+                continue;
+            }
+
+            let field = data.get(pos..pos + offset_size).ok_or_else(corrupt)?;
+            // Sign-extend from the top byte
+            let sign = if field[offset_size - 1] & 0x80 != 0 {
+                -1i64
+            } else {
+                0
+            };
+            let delta = field
+                .iter()
+                .rev()
+                .fold(sign, |acc, &b| (acc << 8) | b as i64);
+            pos += offset_size;
+
+            lcn = lcn
+                .checked_add(delta)
+                .filter(|&l| l >= 0)
+                .ok_or_else(corrupt)?;
+            runs.push(DataRun {
+                lcn: Some(lcn as u64),
+                length,
+            });
+        }
+    }
+
+    /// Map a VCN of a run list to its LCN.
+    fn vcn_to_lcn(runs: &[DataRun], vcn: u64) -> Option<Option<u64>> {
+        let mut start = 0;
+        for run in runs {
+            if vcn < start + run.length {
+                return Some(run.lcn.map(|lcn| lcn + vcn - start));
+            }
+            start += run.length;
+        }
+        None
+    }
+
+    /// Get the parsed boot sector.
+    pub fn boot_sector(&self) -> &NtfsBootSector {
+        &self.boot
+    }
+
+    /// Get the cluster size in bytes.
+    pub fn cluster_size(&self) -> u64 {
+        self.boot.cluster_size()
+    }
+
+    /// Get the image the volume is read from.
+    pub fn image(&self) -> &ImageFile {
+        &self.image
+    }
+
+    /// Read and parse a file record.
+    pub fn read_record(&self, number: u64) -> Result<NtfsRecord> {
+        if number >= self.record_count {
+            return Err(Error::DataCorruption(format!(
+                "MFT record {} is past the end of $MFT",
+                number
+            )));
+        }
+
+        // Records may span clusters, and clusters may sit in different runs
+        let cluster_size = self.cluster_size();
+        let record_size = self.boot.mft_record_size;
+        let mut raw = Vec::with_capacity(record_size as usize);
+        let mut pos = number * record_size;
+        while (raw.len() as u64) < record_size {
+            let lcn = Self::vcn_to_lcn(&self.mft_runs, pos / cluster_size)
+                .flatten()
+                .ok_or_else(|| {
+                    Error::DataCorruption(format!("MFT record {} is not mapped", number))
+                })?;
+            let within = pos % cluster_size;
+            let len = (cluster_size - within).min(record_size - raw.len() as u64);
+            raw.extend(
+                self.image
+                    .read_at(lcn * cluster_size + within, len as usize)?,
+            );
+            pos += len;
+        }
+
+        Self::apply_fixups(&mut raw)?;
+        Self::parse_record(number, &raw)
+    }
+
+    /// Build the directory tree from the names of all user records.
+    fn index_names(&mut self) -> Result<()> {
+        for number in FIRST_USER_RECORD..self.record_count {
+            // Unused records may be zeroed or torn; they hold no files
+            let Ok(record) = self.read_record(number) else {
+                continue;
+            };
+            if !record.is_in_use() || record.base_record != 0 {
+                continue;
+            }
+            if let Some((name, parent)) = record.name {
+                self.children
+                    .entry(parent)
+                    .or_default()
+                    .push((name, number));
+            }
+        }
+
+        for entries in self.children.values_mut() {
+            entries.sort();
+        }
+        Ok(())
+    }
+
+    /// List a directory's children as (name, record number).
+    pub fn read_dir(&self, record: u64) -> &[(String, u64)] {
+        self.children.get(&record).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Resolve an absolute path inside the volume to its record. Win32
+    /// names are case-insensitive.
+    pub fn lookup(&self, path: &Path) -> Result<NtfsRecord> {
+        let mut current = ROOT_RECORD;
+
+        for component in path.components() {
+            let name = match component {
+                Component::RootDir => continue,
+                Component::Normal(name) => name.to_string_lossy().to_lowercase(),
+                _ => return Err(Error::InvalidPath(path.display().to_string())),
+            };
+
+            current = self
+                .read_dir(current)
+                .iter()
+                .find(|(child, _)| child.to_lowercase() == name)
+                .map(|(_, number)| *number)
+                .ok_or_else(|| Error::FileNotFound(path.display().to_string()))?;
+        }
+
+        self.read_record(current)
+    }
+
+    /// List every regular file with its absolute path, sorted by path.
+    pub fn walk_files(&self) -> Result<Vec<(PathBuf, NtfsRecord)>> {
+        let mut files = Vec::new();
+        let mut pending = vec![(PathBuf::from("/"), ROOT_RECORD)];
+
+        while let Some((dir_path, dir)) = pending.pop() {
+            for (name, number) in self.read_dir(dir) {
+                let Ok(record) = self.read_record(*number) else {
+                    continue;
+                };
+                let path = dir_path.join(name);
+                if record.is_directory() {
+                    pending.push((path, *number));
+                } else {
+                    files.push((path, record));
+                }
+            }
+        }
+
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(files)
+    }
+
+    /// Calculate the slack of a file: the bytes between its end and the end
+    /// of the cluster holding its last byte.
+    ///
+    /// Resident files have no clusters and report no slack. Compressed,
+    /// encrypted and sparse files are refused: their on-disk layout doesn't
+    /// follow the file size.
+    ///
+    /// Returns (absolute offset where slack starts, available bytes).
+    pub fn file_slack(&self, record: &NtfsRecord) -> Result<(u64, u64)> {
+        let id = || format!("MFT record {}", record.number);
+        if record.is_directory() {
+            return Err(Error::NotAFile(id()));
+        }
+
+        let data = match &record.data {
+            Some(data) => data,
+            None if record.has_attribute_list => {
+                return Err(Error::Unsupported(format!(
+                    "{}: $DATA stored through an attribute list",
+                    id()
+                )))
+            }
+            None => return Err(Error::DataCorruption(format!("{} has no $DATA", id()))),
+        };
+
+        if !data.non_resident || data.data_size == 0 {
+            return Ok((0, 0));
+        }
+        if data.flags & ATTR_FLAG_COMPRESSED != 0 {
+            return Err(Error::Unsupported(format!("{}: compressed file", id())));
+        }
+        if data.flags & ATTR_FLAG_ENCRYPTED != 0 {
+            return Err(Error::Unsupported(format!("{}: encrypted file", id())));
+        }
+        if data.flags & ATTR_FLAG_SPARSE != 0 || data.runs.iter().any(|r| r.lcn.is_none()) {
+            return Err(Error::Unsupported(format!("{}: sparse file", id())));
+        }
+        if data.starting_vcn != 0 || record.has_attribute_list {
+            return Err(Error::Unsupported(format!(
+                "{}: $DATA split across records",
+                id()
+            )));
+        }
+
+        let cluster_size = self.cluster_size();
+        let last_vcn = (data.data_size - 1) / cluster_size;
+        let last_lcn = Self::vcn_to_lcn(&data.runs, last_vcn)
+            .flatten()
+            .ok_or_else(|| {
+                Error::DataCorruption(format!("{}: data runs are shorter than its size", id()))
+            })?;
+        if (last_lcn + 1) * cluster_size > self.image.len() {
+            return Err(Error::DataCorruption(format!(
+                "{}: data runs point outside the volume",
+                id()
+            )));
+        }
+
+        let used_in_last = data.data_size % cluster_size;
+        if used_in_last == 0 {
+            return Ok(((last_lcn + 1) * cluster_size, 0));
+        }
+
+        Ok((
+            last_lcn * cluster_size + used_in_last,
+            cluster_size - used_in_last,
+        ))
+    }
+}
@@ -0,0 +1,405 @@
+//! Partition gap backend for whole-disk images.
+//!
+//! Hides data in disk space outside every file system: unallocated gaps of
+//! an MBR or GPT disk (typically the sectors between the partition table
+//! and the first 1 MiB-aligned partition, and space left after the last
+//! partition) and the tail of each partition past the end of its file
+//! system. Regions are large and contiguous, so a handful of them can hold
+//! a whole VFS.
+//!
+//! Hosts are named after where the space lies: `/gap-<lba>` for a gap
+//! starting at that sector and `/partition-<n>-tail` for the tail of
+//! partition `n`. The protective MBR, both GPT headers and both GPT entry
+//! arrays are never handed out, and writes overlapping them are refused.
+//! On MBR disks with boot code the space before the first partition is left
+//! to the boot loader. Repartitioning or growing a file system destroys the
+//! data.
+
+mod probe;
+mod table;
+#[cfg(test)]
+mod test_image;
+
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, PersistenceRisk};
+use crate::storage::image_file::ImageFile;
+use crate::storage::slack_backend::{SlackBackend, SlackRegion};
+use std::path::{Path, PathBuf};
+
+pub use probe::{probe, FileSystemKind};
+pub(crate) use table::crc32;
+pub use table::{Partition, PartitionTable, PartitionType, TableKind};
+
+/// Size of the zero buffer used when wiping, as gaps can be gigabytes long.
+const WIPE_CHUNK: u64 = 1024 * 1024;
+
+/// Partition gap backend over a whole-disk image or device.
+pub struct PartitionGapBackend {
+    image: ImageFile,
+    table: PartitionTable,
+}
+
+impl PartitionGapBackend {
+    pub const NAME: &'static str = "partition gaps";
+
+    /// Open a partitioned image file or block device.
+    pub fn open(path: &Path) -> Result<Self> {
+        let image = ImageFile::open(path)?;
+        let table = PartitionTable::read(&image)?;
+        Ok(Self { image, table })
+    }
+
+    /// Get the parsed partition table.
+    pub fn table(&self) -> &PartitionTable {
+        &self.table
+    }
+
+    /// List every gap and file system tail with its region.
+    pub fn candidate_hosts(&self) -> Result<Vec<(PathBuf, SlackRegion)>> {
+        let sector_size = self.table.sector_size();
+        let mut hosts = Vec::new();
+
+        for gap in self.table.gaps() {
+            let path = PathBuf::from(format!("/gap-{}", gap.start / sector_size));
+            hosts.push((path, self.region(gap.start, gap.end - gap.start)));
+        }
+
+        for partition in self.table.partitions() {
+            if partition.is_extended() || partition.end() > self.image.len() {
+                continue;
+            }
+            let Some((_, fs_size)) = probe(&self.image, partition.start, partition.len)? else {
+                continue;
+            };
+            // Round up so a file system ending mid-sector keeps its last one
+            let start = partition.start + fs_size.next_multiple_of(sector_size);
+            if start < partition.end() && !self.table.overlaps_reserved(&(start..partition.end())) {
+                let path = PathBuf::from(format!("/partition-{}-tail", partition.number));
+                hosts.push((path, self.region(start, partition.end() - start)));
+            }
+        }
+
+        hosts.sort_by_key(|(_, region)| region.offset);
+        Ok(hosts)
+    }
+
+    /// Build a region over `len` bytes at `offset`.
+    fn region(&self, offset: u64, len: u64) -> SlackRegion {
+        SlackRegion {
+            device_path: self.image.path().to_path_buf(),
+            offset,
+            available: len,
+            logical_size: 0,
+            block_size: self.table.sector_size(),
+        }
+    }
+
+    /// Ensure an access stays within the region and clear of table
+    /// structures.
+    fn check_bounds(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<()> {
+        region.check_bounds(offset, len)?;
+
+        let start = region.offset + offset;
+        if self.table.overlaps_reserved(&(start..start + len as u64)) {
+            return Err(Error::PermissionDenied(format!(
+                "Refusing to access partition table structures at byte {}",
+                start
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl SlackBackend for PartitionGapBackend {
+    fn get_slack_info(&self, path: &Path) -> Result<SlackRegion> {
+        self.candidate_hosts()?
+            .into_iter()
+            .find(|(host, _)| host == path)
+            .map(|(_, region)| region)
+            .ok_or_else(|| Error::FileNotFound(path.display().to_string()))
+    }
+
+    /// Lists the gaps and tails of the disk; `root` (the directory holding
+    /// the VFS metadata) is not part of it and is ignored.
+    fn enumerate_hosts(&self, _root: &Path) -> Result<Vec<PathBuf>> {
+        Ok(self
+            .candidate_hosts()?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    fn read_slack(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        self.check_bounds(region, offset, len)?;
+        self.image.read_at(region.offset + offset, len)
+    }
+
+    fn write_slack(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        self.check_bounds(region, offset, data.len())?;
+        self.image.write_at(region.offset + offset, data)
+    }
+
+    fn wipe_slack(&self, region: &SlackRegion) -> Result<()> {
+        let zeros = vec![0u8; region.available.min(WIPE_CHUNK) as usize];
+        let mut offset = 0;
+        while offset < region.available {
+            let len = (region.available - offset).min(WIPE_CHUNK) as usize;
+            self.write_slack(region, offset, &zeros[..len])?;
+            offset += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Only partitioning tools and file system resizes reach the gaps.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::Low,
+            changes_visible_metadata: false,
+            needs_root: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.image.path().exists()
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_image::PartitionTestImage;
+
+    fn hosts(backend: &PartitionGapBackend) -> Vec<(String, u64, u64)> {
+        backend
+            .candidate_hosts()
+            .unwrap()
+            .into_iter()
+            .map(|(path, r)| (path.display().to_string(), r.offset, r.available))
+            .collect()
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(table::crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_mbr_gaps_and_tails() {
+        let mut image = PartitionTestImage::mbr(16384);
+        let linux = image.add_partition(2048, 4096);
+        image.add_ext4(linux, 256);
+        image.add_partition(8192, 4096);
+        let file = image.build();
+
+        let backend = PartitionGapBackend::open(file.path()).unwrap();
+        assert_eq!(backend.table().kind(), TableKind::Mbr);
+        assert_eq!(backend.table().partitions().len(), 2);
+
+        // The second partition holds no known file system, so no tail
+        assert_eq!(
+            hosts(&backend),
+            vec![
+                ("/gap-1".to_string(), 512, 2047 * 512),
+                (
+                    "/partition-1-tail".to_string(),
+                    2048 * 512 + (1 << 20),
+                    1 << 20
+                ),
+                ("/gap-6144".to_string(), 6144 * 512, 2048 * 512),
+                ("/gap-12288".to_string(), 12288 * 512, 4096 * 512),
+            ]
+        );
+
+        let region = backend.get_slack_info(Path::new("/gap-1")).unwrap();
+        backend.write_slack(&region, 0, b"after the MBR").unwrap();
+        assert_eq!(
+            backend.read_slack(&region, 0, 13).unwrap(),
+            b"after the MBR"
+        );
+
+        // The table still parses and still offers the same regions
+        let reopened = PartitionGapBackend::open(file.path()).unwrap();
+        assert_eq!(hosts(&reopened), hosts(&backend));
+
+        assert!(matches!(
+            backend.get_slack_info(Path::new("/gap-2")),
+            Err(Error::FileNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_mbr_boot_code_and_extended_partitions() {
+        let mut image = PartitionTestImage::mbr(16384);
+        image.set_boot_code();
+        let linux = image.add_partition(2048, 4096);
+        image.add_ext4(linux, 512);
+        image.add_partition_of_type(6144, 10240, 0x0F);
+        let file = image.build();
+
+        // No gap before the first partition, nothing inside the extended
+        // one, and the file system fills its partition
+        let backend = PartitionGapBackend::open(file.path()).unwrap();
+        assert!(hosts(&backend).is_empty());
+        assert!(backend.table().partitions()[1].is_extended());
+    }
+
+    #[test]
+    fn test_md_superblock_blocks_tail() {
+        let mut image = PartitionTestImage::mbr(8192);
+        let member = image.add_partition(2048, 4096);
+        image.add_ext4(member, 256);
+        image.add_md_superblock(member);
+        let file = image.build();
+
+        let backend = PartitionGapBackend::open(file.path()).unwrap();
+        let names: Vec<_> = hosts(&backend)
+            .into_iter()
+            .map(|(name, _, _)| name)
+            .collect();
+        assert_eq!(names, vec!["/gap-1", "/gap-6144"]);
+    }
+
+    #[test]
+    fn test_gpt_excludes_headers_and_entry_arrays() {
+        for sector_size in [512u64, 4096] {
+            let disk_sectors = (8 << 20) / sector_size;
+            let mut image = PartitionTestImage::gpt(sector_size, disk_sectors);
+            let array = image.entry_sectors();
+            let first = (1 << 20) / sector_size;
+            let ntfs = image.add_partition(first, first * 2);
+            image.add_ntfs(ntfs, first);
+            let file = image.build();
+
+            let backend = PartitionGapBackend::open(file.path()).unwrap();
+            let table = backend.table();
+            assert_eq!(table.kind(), TableKind::Gpt);
+            assert_eq!(table.sector_size(), sector_size);
+
+            let mib = 1u64 << 20;
+            let backup_array = (disk_sectors - 1 - array) * sector_size;
+            assert_eq!(
+                hosts(&backend),
+                vec![
+                    (
+                        format!("/gap-{}", 2 + array),
+                        (2 + array) * sector_size,
+                        mib - (2 + array) * sector_size
+                    ),
+                    // Sectors past the volume plus the backup boot sector
+                    (
+                        "/partition-1-tail".to_string(),
+                        2 * mib + sector_size,
+                        mib - sector_size
+                    ),
+                    (
+                        format!("/gap-{}", 3 * mib / sector_size),
+                        3 * mib,
+                        backup_array - 3 * mib
+                    ),
+                ]
+            );
+
+            // Fill every region, then check the table survived
+            for (_, region) in backend.candidate_hosts().unwrap() {
+                backend
+                    .write_slack(&region, 0, &vec![0xA5; region.available as usize])
+                    .unwrap();
+            }
+            let reopened = PartitionGapBackend::open(file.path()).unwrap();
+            assert_eq!(reopened.table().partitions(), table.partitions());
+            assert_eq!(reopened.table().reserved(), table.reserved());
+
+            // A forged region over the backup header is refused
+            let forged = SlackRegion {
+                device_path: file.path().to_path_buf(),
+                offset: (disk_sectors - 1) * sector_size,
+                available: sector_size,
+                logical_size: 0,
+                block_size: sector_size,
+            };
+            assert!(matches!(
+                backend.write_slack(&forged, 0, b"x"),
+                Err(Error::PermissionDenied(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_gpt_backup_header_fallback() {
+        let mut image = PartitionTestImage::gpt(512, 16384);
+        image.add_partition(2048, 2048);
+        image.corrupt_primary_header();
+        let file = image.build();
+
+        let backend = PartitionGapBackend::open(file.path()).unwrap();
+        assert_eq!(backend.table().partitions().len(), 1);
+        // The damaged primary structures are still kept out of reach
+        assert!(backend.table().overlaps_reserved(&(512..1024)));
+        assert!(backend.table().overlaps_reserved(&(1024..1536)));
+        assert_eq!(hosts(&backend)[0].0, "/gap-34");
+    }
+
+    #[test]
+    fn test_rejects_unpartitioned_and_broken_tables() {
+        let mut image = PartitionTestImage::mbr(4096);
+        image.add_partition(2048, 1024);
+        image.add_partition(2560, 1024);
+        let file = image.build();
+        assert!(matches!(
+            PartitionGapBackend::open(file.path()),
+            Err(Error::DataCorruption(_))
+        ));
+
+        // A bare NTFS volume: boot sector signature but no partition table
+        let mut boot = vec![0u8; 512];
+        boot[0] = 0xEB;
+        boot[3..11].copy_from_slice(b"NTFS    ");
+        boot[446..462].fill(0x33);
+        boot[510] = 0x55;
+        boot[511] = 0xAA;
+
+        let bare = tempfile::NamedTempFile::new().unwrap();
+        let mut disk = vec![0u8; 4096 * 512];
+        disk[..512].copy_from_slice(&boot);
+        std::fs::write(bare.path(), &disk).unwrap();
+        assert!(matches!(
+            PartitionGapBackend::open(bare.path()),
+            Err(Error::Unsupported(_))
+        ));
+
+        std::fs::write(bare.path(), vec![0u8; 4096]).unwrap();
+        assert!(matches!(
+            PartitionGapBackend::open(bare.path()),
+            Err(Error::InvalidMagic)
+        ));
+    }
+
+    #[test]
+    fn test_vfs_on_partition_gaps() {
+        use crate::{SlackVfs, VfsConfig};
+
+        let mut image = PartitionTestImage::gpt(512, 16384);
+        let linux = image.add_partition(2048, 8192);
+        image.add_ext4(linux, 768);
+        let file = image.build();
+        let meta_dir = tempfile::TempDir::new().unwrap();
+        let content: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+
+        {
+            let backend = Box::new(PartitionGapBackend::open(file.path()).unwrap());
+            let mut vfs =
+                SlackVfs::create_with_backend(meta_dir.path(), "pw", VfsConfig::default(), backend)
+                    .unwrap();
+            assert_eq!(vfs.info().host_count, 3);
+            vfs.create_file("/disk.bin", &content).unwrap();
+        }
+
+        let backend = Box::new(PartitionGapBackend::open(file.path()).unwrap());
+        let vfs = SlackVfs::mount_with_backend(meta_dir.path(), "pw", backend).unwrap();
+        assert_eq!(vfs.read_file("/disk.bin").unwrap(), content);
+    }
+}
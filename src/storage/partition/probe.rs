@@ -0,0 +1,111 @@
+//! File system detection inside partitions.
+//!
+//! Only the size a file system claims for itself is needed: the sectors
+//! between its end and the end of its partition are never touched by it.
+//! Partitions holding anything else (swap, LVM, LUKS, unknown data) have no
+//! known end and offer no tail.
+
+use crate::error::Result;
+use crate::storage::image_file::ImageFile;
+
+/// Magic of an MD RAID superblock.
+const MD_MAGIC: u32 = 0xA92B_4EFC;
+
+/// File systems whose size can be read from their superblock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSystemKind {
+    /// ext2, ext3 or ext4.
+    Ext,
+    Fat,
+    Exfat,
+    Ntfs,
+    Xfs,
+}
+
+/// Identify the file system at `start` and return the bytes it spans.
+///
+/// Returns `None` for unrecognised content, and for partitions that also
+/// carry an MD RAID superblock near their end (metadata 0.90 and 1.0 keep
+/// the array's file system at the start of the partition).
+pub fn probe(image: &ImageFile, start: u64, len: u64) -> Result<Option<(FileSystemKind, u64)>> {
+    if len < 4096 || start + 4096 > image.len() {
+        return Ok(None);
+    }
+
+    let head = image.read_at(start, 4096)?;
+    let found = probe_head(&head);
+    if found.is_some() && has_md_superblock(image, start, len)? {
+        return Ok(None);
+    }
+    Ok(found.filter(|&(_, size)| size > 0 && size <= len))
+}
+
+/// Recognise a file system from its first 4 KiB.
+fn probe_head(head: &[u8]) -> Option<(FileSystemKind, u64)> {
+    let le16 = |at: usize| u16::from_le_bytes([head[at], head[at + 1]]) as u64;
+    let le32 = |at: usize| u32::from_le_bytes(head[at..at + 4].try_into().unwrap()) as u64;
+    let le64 = |at: usize| u64::from_le_bytes(head[at..at + 8].try_into().unwrap());
+    let be32 = |at: usize| u32::from_be_bytes(head[at..at + 4].try_into().unwrap()) as u64;
+    let be64 = |at: usize| u64::from_be_bytes(head[at..at + 8].try_into().unwrap());
+
+    // ext2/3/4: superblock at 1024
+    if le16(1024 + 0x38) == 0xEF53 {
+        let block_size = 1024u64.checked_shl(le32(1024 + 0x18) as u32)?;
+        let mut blocks = le32(1024 + 0x04);
+        // INCOMPAT_64BIT
+        if le32(1024 + 0x60) & 0x80 != 0 {
+            blocks |= le32(1024 + 0x150) << 32;
+        }
+        return Some((FileSystemKind::Ext, blocks * block_size));
+    }
+
+    if &head[0..4] == b"XFSB" {
+        return Some((FileSystemKind::Xfs, be64(8) * be32(4)));
+    }
+
+    if head[510..512] != [0x55, 0xAA] {
+        return None;
+    }
+
+    if &head[3..11] == b"NTFS    " {
+        // The backup boot sector sits just past the counted sectors
+        return Some((FileSystemKind::Ntfs, (le64(0x28) + 1) * le16(0x0B)));
+    }
+
+    if &head[3..11] == b"EXFAT   " {
+        let sector = 1u64.checked_shl(head[108] as u32)?;
+        return Some((FileSystemKind::Exfat, le64(72) * sector));
+    }
+
+    let fat_label = &head[54..59] == b"FAT12" || &head[54..59] == b"FAT16";
+    if fat_label || &head[82..87] == b"FAT32" {
+        let sectors = match le16(19) {
+            0 => le32(32),
+            n => n,
+        };
+        return Some((FileSystemKind::Fat, sectors * le16(11)));
+    }
+
+    None
+}
+
+/// Look for an MD RAID superblock where metadata 0.90 or 1.0 puts it.
+fn has_md_superblock(image: &ImageFile, start: u64, len: u64) -> Result<bool> {
+    let candidates = [
+        // 0.90: last 64 KiB-aligned 64 KiB block
+        (len & !0xFFFF).checked_sub(0x10000),
+        // 1.0: 8 KiB from the end, 4 KiB aligned
+        len.checked_sub(0x2000).map(|o| o & !0xFFF),
+    ];
+
+    for offset in candidates.into_iter().flatten() {
+        if start + offset + 4 > image.len() {
+            continue;
+        }
+        let raw = image.read_at(start + offset, 4)?;
+        if u32::from_le_bytes(raw.try_into().unwrap()) == MD_MAGIC {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
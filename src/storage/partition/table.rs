@@ -0,0 +1,443 @@
+//! MBR and GPT partition table parsing.
+//!
+//! Reads the partition table of a whole-disk image and works out which
+//! byte ranges belong to nobody: not to a partition and not to a table
+//! structure. Table structures (the MBR or protective MBR, both GPT headers
+//! and both GPT entry arrays) are recorded as reserved ranges so callers can
+//! refuse to write over them.
+
+use crate::error::{Error, Result};
+use crate::storage::image_file::ImageFile;
+use std::ops::Range;
+
+/// MBR boot signature at bytes 510..512.
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+/// Offset of the first of four MBR partition entries.
+const MBR_ENTRIES: usize = 446;
+/// Bytes of boot code at the start of the MBR.
+const MBR_BOOT_CODE: usize = 440;
+/// MBR sectors are always 512 bytes in practice.
+const MBR_SECTOR: u64 = 512;
+
+/// Partition type of a GPT protective MBR entry.
+const MBR_TYPE_PROTECTIVE: u8 = 0xEE;
+/// Partition types of extended partitions (CHS, LBA, Linux).
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Size of the header fields covered by revision 1.0.
+const GPT_MIN_HEADER_SIZE: usize = 92;
+/// Bound on the entry array so a corrupt header cannot force a huge read.
+const GPT_MAX_ENTRIES: u64 = 16384;
+
+/// Kind of partition table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableKind {
+    Mbr,
+    Gpt,
+}
+
+/// Partition type as stored in the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionType {
+    /// MBR system ID.
+    Mbr(u8),
+    /// GPT partition type GUID in on-disk byte order.
+    Gpt([u8; 16]),
+}
+
+/// A partition table entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// Slot number, starting at 1 (`/dev/sda1` is partition 1).
+    pub number: u32,
+    /// Partition type.
+    pub partition_type: PartitionType,
+    /// Byte offset of the first sector.
+    pub start: u64,
+    /// Length in bytes.
+    pub len: u64,
+}
+
+impl Partition {
+    /// Get the byte offset just past the partition.
+    pub fn end(&self) -> u64 {
+        self.start + self.len
+    }
+
+    /// Check if this is an MBR extended partition. Its logical partitions
+    /// and their boot records are not parsed, so the whole extended
+    /// partition is treated as in use.
+    pub fn is_extended(&self) -> bool {
+        matches!(self.partition_type, PartitionType::Mbr(t) if MBR_TYPES_EXTENDED.contains(&t))
+    }
+}
+
+/// A parsed MBR or GPT partition table.
+#[derive(Debug, Clone)]
+pub struct PartitionTable {
+    kind: TableKind,
+    sector_size: u64,
+    disk_size: u64,
+    /// Partitions sorted by start offset.
+    partitions: Vec<Partition>,
+    /// Byte ranges holding table structures or boot loader code.
+    reserved: Vec<Range<u64>>,
+    /// Byte range partitions may be placed in.
+    usable: Range<u64>,
+}
+
+impl PartitionTable {
+    /// Read the partition table of a whole-disk image.
+    pub fn read(image: &ImageFile) -> Result<Self> {
+        if image.len() < 2 * MBR_SECTOR {
+            return Err(Error::Unsupported(
+                "Image too small for a partition table".to_string(),
+            ));
+        }
+
+        let mbr = image.read_at(0, MBR_SECTOR as usize)?;
+        if mbr[510..512] != MBR_SIGNATURE {
+            return Err(Error::InvalidMagic);
+        }
+
+        let entries: Vec<&[u8]> = (0..4)
+            .map(|i| &mbr[MBR_ENTRIES + i * 16..MBR_ENTRIES + (i + 1) * 16])
+            .collect();
+        // A volume boot sector also ends in 55 AA but has no valid status
+        // bytes where the partition entries would be
+        if entries.iter().any(|e| e[0] != 0x00 && e[0] != 0x80) {
+            return Err(Error::Unsupported(
+                "Image holds a bare file system, not a partition table".to_string(),
+            ));
+        }
+
+        let table = if entries.iter().any(|e| e[4] == MBR_TYPE_PROTECTIVE) {
+            Self::read_gpt(image)?
+        } else {
+            Self::read_mbr(image, &mbr, &entries)?
+        };
+        table.validate()?;
+        Ok(table)
+    }
+
+    /// Parse the four primary MBR entries.
+    fn read_mbr(image: &ImageFile, mbr: &[u8], entries: &[&[u8]]) -> Result<Self> {
+        let disk_size = image.len();
+        let mut partitions = Vec::new();
+
+        for (i, entry) in entries.iter().enumerate() {
+            let first_lba = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64;
+            let sectors = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64;
+            if entry[4] == 0 || sectors == 0 {
+                continue;
+            }
+            if first_lba == 0 {
+                return Err(Error::DataCorruption(format!(
+                    "MBR partition {} overlaps the MBR",
+                    i + 1
+                )));
+            }
+
+            partitions.push(Partition {
+                number: i as u32 + 1,
+                partition_type: PartitionType::Mbr(entry[4]),
+                start: first_lba * MBR_SECTOR,
+                len: sectors * MBR_SECTOR,
+            });
+        }
+        partitions.sort_by_key(|p| p.start);
+
+        let mut reserved = Vec::new();
+        reserved.push(0..MBR_SECTOR);
+        // Boot loaders such as GRUB embed their core image in the sectors
+        // after an MBR carrying boot code
+        if mbr[..MBR_BOOT_CODE].iter().any(|&b| b != 0) {
+            let first = partitions.first().map_or(disk_size, |p| p.start);
+            reserved.push(MBR_SECTOR..first.max(MBR_SECTOR));
+        }
+
+        Ok(Self {
+            kind: TableKind::Mbr,
+            sector_size: MBR_SECTOR,
+            disk_size,
+            partitions,
+            reserved,
+            usable: MBR_SECTOR..disk_size,
+        })
+    }
+
+    /// Parse a GPT, falling back to the backup header if the primary one
+    /// is damaged.
+    fn read_gpt(image: &ImageFile) -> Result<Self> {
+        let disk_size = image.len();
+
+        // The header sits at LBA 1, whose offset depends on the sector size
+        let mut primary = None;
+        for sector_size in [512u64, 4096] {
+            if let Ok(header) = GptHeader::read(image, sector_size, 1) {
+                primary = Some((sector_size, header));
+                break;
+            }
+        }
+
+        let (sector_size, primary, backup) = match primary {
+            Some((sector_size, header)) => {
+                let backup = GptHeader::read(image, sector_size, header.alternate_lba).ok();
+                (sector_size, Some(header), backup)
+            }
+            None => {
+                let mut found = None;
+                for sector_size in [512u64, 4096] {
+                    let Some(last_lba) = (disk_size / sector_size).checked_sub(1) else {
+                        continue;
+                    };
+                    if let Ok(header) = GptHeader::read(image, sector_size, last_lba) {
+                        found = Some((sector_size, header));
+                        break;
+                    }
+                }
+                let (sector_size, header) = found.ok_or_else(|| {
+                    Error::DataCorruption("No valid GPT header found".to_string())
+                })?;
+                (sector_size, None, Some(header))
+            }
+        };
+
+        let header = primary.as_ref().or(backup.as_ref()).unwrap();
+        let raw = image.read_at(
+            header.entries_lba * sector_size,
+            header.entries_bytes() as usize,
+        )?;
+        if crc32(&raw) != header.entries_crc {
+            return Err(Error::DataCorruption(
+                "GPT entry array checksum mismatch".to_string(),
+            ));
+        }
+
+        let mut partitions = Vec::new();
+        for (i, entry) in raw.chunks_exact(header.entry_size as usize).enumerate() {
+            let type_guid: [u8; 16] = entry[0..16].try_into().unwrap();
+            if type_guid == [0u8; 16] {
+                continue;
+            }
+
+            let first_lba = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+            let last_lba = u64::from_le_bytes(entry[40..48].try_into().unwrap());
+            if last_lba < first_lba {
+                return Err(Error::DataCorruption(format!(
+                    "GPT partition {} ends before it starts",
+                    i + 1
+                )));
+            }
+
+            partitions.push(Partition {
+                number: i as u32 + 1,
+                partition_type: PartitionType::Gpt(type_guid),
+                start: first_lba * sector_size,
+                len: (last_lba - first_lba + 1) * sector_size,
+            });
+        }
+        partitions.sort_by_key(|p| p.start);
+
+        // Protective MBR, both headers and both entry arrays. A missing
+        // copy is assumed to sit where the surviving header says it is.
+        let entries_sectors = header.entries_bytes().div_ceil(sector_size);
+        let sectors = |lba: u64, count: u64| lba * sector_size..(lba + count) * sector_size;
+        let mut reserved = vec![sectors(0, 1)];
+        match &primary {
+            Some(h) => {
+                reserved.push(sectors(h.my_lba, 1));
+                reserved.push(sectors(h.entries_lba, entries_sectors));
+            }
+            None => {
+                reserved.push(sectors(1, 1));
+                reserved.push(sectors(2, entries_sectors));
+            }
+        }
+        match &backup {
+            Some(h) => {
+                reserved.push(sectors(h.my_lba, 1));
+                reserved.push(sectors(h.entries_lba, entries_sectors));
+            }
+            None => {
+                let alternate = header.alternate_lba;
+                reserved.push(sectors(alternate, 1));
+                reserved.push(sectors(
+                    alternate.saturating_sub(entries_sectors),
+                    entries_sectors,
+                ));
+            }
+        }
+
+        Ok(Self {
+            kind: TableKind::Gpt,
+            sector_size,
+            disk_size,
+            partitions,
+            reserved,
+            usable: header.first_usable_lba * sector_size
+                ..(header.last_usable_lba + 1) * sector_size,
+        })
+    }
+
+    /// Reject overlapping partitions and a usable range outside the disk.
+    fn validate(&self) -> Result<()> {
+        for pair in self.partitions.windows(2) {
+            if pair[1].start < pair[0].end() {
+                return Err(Error::DataCorruption(format!(
+                    "Partitions {} and {} overlap",
+                    pair[0].number, pair[1].number
+                )));
+            }
+        }
+        if self.usable.start >= self.usable.end {
+            return Err(Error::DataCorruption(
+                "Empty usable area in partition table".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Get the table kind.
+    pub fn kind(&self) -> TableKind {
+        self.kind
+    }
+
+    /// Get the logical sector size.
+    pub fn sector_size(&self) -> u64 {
+        self.sector_size
+    }
+
+    /// Get the image size in bytes.
+    pub fn disk_size(&self) -> u64 {
+        self.disk_size
+    }
+
+    /// Get the partitions, sorted by start offset.
+    pub fn partitions(&self) -> &[Partition] {
+        &self.partitions
+    }
+
+    /// Get the byte ranges that hold table structures or boot loader code.
+    pub fn reserved(&self) -> &[Range<u64>] {
+        &self.reserved
+    }
+
+    /// Check whether a byte range touches a reserved structure.
+    pub fn overlaps_reserved(&self, range: &Range<u64>) -> bool {
+        self.reserved
+            .iter()
+            .any(|r| r.start < range.end && range.start < r.end)
+    }
+
+    /// Find the unallocated byte ranges: the usable area of the disk minus
+    /// partitions and reserved structures, clipped to the image size.
+    pub fn gaps(&self) -> Vec<Range<u64>> {
+        let end = self.usable.end.min(self.disk_size);
+        let mut used: Vec<Range<u64>> = self
+            .partitions
+            .iter()
+            .map(|p| p.start..p.end())
+            .chain(self.reserved.iter().cloned())
+            .collect();
+        used.sort_by_key(|r| r.start);
+
+        let mut gaps = Vec::new();
+        let mut cursor = self.usable.start;
+        for range in used {
+            if range.start > cursor {
+                gaps.push(cursor..range.start.min(end));
+            }
+            cursor = cursor.max(range.end);
+        }
+        if cursor < end {
+            gaps.push(cursor..end);
+        }
+
+        gaps.retain(|g| g.start < g.end);
+        gaps
+    }
+}
+
+/// The fields of a GPT header needed to find the entry array.
+#[derive(Debug, Clone)]
+struct GptHeader {
+    my_lba: u64,
+    alternate_lba: u64,
+    first_usable_lba: u64,
+    last_usable_lba: u64,
+    entries_lba: u64,
+    entry_count: u64,
+    entry_size: u64,
+    entries_crc: u32,
+}
+
+impl GptHeader {
+    /// Read and verify the header at `lba`.
+    fn read(image: &ImageFile, sector_size: u64, lba: u64) -> Result<Self> {
+        let raw = image.read_at(lba * sector_size, sector_size as usize)?;
+        if &raw[0..8] != GPT_SIGNATURE {
+            return Err(Error::InvalidMagic);
+        }
+
+        let le32 = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().unwrap());
+        let le64 = |at: usize| u64::from_le_bytes(raw[at..at + 8].try_into().unwrap());
+
+        let header_size = le32(12) as usize;
+        if !(GPT_MIN_HEADER_SIZE..=sector_size as usize).contains(&header_size) {
+            return Err(Error::DataCorruption(format!(
+                "Invalid GPT header size {}",
+                header_size
+            )));
+        }
+        let mut copy = raw[..header_size].to_vec();
+        copy[16..20].fill(0);
+        if crc32(&copy) != le32(16) {
+            return Err(Error::DataCorruption(
+                "GPT header checksum mismatch".to_string(),
+            ));
+        }
+
+        let header = Self {
+            my_lba: le64(24),
+            alternate_lba: le64(32),
+            first_usable_lba: le64(40),
+            last_usable_lba: le64(48),
+            entries_lba: le64(72),
+            entry_count: le32(80) as u64,
+            entry_size: le32(84) as u64,
+            entries_crc: le32(88),
+        };
+
+        if header.my_lba != lba
+            || header.entry_size < 128
+            || !header.entry_size.is_multiple_of(8)
+            || header.entry_count > GPT_MAX_ENTRIES
+            || header.last_usable_lba < header.first_usable_lba
+        {
+            return Err(Error::DataCorruption(format!(
+                "Inconsistent GPT header at LBA {}",
+                lba
+            )));
+        }
+        Ok(header)
+    }
+
+    /// Get the size of the entry array in bytes.
+    fn entries_bytes(&self) -> u64 {
+        self.entry_count * self.entry_size
+    }
+}
+
+/// CRC-32 (IEEE 802.3) as used by GPT.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
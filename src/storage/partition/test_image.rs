@@ -0,0 +1,197 @@
+//! Minimal partitioned disk image builder for unit tests.
+//!
+//! Produces MBR or GPT disks with a few partitions, optionally holding just
+//! enough of a file system superblock for its size to be probed. Images are
+//! written sparsely.
+
+use super::table::crc32;
+use std::collections::BTreeMap;
+use std::io::{Seek, SeekFrom, Write};
+use tempfile::NamedTempFile;
+
+/// GUID of the "Linux filesystem data" partition type, on-disk byte order.
+const LINUX_FS_GUID: [u8; 16] = [
+    0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4,
+];
+/// Entries in each GPT entry array.
+const GPT_ENTRIES: u64 = 128;
+const GPT_ENTRY_SIZE: u64 = 128;
+
+/// Builder for a small partitioned disk.
+pub(crate) struct PartitionTestImage {
+    gpt: bool,
+    sector_size: u64,
+    disk_sectors: u64,
+    /// (first LBA, sector count, MBR type) per slot.
+    partitions: Vec<(u64, u64, u8)>,
+    /// Data written at absolute offsets.
+    chunks: BTreeMap<u64, Vec<u8>>,
+    boot_code: bool,
+    corrupt_primary: bool,
+}
+
+impl PartitionTestImage {
+    /// Create an MBR disk of `disk_sectors` 512-byte sectors.
+    pub fn mbr(disk_sectors: u64) -> Self {
+        Self::new(false, 512, disk_sectors)
+    }
+
+    /// Create a GPT disk of `disk_sectors` sectors.
+    pub fn gpt(sector_size: u64, disk_sectors: u64) -> Self {
+        Self::new(true, sector_size, disk_sectors)
+    }
+
+    fn new(gpt: bool, sector_size: u64, disk_sectors: u64) -> Self {
+        Self {
+            gpt,
+            sector_size,
+            disk_sectors,
+            partitions: Vec::new(),
+            chunks: BTreeMap::new(),
+            boot_code: false,
+            corrupt_primary: false,
+        }
+    }
+
+    /// Get the sectors taken by one GPT entry array.
+    pub fn entry_sectors(&self) -> u64 {
+        (GPT_ENTRIES * GPT_ENTRY_SIZE).div_ceil(self.sector_size)
+    }
+
+    /// Add a Linux partition and return its number.
+    pub fn add_partition(&mut self, first_lba: u64, sectors: u64) -> u32 {
+        self.add_partition_of_type(first_lba, sectors, 0x83)
+    }
+
+    /// Add an MBR partition with the given system ID and return its number.
+    pub fn add_partition_of_type(&mut self, first_lba: u64, sectors: u64, mbr_type: u8) -> u32 {
+        self.partitions.push((first_lba, sectors, mbr_type));
+        self.partitions.len() as u32
+    }
+
+    /// Put an ext4 superblock spanning `blocks` 4 KiB blocks in a partition.
+    pub fn add_ext4(&mut self, number: u32, blocks: u32) {
+        let mut sb = vec![0u8; 1024];
+        sb[0x04..0x08].copy_from_slice(&blocks.to_le_bytes());
+        sb[0x18..0x1C].copy_from_slice(&2u32.to_le_bytes());
+        sb[0x38..0x3A].copy_from_slice(&0xEF53u16.to_le_bytes());
+        self.chunks.insert(self.partition_start(number) + 1024, sb);
+    }
+
+    /// Put an NTFS boot sector counting `sectors` sectors in a partition.
+    pub fn add_ntfs(&mut self, number: u32, sectors: u64) {
+        let mut boot = vec![0u8; 512];
+        boot[3..11].copy_from_slice(b"NTFS    ");
+        boot[0x0B..0x0D].copy_from_slice(&(self.sector_size as u16).to_le_bytes());
+        boot[0x28..0x30].copy_from_slice(&sectors.to_le_bytes());
+        boot[510] = 0x55;
+        boot[511] = 0xAA;
+        self.chunks.insert(self.partition_start(number), boot);
+    }
+
+    /// Write an MD RAID 1.0 superblock near the end of a partition.
+    pub fn add_md_superblock(&mut self, number: u32) {
+        let (first_lba, sectors, _) = self.partitions[number as usize - 1];
+        let len = sectors * self.sector_size;
+        let offset = first_lba * self.sector_size + ((len - 0x2000) & !0xFFF);
+        self.chunks
+            .insert(offset, 0xA92B_4EFCu32.to_le_bytes().to_vec());
+    }
+
+    /// Fill the MBR boot code area, as an installed boot loader would.
+    pub fn set_boot_code(&mut self) {
+        self.boot_code = true;
+    }
+
+    /// Damage the primary GPT header so only the backup is valid.
+    pub fn corrupt_primary_header(&mut self) {
+        self.corrupt_primary = true;
+    }
+
+    fn partition_start(&self, number: u32) -> u64 {
+        self.partitions[number as usize - 1].0 * self.sector_size
+    }
+
+    /// Write the image to a temporary file.
+    pub fn build(mut self) -> NamedTempFile {
+        let mut mbr = vec![0u8; 512];
+        if self.boot_code {
+            mbr[..440].fill(0x90);
+        }
+        mbr[510] = 0x55;
+        mbr[511] = 0xAA;
+
+        if self.gpt {
+            let protective = (self.disk_sectors - 1).min(u32::MAX as u64);
+            Self::mbr_entry(&mut mbr, 0, 1, protective, 0xEE);
+            self.write_gpt();
+        } else {
+            for (i, &(first_lba, sectors, mbr_type)) in self.partitions.iter().enumerate() {
+                Self::mbr_entry(&mut mbr, i, first_lba, sectors, mbr_type);
+            }
+        }
+        self.chunks.insert(0, mbr);
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.as_file()
+            .set_len(self.disk_sectors * self.sector_size)
+            .unwrap();
+        for (offset, data) in &self.chunks {
+            file.seek(SeekFrom::Start(*offset)).unwrap();
+            file.write_all(data).unwrap();
+        }
+        file.flush().unwrap();
+        file
+    }
+
+    fn mbr_entry(mbr: &mut [u8], slot: usize, first_lba: u64, sectors: u64, mbr_type: u8) {
+        let entry = &mut mbr[446 + slot * 16..446 + (slot + 1) * 16];
+        entry[4] = mbr_type;
+        entry[8..12].copy_from_slice(&(first_lba as u32).to_le_bytes());
+        entry[12..16].copy_from_slice(&(sectors as u32).to_le_bytes());
+    }
+
+    /// Write both GPT headers and entry arrays.
+    fn write_gpt(&mut self) {
+        let mut entries = vec![0u8; (GPT_ENTRIES * GPT_ENTRY_SIZE) as usize];
+        for (i, &(first_lba, sectors, _)) in self.partitions.iter().enumerate() {
+            let entry = &mut entries[i * 128..(i + 1) * 128];
+            entry[0..16].copy_from_slice(&LINUX_FS_GUID);
+            entry[16] = i as u8 + 1;
+            entry[32..40].copy_from_slice(&first_lba.to_le_bytes());
+            entry[40..48].copy_from_slice(&(first_lba + sectors - 1).to_le_bytes());
+        }
+        let entries_crc = crc32(&entries);
+
+        let last_lba = self.disk_sectors - 1;
+        let array = self.entry_sectors();
+        let first_usable = 2 + array;
+        let last_usable = last_lba - array - 1;
+
+        for (my_lba, alternate_lba, entries_lba) in
+            [(1, last_lba, 2), (last_lba, 1, last_lba - array)]
+        {
+            let mut header = vec![0u8; self.sector_size as usize];
+            header[0..8].copy_from_slice(b"EFI PART");
+            header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+            header[12..16].copy_from_slice(&92u32.to_le_bytes());
+            header[24..32].copy_from_slice(&my_lba.to_le_bytes());
+            header[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+            header[40..48].copy_from_slice(&first_usable.to_le_bytes());
+            header[48..56].copy_from_slice(&last_usable.to_le_bytes());
+            header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+            header[80..84].copy_from_slice(&(GPT_ENTRIES as u32).to_le_bytes());
+            header[84..88].copy_from_slice(&(GPT_ENTRY_SIZE as u32).to_le_bytes());
+            header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+            let crc = crc32(&header[..92]);
+            header[16..20].copy_from_slice(&crc.to_le_bytes());
+
+            if my_lba == 1 && self.corrupt_primary {
+                header[40] ^= 0xFF;
+            }
+            self.chunks.insert(my_lba * self.sector_size, header);
+            self.chunks
+                .insert(entries_lba * self.sector_size, entries.clone());
+        }
+    }
+}
@@ -1,6 +1,6 @@
 //! Low-level slack space read/write operations.
 
-use crate::config::wipe_params;
+use crate::config::{wipe_params, ShredPolicy};
 use crate::error::Result;
 use rand::RngCore;
 use std::fs::{File, OpenOptions};
@@ -114,33 +114,82 @@ pub fn wipe_slack(path: &Path, logical_size: u64, passes: Option<u8>) -> Result<
     }
 
     let slack_size = (current_size - logical_size) as usize;
-    let random_passes = passes.unwrap_or(wipe_params::RANDOM_PASSES);
+    let policy = ShredPolicy::Passes(passes.unwrap_or(wipe_params::RANDOM_PASSES));
 
     let mut file = OpenOptions::new().write(true).open(path)?;
 
-    // Random overwrite passes
-    let mut rng = rand::thread_rng();
-    let mut random_data = vec![0u8; slack_size];
+    // Random, then zero overwrite passes
+    shred(slack_size, policy, |data| {
+        file.seek(SeekFrom::Start(logical_size))?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        Ok(())
+    })?;
+
+    // Truncate file to original logical size
+    file.set_len(logical_size)?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/// Overwrite `len` bytes as `policy` says, handing each pass to `write`.
+pub fn shred(
+    len: usize,
+    policy: ShredPolicy,
+    mut write: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    let (random_passes, zero_passes) = match policy {
+        ShredPolicy::None => (0, 0),
+        ShredPolicy::Zero => (0, 1),
+        ShredPolicy::Random => (1, 0),
+        ShredPolicy::Passes(passes) => (passes, wipe_params::ZERO_PASSES),
+    };
 
+    let mut rng = rand::thread_rng();
+    let mut random_data = vec![0u8; len];
     for _ in 0..random_passes {
         rng.fill_bytes(&mut random_data);
-        file.seek(SeekFrom::Start(logical_size))?;
-        file.write_all(&random_data)?;
-        file.sync_all()?;
+        write(&random_data)?;
     }
 
-    // Zero overwrite passes
-    let zero_data = vec![0u8; slack_size];
-    for _ in 0..wipe_params::ZERO_PASSES {
-        file.seek(SeekFrom::Start(logical_size))?;
-        file.write_all(&zero_data)?;
-        file.sync_all()?;
+    let zero_data = vec![0u8; len];
+    for _ in 0..zero_passes {
+        write(&zero_data)?;
+    }
+    Ok(())
+}
+
+/// Shred part of a file's slack without growing the file.
+///
+/// Only bytes before the current end of file are overwritten. If the range
+/// reaches the end of file, the file is then truncated to `start`.
+///
+/// # Arguments
+///
+/// * `path` - Path to the file
+/// * `start` - Absolute offset of the range
+/// * `len` - Length of the range
+/// * `policy` - How to overwrite the range
+pub fn wipe_slack_range(path: &Path, start: u64, len: u64, policy: ShredPolicy) -> Result<()> {
+    let current_size = std::fs::metadata(path)?.len();
+    if policy == ShredPolicy::None || current_size <= start {
+        return Ok(());
     }
+    let end = (start + len).min(current_size);
 
-    // Truncate file to original logical size
-    file.set_len(logical_size)?;
-    file.sync_all()?;
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    shred((end - start) as usize, policy, |data| {
+        file.seek(SeekFrom::Start(start))?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        Ok(())
+    })?;
 
+    if end == current_size {
+        file.set_len(start)?;
+        file.sync_all()?;
+    }
     Ok(())
 }
 
@@ -224,4 +273,21 @@ mod tests {
         let size_after_wipe = std::fs::metadata(file.path()).unwrap().len();
         assert_eq!(size_after_wipe, logical_size);
     }
+
+    #[test]
+    fn test_shred_passes() {
+        let passes = |policy| {
+            let mut written = Vec::new();
+            shred(64, policy, |data| {
+                written.push(data.iter().all(|&b| b == 0));
+                Ok(())
+            })
+            .unwrap();
+            written
+        };
+        assert!(passes(ShredPolicy::None).is_empty());
+        assert_eq!(passes(ShredPolicy::Zero), vec![true]);
+        assert_eq!(passes(ShredPolicy::Random), vec![false]);
+        assert_eq!(passes(ShredPolicy::Passes(2)), vec![false, false, true]);
+    }
 }
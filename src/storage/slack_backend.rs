@@ -1,17 +1,25 @@
 //! Slack space backend abstraction for cross-platform support.
 //!
-//! This module defines the trait for accessing true file system slack space
-//! via raw block device access.
+//! This module defines the trait for backends with exactly one slack region
+//! per host file. Every `SlackBackend` is a `Carrier`, which is what
+//! `SlackVfs` does all symbol I/O through, so the same VFS runs on top of
+//! file-append slack, raw block devices, or file system images.
 
-use crate::error::Result;
+use crate::config::ShredPolicy;
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, Carrier};
+use crate::storage::slack::shred;
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
 
 /// Information about a file's slack space region.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SlackRegion {
-    /// Path to the raw block device (e.g., /dev/sda1 or /dev/rdisk2).
+    /// Path to the storage holding the region: a raw block device (e.g.,
+    /// /dev/sda1 or /dev/rdisk2), an image file, or the host file itself.
     pub device_path: PathBuf,
-    /// Absolute byte offset on the block device where slack starts.
+    /// Absolute byte offset in `device_path` where slack starts.
     pub offset: u64,
     /// Number of available slack bytes.
     pub available: u64,
@@ -21,6 +29,23 @@ pub struct SlackRegion {
     pub block_size: u64,
 }
 
+impl SlackRegion {
+    /// Ensure an access of `len` bytes at `offset` stays within the region.
+    ///
+    /// Every backend checks this before touching its storage, since the
+    /// region's absolute offset alone says nothing about where it ends.
+    pub fn check_bounds(&self, offset: u64, len: usize) -> Result<()> {
+        let end = offset.saturating_add(len as u64);
+        if end > self.available {
+            return Err(Error::InsufficientSpace {
+                needed: end,
+                available: self.available,
+            });
+        }
+        Ok(())
+    }
+}
+
 /// Trait for platform-specific slack space access.
 ///
 /// Implementations must provide raw block device access to read/write
@@ -32,6 +57,27 @@ pub trait SlackBackend: Send + Sync {
     /// along with the available capacity.
     fn get_slack_info(&self, path: &Path) -> Result<SlackRegion>;
 
+    /// Get slack space information for a file whose original logical size
+    /// is known.
+    ///
+    /// Backends that store data past the end of file grow the file as they
+    /// write, so the current size no longer marks where slack starts. The
+    /// default ignores `logical_size`, which is right for backends that
+    /// leave the file's size untouched.
+    fn get_slack_info_at(&self, path: &Path, logical_size: u64) -> Result<SlackRegion> {
+        let _ = logical_size;
+        self.get_slack_info(path)
+    }
+
+    /// List candidate host files under `root`.
+    ///
+    /// The default walks the directory tree and skips hidden files (which
+    /// includes the VFS metadata file). Backends with their own namespace,
+    /// such as file system images, override this.
+    fn enumerate_hosts(&self, root: &Path) -> Result<Vec<PathBuf>> {
+        walk_host_files(root)
+    }
+
     /// Read bytes from a slack region.
     ///
     /// # Arguments
@@ -55,28 +101,84 @@ pub trait SlackBackend: Send + Sync {
     /// Wipe slack space by overwriting with zeros or random data.
     fn wipe_slack(&self, region: &SlackRegion) -> Result<()>;
 
+    /// Overwrite `len` bytes of a slack region from `offset` as `policy`
+    /// says.
+    ///
+    /// The default overwrites the range through `write_slack`.
+    fn wipe_slack_range(
+        &self,
+        region: &SlackRegion,
+        offset: u64,
+        len: u64,
+        policy: ShredPolicy,
+    ) -> Result<()> {
+        shred(len as usize, policy, |data| {
+            self.write_slack(region, offset, data)
+        })
+    }
+
+    /// Make every write so far durable.
+    ///
+    /// Backends that hold writes back must save them here; the VFS calls
+    /// it before committing anything that refers to them. The default does
+    /// nothing, for backends that write through.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Report what writing to this backend's regions implies.
+    fn capabilities(&self) -> Capabilities;
+
     /// Check if this backend is available on the current system.
     fn is_available(&self) -> bool;
 
-    /// Get the name of this backend (for logging).
+    /// Get the name of this backend, as recorded in the bootstrap metadata.
+    ///
+    /// Backends also expose it as an associated `NAME` constant, so a
+    /// recorded name can be checked before any backend is opened.
     fn name(&self) -> &'static str;
 }
 
+/// List the non-hidden files under `root`, which includes skipping the VFS
+/// metadata file.
+pub(crate) fn walk_host_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut hosts = Vec::new();
+
+    for entry in WalkDir::new(root)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if entry.path().is_dir() {
+            continue;
+        }
+        let hidden = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with('.'));
+        if !hidden {
+            hosts.push(entry.into_path());
+        }
+    }
+
+    Ok(hosts)
+}
+
 /// Create the appropriate slack backend for the current platform.
 #[cfg(target_os = "linux")]
-pub fn create_backend() -> Result<Box<dyn SlackBackend>> {
+pub fn create_backend() -> Result<Box<dyn Carrier>> {
     use super::linux::LinuxSlackBackend;
     Ok(Box::new(LinuxSlackBackend::new()?))
 }
 
 #[cfg(target_os = "macos")]
-pub fn create_backend() -> Result<Box<dyn SlackBackend>> {
+pub fn create_backend() -> Result<Box<dyn Carrier>> {
     use super::macos::MacSlackBackend;
     Ok(Box::new(MacSlackBackend::new()?))
 }
 
 #[cfg(not(any(target_os = "linux", target_os = "macos")))]
-pub fn create_backend() -> Result<Box<dyn SlackBackend>> {
+pub fn create_backend() -> Result<Box<dyn Carrier>> {
     Err(crate::error::Error::Unsupported(
         "Block device slack access not supported on this platform".to_string(),
     ))
@@ -0,0 +1,653 @@
+//! Extended attribute backend.
+//!
+//! Stores data in `user.*` extended attributes of host files instead of past
+//! their end of file, so file sizes and contents never change. Needs no
+//! privileges, only a file system mounted with user xattr support.
+//!
+//! A naming scheme mimics what common applications leave on files, and each
+//! of its attribute names is one region of the host. Region sizes follow
+//! only the file system's limits: ext2/3/4 (and unknown file systems,
+//! conservatively) keep all of a file's attributes in one block, Btrfs
+//! bounds each value by its node size, and XFS allows 64 KiB per value.
+//! Attributes of other applications leave less room for new writes in a
+//! shared block, but never move or shrink a region.
+//!
+//! Values written here always fill their region, which tells them apart
+//! from an application's attribute of the same name, such as an
+//! `user.xdg.origin.url` a browser set. Regions whose name is taken that
+//! way are listed without space and the attribute is left alone.
+//!
+//! Setting an attribute updates the host's ctime (not its mtime). Copying a
+//! host without `--preserve=xattr`, or an editor replacing it, drops the
+//! data.
+
+use crate::error::{Error, Result};
+use crate::storage::carrier::{Capabilities, Carrier, PersistenceRisk};
+use crate::storage::slack_backend::SlackRegion;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Largest attribute value Linux accepts (`XATTR_SIZE_MAX`).
+const XATTR_SIZE_MAX: u64 = 65536;
+
+/// ext4 attribute block: 32-byte header plus a 4-byte end marker.
+const EXT4_BLOCK_OVERHEAD: u64 = 36;
+/// ext4 attribute entry header, before the name.
+const EXT4_ENTRY_OVERHEAD: u64 = 16;
+
+/// Btrfs per-item overhead: node header, item and dir item headers.
+const BTRFS_ITEM_OVERHEAD: u64 = 101 + 25 + 30;
+
+/// `statfs` magic numbers.
+const EXT4_SUPER_MAGIC: i64 = 0xEF53;
+const XFS_SUPER_MAGIC: i64 = 0x5846_5342;
+const BTRFS_SUPER_MAGIC: i64 = 0x9123_683E;
+
+/// Attribute naming schemes modeled on real applications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XattrNaming {
+    /// freedesktop.org common attributes, as set by browsers, mail clients
+    /// and file managers.
+    Xdg,
+    /// Attributes of the Dropbox client.
+    Dropbox,
+    /// Attributes of `rsync --fake-super`.
+    Rsync,
+    /// Checksum attributes of `shatag`.
+    Shatag,
+}
+
+impl XattrNaming {
+    /// Get the attribute names, one per region, in region order.
+    pub fn names(&self) -> &'static [&'static str] {
+        match self {
+            Self::Xdg => &[
+                "user.xdg.origin.url",
+                "user.xdg.referrer.url",
+                "user.xdg.comment",
+                "user.xdg.tags",
+                "user.xdg.origin.email.subject",
+                "user.xdg.origin.email.from",
+                "user.xdg.origin.email.message-id",
+                "user.xdg.language",
+                "user.xdg.creator",
+                "user.xdg.publisher",
+            ],
+            Self::Dropbox => &["user.com.dropbox.attrs", "user.com.dropbox.attributes"],
+            Self::Rsync => &["user.rsync.%stat", "user.rsync.%aacl", "user.rsync.%dacl"],
+            Self::Shatag => &["user.shatag.sha256", "user.shatag.ts"],
+        }
+    }
+
+    /// Get the backend name recorded in the bootstrap metadata. The scheme
+    /// is part of it, since data is only found again under the same names.
+    pub fn backend_name(&self) -> &'static str {
+        match self {
+            Self::Xdg => "xattr (xdg)",
+            Self::Dropbox => "xattr (dropbox)",
+            Self::Rsync => "xattr (rsync)",
+            Self::Shatag => "xattr (shatag)",
+        }
+    }
+}
+
+/// How a file system limits a file's extended attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum XattrLimits {
+    /// All attributes share one block of this size (ext2/3/4).
+    SharedBlock(u64),
+    /// Each value may hold up to this many bytes.
+    PerValue(u64),
+}
+
+impl XattrLimits {
+    /// Look up the limits of the file system holding `path`.
+    fn of(path: &Path) -> Result<Self> {
+        let c_path = c_string(path.as_os_str().as_bytes())?;
+        let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let block_size = stat.f_bsize as u64;
+        Ok(match stat.f_type as i64 {
+            XFS_SUPER_MAGIC => Self::PerValue(XATTR_SIZE_MAX),
+            // Nodes are at least one sector, which is what statfs reports
+            BTRFS_SUPER_MAGIC => Self::PerValue(block_size - BTRFS_ITEM_OVERHEAD),
+            EXT4_SUPER_MAGIC => Self::SharedBlock(block_size),
+            _ => Self::SharedBlock(block_size.min(4096)),
+        })
+    }
+
+    /// Bytes an attribute takes in an ext4 attribute block.
+    fn entry_cost(name: &str, value_len: u64) -> u64 {
+        EXT4_ENTRY_OVERHEAD
+            + (name.len() as u64).next_multiple_of(4)
+            + value_len.next_multiple_of(4)
+    }
+}
+
+/// Slack backend storing data in `user.*` extended attributes.
+#[derive(Debug, Clone)]
+pub struct XattrBackend {
+    naming: XattrNaming,
+}
+
+impl XattrBackend {
+    /// Create a backend using the given naming scheme.
+    pub fn new(naming: XattrNaming) -> Self {
+        Self { naming }
+    }
+
+    /// Get the naming scheme.
+    pub fn naming(&self) -> XattrNaming {
+        self.naming
+    }
+
+    /// Work out how many bytes each name of the scheme holds on a host.
+    ///
+    /// Depends only on the file system and the scheme, so stored data stays
+    /// addressable when attributes of other applications come and go.
+    fn region_sizes(&self, path: &Path) -> Result<Vec<u64>> {
+        let names = self.naming.names();
+        let longest = names.iter().map(|n| n.len()).max().unwrap_or(0) as u64;
+
+        match XattrLimits::of(path)? {
+            XattrLimits::PerValue(max) => {
+                let slot = (max - longest).min(XATTR_SIZE_MAX) / 4 * 4;
+                Ok(vec![slot; names.len()])
+            }
+            XattrLimits::SharedBlock(block_size) => {
+                let slot = (block_size - EXT4_BLOCK_OVERHEAD - EXT4_ENTRY_OVERHEAD)
+                    .saturating_sub(longest.next_multiple_of(4));
+
+                // Names fill the block in order; once one cannot be whole,
+                // the rest get nothing
+                let mut budget = block_size - EXT4_BLOCK_OVERHEAD;
+                let mut sizes = Vec::with_capacity(names.len());
+                for name in names {
+                    let overhead = XattrLimits::entry_cost(name, 0);
+                    let size = (budget.saturating_sub(overhead) / 4 * 4).min(slot);
+                    sizes.push(size);
+                    budget = budget.saturating_sub(overhead + size);
+                }
+                Ok(sizes)
+            }
+        }
+    }
+
+    /// Get the attribute name of a region.
+    fn name_of(&self, region: &SlackRegion) -> Result<&'static str> {
+        self.naming
+            .names()
+            .get(region.offset as usize)
+            .copied()
+            .ok_or_else(|| {
+                Error::DataCorruption(format!("No attribute for region {}", region.offset))
+            })
+    }
+}
+
+impl Carrier for XattrBackend {
+    /// Lists one region per name of the scheme. `offset` in a region is the
+    /// index of its name and `block_size` the size of its value.
+    fn regions(&self, path: &Path) -> Result<Vec<SlackRegion>> {
+        let metadata = std::fs::symlink_metadata(path)?;
+        if !metadata.is_file() {
+            return Err(Error::NotAFile(path.display().to_string()));
+        }
+
+        // Fails with ENOTSUP where user attributes are disabled
+        get_attr(path, self.naming.names()[0])?;
+
+        let names = self.naming.names();
+        let sizes = self.region_sizes(path)?;
+        names
+            .iter()
+            .zip(sizes)
+            .enumerate()
+            .map(|(index, (name, size))| {
+                let taken = get_attr(path, name)?.is_some_and(|v| v.len() as u64 != size);
+                Ok(SlackRegion {
+                    device_path: path.to_path_buf(),
+                    offset: index as u64,
+                    available: if taken { 0 } else { size },
+                    logical_size: metadata.len(),
+                    block_size: size,
+                })
+            })
+            .collect()
+    }
+
+    fn read_region(&self, region: &SlackRegion, offset: u64, len: usize) -> Result<Vec<u8>> {
+        region.check_bounds(offset, len)?;
+        let name = self.name_of(region)?;
+
+        let value = get_attr(&region.device_path, name)?.unwrap_or_default();
+        if value.len() as u64 != region.block_size {
+            return Err(Error::DataCorruption(format!(
+                "Attribute {} of {} is missing or not ours",
+                name,
+                region.device_path.display()
+            )));
+        }
+        let start = offset as usize;
+        Ok(value[start..start + len].to_vec())
+    }
+
+    fn write_region(&self, region: &SlackRegion, offset: u64, data: &[u8]) -> Result<()> {
+        region.check_bounds(offset, data.len())?;
+        let name = self.name_of(region)?;
+
+        let existing = get_attr(&region.device_path, name)?;
+        let replace = existing.is_some();
+        let mut value = match existing {
+            Some(value) if value.len() as u64 == region.block_size => value,
+            // An application took the name since the region was listed
+            Some(_) => {
+                return Err(Error::DataCorruption(format!(
+                    "Attribute {} of {} belongs to another application",
+                    name,
+                    region.device_path.display()
+                )))
+            }
+            None => vec![0u8; region.block_size as usize],
+        };
+        let start = offset as usize;
+        value[start..start + data.len()].copy_from_slice(data);
+        set_attr(&region.device_path, name, &value, replace)
+    }
+
+    /// Zeroes and then removes the region's attribute, unless another
+    /// application owns it.
+    fn wipe_region(&self, region: &SlackRegion) -> Result<()> {
+        let name = self.name_of(region)?;
+        if let Some(value) = get_attr(&region.device_path, name)? {
+            if value.len() as u64 == region.block_size {
+                set_attr(&region.device_path, name, &vec![0u8; value.len()], true)?;
+                remove_attr(&region.device_path, name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The attributes show up in `getfattr` listings and are dropped by
+    /// copies that do not preserve extended attributes.
+    fn capabilities(&self, _region: &SlackRegion) -> Capabilities {
+        Capabilities {
+            persistence_risk: PersistenceRisk::Moderate,
+            changes_visible_metadata: true,
+            needs_root: false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &'static str {
+        self.naming.backend_name()
+    }
+}
+
+fn c_string(bytes: &[u8]) -> Result<CString> {
+    CString::new(bytes).map_err(|_| Error::InvalidPath(String::from_utf8_lossy(bytes).into_owned()))
+}
+
+/// Read an attribute, or `None` if the file does not have it.
+fn get_attr(path: &Path, name: &str) -> Result<Option<Vec<u8>>> {
+    let c_path = c_string(path.as_os_str().as_bytes())?;
+    let c_name = c_string(name.as_bytes())?;
+
+    loop {
+        let size =
+            unsafe { libc::getxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::ENODATA) => Ok(None),
+                e => Err(attr_error(path, e)),
+            };
+        }
+
+        let mut value = vec![0u8; size as usize];
+        let read = unsafe {
+            libc::getxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_mut_ptr() as *mut libc::c_void,
+                value.len(),
+            )
+        };
+        if read >= 0 {
+            value.truncate(read as usize);
+            return Ok(Some(value));
+        }
+        // The value grew between the two calls; ask for its size again
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ERANGE) {
+            return Err(attr_error(path, err));
+        }
+    }
+}
+
+/// Create an attribute, or replace it if `replace` is set. Either fails if
+/// the attribute was created or removed meanwhile.
+fn set_attr(path: &Path, name: &str, value: &[u8], replace: bool) -> Result<()> {
+    let c_path = c_string(path.as_os_str().as_bytes())?;
+    let c_name = c_string(name.as_bytes())?;
+
+    let rc = unsafe {
+        libc::setxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            if replace {
+                libc::XATTR_REPLACE
+            } else {
+                libc::XATTR_CREATE
+            },
+        )
+    };
+    if rc != 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENOSPC) | Some(libc::E2BIG) => Err(Error::InsufficientSpace {
+                needed: value.len() as u64,
+                available: 0,
+            }),
+            _ => Err(attr_error(path, err)),
+        };
+    }
+    Ok(())
+}
+
+/// Remove an attribute if it exists.
+fn remove_attr(path: &Path, name: &str) -> Result<()> {
+    let c_path = c_string(path.as_os_str().as_bytes())?;
+    let c_name = c_string(name.as_bytes())?;
+
+    if unsafe { libc::removexattr(c_path.as_ptr(), c_name.as_ptr()) } != 0 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ENODATA) {
+            return Err(attr_error(path, err));
+        }
+    }
+    Ok(())
+}
+
+/// Map an attribute syscall failure to the crate's error type.
+fn attr_error(path: &Path, err: io::Error) -> Error {
+    match err.raw_os_error() {
+        Some(libc::ENOTSUP) => Error::Unsupported(format!(
+            "User extended attributes are not supported on {}",
+            path.display()
+        )),
+        Some(libc::EACCES) | Some(libc::EPERM) => {
+            Error::PermissionDenied(path.display().to_string())
+        }
+        _ => err.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Create a host file, or `None` if the temp file system has no user
+    /// attribute support.
+    fn host(dir: &TempDir, name: &str, content: &[u8]) -> Option<std::path::PathBuf> {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        match set_attr(&path, "user.probe", b"1", false) {
+            Err(Error::Unsupported(_)) => None,
+            result => {
+                result.unwrap();
+                remove_attr(&path, "user.probe").unwrap();
+                Some(path)
+            }
+        }
+    }
+
+    /// List the names of a file's attributes in all namespaces visible to us.
+    fn list_attrs(path: &Path) -> Result<Vec<String>> {
+        let c_path = c_string(path.as_os_str().as_bytes())?;
+
+        loop {
+            let size = unsafe { libc::listxattr(c_path.as_ptr(), std::ptr::null_mut(), 0) };
+            if size < 0 {
+                return Err(attr_error(path, io::Error::last_os_error()));
+            }
+
+            let mut list = vec![0u8; size as usize];
+            let read = unsafe {
+                libc::listxattr(
+                    c_path.as_ptr(),
+                    list.as_mut_ptr() as *mut libc::c_char,
+                    list.len(),
+                )
+            };
+            if read >= 0 {
+                list.truncate(read as usize);
+                return Ok(list
+                    .split(|&b| b == 0)
+                    .filter(|name| !name.is_empty())
+                    .map(|name| String::from_utf8_lossy(name).into_owned())
+                    .collect());
+            }
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ERANGE) {
+                return Err(attr_error(path, err));
+            }
+        }
+    }
+
+    fn user_attrs(path: &Path) -> Vec<String> {
+        let mut names = list_attrs(path).unwrap();
+        names.retain(|name| name.starts_with("user."));
+        names
+    }
+
+    #[test]
+    fn test_round_trip_leaves_file_untouched() {
+        let dir = TempDir::new().unwrap();
+        let Some(path) = host(&dir, "photo.jpg", &[b'j'; 1234]) else {
+            eprintln!("user xattrs not supported, skipping");
+            return;
+        };
+        let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        let backend = XattrBackend::new(XattrNaming::Xdg);
+        let regions = backend.regions(&path).unwrap();
+        assert_eq!(regions.len(), XattrNaming::Xdg.names().len());
+        let region = &regions[0];
+        assert_eq!(region.logical_size, 1234);
+        assert!(region.available > 0);
+
+        backend.write_region(region, 10, b"hidden").unwrap();
+        assert_eq!(backend.read_region(region, 10, 6).unwrap(), b"hidden");
+        assert_eq!(backend.read_region(region, 0, 10).unwrap(), vec![0u8; 10]);
+
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.len(), 1234);
+        assert_eq!(metadata.modified().unwrap(), mtime);
+        assert_eq!(std::fs::read(&path).unwrap(), vec![b'j'; 1234]);
+        assert_eq!(user_attrs(&path), vec!["user.xdg.origin.url"]);
+
+        // Unwritten regions cannot be read
+        assert!(backend.read_region(&regions[1], 0, 6).is_err());
+
+        backend.wipe_region(region).unwrap();
+        assert!(user_attrs(&path).is_empty());
+    }
+
+    #[test]
+    fn test_fill_every_region() {
+        let dir = TempDir::new().unwrap();
+        let Some(path) = host(&dir, "notes.txt", b"notes") else {
+            eprintln!("user xattrs not supported, skipping");
+            return;
+        };
+
+        for naming in [
+            XattrNaming::Xdg,
+            XattrNaming::Dropbox,
+            XattrNaming::Rsync,
+            XattrNaming::Shatag,
+        ] {
+            let backend = XattrBackend::new(naming);
+            let regions = backend.regions(&path).unwrap();
+            let used: Vec<_> = regions.iter().filter(|r| r.available > 0).collect();
+            assert!(!used.is_empty());
+
+            // The computed sizes are what the file system accepts
+            for region in &used {
+                let data: Vec<u8> = (0..region.available).map(|i| (i % 253) as u8).collect();
+                backend.write_region(region, 0, &data).unwrap();
+            }
+            for region in &used {
+                let data: Vec<u8> = (0..region.available).map(|i| (i % 253) as u8).collect();
+                let read = backend.read_region(region, 0, data.len());
+                assert_eq!(read.unwrap(), data, "{:?}", naming);
+            }
+            let again = backend.regions(&path).unwrap();
+            let available = |regions: &[SlackRegion]| -> Vec<u64> {
+                regions.iter().map(|r| r.available).collect()
+            };
+            assert_eq!(available(&again), available(&regions), "{:?}", naming);
+
+            for region in used {
+                backend.wipe_region(region).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_foreign_attributes_keep_regions() {
+        let dir = TempDir::new().unwrap();
+        let Some(path) = host(&dir, "report.pdf", b"%PDF-1.7") else {
+            eprintln!("user xattrs not supported, skipping");
+            return;
+        };
+        let backend = XattrBackend::new(XattrNaming::Dropbox);
+        let before = backend.regions(&path).unwrap();
+        let end = before[0].available;
+        backend
+            .write_region(&before[0], end - 8, b"tail end")
+            .unwrap();
+
+        // Stored data stays where it was, whatever else is added
+        set_attr(&path, "user.mime_type", b"application/pdf", false).unwrap();
+        let after = backend.regions(&path).unwrap();
+        for (old, new) in before.iter().zip(&after) {
+            assert_eq!(new.available, old.available);
+            assert_eq!(new.block_size, old.block_size);
+        }
+        assert_eq!(
+            backend.read_region(&after[0], end - 8, 8).unwrap(),
+            b"tail end"
+        );
+        assert_eq!(
+            get_attr(&path, "user.mime_type").unwrap().unwrap(),
+            b"application/pdf"
+        );
+    }
+
+    #[test]
+    fn test_skips_names_other_applications_use() {
+        let dir = TempDir::new().unwrap();
+        let Some(path) = host(&dir, "page.html", b"<html>") else {
+            eprintln!("user xattrs not supported, skipping");
+            return;
+        };
+        let url = b"https://example.com/page.html";
+        set_attr(&path, "user.xdg.origin.url", url, false).unwrap();
+
+        let backend = XattrBackend::new(XattrNaming::Xdg);
+        let regions = backend.regions(&path).unwrap();
+        assert_eq!(regions[0].available, 0);
+
+        // Even a region listed before the name was taken leaves it alone
+        let stale = SlackRegion {
+            available: regions[0].block_size,
+            ..regions[0].clone()
+        };
+        assert!(backend.write_region(&stale, 0, b"hidden").is_err());
+        backend.wipe_region(&stale).unwrap();
+        assert_eq!(
+            get_attr(&path, "user.xdg.origin.url").unwrap().unwrap(),
+            url
+        );
+    }
+
+    #[test]
+    fn test_rejects_non_files() {
+        let dir = TempDir::new().unwrap();
+        let backend = XattrBackend::new(XattrNaming::Xdg);
+        assert!(matches!(
+            backend.regions(dir.path()),
+            Err(Error::NotAFile(_))
+        ));
+
+        let Some(target) = host(&dir, "target.txt", b"t") else {
+            return;
+        };
+        let link = dir.path().join("link.txt");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert!(matches!(backend.regions(&link), Err(Error::NotAFile(_))));
+    }
+
+    #[test]
+    fn test_vfs_on_xattrs() {
+        use crate::{SlackVfs, VfsConfig};
+
+        let dir = TempDir::new().unwrap();
+        let mut sizes = Vec::new();
+        for i in 0..8 {
+            let content = vec![b'd'; 100 + i];
+            if host(&dir, &format!("doc_{}.txt", i), &content).is_none() {
+                eprintln!("user xattrs not supported, skipping");
+                return;
+            }
+            sizes.push(content.len() as u64);
+        }
+
+        {
+            let backend = Box::new(XattrBackend::new(XattrNaming::Xdg));
+            let mut vfs =
+                SlackVfs::create_with_backend(dir.path(), "pw", VfsConfig::default(), backend)
+                    .unwrap();
+            assert_eq!(vfs.info().host_count, 8);
+            vfs.create_file("/secret.txt", b"stored in extended attributes")
+                .unwrap();
+        }
+
+        // A different scheme is a different backend
+        let backend = Box::new(XattrBackend::new(XattrNaming::Rsync));
+        assert!(matches!(
+            SlackVfs::mount_with_backend(dir.path(), "pw", backend),
+            Err(Error::Unsupported(_))
+        ));
+
+        // Attributes other applications add later cost nothing stored
+        for i in 0..8 {
+            let path = dir.path().join(format!("doc_{}.txt", i));
+            set_attr(&path, "user.mime_type", b"text/plain", false).unwrap();
+        }
+
+        let backend = Box::new(XattrBackend::new(XattrNaming::Xdg));
+        let vfs = SlackVfs::mount_with_backend(dir.path(), "pw", backend).unwrap();
+        assert_eq!(
+            vfs.read_file("/secret.txt").unwrap(),
+            b"stored in extended attributes"
+        );
+
+        for (i, size) in sizes.iter().enumerate() {
+            let path = dir.path().join(format!("doc_{}.txt", i));
+            assert_eq!(std::fs::metadata(path).unwrap().len(), *size);
+        }
+    }
+}
@@ -8,7 +8,7 @@ mod path;
 pub(crate) mod superblock;
 pub mod types;
 
-pub use operations::{HealthReport, SlackVfs};
+pub use operations::{CompactReport, HealthReport, SlackVfs};
 pub use path::VfsPath;
 pub use superblock::{HostAllocation, Superblock, SymbolAllocation};
 pub use types::{DirEntry, Inode, InodeId, InodeType};
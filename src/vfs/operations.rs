@@ -1,17 +1,20 @@
 //! VFS operations - the main interface.
 
-use crate::config::VfsConfig;
+use crate::config::{FaultTolerance, FitStrategy, PlacementPolicy, ShredPolicy, VfsConfig};
 use crate::crypto::{
     decrypt_data, decrypt_with_key, encrypt_data, encrypt_with_key, EncryptedData, KeyDerivation,
 };
 use crate::encoding::{decode, encode, EncodedData, EncodingSymbol};
 use crate::error::{Error, Result};
+use crate::storage::metadata::{METADATA_VERSION, REGION_OFFSETS_VERSION};
 use crate::storage::{
-    read_slack, wipe_slack, write_slack, HostManager, SlackMetadata,
+    Carrier, DomainKey, FileSlackBackend, HostManager, HostPolicy, SlackMetadata, SlackRegion,
+    SymbolLocation,
 };
 use crate::vfs::path::VfsPath;
 use crate::vfs::superblock::{Superblock, SymbolAllocation};
 use crate::vfs::types::{DirEntry, EncodingInfo, Inode, InodeId, ROOT_INODE_ID};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 /// Health report for the VFS.
@@ -23,6 +26,11 @@ pub struct HealthReport {
     pub recoverable_files: usize,
     /// Files with damage (path, percent symbols lost).
     pub damaged_files: Vec<(String, f32)>,
+    /// Failure domains each recoverable file can still lose (path,
+    /// domains). Files holding no symbols are left out.
+    pub fault_tolerance: Vec<(String, usize)>,
+    /// Losses the VFS was set up to survive.
+    pub target_tolerance: FaultTolerance,
     /// Total slack capacity.
     pub total_capacity: u64,
     /// Used slack capacity.
@@ -31,12 +39,46 @@ pub struct HealthReport {
     pub host_count: usize,
 }
 
+/// Outcome of compacting a VFS.
+#[derive(Debug, Clone)]
+pub struct CompactReport {
+    /// Symbols moved toward the start of their host's slack.
+    pub symbols_moved: usize,
+    /// Slack that nothing live was holding, such as symbols of failed
+    /// writes, now free again.
+    pub recovered: u64,
+    /// Freed slack overwritten as the shred policy says: what was
+    /// recovered and the old places of moved symbols.
+    pub wiped: u64,
+    /// Largest free extent of any region before compacting.
+    pub largest_free_before: u64,
+    /// Largest free extent of any region after compacting.
+    pub largest_free_after: u64,
+}
+
+/// Number of failure domains that can be lost, fullest first, while no
+/// more than `spare` symbols go with them.
+fn losable_domains(mut counts: Vec<usize>, mut spare: usize) -> usize {
+    counts.sort_unstable_by(|a, b| b.cmp(a));
+    let mut domains = 0;
+    for count in counts {
+        if count > spare {
+            break;
+        }
+        spare -= count;
+        domains += 1;
+    }
+    domains
+}
+
 /// The main Slack VFS interface.
 pub struct SlackVfs {
     /// VFS superblock.
     superblock: Superblock,
     /// Host file manager.
     host_manager: HostManager,
+    /// Storage backend for all slack I/O.
+    backend: Box<dyn Carrier>,
     /// Slack space metadata.
     metadata: SlackMetadata,
     /// Encryption key derived from password.
@@ -45,10 +87,14 @@ pub struct SlackVfs {
     host_dir: PathBuf,
     /// Whether there are unsaved changes.
     dirty: bool,
+    /// Slack of deleted files, given back once a superblock without them
+    /// has been committed.
+    released: Vec<SymbolAllocation>,
 }
 
 impl SlackVfs {
-    /// Create a new VFS in the given directory.
+    /// Create a new VFS in the given directory, storing data after the end
+    /// of host files (file-append mode).
     ///
     /// # Arguments
     ///
@@ -56,6 +102,25 @@ impl SlackVfs {
     /// * `password` - Password for encryption
     /// * `config` - VFS configuration
     pub fn create(host_dir: &Path, password: &str, config: VfsConfig) -> Result<Self> {
+        let backend = FileSlackBackend::new(config.block_size);
+        Self::create_with_backend(host_dir, password, config, Box::new(backend))
+    }
+
+    /// Create a new VFS whose symbols are stored through the given backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `host_dir` - Directory holding the bootstrap metadata; also the
+    ///   root the backend enumerates host files from
+    /// * `password` - Password for encryption
+    /// * `config` - VFS configuration
+    /// * `backend` - Storage backend for slack I/O
+    pub fn create_with_backend(
+        host_dir: &Path,
+        password: &str,
+        config: VfsConfig,
+        backend: Box<dyn Carrier>,
+    ) -> Result<Self> {
         config.validate().map_err(Error::InvalidPath)?;
 
         // Check if VFS already exists
@@ -64,11 +129,24 @@ impl SlackVfs {
             return Err(Error::AlreadyInitialized(host_dir.to_path_buf()));
         }
 
-        // Scan for host files
-        let host_manager = HostManager::scan(host_dir, config.block_size)?;
+        if !backend.is_available() {
+            return Err(Error::Unsupported(format!(
+                "{} backend is not available",
+                backend.name()
+            )));
+        }
+
+        // Scan for host files the policy allows
+        let mut host_manager = HostManager::scan_with_policy(
+            host_dir,
+            config.block_size,
+            backend.as_ref(),
+            &config.host_policy,
+        )?;
         if host_manager.host_count() == 0 {
             return Err(Error::NoHostFiles(host_dir.to_path_buf()));
         }
+        host_manager.set_fit_strategy(config.fit);
 
         // Create key derivation with random salt
         let kdf = KeyDerivation::new();
@@ -80,14 +158,20 @@ impl SlackVfs {
         // Create metadata with salt for later decryption
         let mut metadata = SlackMetadata::new(config.block_size);
         metadata.salt = Some(*kdf.salt());
+        metadata.backend = Some(backend.name().to_string());
+        if !config.host_policy.is_unrestricted() {
+            metadata.host_policy = Some(config.host_policy.clone());
+        }
 
         let mut vfs = Self {
             superblock,
             host_manager,
+            backend,
             metadata,
             key,
             host_dir: host_dir.to_path_buf(),
             dirty: true,
+            released: Vec::new(),
         };
 
         // Save initial state
@@ -96,43 +180,119 @@ impl SlackVfs {
         Ok(vfs)
     }
 
-    /// Mount an existing VFS.
+    /// Mount an existing file-append mode VFS.
     ///
     /// # Arguments
     ///
     /// * `host_dir` - Directory containing host files
     /// * `password` - Password for decryption
     pub fn mount(host_dir: &Path, password: &str) -> Result<Self> {
-        // Load metadata
         let metadata = SlackMetadata::load(host_dir)?;
+        let backend = FileSlackBackend::new(metadata.block_size);
+        Self::mount_with_backend(host_dir, password, Box::new(backend))
+    }
+
+    /// Mount an existing VFS whose symbols are stored through the given
+    /// backend. It must be the same kind of backend the VFS was created with.
+    ///
+    /// # Arguments
+    ///
+    /// * `host_dir` - Directory holding the bootstrap metadata
+    /// * `password` - Password for decryption
+    /// * `backend` - Storage backend for slack I/O
+    pub fn mount_with_backend(
+        host_dir: &Path,
+        password: &str,
+        backend: Box<dyn Carrier>,
+    ) -> Result<Self> {
+        // Load metadata. Fields older versions lack have defaults, and the
+        // next commit saves it in the current format.
+        let mut metadata = SlackMetadata::load(host_dir)?;
         if !metadata.is_initialized() {
             return Err(Error::NotInitialized(host_dir.to_path_buf()));
         }
+        if metadata.version > METADATA_VERSION {
+            return Err(Error::VersionMismatch {
+                expected: METADATA_VERSION,
+                found: metadata.version,
+            });
+        }
+        // Older metadata holds absolute offsets: read each superblock symbol
+        // as the start of a region until the superblock says where the
+        // host's slack begins
+        let absolute_offsets = metadata.version < REGION_OFFSETS_VERSION;
+        if absolute_offsets {
+            for loc in &mut metadata.superblock_symbols {
+                loc.logical_size = loc.offset;
+                loc.offset = 0;
+            }
+        }
+        metadata.version = METADATA_VERSION;
+
+        let created_with = metadata
+            .backend
+            .as_deref()
+            .unwrap_or(FileSlackBackend::NAME);
+        if created_with != backend.name() {
+            return Err(Error::Unsupported(format!(
+                "VFS was created with the {} backend, not {}",
+                created_with,
+                backend.name()
+            )));
+        }
 
-        // Scan host files
-        let mut host_manager = HostManager::scan(host_dir, metadata.block_size)?;
+        // Scan host files, under the policy the VFS was created with
+        let mut host_manager = HostManager::scan_with_policy(
+            host_dir,
+            metadata.block_size,
+            backend.as_ref(),
+            &metadata.host_policy.clone().unwrap_or_default(),
+        )?;
 
         // Read and decrypt superblock
-        let superblock = Self::read_superblock(&metadata, Some(&host_manager), password)?;
+        let superblock = Self::read_superblock(&metadata, backend.as_ref(), password)?;
+        if absolute_offsets {
+            Self::rebase_superblock_locations(&mut metadata.superblock_symbols, &superblock);
+        }
 
-        // Apply used slack from superblock (Files)
-        for (path, host_alloc) in &superblock.hosts {
-            host_manager.apply_used_slack(path, host_alloc.slack_used);
+        // Hosts we wrote to may have grown since the VFS was created; resolve
+        // their regions from the logical sizes recorded at write time. Hosts
+        // that have since disappeared are left out; hosts the policy no
+        // longer allows (writes change their size and age) are kept.
+        let recorded_sizes = superblock
+            .hosts
+            .iter()
+            .map(|(path, host_alloc)| (path, host_alloc.logical_size))
+            .chain(
+                metadata
+                    .superblock_symbols
+                    .iter()
+                    .map(|loc| (&loc.host_path, loc.logical_size)),
+            );
+        for (path, logical_size) in recorded_sizes.filter(|(_, size)| *size > 0) {
+            let _ = host_manager.pin_logical_size(path, logical_size, backend.as_ref());
+        }
+
+        // Free slack as of the last commit. The superblock's own symbols are
+        // only listed in the metadata; file symbols are reserved again in
+        // case the map and the symbol table ever disagree.
+        host_manager.set_fit_strategy(superblock.fit);
+        host_manager.apply_free_space(&superblock.free_space);
+        for sym in &superblock.symbols {
+            host_manager.reserve(
+                &sym.host_path,
+                sym.region_index,
+                sym.offset,
+                sym.length as u64,
+            );
         }
-        
-        // Apply used slack from metadata (Superblock itself)
-        // Note: apply_used_slack overwrites? No, it sets used_slack.
-        // We need to ADD.
-        // host_manager.apply_used_slack only sets.
-        // We should probably rely on `clean usage` -> `apply files` -> `add superblock`.
-        // Or better: HostManager tracks TOTAL usage.
-        // But `Superblock.hosts` only tracks FILE usage now (per my design).
-        // So we need to ADD simple logic here.
-        
         for loc in &metadata.superblock_symbols {
-             if let Some(host) = host_manager.get_host_mut(&loc.host_path) {
-                 host.used_slack += loc.length as u64;
-             }
+            host_manager.reserve(
+                &loc.host_path,
+                loc.region_index,
+                loc.offset,
+                loc.length as u64,
+            );
         }
 
         // Derive key
@@ -142,17 +302,42 @@ impl SlackVfs {
         Ok(Self {
             superblock,
             host_manager,
+            backend,
             metadata,
             key,
             host_dir: host_dir.to_path_buf(),
             dirty: false,
+            released: Vec::new(),
         })
     }
 
+    /// Make superblock locations read from metadata with absolute offsets
+    /// relative to their host's slack again. Slack starts at the logical
+    /// size the superblock recorded for the host or, on hosts holding only
+    /// superblock symbols, at the lowest of them.
+    fn rebase_superblock_locations(locations: &mut [SymbolLocation], superblock: &Superblock) {
+        let mut starts: HashMap<PathBuf, u64> = HashMap::new();
+        for loc in locations.iter() {
+            let absolute = loc.logical_size;
+            let start = superblock
+                .get_logical_size(&loc.host_path)
+                .map_or(absolute, |size| size.min(absolute));
+            starts
+                .entry(loc.host_path.clone())
+                .and_modify(|lowest| *lowest = (*lowest).min(start))
+                .or_insert(start);
+        }
+        for loc in locations {
+            let absolute = loc.logical_size + loc.offset;
+            loc.logical_size = starts[&loc.host_path];
+            loc.offset = absolute - loc.logical_size;
+        }
+    }
+
     /// Read and decrypt the superblock from slack space.
     fn read_superblock(
         metadata: &SlackMetadata,
-        _host_manager: Option<&HostManager>,
+        backend: &dyn Carrier,
         password: &str,
     ) -> Result<Superblock> {
         let salt = metadata
@@ -167,9 +352,16 @@ impl SlackVfs {
             let mut symbols = Vec::new();
             
             for loc in &metadata.superblock_symbols {
-                // Try read
-                // We use loc.length (u32)
-                if let Ok(data) = read_slack(&loc.host_path, loc.offset, loc.length as usize) {
+                // Unreadable symbols are left to erasure coding
+                let data = backend
+                    .regions_at(&loc.host_path, loc.logical_size)
+                    .and_then(|regions| {
+                        let region = regions
+                            .get(loc.region_index as usize)
+                            .ok_or_else(|| Error::HostFileNotFound(loc.host_path.clone()))?;
+                        backend.read_region(region, loc.offset, loc.length as usize)
+                    });
+                if let Some(data) = data.ok().filter(|d| d.len() == loc.length as usize) {
                     symbols.push(EncodingSymbol {
                          id: loc.symbol_id,
                          data,
@@ -200,33 +392,53 @@ impl SlackVfs {
     }
 
     /// Collect all symbols for a file from slack space using superblock.
+    ///
+    /// Symbols on hosts that are gone, unreadable or truncated are skipped;
+    /// erasure coding recovers the file if enough remain.
     fn collect_file_symbols(&self, file_id: InodeId) -> Result<Vec<EncodingSymbol>> {
         let stored_symbols = self.superblock.get_symbols_for_file(file_id);
         let mut symbols = Vec::new();
 
         for alloc in stored_symbols {
-            // Get logical size for this host
-            let logical_size = self
-                .superblock
-                .get_logical_size(&alloc.host_path)
-                .unwrap_or(0);
-
-            // Read symbol data from slack
-            let data = read_slack(
-                &alloc.host_path,
-                logical_size + alloc.offset,
-                alloc.length as usize,
-            )?;
-
-            symbols.push(EncodingSymbol {
-                id: alloc.symbol_id,
-                data,
-            });
+            if let Some(data) = self.read_symbol(alloc) {
+                symbols.push(EncodingSymbol {
+                    id: alloc.symbol_id,
+                    data,
+                });
+            }
         }
 
         Ok(symbols)
     }
 
+    /// Read one stored symbol, if its host still holds all of it.
+    fn read_symbol(&self, alloc: &SymbolAllocation) -> Option<Vec<u8>> {
+        let region = self
+            .host_region(&alloc.host_path, alloc.region_index)
+            .ok()?;
+        self.backend
+            .read_region(&region, alloc.offset, alloc.length as usize)
+            .ok()
+            .filter(|d| d.len() == alloc.length as usize)
+    }
+
+    /// Resolve a slack region of a host holding file symbols.
+    fn host_region(&self, path: &Path, region_index: u32) -> Result<SlackRegion> {
+        if let Some(host) = self.host_manager.get_region(path, region_index) {
+            return Ok(host.region.clone());
+        }
+
+        let logical_size = self
+            .superblock
+            .get_logical_size(path)
+            .ok_or_else(|| Error::HostFileNotFound(path.to_path_buf()))?;
+        self.backend
+            .regions_at(path, logical_size)?
+            .into_iter()
+            .nth(region_index as usize)
+            .ok_or_else(|| Error::HostFileNotFound(path.to_path_buf()))
+    }
+
     /// Write the superblock to slack space.
     fn write_superblock(&mut self) -> Result<()> {
         self.superblock.sequence_number += 1;
@@ -238,16 +450,50 @@ impl SlackVfs {
         }
         for sym in &self.superblock.symbols {
              // Get the correct logical_size from host_manager
-             let logical_size = self.host_manager.get_host(&sym.host_path)
+             let logical_size = self.host_manager.get_region(&sym.host_path, sym.region_index)
                  .map(|h| h.logical_size)
                  .unwrap_or(0);
              
              let host = self.superblock.hosts.entry(sym.host_path.clone())
                  .or_insert(crate::vfs::superblock::HostAllocation { logical_size, slack_used: 0 });
-             // Use high-water mark: next free offset is max(current_used, offset + length)
-             // This correctly handles non-contiguous allocations
-             host.slack_used = host.slack_used.max(sym.offset + sym.length as u64);
+             host.slack_used += sym.length as u64;
+        }
+
+        // Record the original size of every host in use (including those
+        // holding the previous superblock) so they can be found on remount
+        for host in self.host_manager.hosts() {
+            if host.used() > 0 {
+                self.superblock
+                    .set_logical_size(&host.path, host.logical_size);
+            }
+        }
+
+        // Free space to record: what is free now, plus the previous
+        // superblock and deleted files, which this superblock no longer needs
+        let mut free_space = self.host_manager.free_space();
+        let freed = self
+            .metadata
+            .superblock_symbols
+            .iter()
+            .map(|loc| (&loc.host_path, loc.region_index, loc.offset, loc.length))
+            .chain(
+                self.released
+                    .iter()
+                    .map(|sym| (&sym.host_path, sym.region_index, sym.offset, sym.length)),
+            );
+        for (path, region_index, offset, length) in freed {
+            if let Some(free) = free_space.get_mut(&(path.clone(), region_index)) {
+                free.release(offset, length as u64);
+            }
         }
+        // Regions left out are wholly free, which keeps the map small on
+        // carriers with many tiny regions
+        free_space.retain(|(path, region_index), free| {
+            self.host_manager
+                .get_region(path, *region_index)
+                .is_some_and(|host| free.total() < host.slack_capacity)
+        });
+        self.superblock.free_space = free_space;
 
         // Serialize
         let sb_bytes = self.superblock.to_bytes()?;
@@ -260,26 +506,16 @@ impl SlackVfs {
         let encoded = encode(&encrypted, &config)?;
         
         // Allocate space for symbols
-        let mut locations = self.host_manager.allocate(
-            encoded.symbols.len(),
-            encoded.symbol_size as usize,
-            0 // Start ID
-        )?;
+        let locations = self.allocate_symbols(&encoded, 0)?;
         
         // Write symbols
-        for (i, loc) in locations.iter_mut().enumerate() {
-            let symbol = &encoded.symbols[i];
-            
-            // Get logical size for absolute offset
-            let host_logical_size = self.host_manager.get_host(&loc.host_path)
-                .map(|h| h.logical_size)
+        for (loc, symbol) in locations.iter().zip(&encoded.symbols) {
+            let host = self
+                .host_manager
+                .get_region(&loc.host_path, loc.region_index)
                 .ok_or_else(|| Error::DataCorruption("Allocated on missing host".to_string()))?;
-
-            let absolute_offset = host_logical_size + loc.offset;
-            write_slack(&loc.host_path, &symbol.data, absolute_offset)?;
-            
-            // UPDATE to absolute offset for metadata persistence
-            loc.offset = absolute_offset;
+            self.backend
+                .write_region(&host.region, loc.offset, &symbol.data)?;
         }
         
         // Update Metadata
@@ -289,26 +525,50 @@ impl SlackVfs {
             repair_symbols: encoded.repair_symbols,
             symbol_size: encoded.symbol_size,
         });
-        self.metadata.superblock_symbols = locations;
-        
+        let previous = std::mem::replace(&mut self.metadata.superblock_symbols, locations);
+
+        // Nothing may point at symbols the backend still holds back
+        self.backend.flush()?;
+
         // Atomic Save
         self.metadata.save(&self.host_dir)?;
         
-        // Sync HostManager to reflect New SB Usage (Files + New SB)
-        // 1. Apply Files
-        for (path, host_alloc) in &self.superblock.hosts {
-            self.host_manager.apply_used_slack(path, host_alloc.slack_used);
-        }
-        // 2. Add New SB
-        for loc in &self.metadata.superblock_symbols {
-             if let Some(host) = self.host_manager.get_host_mut(&loc.host_path) {
-                 host.used_slack += loc.length as u64;
-             }
+        // Only now that nothing refers to them can the previous superblock
+        // and deleted files' slack be shredded and handed out again
+        let freed: Vec<_> = previous
+            .into_iter()
+            .map(|loc| (loc.host_path, loc.region_index, loc.offset, loc.length))
+            .chain(
+                self.released
+                    .drain(..)
+                    .map(|sym| (sym.host_path, sym.region_index, sym.offset, sym.length)),
+            )
+            .collect();
+        for (path, region_index, offset, length) in freed {
+            let length = length as u64;
+            self.shred(&path, region_index, offset, length)?;
+            self.host_manager
+                .release(&path, region_index, offset, length);
         }
+        self.backend.flush()?;
 
         Ok(())
     }
 
+    /// Overwrite freed slack as the VFS's shred policy says. The slack of
+    /// hosts that have gone is skipped.
+    fn shred(&self, path: &Path, region_index: u32, offset: u64, length: u64) -> Result<()> {
+        let policy = self.superblock.shred;
+        if policy == ShredPolicy::None {
+            return Ok(());
+        }
+        if let Some(host) = self.host_manager.get_region(path, region_index) {
+            self.backend
+                .wipe_range(&host.region, offset, length, policy)?;
+        }
+        Ok(())
+    }
+
     /// Sync all changes to disk.
     pub fn sync(&mut self) -> Result<()> {
         if !self.dirty {
@@ -399,11 +659,12 @@ impl SlackVfs {
         let encoded = encode(&encrypted_bytes, &config)?;
 
         // Allocate space and store symbols
+        let locations = self.allocate_symbols(&encoded, 0)?;
         let inode_id = self.superblock.alloc_inode_id();
 
         // Store each symbol
-        for symbol in &encoded.symbols {
-            self.store_symbol(symbol, inode_id)?;
+        for (symbol, location) in encoded.symbols.iter().zip(&locations) {
+            self.store_symbol(symbol, location, inode_id)?;
         }
 
         // Create inode
@@ -431,36 +692,61 @@ impl SlackVfs {
         Ok(inode_id)
     }
 
-    /// Store a single symbol in slack space.
-    fn store_symbol(&mut self, symbol: &EncodingSymbol, file_id: InodeId) -> Result<()> {
-        // Find a host with enough space
-        for host in self.host_manager.hosts_mut() {
-            if host.can_fit(symbol.data.len() as u64) {
-                let offset = host.allocate(symbol.data.len() as u64).unwrap();
-
-                // Write to slack
-                write_slack(&host.path, &symbol.data, host.logical_size + offset)?;
-
-                // Record in superblock
-                self.superblock.add_symbol(SymbolAllocation {
-                    symbol_id: symbol.id,
-                    host_path: host.path.clone(),
-                    offset,
-                    length: symbol.data.len() as u32,
-                    file_id,
-                });
+    /// Allocate slack for the symbols of an encoded object, spread by the
+    /// placement policy so that losing any of the tolerated failure domains
+    /// costs no more symbols than the object can afford.
+    fn allocate_symbols(
+        &mut self,
+        encoded: &EncodedData,
+        start_symbol_id: u32,
+    ) -> Result<Vec<SymbolLocation>> {
+        let tolerance = self.superblock.fault_tolerance;
+        if !encoded.symbols.is_empty() && encoded.repair_symbols < tolerance.losses {
+            return Err(Error::Encoding(format!(
+                "{} repair symbols cannot survive losing {}",
+                encoded.repair_symbols, tolerance
+            )));
+        }
+        self.host_manager.allocate(
+            encoded.symbols.len(),
+            encoded.symbol_size as usize,
+            start_symbol_id,
+            self.superblock.placement,
+            tolerance.domain,
+            tolerance.per_domain_cap(encoded.repair_symbols),
+        )
+    }
 
-                self.superblock
-                    .set_logical_size(&host.path, host.logical_size);
+    /// Store a single symbol at an allocated location in slack space.
+    fn store_symbol(
+        &mut self,
+        symbol: &EncodingSymbol,
+        location: &SymbolLocation,
+        file_id: InodeId,
+    ) -> Result<()> {
+        let host = self
+            .host_manager
+            .get_region(&location.host_path, location.region_index)
+            .ok_or_else(|| Error::DataCorruption("Allocated on missing host".to_string()))?;
+
+        // Write to slack
+        self.backend
+            .write_region(&host.region, location.offset, &symbol.data)?;
+
+        // Record in superblock
+        self.superblock.add_symbol(SymbolAllocation {
+            symbol_id: symbol.id,
+            host_path: host.path.clone(),
+            region_index: host.region_index,
+            offset: location.offset,
+            length: symbol.data.len() as u32,
+            file_id,
+        });
 
-                return Ok(());
-            }
-        }
+        self.superblock
+            .set_logical_size(&host.path, host.logical_size);
 
-        Err(Error::InsufficientSpace {
-            needed: symbol.data.len() as u64,
-            available: self.host_manager.total_available(),
-        })
+        Ok(())
     }
 
     /// Read a file from the VFS.
@@ -533,8 +819,10 @@ impl SlackVfs {
             .unwrap()
             .remove_child(inode_id);
 
-        // Remove symbols from superblock (updating host allocations)
-        self.superblock.remove_symbols_for_file(inode_id);
+        // Remove symbols from superblock (updating host allocations); their
+        // slack is freed when the next superblock is committed
+        let removed = self.superblock.remove_symbols_for_file(inode_id);
+        self.released.extend(removed);
 
         // Remove inode
         self.superblock.remove_inode(inode_id);
@@ -633,43 +921,85 @@ impl SlackVfs {
     }
 
     /// Get VFS health report.
+    ///
+    /// Besides which files can still be read, reports how many failure
+    /// domains each readable file can still lose, assuming the worst: the
+    /// domains holding most of its remaining symbols go first.
     pub fn health_check(&self) -> Result<HealthReport> {
+        let domain = self.superblock.fault_tolerance.domain;
+        let paths = self.inode_paths();
         let mut total_files = 0;
         let mut recoverable_files = 0;
         let mut damaged_files = Vec::new();
+        let mut fault_tolerance = Vec::new();
 
         for inode in self.superblock.inodes.values() {
             if inode.is_file() {
                 total_files += 1;
 
                 if let Some(encoding_info) = &inode.encoding_info {
-                    // Count available symbols
-                    let symbols = self.collect_file_symbols(inode.id)?;
+                    // Count readable symbols in each failure domain
+                    let mut per_domain: HashMap<DomainKey, usize> = HashMap::new();
+                    for alloc in self.superblock.get_symbols_for_file(inode.id) {
+                        if self.read_symbol(alloc).is_some() {
+                            let key = DomainKey::of(&alloc.host_path, domain);
+                            *per_domain.entry(key).or_default() += 1;
+                        }
+                    }
 
-                    let available = symbols.len();
+                    let available: usize = per_domain.values().sum();
                     let required = encoding_info.source_symbols;
+                    let path = paths[&inode.id].clone();
 
                     if available >= required {
                         recoverable_files += 1;
+                        if available > 0 {
+                            let counts = per_domain.into_values().collect();
+                            let tolerance = losable_domains(counts, available - required);
+                            fault_tolerance.push((path, tolerance));
+                        }
                     } else {
                         let loss_percent = (1.0 - available as f32 / required as f32) * 100.0;
-                        // Find path for this file (simplified - just use name)
-                        damaged_files.push((inode.name.clone(), loss_percent));
+                        damaged_files.push((path, loss_percent));
                     }
                 }
             }
         }
 
+        damaged_files.sort_by(|a, b| a.0.cmp(&b.0));
+        fault_tolerance.sort();
+
         Ok(HealthReport {
             total_files,
             recoverable_files,
             damaged_files,
+            fault_tolerance,
+            target_tolerance: self.superblock.fault_tolerance,
             total_capacity: self.host_manager.total_capacity(),
             used_capacity: self.host_manager.total_used(),
             host_count: self.host_manager.host_count(),
         })
     }
 
+    /// Get the full path of every inode.
+    fn inode_paths(&self) -> HashMap<InodeId, String> {
+        let mut paths = HashMap::new();
+        let mut pending = vec![(ROOT_INODE_ID, "/".to_string())];
+
+        while let Some((id, path)) = pending.pop() {
+            let children = self.superblock.get_inode(id).and_then(|i| i.children());
+            for &child_id in children.into_iter().flatten() {
+                if let Some(child) = self.superblock.get_inode(child_id) {
+                    let child_path = format!("{}/{}", path.trim_end_matches('/'), child.name);
+                    pending.push((child_id, child_path));
+                }
+            }
+            paths.insert(id, path);
+        }
+
+        paths
+    }
+
     /// Change the VFS password.
     pub fn change_password(&mut self, old_password: &str, new_password: &str) -> Result<()> {
         // Verify old password
@@ -694,14 +1024,185 @@ impl SlackVfs {
         Ok(())
     }
 
+    /// Move live symbols toward the start of each host's slack, free any
+    /// slack nothing live holds, and shred what was freed as the VFS's shred
+    /// policy says.
+    ///
+    /// Symbols are copied before a superblock pointing at their new place
+    /// is committed, and their old place is only reused once it has been,
+    /// so the VFS stays readable if compaction is interrupted.
+    pub fn compact(&mut self) -> Result<CompactReport> {
+        self.sync()?;
+        let largest_free_before = self.largest_free();
+
+        // Rebuild free space from the live file and superblock symbols
+        let available = self.host_manager.total_available();
+        let free_before = self.host_manager.free_space();
+        self.host_manager.reset_allocations();
+        for sym in &self.superblock.symbols {
+            self.host_manager.reserve(
+                &sym.host_path,
+                sym.region_index,
+                sym.offset,
+                sym.length as u64,
+            );
+        }
+        for loc in &self.metadata.superblock_symbols {
+            self.host_manager.reserve(
+                &loc.host_path,
+                loc.region_index,
+                loc.offset,
+                loc.length as u64,
+            );
+        }
+        let recovered = self.host_manager.total_available() - available;
+
+        // Recovered slack may still hold symbols of failed writes; shred it
+        // before moved symbols can take it
+        let mut freed = 0;
+        for host in self.host_manager.hosts() {
+            let Some(before) = free_before.get(&(host.path.clone(), host.region_index)) else {
+                continue;
+            };
+            let mut recovered = host.free.clone();
+            for extent in before.extents() {
+                recovered.reserve(extent.offset, extent.length);
+            }
+            for extent in recovered.extents() {
+                self.shred(&host.path, host.region_index, extent.offset, extent.length)?;
+                freed += extent.length;
+            }
+        }
+
+        // First fit keeps the superblock low too
+        self.host_manager.set_fit_strategy(FitStrategy::FirstFit);
+        let moved = self.compact_rounds();
+        self.host_manager.set_fit_strategy(self.superblock.fit);
+        let (symbols_moved, moved_bytes) = moved?;
+        freed += moved_bytes;
+
+        let wiped = if self.superblock.shred == ShredPolicy::None {
+            0
+        } else {
+            freed
+        };
+
+        Ok(CompactReport {
+            symbols_moved,
+            recovered,
+            wiped,
+            largest_free_before,
+            largest_free_after: self.largest_free(),
+        })
+    }
+
+    /// Move file symbols lower and commit, until nothing moves. Returns the
+    /// symbols moved and the bytes their old places freed.
+    fn compact_rounds(&mut self) -> Result<(usize, u64)> {
+        let mut symbols_moved = 0;
+        let mut bytes_moved = 0;
+        let mut settled = false;
+        loop {
+            let pending = self.released.len();
+            let moved = self.move_symbols_lower()?;
+            symbols_moved += moved;
+            bytes_moved += self.released[pending..]
+                .iter()
+                .map(|sym| sym.length as u64)
+                .sum::<u64>();
+
+            // Commit, freeing the old places for the next round
+            self.dirty = true;
+            self.sync()?;
+
+            if moved > 0 {
+                continue;
+            }
+            // The superblock was placed while its previous copy still held
+            // slack; once that is free, one more commit may move it lower
+            if settled || !self.superblock_can_go_lower() {
+                return Ok((symbols_moved, bytes_moved));
+            }
+            settled = true;
+        }
+    }
+
+    /// Check whether any superblock symbol would fit in free slack before
+    /// it in its region.
+    fn superblock_can_go_lower(&self) -> bool {
+        self.metadata.superblock_symbols.iter().any(|loc| {
+            self.host_manager
+                .get_region(&loc.host_path, loc.region_index)
+                .is_some_and(|host| {
+                    host.free
+                        .extents()
+                        .iter()
+                        .any(|e| e.offset < loc.offset && e.length >= loc.length as u64)
+                })
+        })
+    }
+
+    /// Copy every file symbol that can go lower in its host to the lowest
+    /// free place, leaving the old place to be released on commit.
+    fn move_symbols_lower(&mut self) -> Result<usize> {
+        // Lowest first, so each hole goes to the symbol nearest to it
+        let symbols = &self.superblock.symbols;
+        let mut order: Vec<usize> = (0..symbols.len()).collect();
+        order.sort_by_key(|&i| {
+            let sym = &symbols[i];
+            (&sym.host_path, sym.region_index, sym.offset)
+        });
+
+        let mut moved = 0;
+        for i in order {
+            let sym = self.superblock.symbols[i].clone();
+            let length = sym.length as u64;
+            let Some((region_index, offset)) = self.host_manager.allocate_lower(
+                &sym.host_path,
+                sym.region_index,
+                sym.offset,
+                length,
+            ) else {
+                continue;
+            };
+
+            // Symbols that cannot be read whole are left where they are
+            let Some(data) = self.read_symbol(&sym) else {
+                self.host_manager
+                    .release(&sym.host_path, region_index, offset, length);
+                continue;
+            };
+            let region = self.host_region(&sym.host_path, region_index)?;
+            self.backend.write_region(&region, offset, &data)?;
+
+            let new = &mut self.superblock.symbols[i];
+            new.region_index = region_index;
+            new.offset = offset;
+            self.released.push(sym);
+            moved += 1;
+        }
+        Ok(moved)
+    }
+
+    /// Get the largest free extent of any region.
+    fn largest_free(&self) -> u64 {
+        self.host_manager
+            .hosts()
+            .iter()
+            .map(|host| host.free.largest())
+            .max()
+            .unwrap_or(0)
+    }
+
     /// Securely wipe all VFS data.
     pub fn wipe(&mut self) -> Result<()> {
         // Wipe all host files' slack space
         for host in self.host_manager.hosts() {
-            if let Some(logical_size) = self.superblock.get_logical_size(&host.path) {
-                wipe_slack(&host.path, logical_size, None)?;
+            if host.used() > 0 || self.superblock.get_logical_size(&host.path).is_some() {
+                self.backend.wipe_region(&host.region)?;
             }
         }
+        self.backend.flush()?;
 
         // Delete the metadata file completely
         let metadata_path = self.host_dir.join(".slack_meta.json");
@@ -719,7 +1220,9 @@ impl SlackVfs {
     pub fn info(&self) -> VfsInfo {
         VfsInfo {
             host_dir: self.host_dir.clone(),
+            backend: self.backend.name(),
             host_count: self.host_manager.host_count(),
+            region_count: self.host_manager.region_count(),
             total_capacity: self.host_manager.total_capacity(),
             used_capacity: self.host_manager.total_used(),
             available_capacity: self.host_manager.total_available(),
@@ -728,6 +1231,11 @@ impl SlackVfs {
             total_file_size: self.superblock.total_size(),
             block_size: self.superblock.block_size,
             redundancy_ratio: self.superblock.redundancy_ratio,
+            placement: self.superblock.placement,
+            fault_tolerance: self.superblock.fault_tolerance,
+            fit: self.superblock.fit,
+            shred: self.superblock.shred,
+            host_policy: self.metadata.host_policy.clone().unwrap_or_default(),
         }
     }
 }
@@ -736,7 +1244,9 @@ impl SlackVfs {
 #[derive(Debug)]
 pub struct VfsInfo {
     pub host_dir: PathBuf,
+    pub backend: &'static str,
     pub host_count: usize,
+    pub region_count: usize,
     pub total_capacity: u64,
     pub used_capacity: u64,
     pub available_capacity: u64,
@@ -745,6 +1255,11 @@ pub struct VfsInfo {
     pub total_file_size: u64,
     pub block_size: u64,
     pub redundancy_ratio: f32,
+    pub placement: PlacementPolicy,
+    pub fault_tolerance: FaultTolerance,
+    pub fit: FitStrategy,
+    pub shred: ShredPolicy,
+    pub host_policy: HostPolicy,
 }
 
 impl Drop for SlackVfs {
@@ -872,6 +1387,315 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_write_after_remount() {
+        let dir = create_test_host_dir();
+        let password = "test_password";
+
+        {
+            let mut vfs = SlackVfs::create(dir.path(), password, VfsConfig::default()).unwrap();
+            vfs.create_file("/first.txt", b"First").unwrap();
+        }
+
+        // Hosts have grown past their original size; new symbols must still
+        // land in the original slack regions
+        {
+            let mut vfs = SlackVfs::mount(dir.path(), password).unwrap();
+            vfs.create_file("/second.txt", b"Second").unwrap();
+        }
+
+        let vfs = SlackVfs::mount(dir.path(), password).unwrap();
+        assert_eq!(vfs.read_file("/first.txt").unwrap(), b"First");
+        assert_eq!(vfs.read_file("/second.txt").unwrap(), b"Second");
+        for host in vfs.host_manager.hosts() {
+            assert_eq!(host.logical_size, 100);
+        }
+    }
+
+    #[test]
+    fn test_deleted_slack_is_reused() {
+        let dir = create_test_host_dir();
+        let data: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+
+        let mut vfs = SlackVfs::create(dir.path(), "pw", VfsConfig::default()).unwrap();
+        let baseline = vfs.host_manager.total_used();
+
+        // Far more slack is written over the cycles than the hosts hold
+        for cycle in 0..12 {
+            if cycle % 4 == 3 {
+                drop(vfs);
+                vfs = SlackVfs::mount(dir.path(), "pw").unwrap();
+            }
+            vfs.create_file("/churn.bin", &data).unwrap();
+            assert_eq!(vfs.read_file("/churn.bin").unwrap(), data);
+            vfs.delete_file("/churn.bin").unwrap();
+            assert!(vfs.host_manager.total_used() <= baseline + 2 * 1024);
+        }
+    }
+
+    #[test]
+    fn test_mount_metadata_versions() {
+        let dir = create_test_host_dir();
+        SlackVfs::create(dir.path(), "pw", VfsConfig::default()).unwrap();
+        let set_version = |version| {
+            let mut metadata = SlackMetadata::load(dir.path()).unwrap();
+            metadata.version = version;
+            metadata.save(dir.path()).unwrap();
+        };
+
+        // Older metadata is accepted and upgraded by the next commit
+        set_version(4);
+        {
+            let mut vfs = SlackVfs::mount(dir.path(), "pw").unwrap();
+            vfs.create_file("/a.txt", b"data").unwrap();
+        }
+        let metadata = SlackMetadata::load(dir.path()).unwrap();
+        assert_eq!(metadata.version, METADATA_VERSION);
+
+        set_version(METADATA_VERSION + 1);
+        assert!(matches!(
+            SlackVfs::mount(dir.path(), "pw"),
+            Err(Error::VersionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_mount_absolute_superblock_offsets() {
+        let dir = create_test_host_dir();
+        {
+            let mut vfs = SlackVfs::create(dir.path(), "pw", VfsConfig::default()).unwrap();
+            vfs.create_file("/a.txt", b"Before").unwrap();
+        }
+
+        // Rewrite the metadata as version 3 recorded it
+        let mut metadata = SlackMetadata::load(dir.path()).unwrap();
+        metadata.version = REGION_OFFSETS_VERSION - 1;
+        for loc in &mut metadata.superblock_symbols {
+            loc.offset += loc.logical_size;
+            loc.logical_size = 0;
+        }
+        metadata.save(dir.path()).unwrap();
+
+        {
+            let mut vfs = SlackVfs::mount(dir.path(), "pw").unwrap();
+            assert_eq!(vfs.read_file("/a.txt").unwrap(), b"Before");
+            vfs.create_file("/b.txt", b"After").unwrap();
+        }
+        let metadata = SlackMetadata::load(dir.path()).unwrap();
+        assert_eq!(metadata.version, METADATA_VERSION);
+        let symbols = &metadata.superblock_symbols;
+        assert!(symbols.iter().all(|loc| loc.logical_size > 0));
+
+        let vfs = SlackVfs::mount(dir.path(), "pw").unwrap();
+        assert_eq!(vfs.read_file("/a.txt").unwrap(), b"Before");
+        assert_eq!(vfs.read_file("/b.txt").unwrap(), b"After");
+    }
+
+    #[test]
+    fn test_free_space_survives_remount() {
+        let dir = create_test_host_dir();
+        let free_space = {
+            let mut vfs = SlackVfs::create(dir.path(), "pw", VfsConfig::default()).unwrap();
+            vfs.create_file("/a.txt", b"First").unwrap();
+            vfs.create_file("/b.txt", b"Second").unwrap();
+            vfs.delete_file("/a.txt").unwrap();
+            vfs.host_manager.free_space()
+        };
+
+        let vfs = SlackVfs::mount(dir.path(), "pw").unwrap();
+        assert_eq!(vfs.host_manager.free_space(), free_space);
+        assert_eq!(vfs.read_file("/b.txt").unwrap(), b"Second");
+    }
+
+    #[test]
+    fn test_host_policy_applied_on_create_and_mount() {
+        let dir = create_test_host_dir();
+        std::fs::write(dir.path().join("server.log"), vec![0u8; 100]).unwrap();
+        let config = VfsConfig {
+            host_policy: HostPolicy {
+                exclude: vec!["*.log".to_string()],
+                max_size: Some(1000),
+                ..HostPolicy::default()
+            },
+            ..VfsConfig::default()
+        };
+        let data: Vec<u8> = (0..2000).map(|i| (i % 251) as u8).collect();
+
+        {
+            let mut vfs = SlackVfs::create(dir.path(), "pw", config.clone()).unwrap();
+            assert_eq!(vfs.host_manager.host_count(), 5);
+            vfs.create_file("/a.bin", &data).unwrap();
+        }
+        // Writing grew some hosts past the size limit
+        assert!(host_bytes(&dir) > 5 * 1000);
+        std::fs::write(dir.path().join("late.log"), vec![0u8; 100]).unwrap();
+        std::fs::write(dir.path().join("late.dat"), vec![0u8; 100]).unwrap();
+
+        let vfs = SlackVfs::mount(dir.path(), "pw").unwrap();
+        assert_eq!(vfs.info().host_policy, config.host_policy);
+        // The new .dat joins; hosts holding symbols stay whatever their size
+        let hosts: std::collections::HashSet<_> =
+            vfs.host_manager.hosts().iter().map(|h| &h.path).collect();
+        assert_eq!(hosts.len(), 6);
+        assert!(hosts.contains(&dir.path().join("late.dat")));
+        assert!(hosts.iter().all(|path| path.extension().unwrap() == "dat"));
+        assert_eq!(vfs.read_file("/a.bin").unwrap(), data);
+    }
+
+    fn host_bytes(dir: &TempDir) -> u64 {
+        (0..5)
+            .map(|i| {
+                let path = dir.path().join(format!("host_{}.dat", i));
+                std::fs::metadata(path).unwrap().len()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_compact_moves_symbols_down() {
+        let dir = create_test_host_dir();
+        let data: Vec<u8> = (0..900).map(|i| (i % 251) as u8).collect();
+
+        let mut vfs = SlackVfs::create(dir.path(), "pw", VfsConfig::default()).unwrap();
+        vfs.create_file("/a.bin", &data).unwrap();
+        vfs.create_file("/b.bin", &data).unwrap();
+        vfs.create_file("/keep.txt", b"Keep me").unwrap();
+        vfs.delete_file("/a.bin").unwrap();
+        vfs.delete_file("/b.bin").unwrap();
+
+        let highest = |vfs: &SlackVfs| vfs.superblock.symbols.iter().map(|s| s.offset).max();
+        let before = highest(&vfs).unwrap();
+        let bytes_before = host_bytes(&dir);
+
+        let report = vfs.compact().unwrap();
+        assert!(report.symbols_moved > 0);
+        assert!(report.wiped > 0);
+        assert!(report.largest_free_after >= report.largest_free_before);
+        assert!(highest(&vfs).unwrap() < before);
+        // Shredding the freed places never grows file-append hosts
+        assert!(host_bytes(&dir) <= bytes_before);
+        assert_eq!(vfs.read_file("/keep.txt").unwrap(), b"Keep me");
+
+        drop(vfs);
+        let mut vfs = SlackVfs::mount(dir.path(), "pw").unwrap();
+        assert_eq!(vfs.read_file("/keep.txt").unwrap(), b"Keep me");
+        let report = vfs.compact().unwrap();
+        assert_eq!(report.symbols_moved, 0);
+        assert_eq!(report.recovered, 0);
+        vfs.create_file("/b.bin", &data).unwrap();
+        assert_eq!(vfs.read_file("/b.bin").unwrap(), data);
+    }
+
+    #[test]
+    fn test_compact_recovers_leaked_slack() {
+        use crate::config::ShredPolicy;
+
+        for shred in [ShredPolicy::None, ShredPolicy::Zero] {
+            let dir = create_test_host_dir();
+            let config = VfsConfig {
+                shred,
+                ..VfsConfig::default()
+            };
+            let mut vfs = SlackVfs::create(dir.path(), "pw", config).unwrap();
+            vfs.create_file("/keep.txt", b"Keep me").unwrap();
+
+            // Slack taken by a write that never made it into the superblock
+            let path = vfs.host_manager.hosts()[0].path.clone();
+            let region = vfs.host_region(&path, 0).unwrap();
+            vfs.backend
+                .write_region(&region, 3000, &[0xAB; 500])
+                .unwrap();
+            vfs.host_manager.reserve(&path, 0, 3000, 500);
+            let bytes_before = host_bytes(&dir);
+
+            let report = vfs.compact().unwrap();
+            assert_eq!(report.recovered, 500);
+            assert_eq!(vfs.read_file("/keep.txt").unwrap(), b"Keep me");
+            // Only the freed slack is shredded, and hosts never grow for it
+            assert!(host_bytes(&dir) <= bytes_before);
+            let leaked = vfs.backend.read_region(&region, 3000, 500).unwrap();
+            match shred {
+                ShredPolicy::None => {
+                    assert_eq!(report.wiped, 0);
+                    assert_eq!(leaked, [0xAB; 500]);
+                }
+                _ => {
+                    assert_eq!(report.wiped, 500);
+                    assert!(leaked.iter().all(|&b| b == 0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_freed_symbols_shredded() {
+        use crate::config::ShredPolicy;
+
+        let data: Vec<u8> = (0..900).map(|i| (i % 251) as u8).collect();
+        for shred in [ShredPolicy::None, ShredPolicy::Zero, ShredPolicy::Random] {
+            let dir = create_test_host_dir();
+            let config = VfsConfig {
+                shred,
+                ..VfsConfig::default()
+            };
+            let mut vfs = SlackVfs::create(dir.path(), "pw", config).unwrap();
+            vfs.create_file("/keep.txt", b"Keep me").unwrap();
+            vfs.create_file("/gone.bin", &data).unwrap();
+
+            let raw = |vfs: &SlackVfs, path: &Path, region_index, offset, length| {
+                let region = vfs.host_region(path, region_index).unwrap();
+                vfs.backend.read_region(&region, offset, length).unwrap()
+            };
+            let file_id = vfs
+                .resolve_path(&VfsPath::parse("/gone.bin").unwrap())
+                .unwrap();
+            let symbols: Vec<_> = vfs
+                .superblock
+                .symbols
+                .iter()
+                .filter(|s| s.file_id == file_id)
+                .map(|s| {
+                    (
+                        s.clone(),
+                        raw(&vfs, &s.host_path, s.region_index, s.offset, 1024),
+                    )
+                })
+                .collect();
+            let superblock: Vec<_> = vfs
+                .metadata
+                .superblock_symbols
+                .iter()
+                .map(|l| {
+                    (
+                        l.clone(),
+                        raw(&vfs, &l.host_path, l.region_index, l.offset, 1024),
+                    )
+                })
+                .collect();
+
+            // Deleting frees the file's symbols and the previous superblock
+            vfs.delete_file("/gone.bin").unwrap();
+            let freed = symbols
+                .iter()
+                .map(|(s, old)| (&s.host_path, s.region_index, s.offset, old))
+                .chain(
+                    superblock
+                        .iter()
+                        .map(|(l, old)| (&l.host_path, l.region_index, l.offset, old)),
+                );
+            for (path, region_index, offset, old) in freed {
+                let now = raw(&vfs, path, region_index, offset, 1024);
+                match shred {
+                    ShredPolicy::None => assert_eq!(&now, old),
+                    // File-append hosts lose a shredded tail altogether
+                    ShredPolicy::Zero => assert!(now.iter().all(|&b| b == 0)),
+                    _ => assert!(now.is_empty() || &now != old),
+                }
+            }
+            assert_eq!(vfs.read_file("/keep.txt").unwrap(), b"Keep me");
+        }
+    }
+
     #[test]
     fn test_wrong_password() {
         let dir = create_test_host_dir();
@@ -887,4 +1711,135 @@ mod tests {
         let result = SlackVfs::mount(dir.path(), "wrong_password");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_file_survives_losing_any_host() {
+        let data: Vec<u8> = (0..2000).map(|i| (i % 251) as u8).collect();
+        for placement in [
+            PlacementPolicy::RoundRobin,
+            PlacementPolicy::FewestPerHost,
+            PlacementPolicy::WeightedByFree,
+        ] {
+            let dir = create_test_host_dir();
+            let config = VfsConfig {
+                symbol_size: 256,
+                placement,
+                ..VfsConfig::default()
+            };
+
+            let busiest = {
+                let mut vfs = SlackVfs::create(dir.path(), "pw", config).unwrap();
+                let id = vfs.create_file("/data.bin", &data).unwrap();
+                let budget = vfs
+                    .stat("/data.bin")
+                    .unwrap()
+                    .encoding_info
+                    .unwrap()
+                    .repair_symbols;
+
+                let mut counts: HashMap<PathBuf, usize> = HashMap::new();
+                for sym in vfs.superblock.symbols.iter().filter(|s| s.file_id == id) {
+                    *counts.entry(sym.host_path.clone()).or_default() += 1;
+                }
+                assert!(counts.len() > 1);
+                assert!(counts.values().all(|&n| n <= budget), "{:?}", placement);
+                counts.into_iter().max_by_key(|(_, n)| *n).unwrap().0
+            };
+
+            // Cut the host holding most of the file back to its own data
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(&busiest)
+                .unwrap()
+                .set_len(100)
+                .unwrap();
+
+            let vfs = SlackVfs::mount(dir.path(), "pw").unwrap();
+            assert_eq!(vfs.read_file("/data.bin").unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_survives_any_two_lost_hosts() {
+        use crate::config::{FailureDomain, FaultTolerance};
+
+        let dir = TempDir::new().unwrap();
+        for i in 0..8 {
+            std::fs::write(dir.path().join(format!("host_{}.dat", i)), [0u8; 100]).unwrap();
+        }
+        let config = VfsConfig {
+            symbol_size: 256,
+            fault_tolerance: FaultTolerance {
+                domain: FailureDomain::Host,
+                losses: 2,
+            },
+            ..VfsConfig::default()
+        };
+        let data: Vec<u8> = (0..2000).map(|i| (i % 251) as u8).collect();
+
+        let mut busiest = {
+            let mut vfs = SlackVfs::create(dir.path(), "pw", config).unwrap();
+            let id = vfs.create_file("/data.bin", &data).unwrap();
+            vfs.create_file("/tiny.txt", b"x").unwrap();
+
+            let report = vfs.health_check().unwrap();
+            assert_eq!(report.target_tolerance.losses, 2);
+            assert_eq!(report.fault_tolerance.len(), 2);
+            assert!(report.fault_tolerance.iter().all(|(_, n)| *n >= 2));
+
+            let mut counts: HashMap<PathBuf, usize> = HashMap::new();
+            for sym in vfs.superblock.symbols.iter().filter(|s| s.file_id == id) {
+                *counts.entry(sym.host_path.clone()).or_default() += 1;
+            }
+            let mut counts: Vec<_> = counts.into_iter().collect();
+            counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+            counts
+        };
+
+        // Cut the two hosts holding most of the file back to their own data
+        busiest.truncate(2);
+        for (path, _) in &busiest {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_len(100)
+                .unwrap();
+        }
+
+        let vfs = SlackVfs::mount(dir.path(), "pw").unwrap();
+        assert_eq!(vfs.read_file("/data.bin").unwrap(), data);
+        assert_eq!(vfs.read_file("/tiny.txt").unwrap(), b"x");
+        let report = vfs.health_check().unwrap();
+        assert_eq!(report.recoverable_files, 2);
+        let (path, left) = &report.fault_tolerance[0];
+        assert_eq!(path, "/data.bin");
+        assert!(*left < 2);
+    }
+
+    #[test]
+    fn test_too_few_failure_domains() {
+        use crate::config::{FailureDomain, FaultTolerance};
+
+        // All five hosts share one directory
+        let dir = create_test_host_dir();
+        let config = VfsConfig {
+            fault_tolerance: FaultTolerance {
+                domain: FailureDomain::Directory,
+                losses: 1,
+            },
+            ..VfsConfig::default()
+        };
+        let result = SlackVfs::create(dir.path(), "pw", config);
+        assert!(matches!(result, Err(Error::InsufficientHosts { .. })));
+    }
+
+    #[test]
+    fn test_losable_domains() {
+        assert_eq!(losable_domains(vec![2, 3, 1], 0), 0);
+        assert_eq!(losable_domains(vec![2, 3, 1], 3), 1);
+        assert_eq!(losable_domains(vec![2, 3, 1], 4), 1);
+        assert_eq!(losable_domains(vec![2, 3, 1], 5), 2);
+        assert_eq!(losable_domains(vec![2, 3, 1], 6), 3);
+    }
 }
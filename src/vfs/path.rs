@@ -78,15 +78,6 @@ impl VfsPath {
         Ok(Self { components })
     }
 
-    /// Convert to string representation.
-    pub fn to_string(&self) -> String {
-        if self.is_root() {
-            "/".to_string()
-        } else {
-            format!("/{}", self.components.join("/"))
-        }
-    }
-
     /// Get the depth of this path.
     pub fn depth(&self) -> usize {
         self.components.len()
@@ -95,7 +86,11 @@ impl VfsPath {
 
 impl std::fmt::Display for VfsPath {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_string())
+        if self.is_root() {
+            write!(f, "/")
+        } else {
+            write!(f, "/{}", self.components.join("/"))
+        }
     }
 }
 
@@ -1,8 +1,13 @@
 //! VFS superblock - the root metadata structure.
 
-use crate::config::{EncodingConfig, VfsConfig, VFS_MAGIC, VFS_VERSION};
+use crate::config::{
+    EncodingConfig, FaultTolerance, FitStrategy, PlacementPolicy, ShredPolicy, VfsConfig,
+    VFS_MAGIC, VFS_VERSION,
+};
 use crate::error::{Error, Result};
+use crate::storage::FreeExtents;
 use crate::vfs::types::{Inode, InodeId, ROOT_INODE_ID};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
@@ -12,7 +17,7 @@ use std::path::PathBuf;
 pub struct HostAllocation {
     /// Original logical size of the file (before hidden data).
     pub logical_size: u64,
-    /// Total slack space used in this host.
+    /// Total slack space used in this host, across all its regions.
     pub slack_used: u64,
 }
 
@@ -23,7 +28,9 @@ pub struct SymbolAllocation {
     pub symbol_id: u32,
     /// Path to the host file.
     pub host_path: PathBuf,
-    /// Offset within slack space.
+    /// Index of the region in the carrier's list for the host.
+    pub region_index: u32,
+    /// Offset within the region.
     pub offset: u64,
     /// Length of symbol data.
     pub length: u32,
@@ -31,6 +38,17 @@ pub struct SymbolAllocation {
     pub file_id: InodeId,
 }
 
+/// A symbol as stored by version 1 superblocks, before carriers had more
+/// than one region per host.
+#[derive(Deserialize)]
+struct SymbolAllocationV1 {
+    symbol_id: u32,
+    host_path: PathBuf,
+    offset: u64,
+    length: u32,
+    file_id: InodeId,
+}
+
 /// The superblock contains all VFS metadata.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Superblock {
@@ -44,6 +62,18 @@ pub struct Superblock {
     pub redundancy_ratio: f32,
     /// Symbol size for encoding.
     pub symbol_size: u16,
+    /// How symbols are spread over host files.
+    pub placement: PlacementPolicy,
+    /// Failure domains every file must be able to lose.
+    pub fault_tolerance: FaultTolerance,
+    /// How free slack is picked for new symbols.
+    pub fit: FitStrategy,
+    /// How freed slack is overwritten.
+    pub shred: ShredPolicy,
+    /// Free extents of regions holding file symbols, by host and region
+    /// index; regions not listed are wholly free. The superblock's own
+    /// symbols are listed in the bootstrap metadata instead.
+    pub free_space: HashMap<(PathBuf, u32), FreeExtents>,
     /// Root inode ID.
     pub root_inode: InodeId,
     /// Next available inode ID.
@@ -77,6 +107,11 @@ impl Superblock {
             block_size: config.block_size,
             redundancy_ratio: config.redundancy_ratio,
             symbol_size: config.symbol_size,
+            placement: config.placement,
+            fault_tolerance: config.fault_tolerance,
+            fit: config.fit,
+            shred: config.shred,
+            free_space: HashMap::new(),
             root_inode: ROOT_INODE_ID,
             next_inode_id: 1,
             inodes,
@@ -94,12 +129,17 @@ impl Superblock {
         if self.magic != VFS_MAGIC {
             return Err(Error::InvalidMagic);
         }
-        if self.version != VFS_VERSION {
+        if self.version > VFS_VERSION {
             return Err(Error::VersionMismatch {
                 expected: VFS_VERSION,
                 found: self.version,
             });
         }
+        if self.symbol_size == 0 {
+            return Err(Error::DataCorruption(
+                "Superblock symbol size is 0".to_string(),
+            ));
+        }
         Ok(())
     }
 
@@ -147,6 +187,7 @@ impl Superblock {
         EncodingConfig {
             symbol_size: self.symbol_size,
             redundancy_ratio: self.redundancy_ratio,
+            min_repair_symbols: self.fault_tolerance.losses,
         }
     }
 
@@ -156,13 +197,111 @@ impl Superblock {
     }
 
     /// Deserialize from bytes.
+    ///
+    /// Superblocks written by older versions are upgraded as they are
+    /// read: settings they predate take their defaults, and the next commit
+    /// writes them back in the current layout. Newer versions are refused.
     pub fn from_bytes(data: &[u8]) -> Result<Self> {
-        let sb: Superblock =
+        let (magic, version): ([u8; 4], u32) =
             bincode::deserialize(data).map_err(|e| Error::Serialization(e.to_string()))?;
+        if magic != VFS_MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+
+        let sb: Superblock = match version {
+            VFS_VERSION => {
+                bincode::deserialize(data).map_err(|e| Error::Serialization(e.to_string()))?
+            }
+            1..VFS_VERSION => Self::from_older_bytes(version, data)?,
+            _ => {
+                return Err(Error::VersionMismatch {
+                    expected: VFS_VERSION,
+                    found: version,
+                })
+            }
+        };
         sb.validate()?;
         Ok(sb)
     }
 
+    /// Decode the layout of an older version field by field.
+    ///
+    /// Version 2 added region indexes to symbols (region 0 before), 3 the
+    /// placement policy, 4 fault tolerance, 5 the fit strategy and free
+    /// space map, and 6 the shred policy. An empty free space map is safe:
+    /// mounting reserves every symbol again.
+    fn from_older_bytes(version: u32, data: &[u8]) -> Result<Self> {
+        fn field<T: DeserializeOwned>(reader: &mut &[u8]) -> Result<T> {
+            bincode::deserialize_from(reader).map_err(|e| Error::Serialization(e.to_string()))
+        }
+        fn field_since<T: DeserializeOwned + Default>(
+            reader: &mut &[u8],
+            version: u32,
+            since: u32,
+        ) -> Result<T> {
+            if version >= since {
+                field(reader)
+            } else {
+                Ok(T::default())
+            }
+        }
+
+        let reader = &mut &data[..];
+        let magic = field(reader)?;
+        let _version: u32 = field(reader)?;
+        let block_size = field(reader)?;
+        let redundancy_ratio = field(reader)?;
+        let symbol_size = field(reader)?;
+        let placement = field_since(reader, version, 3)?;
+        let fault_tolerance = field_since(reader, version, 4)?;
+        let fit = field_since(reader, version, 5)?;
+        let shred = field_since(reader, version, 6)?;
+        let free_space = field_since(reader, version, 5)?;
+        let root_inode = field(reader)?;
+        let next_inode_id = field(reader)?;
+        let inodes = field(reader)?;
+        let salt = field(reader)?;
+        let next_symbol_id = field(reader)?;
+        let hosts = field(reader)?;
+        let symbols = if version >= 2 {
+            field(reader)?
+        } else {
+            field::<Vec<SymbolAllocationV1>>(reader)?
+                .into_iter()
+                .map(|sym| SymbolAllocation {
+                    symbol_id: sym.symbol_id,
+                    host_path: sym.host_path,
+                    region_index: 0,
+                    offset: sym.offset,
+                    length: sym.length,
+                    file_id: sym.file_id,
+                })
+                .collect()
+        };
+
+        Ok(Self {
+            magic,
+            version: VFS_VERSION,
+            block_size,
+            redundancy_ratio,
+            symbol_size,
+            placement,
+            fault_tolerance,
+            fit,
+            shred,
+            free_space,
+            root_inode,
+            next_inode_id,
+            inodes,
+            salt,
+            next_symbol_id,
+            hosts,
+            symbols,
+            sequence_number: field(reader)?,
+            uuid: field(reader)?,
+        })
+    }
+
     /// Get total number of files.
     pub fn file_count(&self) -> usize {
         self.inodes.values().filter(|i| i.is_file()).count()
@@ -214,8 +353,8 @@ impl Superblock {
             .collect()
     }
 
-    /// Remove all symbols for a specific file.
-    pub fn remove_symbols_for_file(&mut self, file_id: InodeId) {
+    /// Remove all symbols for a specific file, returning them.
+    pub fn remove_symbols_for_file(&mut self, file_id: InodeId) -> Vec<SymbolAllocation> {
         // Update host slack usage
         for symbol in self.symbols.iter().filter(|s| s.file_id == file_id) {
             if let Some(host) = self.hosts.get_mut(&symbol.host_path) {
@@ -224,11 +363,16 @@ impl Superblock {
         }
 
         // Remove symbols
-        self.symbols.retain(|s| s.file_id != file_id);
+        let (removed, kept) = std::mem::take(&mut self.symbols)
+            .into_iter()
+            .partition(|s| s.file_id == file_id);
+        self.symbols = kept;
 
         // Clean up hosts with no symbols
         self.hosts
             .retain(|_, h| h.slack_used > 0 || h.logical_size > 0);
+
+        removed
     }
 
     /// Get logical size for a host file.
@@ -257,6 +401,7 @@ impl Superblock {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::Path;
 
     #[test]
     fn test_new_superblock() {
@@ -299,6 +444,39 @@ mod tests {
         assert_eq!(restored.inodes.len(), sb.inodes.len());
     }
 
+    #[test]
+    fn test_used_slack() {
+        let config = VfsConfig::default();
+        let mut sb = Superblock::new(&config, [0u8; 32]);
+        for (region_index, offset) in [(0, 0), (0, 100), (1, 0)] {
+            sb.add_symbol(SymbolAllocation {
+                symbol_id: sb.next_symbol_id,
+                host_path: PathBuf::from("/host"),
+                region_index,
+                offset,
+                length: 100,
+                file_id: 1,
+            });
+        }
+        assert_eq!(sb.get_used_slack(Path::new("/host")), 300);
+
+        sb.remove_symbols_for_file(1);
+        assert_eq!(sb.get_used_slack(Path::new("/host")), 0);
+    }
+
+    #[test]
+    fn test_free_space_round_trip() {
+        let config = VfsConfig::default();
+        let mut sb = Superblock::new(&config, [0u8; 32]);
+        let mut free = FreeExtents::new(1000);
+        free.reserve(100, 200);
+        sb.free_space
+            .insert((PathBuf::from("/host"), 1), free.clone());
+
+        let restored = Superblock::from_bytes(&sb.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.free_space[&(PathBuf::from("/host"), 1)], free);
+    }
+
     #[test]
     fn test_validate_bad_magic() {
         let config = VfsConfig::default();
@@ -308,4 +486,67 @@ mod tests {
 
         assert!(matches!(sb.validate(), Err(Error::InvalidMagic)));
     }
+
+    #[test]
+    fn test_validate_zero_symbol_size() {
+        let mut sb = Superblock::new(&VfsConfig::default(), [0u8; 32]);
+        sb.symbol_size = 0;
+
+        let bytes = sb.to_bytes().unwrap();
+        assert!(matches!(
+            Superblock::from_bytes(&bytes),
+            Err(Error::DataCorruption(_))
+        ));
+    }
+
+    #[test]
+    fn test_upgrade_version_1() {
+        let config = VfsConfig::default();
+        let mut sb = Superblock::new(&config, [7u8; 32]);
+        let file = Inode::new_file(sb.alloc_inode_id(), "a.txt".to_string(), 10);
+        sb.insert_inode(file);
+        let host = PathBuf::from("/host");
+        sb.hosts.insert(
+            host.clone(),
+            HostAllocation {
+                logical_size: 100,
+                slack_used: 64,
+            },
+        );
+
+        // The version 1 layout: no policies, no free space map and symbols
+        // without a region index
+        let symbols = vec![(3u32, host.clone(), 200u64, 64u32, 1 as InodeId)];
+        let header = (VFS_MAGIC, 1u32, sb.block_size, sb.redundancy_ratio);
+        let bytes = bincode::serialize(&(
+            (header, sb.symbol_size),
+            (sb.root_inode, sb.next_inode_id, &sb.inodes, sb.salt, 4u32),
+            (&sb.hosts, symbols, 9u64, sb.uuid),
+        ))
+        .unwrap();
+
+        let upgraded = Superblock::from_bytes(&bytes).unwrap();
+        assert_eq!(upgraded.version, VFS_VERSION);
+        assert_eq!(upgraded.inodes.len(), 2);
+        assert_eq!(upgraded.hosts[&host].slack_used, 64);
+        assert_eq!(upgraded.symbols[0].region_index, 0);
+        assert_eq!(upgraded.symbols[0].offset, 200);
+        assert_eq!(upgraded.sequence_number, 9);
+        assert_eq!(upgraded.uuid, sb.uuid);
+        assert_eq!(upgraded.placement, PlacementPolicy::default());
+        assert_eq!(upgraded.shred, ShredPolicy::default());
+        assert!(upgraded.free_space.is_empty());
+    }
+
+    #[test]
+    fn test_refuse_newer_version() {
+        let config = VfsConfig::default();
+        let mut sb = Superblock::new(&config, [0u8; 32]);
+        sb.version = VFS_VERSION + 1;
+
+        assert!(matches!(
+            Superblock::from_bytes(&sb.to_bytes().unwrap()),
+            Err(Error::VersionMismatch { found, .. }) if found == VFS_VERSION + 1
+        ));
+    }
 }
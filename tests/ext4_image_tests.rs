@@ -0,0 +1,433 @@
+//! Ext4 image backend tests against images built by `mkfs.ext4`.
+//!
+//! These tests need `mkfs.ext4` (e2fsprogs) but no root privileges or
+//! mounting. They are skipped when the tool is not installed.
+
+#![cfg(target_os = "linux")]
+
+use slack_vfs::storage::linux::{
+    Ext4DirentPaddingBackend, Ext4ImageBackend, Ext4InodePaddingBackend,
+};
+use slack_vfs::storage::{Carrier, SlackBackend};
+use slack_vfs::{Error, SlackVfs, VfsConfig};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::TempDir;
+
+/// Build an ext4 image populated from `src` with `mkfs.ext4 -d`.
+///
+/// Returns `None` if `mkfs.ext4` is not available.
+fn mkfs_ext4(dir: &TempDir, src: &Path, extra_args: &[&str]) -> Option<std::path::PathBuf> {
+    let image = dir.path().join("fs.img");
+    fs::File::create(&image).unwrap();
+    let status = Command::new("mkfs.ext4")
+        .args(["-q", "-F"])
+        .args(extra_args)
+        .arg("-d")
+        .arg(src)
+        .arg(&image)
+        .arg("8M")
+        .status()
+        .ok()?;
+    assert!(status.success(), "mkfs.ext4 failed");
+    Some(image)
+}
+
+/// Rebuild directory indexes with `e2fsck -fyD` so large directories use
+/// htrees. Returns false if `e2fsck` is not available.
+fn index_directories(image: &Path) -> bool {
+    Command::new("e2fsck")
+        .args(["-f", "-y", "-D"])
+        .arg(image)
+        .output()
+        .is_ok()
+}
+
+/// Run a read-only `e2fsck` and report whether the image is clean.
+fn fsck_clean(image: &Path) -> bool {
+    Command::new("e2fsck")
+        .args(["-f", "-n"])
+        .arg(image)
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(true)
+}
+
+fn populate(dir: &TempDir) -> std::path::PathBuf {
+    let src = dir.path().join("src");
+    fs::create_dir_all(src.join("docs/nested")).unwrap();
+    fs::write(src.join("readme.txt"), b"hello ext4").unwrap();
+    fs::write(src.join("docs/report.bin"), vec![0xABu8; 5000]).unwrap();
+    fs::write(src.join("docs/nested/deep.txt"), vec![b'd'; 700]).unwrap();
+    src
+}
+
+#[test]
+fn test_image_slack_round_trip() {
+    let dir = TempDir::new().unwrap();
+    let src = populate(&dir);
+    let Some(image) = mkfs_ext4(&dir, &src, &[]) else {
+        eprintln!("mkfs.ext4 not available, skipping");
+        return;
+    };
+
+    let backend = Ext4ImageBackend::open(&image).unwrap();
+    let region = backend
+        .get_slack_info(Path::new("/docs/report.bin"))
+        .unwrap();
+
+    assert_eq!(region.logical_size, 5000);
+    assert_eq!(
+        region.available,
+        region.block_size - (5000 % region.block_size)
+    );
+
+    let hidden = b"hidden in ext4 slack";
+    backend.write_slack(&region, 0, hidden).unwrap();
+    assert_eq!(
+        backend.read_slack(&region, 0, hidden.len()).unwrap(),
+        hidden
+    );
+
+    // Host content directly before the slack is untouched
+    let raw = fs::read(&image).unwrap();
+    let tail_start = (region.offset - 100) as usize;
+    assert_eq!(&raw[tail_start..region.offset as usize], &[0xABu8; 100][..]);
+
+    assert!(fsck_clean(&image), "image must stay consistent");
+}
+
+#[test]
+fn test_image_nested_lookup() {
+    let dir = TempDir::new().unwrap();
+    let src = populate(&dir);
+    let Some(image) = mkfs_ext4(&dir, &src, &[]) else {
+        eprintln!("mkfs.ext4 not available, skipping");
+        return;
+    };
+
+    let backend = Ext4ImageBackend::open(&image).unwrap();
+    let region = backend
+        .get_slack_info(Path::new("/docs/nested/deep.txt"))
+        .unwrap();
+    assert_eq!(region.logical_size, 700);
+
+    let raw = fs::read(&image).unwrap();
+    let data_start = (region.offset - region.logical_size) as usize;
+    assert_eq!(&raw[data_start..data_start + 700], &[b'd'; 700][..]);
+}
+
+#[test]
+fn test_image_htree_directory() {
+    let dir = TempDir::new().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(src.join("big")).unwrap();
+    // Enough entries for an indexed directory, few enough to stay within
+    // the extents that fit in the inode
+    for i in 0..150 {
+        fs::write(
+            src.join(format!("big/host_file_with_a_long_name_{}.txt", i)),
+            format!("content {}", i),
+        )
+        .unwrap();
+    }
+    let Some(image) = mkfs_ext4(&dir, &src, &["-b", "4096"]) else {
+        eprintln!("mkfs.ext4 not available, skipping");
+        return;
+    };
+    if !index_directories(&image) {
+        eprintln!("e2fsck not available, skipping");
+        return;
+    }
+
+    let backend = Ext4ImageBackend::open(&image).unwrap();
+    let parser = backend.parser();
+    let big = parser.lookup(Path::new("/big")).unwrap();
+    assert_ne!(
+        parser.read_inode(big).unwrap().flags & 0x1000,
+        0,
+        "directory should be htree-indexed"
+    );
+    let entries = parser.read_dir(big).unwrap();
+    assert_eq!(entries.iter().filter(|e| !e.is_dot()).count(), 150);
+
+    let region = backend
+        .get_slack_info(Path::new("/big/host_file_with_a_long_name_123.txt"))
+        .unwrap();
+    assert_eq!(region.logical_size, "content 123".len() as u64);
+}
+
+#[test]
+fn test_image_candidate_hosts() {
+    let dir = TempDir::new().unwrap();
+    let src = populate(&dir);
+    fs::write(src.join("empty.txt"), b"").unwrap();
+    let Some(image) = mkfs_ext4(&dir, &src, &[]) else {
+        eprintln!("mkfs.ext4 not available, skipping");
+        return;
+    };
+
+    let backend = Ext4ImageBackend::open(&image).unwrap();
+    let hosts: Vec<_> = backend
+        .candidate_hosts()
+        .unwrap()
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+
+    assert_eq!(
+        hosts,
+        vec![
+            Path::new("/docs/nested/deep.txt"),
+            Path::new("/docs/report.bin"),
+            Path::new("/readme.txt"),
+        ]
+    );
+}
+
+#[test]
+fn test_image_multi_level_extent_directory() {
+    let dir = TempDir::new().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(src.join("big")).unwrap();
+    // Rebuilding the index of a directory this large scatters its blocks
+    // over more extents than fit in the inode
+    for i in 0..300 {
+        fs::write(
+            src.join(format!("big/host_file_with_a_long_name_{}.txt", i)),
+            format!("content {}", i),
+        )
+        .unwrap();
+    }
+    let Some(image) = mkfs_ext4(&dir, &src, &["-b", "4096"]) else {
+        eprintln!("mkfs.ext4 not available, skipping");
+        return;
+    };
+
+    if !index_directories(&image) {
+        eprintln!("e2fsck not available, skipping");
+        return;
+    }
+
+    let backend = Ext4ImageBackend::open(&image).unwrap();
+    let parser = backend.parser();
+    let big = parser
+        .read_inode(parser.lookup(Path::new("/big")).unwrap())
+        .unwrap();
+    assert!(
+        big.extents.len() > 4,
+        "directory should need an extent tree"
+    );
+
+    let hosts = backend.candidate_hosts().unwrap();
+    assert_eq!(hosts.len(), 300);
+}
+
+#[test]
+fn test_image_ext3_block_map() {
+    let dir = TempDir::new().unwrap();
+    let src = populate(&dir);
+    let Some(image) = mkfs_ext4(&dir, &src, &["-t", "ext3"]) else {
+        eprintln!("mkfs.ext4 not available, skipping");
+        return;
+    };
+
+    let backend = Ext4ImageBackend::open(&image).unwrap();
+    let parser = backend.parser();
+    let inode = parser
+        .read_inode(parser.lookup(Path::new("/docs/report.bin")).unwrap())
+        .unwrap();
+    assert_eq!(inode.flags & 0x80000, 0, "ext3 files use block maps");
+
+    let region = backend
+        .get_slack_info(Path::new("/docs/report.bin"))
+        .unwrap();
+    assert_eq!(region.logical_size, 5000);
+
+    let hidden = b"hidden in ext3 slack";
+    backend.write_slack(&region, 0, hidden).unwrap();
+    assert_eq!(
+        backend.read_slack(&region, 0, hidden.len()).unwrap(),
+        hidden
+    );
+
+    let raw = fs::read(&image).unwrap();
+    let tail_start = (region.offset - 100) as usize;
+    assert_eq!(&raw[tail_start..region.offset as usize], &[0xABu8; 100][..]);
+
+    assert!(fsck_clean(&image), "image must stay consistent");
+}
+
+#[test]
+fn test_image_inline_data() {
+    let dir = TempDir::new().unwrap();
+    let src = populate(&dir);
+    let Some(image) = mkfs_ext4(&dir, &src, &["-O", "inline_data"]) else {
+        eprintln!("mkfs.ext4 not available, skipping");
+        return;
+    };
+
+    let backend = Ext4ImageBackend::open(&image).unwrap();
+    let parser = backend.parser();
+    let inode = parser
+        .read_inode(parser.lookup(Path::new("/readme.txt")).unwrap())
+        .unwrap();
+    assert!(inode.has_inline_data());
+    assert_eq!(&inode.inline_data[..10], b"hello ext4");
+
+    // Inline files have no slack, block-backed files still do
+    assert_eq!(
+        backend
+            .get_slack_info(Path::new("/readme.txt"))
+            .unwrap()
+            .available,
+        0
+    );
+    let hosts: Vec<_> = backend
+        .candidate_hosts()
+        .unwrap()
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    assert_eq!(
+        hosts,
+        vec![
+            Path::new("/docs/nested/deep.txt"),
+            Path::new("/docs/report.bin"),
+        ]
+    );
+}
+
+#[test]
+fn test_vfs_on_image_backend() {
+    let dir = TempDir::new().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    for i in 0..8 {
+        fs::write(src.join(format!("host_{}.txt", i)), vec![b'h'; 300 + i]).unwrap();
+    }
+    let Some(image) = mkfs_ext4(&dir, &src, &["-b", "4096"]) else {
+        eprintln!("mkfs.ext4 not available, skipping");
+        return;
+    };
+    let meta_dir = dir.path().join("meta");
+    fs::create_dir(&meta_dir).unwrap();
+    let image_size = fs::metadata(&image).unwrap().len();
+
+    {
+        let backend = Box::new(Ext4ImageBackend::open(&image).unwrap());
+        let mut vfs =
+            SlackVfs::create_with_backend(&meta_dir, "pw", VfsConfig::default(), backend).unwrap();
+        assert_eq!(vfs.info().host_count, 8);
+        vfs.create_file("/secret.txt", b"stored inside the image")
+            .unwrap();
+    }
+
+    let backend = Box::new(Ext4ImageBackend::open(&image).unwrap());
+    let vfs = SlackVfs::mount_with_backend(&meta_dir, "pw", backend).unwrap();
+    assert_eq!(
+        vfs.read_file("/secret.txt").unwrap(),
+        b"stored inside the image"
+    );
+    drop(vfs);
+
+    // Slack writes never change the image or its files' sizes
+    assert_eq!(fs::metadata(&image).unwrap().len(), image_size);
+    assert!(fsck_clean(&image), "image must stay consistent");
+
+    // The metadata records the backend, so file-append mode refuses it
+    assert!(matches!(
+        SlackVfs::mount(&meta_dir, "pw"),
+        Err(Error::Unsupported(_))
+    ));
+}
+
+#[test]
+fn test_inode_padding_keeps_checksums_valid() {
+    let dir = TempDir::new().unwrap();
+    let src = populate(&dir);
+    fs::write(src.join("empty.txt"), b"").unwrap();
+    let Some(image) = mkfs_ext4(&dir, &src, &["-I", "256", "-O", "metadata_csum"]) else {
+        eprintln!("mkfs.ext4 not available, skipping");
+        return;
+    };
+
+    let backend = Ext4InodePaddingBackend::open(&image).unwrap();
+    assert!(backend.parser().superblock().has_metadata_csum());
+
+    // Every file qualifies, including the empty one
+    let hosts = backend.candidate_hosts().unwrap();
+    assert_eq!(hosts.len(), 4);
+
+    for (i, (_, region)) in hosts.iter().enumerate() {
+        let data = vec![i as u8 + 1; region.available as usize];
+        backend.write_slack(region, 0, &data).unwrap();
+        assert_eq!(backend.read_slack(region, 0, data.len()).unwrap(), data);
+    }
+
+    assert!(fsck_clean(&image), "inode checksums must stay valid");
+
+    let parser = backend.parser();
+    let inode = parser
+        .read_inode(parser.lookup(Path::new("/docs/report.bin")).unwrap())
+        .unwrap();
+    assert_eq!(inode.size, 5000);
+}
+
+#[test]
+fn test_dirent_padding_keeps_checksums_valid() {
+    let dir = TempDir::new().unwrap();
+    let src = populate(&dir);
+    fs::create_dir(src.join("big")).unwrap();
+    for i in 0..300 {
+        fs::write(
+            src.join(format!("big/entry_with_a_long_name_{}.txt", i)),
+            b"x",
+        )
+        .unwrap();
+    }
+    let Some(image) = mkfs_ext4(&dir, &src, &["-O", "metadata_csum"]) else {
+        eprintln!("mkfs.ext4 not available, skipping");
+        return;
+    };
+    if !index_directories(&image) {
+        eprintln!("e2fsck not available, skipping");
+        return;
+    }
+
+    let backend = Ext4DirentPaddingBackend::open(&image).unwrap();
+    assert!(backend.parser().superblock().has_metadata_csum());
+    let big = backend.parser().lookup(Path::new("/big")).unwrap();
+    assert_ne!(
+        backend.parser().read_inode(big).unwrap().flags & 0x1000,
+        0,
+        "directory should be htree-indexed"
+    );
+
+    // Root, lost+found, big, docs and docs/nested
+    let hosts = backend.candidate_hosts().unwrap();
+    assert_eq!(hosts.len(), 5);
+
+    // Every leaf block of the indexed directory is a region of its own
+    let regions: Vec<_> = hosts.iter().flat_map(|(_, regions)| regions).collect();
+    assert!(regions.len() > hosts.len());
+
+    for (i, region) in regions.into_iter().enumerate() {
+        let data = vec![i as u8 + 1; region.available as usize];
+        backend.write_region(region, 0, &data).unwrap();
+        assert_eq!(backend.read_region(region, 0, data.len()).unwrap(), data);
+    }
+
+    assert!(
+        fsck_clean(&image),
+        "directory block checksums must stay valid"
+    );
+
+    let entries = backend.parser().read_dir(big).unwrap();
+    assert_eq!(entries.iter().filter(|e| !e.is_dot()).count(), 300);
+    assert!(backend
+        .parser()
+        .lookup(Path::new("/docs/nested/deep.txt"))
+        .is_ok());
+}
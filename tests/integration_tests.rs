@@ -3,6 +3,7 @@
 use slack_vfs::config::VfsConfig;
 use slack_vfs::vfs::SlackVfs;
 use std::fs;
+use std::path::Path;
 use tempfile::TempDir;
 
 /// Helper to create a test environment with host files.
@@ -275,3 +276,22 @@ fn test_empty_file() {
         .expect("Failed to read empty file");
     assert!(content.is_empty());
 }
+
+/// The VFS in `tests/fixtures/baseline_vfs` (password "baseline") was made
+/// by the first release, whose metadata records absolute superblock offsets.
+/// Its host paths are relative to the crate root, so it is read in place.
+#[test]
+fn test_mount_first_release_vfs() {
+    let host_path = Path::new("tests/fixtures/baseline_vfs");
+    let vfs = SlackVfs::mount(host_path, "baseline").expect("Failed to mount VFS");
+
+    assert_eq!(
+        vfs.read_file("/hello.txt").expect("Failed to read file"),
+        b"Written before the upgrade"
+    );
+    let note: Vec<u8> = (0..1500).map(|i| ((i * 31) % 251) as u8).collect();
+    let read = vfs
+        .read_file("/docs/note.bin")
+        .expect("Failed to read file");
+    assert_eq!(read, note);
+}
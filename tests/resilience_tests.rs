@@ -1,9 +1,12 @@
 //! Resilience tests - simulate data loss and verify recovery.
 
 use slack_vfs::config::VfsConfig;
+use slack_vfs::storage::{MemorySlackBackend, SlackMetadata};
 use slack_vfs::vfs::SlackVfs;
+use std::collections::HashSet;
 use std::fs;
 use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
 use tempfile::TempDir;
 
 /// Helper to create a test environment with host files.
@@ -29,7 +32,7 @@ fn corrupt_slack_space(file_path: &std::path::Path, block_size: u64, corruption_
     let file_size = metadata.len();
 
     // Calculate where slack space starts
-    let blocks = (file_size + block_size - 1) / block_size;
+    let blocks = file_size.div_ceil(block_size);
     let allocated_size = blocks * block_size;
     let slack_start = file_size;
 
@@ -267,7 +270,7 @@ fn test_superblock_survives_with_encryption() {
     // Remount multiple times to verify superblock persistence
     for i in 0..3 {
         let vfs = SlackVfs::mount(host_path, password)
-            .expect(&format!("Failed to mount VFS on iteration {}", i));
+            .unwrap_or_else(|_| panic!("Failed to mount VFS on iteration {}", i));
 
         let entries = vfs.list_dir("/").expect("Failed to list root");
         assert!(
@@ -319,8 +322,10 @@ fn test_superblock_partial_recovery() {
     let password = "ec_test";
 
     // Create VFS with high redundancy
-    let mut config = VfsConfig::default();
-    config.redundancy_ratio = 1.0; // 100% redundancy (double symbols)
+    let config = VfsConfig {
+        redundancy_ratio: 1.0, // 100% redundancy (double symbols)
+        ..VfsConfig::default()
+    };
     
     let mut vfs = SlackVfs::create(host_path, password, config).expect("Created");
     vfs.sync().expect("Synced");
@@ -337,7 +342,7 @@ fn test_superblock_partial_recovery() {
     
     for i in 0..symbols_to_corrupt {
         let loc = &meta.superblock_symbols[i];
-         overwrite_slack_portion(&loc.host_path, loc.offset, 0, &[0u8; 50]);
+        overwrite_slack_portion(&loc.host_path, loc.logical_size, loc.offset, &[0u8; 50]);
     }
     
     // Try to mount - should succeed via EC recovery
@@ -347,7 +352,7 @@ fn test_superblock_partial_recovery() {
     // Corrupt 90% of symbols (beyond recovery)
     for i in 0..(total_symbols - 1) { // Leave 1 symbol
         let loc = &meta.superblock_symbols[i];
-         overwrite_slack_portion(&loc.host_path, loc.offset, 0, &[0u8; 50]);
+        overwrite_slack_portion(&loc.host_path, loc.logical_size, loc.offset, &[0u8; 50]);
     }
     
     // Try to mount - should fail
@@ -424,3 +429,136 @@ fn test_superblock_discovery_after_rename() {
     );
     */
 }
+
+/// Create an in-memory backend with `num_hosts` virtual hosts, plus a
+/// directory for the bootstrap metadata.
+fn setup_memory_env(num_hosts: usize) -> (TempDir, MemorySlackBackend) {
+    let meta_dir = TempDir::new().expect("Failed to create temp dir");
+    let backend = MemorySlackBackend::new();
+    for i in 0..num_hosts {
+        backend.add_host(format!("host_{:02}.dat", i), 100 + (i as u64 * 7), 4096);
+    }
+    (meta_dir, backend)
+}
+
+/// Hosts that hold symbols but none of the current superblock's.
+fn file_only_hosts(meta_dir: &TempDir, backend: &MemorySlackBackend) -> Vec<PathBuf> {
+    let meta = SlackMetadata::load(meta_dir.path()).expect("Loaded metadata");
+    let superblock_hosts: HashSet<_> = meta
+        .superblock_symbols
+        .iter()
+        .map(|loc| loc.host_path.clone())
+        .collect();
+
+    backend
+        .host_paths()
+        .into_iter()
+        .filter(|path| !superblock_hosts.contains(path))
+        .filter(|path| backend.slack_bytes(path).unwrap().iter().any(|&b| b != 0))
+        .collect()
+}
+
+fn write_memory_vfs(meta_dir: &TempDir, backend: &MemorySlackBackend, content: &[u8]) {
+    let mut vfs = SlackVfs::create_with_backend(
+        meta_dir.path(),
+        "memory_test",
+        VfsConfig::default(),
+        Box::new(backend.clone()),
+    )
+    .expect("Failed to create VFS");
+    vfs.create_file("/data.bin", content)
+        .expect("Failed to create file");
+}
+
+fn mount_memory_vfs(meta_dir: &TempDir, backend: &MemorySlackBackend) -> SlackVfs {
+    SlackVfs::mount_with_backend(meta_dir.path(), "memory_test", Box::new(backend.clone()))
+        .expect("Failed to mount VFS")
+}
+
+#[test]
+fn test_memory_backend_round_trip() {
+    let (meta_dir, backend) = setup_memory_env(10);
+    let content: Vec<u8> = (0..8000).map(|i| (i % 253) as u8).collect();
+    write_memory_vfs(&meta_dir, &backend, &content);
+
+    let vfs = mount_memory_vfs(&meta_dir, &backend);
+    assert_eq!(vfs.read_file("/data.bin").unwrap(), content);
+
+    // Host content is never touched
+    for (i, path) in backend.host_paths().iter().enumerate() {
+        assert_eq!(backend.logical_size(path), Some(100 + i as u64 * 7));
+    }
+}
+
+#[test]
+fn test_memory_recovery_after_host_deleted() {
+    let (meta_dir, backend) = setup_memory_env(10);
+    let content: Vec<u8> = (0..8000).map(|i| (i % 253) as u8).collect();
+    write_memory_vfs(&meta_dir, &backend, &content);
+
+    let victims = file_only_hosts(&meta_dir, &backend);
+    assert!(
+        !victims.is_empty(),
+        "file symbols should span several hosts"
+    );
+    backend.delete(&victims[0]).unwrap();
+
+    let vfs = mount_memory_vfs(&meta_dir, &backend);
+    let health = vfs.health_check().unwrap();
+    assert_eq!(health.recoverable_files, 1);
+    assert_eq!(vfs.read_file("/data.bin").unwrap(), content);
+}
+
+#[test]
+fn test_memory_recovery_after_host_truncated() {
+    let (meta_dir, backend) = setup_memory_env(10);
+    let content: Vec<u8> = (0..8000).map(|i| (i % 253) as u8).collect();
+    write_memory_vfs(&meta_dir, &backend, &content);
+
+    // Truncating to zero frees the block; its symbols read back short
+    let victims = file_only_hosts(&meta_dir, &backend);
+    backend.truncate(&victims[0], 0).unwrap();
+
+    let vfs = mount_memory_vfs(&meta_dir, &backend);
+    assert_eq!(vfs.read_file("/data.bin").unwrap(), content);
+}
+
+#[test]
+fn test_memory_too_many_hosts_deleted() {
+    let (meta_dir, backend) = setup_memory_env(10);
+    let content: Vec<u8> = (0..8000).map(|i| (i % 253) as u8).collect();
+    write_memory_vfs(&meta_dir, &backend, &content);
+
+    for path in file_only_hosts(&meta_dir, &backend) {
+        backend.delete(&path).unwrap();
+    }
+
+    let vfs = mount_memory_vfs(&meta_dir, &backend);
+    let health = vfs.health_check().unwrap();
+    assert_eq!(health.total_files, 1);
+    assert_eq!(health.recoverable_files, 0);
+    assert_eq!(health.damaged_files.len(), 1);
+    assert!(vfs.read_file("/data.bin").is_err());
+}
+
+#[test]
+fn test_memory_append_to_unused_host() {
+    let (meta_dir, backend) = setup_memory_env(12);
+    write_memory_vfs(&meta_dir, &backend, b"small file");
+
+    // Hosts without symbols can change freely
+    let unused: Vec<_> = backend
+        .host_paths()
+        .into_iter()
+        .filter(|path| backend.slack_bytes(path).unwrap().iter().all(|&b| b == 0))
+        .collect();
+    assert!(!unused.is_empty());
+    for path in &unused {
+        backend.append(path, b"user edits").unwrap();
+    }
+
+    let mut vfs = mount_memory_vfs(&meta_dir, &backend);
+    assert_eq!(vfs.read_file("/data.bin").unwrap(), b"small file");
+    vfs.create_file("/second.bin", b"after edits").unwrap();
+    assert_eq!(vfs.read_file("/second.bin").unwrap(), b"after edits");
+}
@@ -0,0 +1,239 @@
+//! XFS image backend tests against images built by `mkfs.xfs`.
+//!
+//! Images are populated from a protofile, so these tests need `mkfs.xfs`
+//! (xfsprogs) but no root privileges or mounting. They are skipped when the
+//! tool is not installed.
+
+#![cfg(target_os = "linux")]
+
+use slack_vfs::storage::linux::XfsImageBackend;
+use slack_vfs::storage::SlackBackend;
+use slack_vfs::{SlackVfs, VfsConfig};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+
+/// mkfs.xfs refuses file systems below 300 MiB; the image stays sparse.
+const IMAGE_SIZE: u64 = 320 * 1024 * 1024;
+
+/// Write protofile lines for the contents of `dir`.
+fn proto_entries(dir: &Path, out: &mut String) {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        let name = path.file_name().unwrap().to_string_lossy();
+        if path.is_dir() {
+            writeln!(out, "{} d--755 0 0", name).unwrap();
+            proto_entries(&path, out);
+            writeln!(out, "$").unwrap();
+        } else {
+            writeln!(out, "{} ---644 0 0 {}", name, path.display()).unwrap();
+        }
+    }
+}
+
+/// Build an XFS image populated from `src` with `mkfs.xfs -p`.
+///
+/// Returns `None` if `mkfs.xfs` is not available.
+fn mkfs_xfs(dir: &TempDir, src: &Path, extra_args: &[&str]) -> Option<PathBuf> {
+    let mut proto = String::from("/dev/null\n0 0\nd--755 0 0\n");
+    proto_entries(src, &mut proto);
+    proto.push_str("$\n");
+    let proto_path = dir.path().join("proto.txt");
+    fs::write(&proto_path, proto).unwrap();
+
+    let image = dir.path().join("fs.img");
+    fs::File::create(&image)
+        .unwrap()
+        .set_len(IMAGE_SIZE)
+        .unwrap();
+    let output = Command::new("mkfs.xfs")
+        .args(["-q", "-f"])
+        .args(extra_args)
+        .arg("-p")
+        .arg(&proto_path)
+        .arg(&image)
+        .output()
+        .ok()?;
+    assert!(
+        output.status.success(),
+        "mkfs.xfs failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Some(image)
+}
+
+/// Run `xfs_repair -n` and report whether the image is clean.
+fn repair_clean(image: &Path) -> bool {
+    Command::new("xfs_repair")
+        .arg("-n")
+        .arg("-f")
+        .arg(image)
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(true)
+}
+
+fn populate(dir: &TempDir) -> PathBuf {
+    let src = dir.path().join("src");
+    fs::create_dir_all(src.join("docs/nested")).unwrap();
+    fs::write(src.join("readme.txt"), b"hello xfs").unwrap();
+    fs::write(src.join("docs/report.bin"), vec![0xABu8; 5000]).unwrap();
+    fs::write(src.join("docs/nested/deep.txt"), vec![b'd'; 700]).unwrap();
+    src
+}
+
+#[test]
+fn test_image_slack_round_trip() {
+    let dir = TempDir::new().unwrap();
+    let src = populate(&dir);
+    let Some(image) = mkfs_xfs(&dir, &src, &[]) else {
+        eprintln!("mkfs.xfs not available, skipping");
+        return;
+    };
+
+    let backend = XfsImageBackend::open(&image).unwrap();
+    let region = backend
+        .get_slack_info(Path::new("/docs/report.bin"))
+        .unwrap();
+
+    assert_eq!(region.logical_size, 5000);
+    assert_eq!(
+        region.available,
+        region.block_size - (5000 % region.block_size)
+    );
+
+    let hidden = b"hidden in xfs slack";
+    backend.write_slack(&region, 0, hidden).unwrap();
+    assert_eq!(
+        backend.read_slack(&region, 0, hidden.len()).unwrap(),
+        hidden
+    );
+
+    // Host content directly before the slack is untouched
+    let raw = fs::read(&image).unwrap();
+    let tail_start = (region.offset - 100) as usize;
+    assert_eq!(&raw[tail_start..region.offset as usize], &[0xABu8; 100][..]);
+
+    assert!(repair_clean(&image), "image must stay consistent");
+}
+
+#[test]
+fn test_image_candidate_hosts() {
+    let dir = TempDir::new().unwrap();
+    let src = populate(&dir);
+    fs::write(src.join("empty.txt"), b"").unwrap();
+    let Some(image) = mkfs_xfs(&dir, &src, &[]) else {
+        eprintln!("mkfs.xfs not available, skipping");
+        return;
+    };
+
+    let backend = XfsImageBackend::open(&image).unwrap();
+    let hosts: Vec<_> = backend
+        .candidate_hosts()
+        .unwrap()
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+
+    assert_eq!(
+        hosts,
+        vec![
+            Path::new("/docs/nested/deep.txt"),
+            Path::new("/docs/report.bin"),
+            Path::new("/readme.txt"),
+        ]
+    );
+}
+
+#[test]
+fn test_image_large_directory() {
+    let dir = TempDir::new().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(src.join("big")).unwrap();
+    // Too many entries for a shortform or single-block directory
+    for i in 0..400 {
+        fs::write(
+            src.join(format!("big/host_file_with_a_long_name_{}.txt", i)),
+            format!("content {}", i),
+        )
+        .unwrap();
+    }
+    let Some(image) = mkfs_xfs(&dir, &src, &[]) else {
+        eprintln!("mkfs.xfs not available, skipping");
+        return;
+    };
+
+    let backend = XfsImageBackend::open(&image).unwrap();
+    let parser = backend.parser();
+    let big = parser.lookup(Path::new("/big")).unwrap();
+    let entries = parser.read_dir(big).unwrap();
+    assert_eq!(entries.iter().filter(|e| !e.is_dot()).count(), 400);
+
+    let region = backend
+        .get_slack_info(Path::new("/big/host_file_with_a_long_name_321.txt"))
+        .unwrap();
+    assert_eq!(region.logical_size, "content 321".len() as u64);
+}
+
+#[test]
+fn test_image_v4_format() {
+    let dir = TempDir::new().unwrap();
+    let src = populate(&dir);
+    let Some(image) = mkfs_xfs(&dir, &src, &["-m", "crc=0"]) else {
+        eprintln!("mkfs.xfs not available, skipping");
+        return;
+    };
+
+    let backend = XfsImageBackend::open(&image).unwrap();
+    assert!(!backend.parser().superblock().has_crc());
+    let region = backend
+        .get_slack_info(Path::new("/docs/nested/deep.txt"))
+        .unwrap();
+    assert_eq!(region.logical_size, 700);
+
+    let raw = fs::read(&image).unwrap();
+    let data_start = (region.offset - region.logical_size) as usize;
+    assert_eq!(&raw[data_start..data_start + 700], &[b'd'; 700][..]);
+}
+
+#[test]
+fn test_vfs_on_xfs_image() {
+    let dir = TempDir::new().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    for i in 0..8 {
+        fs::write(src.join(format!("host_{}.txt", i)), vec![b'h'; 300 + i]).unwrap();
+    }
+    let Some(image) = mkfs_xfs(&dir, &src, &[]) else {
+        eprintln!("mkfs.xfs not available, skipping");
+        return;
+    };
+    let meta_dir = dir.path().join("meta");
+    fs::create_dir(&meta_dir).unwrap();
+
+    {
+        let backend = Box::new(XfsImageBackend::open(&image).unwrap());
+        let mut vfs =
+            SlackVfs::create_with_backend(&meta_dir, "pw", VfsConfig::default(), backend).unwrap();
+        assert_eq!(vfs.info().host_count, 8);
+        vfs.create_file("/secret.txt", b"stored inside the image")
+            .unwrap();
+    }
+
+    let backend = Box::new(XfsImageBackend::open(&image).unwrap());
+    let vfs = SlackVfs::mount_with_backend(&meta_dir, "pw", backend).unwrap();
+    assert_eq!(
+        vfs.read_file("/secret.txt").unwrap(),
+        b"stored inside the image"
+    );
+    drop(vfs);
+
+    assert!(repair_clean(&image), "image must stay consistent");
+}